@@ -0,0 +1,54 @@
+//! Throughput of the expansion of the received bits into 8-bit
+//! samples, byte by byte and in bulk. Run with `cargo bench`.
+
+use std::{hint::black_box, time::Instant};
+
+use esp32_signal::io;
+
+/// 4 MiB of received data, 32M samples.
+const INPUT_LEN: usize = 4 << 20;
+const ROUNDS: usize = 20;
+
+fn measure(name: &str, input: &[u8], mut decode: impl FnMut(&[u8], &mut Vec<u8>)) {
+    let mut output = Vec::with_capacity(input.len() * 8);
+    // Warm up the caches and the allocation.
+    decode(input, &mut output);
+
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        output.clear();
+        decode(black_box(input), &mut output);
+        black_box(&output);
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    let samples = (input.len() * 8 * ROUNDS) as f64;
+    println!(
+        "{:<10} {:>8.1} Msamples/s ({:.2} ms per MiB received)",
+        name,
+        samples / elapsed / 1e6,
+        elapsed * 1e3 / (input.len() * ROUNDS) as f64 * (1 << 20) as f64
+    );
+}
+
+fn main() {
+    // Pseudo-random bytes, as a noisy signal.
+    let mut state: u32 = 0x12345678;
+    let input: Vec<u8> = (0..INPUT_LEN)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+
+    measure("per byte", &input, |input, output| {
+        for byte in input {
+            output.extend_from_slice(&io::decode_esp32_sample_unsigned_full_range(*byte));
+        }
+    });
+    measure("block", &input, |input, output| {
+        output.resize(input.len() * 8, 0);
+        io::decode_block(input, output, 0, 255);
+    });
+}
@@ -0,0 +1,78 @@
+//! Throughput of writing samples to a WAV file in memory, sample by
+//! sample as read-wav used to and in blocks. Run with `cargo bench`.
+
+use std::{hint::black_box, io::Cursor, time::Instant};
+
+use esp32_signal::{
+    dsp::{Sample, I24},
+    io,
+    wav::{self, WavFileWriter, WavSample},
+};
+use hound::WavWriter;
+
+type File<'a> = &'a mut Cursor<Vec<u8>>;
+
+/// 8M samples, 8 seconds at 1 MHz.
+const SAMPLES: usize = 8 << 20;
+const ROUNDS: usize = 5;
+
+fn measure<S: WavSample>(name: &str, samples: &[S]) {
+    let spec = wav::spec::<S>(1_000_000, 1);
+    let mut file = Cursor::new(Vec::with_capacity(samples.len() * 4 + 1024));
+    let mut run = |write: &dyn Fn(File)| {
+        let start = Instant::now();
+        for _ in 0..ROUNDS {
+            file.get_mut().clear();
+            file.set_position(0);
+            write(&mut file);
+            black_box(file.get_ref());
+        }
+        let elapsed = start.elapsed().as_secs_f64();
+        (samples.len() * 4 * ROUNDS) as f64 / elapsed / 1e6
+    };
+
+    // In the chunks of a capture, a quarter of a second each.
+    let per_sample = run(&|file| {
+        let mut writer = WavWriter::new(file, spec).unwrap();
+        for _ in 0..4 {
+            for sample in samples {
+                io::retry_if_interrupted(
+                    || sample.write_to(&mut writer),
+                    |e| match e {
+                        hound::Error::IoError(e) => Some(e),
+                        _ => None,
+                    },
+                )
+                .unwrap();
+            }
+        }
+        writer.finalize().unwrap();
+    });
+    let block = run(&|file| {
+        let mut writer = WavFileWriter::new(file, spec).unwrap();
+        for _ in 0..4 {
+            writer.write_samples(black_box(samples)).unwrap();
+        }
+        writer.finalize().unwrap();
+    });
+    println!(
+        "{:<4} per sample {:>7.1} Msamples/s, in blocks {:>7.1} Msamples/s ({:.1}x)",
+        name,
+        per_sample,
+        block,
+        block / per_sample
+    );
+}
+
+fn samples<S: Sample>(low: S, high: S) -> Vec<S> {
+    (0..SAMPLES / 4)
+        .map(|i| if i % 7 < 3 { high } else { low })
+        .collect()
+}
+
+fn main() {
+    measure("8", &samples(-128i8, 127));
+    measure("16", &samples(i16::MIN, i16::MAX));
+    measure("24", &samples(I24::MIN, I24::MAX));
+    measure("32f", &samples(-1.0f32, 1.0));
+}
@@ -0,0 +1,88 @@
+//! Scopes for running long commands that stop gracefully on Ctrl+C,
+//! running the cleanups registered on them before returning.
+
+use std::{
+    cell::RefCell,
+    sync::{atomic::AtomicBool, Arc},
+};
+
+use crate::{ctrlc, pipeline::ShutdownToken};
+
+type Cleanup<'s> = Box<dyn FnOnce() -> anyhow::Result<()> + 's>;
+
+pub struct CancellationScope<'s> {
+    token: ShutdownToken,
+    cleanups: RefCell<Vec<Cleanup<'s>>>,
+}
+
+pub struct ScopeOutput<A> {
+    pub interrupted: bool,
+    pub output: A,
+}
+
+impl<'s> CancellationScope<'s> {
+    fn new(token: ShutdownToken) -> CancellationScope<'s> {
+        CancellationScope {
+            token,
+            cleanups: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Whether the scope, or any of its parents, has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_shutdown()
+    }
+
+    /// Registers f to be run when the scope ends. Cleanups run in the
+    /// reverse order they were registered.
+    pub fn on_exit<F: FnOnce() -> anyhow::Result<()> + 's>(&self, f: F) {
+        self.cleanups.borrow_mut().push(Box::new(f));
+    }
+
+    /// Runs f in a child scope, cancelled along with this one, whose
+    /// cleanups run as soon as f returns.
+    pub fn nested<'c, A, F: FnOnce(&CancellationScope<'c>) -> A>(&self, f: F) -> anyhow::Result<A> {
+        let child = CancellationScope::new(self.token.child());
+        let output = f(&child);
+        child.run_cleanups()?;
+        Ok(output)
+    }
+
+    fn run_cleanups(&self) -> anyhow::Result<()> {
+        let cleanups = self.cleanups.take();
+        let mut result = Ok(());
+        for cleanup in cleanups.into_iter().rev() {
+            let cleanup_result = cleanup();
+            if result.is_ok() {
+                result = cleanup_result;
+            }
+        }
+        result
+    }
+}
+
+impl Drop for CancellationScope<'_> {
+    fn drop(&mut self) {
+        // Only reached with pending cleanups when unwinding.
+        if let Err(e) = self.run_cleanups() {
+            tracing::error!("Cleanup failed: {:#}", e);
+        }
+    }
+}
+
+/// Runs f in a root scope that gets cancelled on Ctrl+C instead of
+/// terminating the program.
+pub fn with_scope<'s, A, F: FnOnce(&CancellationScope<'s>) -> A>(
+    f: F,
+) -> anyhow::Result<ScopeOutput<A>> {
+    let flag = Arc::new(AtomicBool::new(false));
+    let _guard = ctrlc::catch_ctrlc(flag.clone())?;
+    let scope = CancellationScope::new(ShutdownToken::from_flag(flag));
+    let output = f(&scope);
+    let interrupted = scope.is_cancelled();
+    scope.run_cleanups()?;
+    Ok(ScopeOutput {
+        interrupted,
+        output,
+    })
+}
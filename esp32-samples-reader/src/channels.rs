@@ -0,0 +1,154 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+use crate::dsp::{Chain, Processor, Sample};
+
+/// How the firmware packs the bits of several channels into the
+/// stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interleave {
+    /// One bit of each channel in turn.
+    Bit,
+    /// Each byte split in one group of consecutive bits per channel,
+    /// like 2 channels of 4 bits each.
+    Group,
+    /// One byte of each channel in turn.
+    Byte,
+}
+
+impl FromStr for Interleave {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bit" => Ok(Interleave::Bit),
+            "group" => Ok(Interleave::Group),
+            "byte" => Ok(Interleave::Byte),
+            _ => Err(format!(
+                "Invalid interleave layout '{}'. Expected bit, group or byte",
+                s
+            )),
+        }
+    }
+}
+
+/// Splits a stream holding several interleaved channels into one bit
+/// stream per channel, packed like the single channel ones. Every N
+/// bytes of input, N being the number of channels, make one byte of
+/// each channel.
+pub struct Deinterleaver {
+    layout: Interleave,
+    channels: Vec<Vec<u8>>,
+}
+
+impl Deinterleaver {
+    pub fn new(channels: usize, layout: Interleave) -> anyhow::Result<Deinterleaver> {
+        if channels == 0 {
+            return Err(anyhow!("At least one channel is required"));
+        }
+        if layout != Interleave::Byte && 8 % channels != 0 {
+            return Err(anyhow!(
+                "Bits of {} channels can't be interleaved within a byte. Use 1, 2, 4 or 8 channels, or the byte layout",
+                channels
+            ));
+        }
+        Ok(Deinterleaver {
+            layout,
+            channels: vec![Vec::new(); channels],
+        })
+    }
+
+    /// Splits the given bytes, whose length must be a multiple of the
+    /// number of channels. Trailing bytes of an incomplete frame are
+    /// ignored.
+    pub fn split(&mut self, input: &[u8]) -> &[Vec<u8>] {
+        let count = self.channels.len();
+        let bits_per_group = 8 / count.min(8);
+        for channel in &mut self.channels {
+            channel.clear();
+        }
+
+        for frame in input.chunks_exact(count) {
+            for (index, channel) in self.channels.iter_mut().enumerate() {
+                let byte = match self.layout {
+                    Interleave::Byte => frame[index],
+                    Interleave::Bit | Interleave::Group => (0..8).fold(0, |byte, sample| {
+                        // Position of the sample in the bits of the
+                        // frame, most significant bit first.
+                        let position = match self.layout {
+                            Interleave::Bit => sample * count + index,
+                            _ => {
+                                (sample / bits_per_group) * 8
+                                    + index * bits_per_group
+                                    + sample % bits_per_group
+                            }
+                        };
+                        let bit = (frame[position / 8] >> (7 - position % 8)) & 1;
+                        (byte << 1) | bit
+                    }),
+                };
+                channel.push(byte);
+            }
+        }
+        &self.channels
+    }
+}
+
+/// Runs each channel of an interleaved stream through its own
+/// [`Chain`], interleaving the resulting samples again as frames of
+/// the output.
+pub struct MultiChain<S> {
+    deinterleaver: Deinterleaver,
+    chains: Vec<Chain<S>>,
+    interleaved: Vec<S>,
+}
+
+impl<S: Sample> MultiChain<S> {
+    /// Creates the processor of the given layout, with one chain per
+    /// channel.
+    pub fn new(layout: Interleave, chains: Vec<Chain<S>>) -> anyhow::Result<MultiChain<S>> {
+        Ok(MultiChain {
+            deinterleaver: Deinterleaver::new(chains.len(), layout)?,
+            chains,
+            interleaved: Vec::new(),
+        })
+    }
+
+    fn interleave(output: &mut Vec<S>, channels: &[&[S]]) {
+        output.clear();
+        // Every chain does the same processing, so they all produce
+        // the same amount of samples.
+        let len = channels
+            .iter()
+            .map(|samples| samples.len())
+            .min()
+            .unwrap_or(0);
+        output.reserve(len * channels.len());
+        for index in 0..len {
+            output.extend(channels.iter().map(|samples| samples[index]));
+        }
+    }
+}
+
+impl<S: Sample> Processor<S> for MultiChain<S> {
+    fn process(&mut self, input: &[u8], decode_bits: fn(u8) -> [S; 8]) -> &[S] {
+        let split = self.deinterleaver.split(input);
+        let samples: Vec<&[S]> = self
+            .chains
+            .iter_mut()
+            .zip(split)
+            .map(|(chain, bytes)| chain.process(bytes, decode_bits))
+            .collect();
+        Self::interleave(&mut self.interleaved, &samples);
+        &self.interleaved
+    }
+
+    fn finish(&mut self) -> Vec<S> {
+        let tails: Vec<Vec<S>> = self.chains.iter_mut().map(Chain::finish).collect();
+        let tails: Vec<&[S]> = tails.iter().map(Vec::as_slice).collect();
+        let mut output = Vec::new();
+        Self::interleave(&mut output, &tails);
+        output
+    }
+}
@@ -0,0 +1,136 @@
+use std::{iter, process::ExitCode, sync::atomic::Ordering};
+
+use alsa::{
+    pcm::{Access, Format, HwParams, PCM},
+    Direction, ValueOr,
+};
+use anyhow::anyhow;
+use clap::Parser;
+
+use super::{
+    stream::{self, MonitorArgs, OutputArgs, OutputSpec, StreamInput},
+    ChannelArgs, InputArgs, ProcessingArgs, WaveAmplitude,
+};
+use crate::{metrics::METRICS, pipeline::SinkWriter};
+
+/// Microseconds of audio buffered by ALSA. Playback starts once half
+/// of it is filled, leaving room for the jitter of the serial link.
+const BUFFER_TIME_US: u32 = 500_000;
+
+#[derive(Parser)]
+pub struct AlsaStreamArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[arg(short, long)]
+    pub sampling_rate: u32,
+
+    #[arg(short, long, default_value_t = WaveAmplitude::Full)]
+    pub wave_amplitude: WaveAmplitude,
+
+    /// ALSA PCM device the samples are played on, like hw:0,0. The
+    /// default device usually resamples to whatever rate the card
+    /// supports; hw: devices only accept the rates of the card.
+    #[arg(short, long, default_value = "default")]
+    pub device: String,
+
+    #[command(flatten)]
+    pub monitor: MonitorArgs,
+
+    // Outputs the samples also go to, besides the device.
+    #[command(flatten)]
+    pub outputs: OutputArgs,
+
+    #[command(flatten)]
+    pub channels: ChannelArgs,
+
+    #[command(flatten)]
+    pub processing: ProcessingArgs,
+}
+
+/// Playback of unsigned 8-bit samples on an ALSA PCM device.
+pub struct AlsaSink {
+    pcm: PCM,
+    written: u64,
+}
+
+impl AlsaSink {
+    pub fn open(device: &str, rate: u32, channels: u16) -> anyhow::Result<AlsaSink> {
+        let pcm = PCM::new(device, Direction::Playback, false)?;
+        {
+            let params = HwParams::any(&pcm)?;
+            params.set_channels(channels as u32)?;
+            params.set_rate(rate, ValueOr::Nearest)?;
+            params.set_format(Format::U8)?;
+            params.set_access(Access::RWInterleaved)?;
+            params.set_buffer_time_near(BUFFER_TIME_US, ValueOr::Nearest)?;
+            pcm.hw_params(&params)?;
+        }
+
+        {
+            let params = pcm.hw_params_current()?;
+            let actual_rate = params.get_rate()?;
+            if actual_rate != rate {
+                return Err(anyhow!(
+                    "Device {} doesn't support a rate of {} Hz (closest is {} Hz). Try the default device or a plug: one",
+                    device,
+                    rate,
+                    actual_rate
+                ));
+            }
+
+            let sw_params = pcm.sw_params_current()?;
+            sw_params.set_start_threshold(params.get_buffer_size()? / 2)?;
+            pcm.sw_params(&sw_params)?;
+        }
+
+        METRICS.counts_underruns.store(true, Ordering::Relaxed);
+        Ok(AlsaSink { pcm, written: 0 })
+    }
+}
+
+impl SinkWriter<u8> for AlsaSink {
+    fn write_samples(&mut self, mut samples: &[u8]) -> anyhow::Result<()> {
+        let io = self.pcm.io_u8()?;
+        while !samples.is_empty() {
+            match io.writei(samples) {
+                Ok(written) => {
+                    samples = &samples[written..];
+                    self.written += written as u64;
+                }
+                // Underruns happen when the link stalls for longer
+                // than the buffer lasts. Playback restarts once the
+                // buffer is filled again.
+                Err(e) => {
+                    METRICS.underruns.fetch_add(1, Ordering::Relaxed);
+                    self.pcm.try_recover(e, true)?
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        Ok(self.pcm.drain()?)
+    }
+
+    fn bytes_written(&self) -> Option<u64> {
+        Some(self.written)
+    }
+}
+
+/// Same as stream with the device as the first of its outputs.
+pub fn run_alsa_stream_command(args: &AlsaStreamArgs) -> anyhow::Result<ExitCode> {
+    let stream = StreamInput {
+        input: &args.input,
+        sampling_rate: args.sampling_rate,
+        wave_amplitude: args.wave_amplitude.clone(),
+        channels: &args.channels,
+        processing: &args.processing,
+        monitor: &args.monitor,
+    };
+    let outputs: Vec<_> = iter::once(OutputSpec::Alsa(args.device.clone()).into())
+        .chain(args.outputs.outputs.iter().cloned())
+        .collect();
+    stream::run_stream(&stream, &outputs, &args.outputs)
+}
@@ -0,0 +1,406 @@
+use std::{process::ExitCode, time::Duration};
+
+use anyhow::anyhow;
+use clap::{Parser, Subcommand};
+use nix::libc::SIGINT;
+
+use super::{InputArgs, ProcessingArgs};
+use crate::{
+    cancel,
+    dsp::{self, goertzel::ToneBank, spectrum::Spectrum},
+    io, rate,
+};
+
+const DTMF_LOW_FREQUENCIES: [f32; 4] = [697.0, 770.0, 852.0, 941.0];
+const DTMF_HIGH_FREQUENCIES: [f32; 4] = [1209.0, 1336.0, 1477.0, 1633.0];
+const DTMF_DIGITS: [[char; 4]; 4] = [
+    ['1', '2', '3', 'A'],
+    ['4', '5', '6', 'B'],
+    ['7', '8', '9', 'C'],
+    ['*', '0', '#', 'D'],
+];
+
+#[derive(Subcommand)]
+pub enum AnalyzeCommands {
+    /// Log the tones detected in the signal.
+    Tones(TonesArgs),
+    /// Report the strongest frequency of the signal over time, or draw
+    /// its spectrogram.
+    Spectrum(SpectrumArgs),
+}
+
+#[derive(Parser)]
+pub struct AnalyzeArgs {
+    #[command(subcommand)]
+    pub command: AnalyzeCommands,
+}
+
+#[derive(Parser)]
+pub struct TonesArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[arg(short, long)]
+    pub sampling_rate: u32,
+
+    /// Frequencies to detect, in Hz.
+    #[arg(short, long, value_delimiter = ',', required_unless_present = "dtmf")]
+    pub frequencies: Vec<f32>,
+
+    /// Detect DTMF tones and log the dialed digits.
+    #[arg(long)]
+    pub dtmf: bool,
+
+    /// Minimum level, relative to full scale, for a tone to be
+    /// considered present.
+    #[arg(long, default_value_t = 0.2)]
+    pub threshold: f32,
+
+    /// Length of each analyzed block. Longer blocks allow telling
+    /// closer frequencies apart, at the expense of time resolution.
+    #[arg(long, value_name = "MS", default_value_t = 20)]
+    pub block_duration: u32,
+
+    #[command(flatten)]
+    pub processing: ProcessingArgs,
+}
+
+#[derive(Parser)]
+pub struct SpectrumArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[arg(short, long)]
+    pub sampling_rate: u32,
+
+    /// Samples of each FFT. Longer ones tell closer frequencies apart,
+    /// at the expense of time resolution.
+    #[arg(long, value_name = "SAMPLES", default_value_t = 4096)]
+    #[arg(value_parser = clap::value_parser!(u32).range(16..=1 << 20))]
+    pub fft_size: u32,
+
+    /// Time covered by each line of output, in milliseconds. The FFTs
+    /// within it are averaged.
+    #[arg(long, value_name = "MS", default_value_t = 250)]
+    pub interval: u32,
+
+    /// Lowest frequency shown and considered for the peak, in Hz. The
+    /// DC level is skipped by default.
+    #[arg(long, value_name = "HZ")]
+    pub min_frequency: Option<f32>,
+
+    /// Highest frequency shown and considered for the peak, in Hz. Up
+    /// to half the rate of the decoded samples by default.
+    #[arg(long, value_name = "HZ")]
+    pub max_frequency: Option<f32>,
+
+    /// Draw each line as a row of a spectrogram, with the level of each
+    /// band as a character from ' ' (-80 dB or less) to '@' (0 dB).
+    #[arg(long)]
+    pub spectrogram: bool,
+
+    /// Columns of the spectrogram.
+    #[arg(long, default_value_t = 64, value_parser = clap::value_parser!(u32).range(1..=1000))]
+    pub width: u32,
+
+    #[command(flatten)]
+    pub processing: ProcessingArgs,
+}
+
+/// Characters of the spectrogram, from the lowest level to the highest.
+const SPECTROGRAM_SHADES: &[u8] = b" .:-=+*#%@";
+
+/// Level below which the spectrogram is blank.
+const SPECTROGRAM_FLOOR_DB: f32 = -80.0;
+
+fn decibels(amplitude: f32) -> f32 {
+    20.0 * amplitude.max(1e-9).log10()
+}
+
+/// Averages the spectra of the FFTs within each interval, and prints
+/// their peak and, optionally, a row of the spectrogram.
+struct SpectrumPrinter {
+    sum: Vec<f32>,
+    blocks: usize,
+    /// Range of bins shown.
+    bins: (usize, usize),
+    bin_width: f32,
+    spectrogram: Option<usize>,
+}
+
+impl SpectrumPrinter {
+    /// Adds the amplitudes of the bins of a block.
+    fn add(&mut self, magnitudes: &[f32]) {
+        for (sum, magnitude) in self.sum.iter_mut().zip(magnitudes) {
+            *sum += magnitude * magnitude;
+        }
+        self.blocks += 1;
+    }
+
+    /// Prints the average of the blocks added since the last line.
+    fn print(&mut self, time: f32) {
+        if self.blocks == 0 {
+            return;
+        }
+        let levels: Vec<f32> = self
+            .sum
+            .iter()
+            .map(|sum| (sum / self.blocks as f32).sqrt())
+            .collect();
+        self.sum.fill(0.0);
+        self.blocks = 0;
+
+        let (first, last) = self.bins;
+        let peak = (first..=last)
+            .max_by(|a, b| levels[*a].total_cmp(&levels[*b]))
+            .unwrap();
+        // Interpolated between the neighbouring bins, as tones rarely
+        // fall right on a bin.
+        let offset = if peak > 0 && peak + 1 < levels.len() {
+            let (a, b, c) = (
+                decibels(levels[peak - 1]),
+                decibels(levels[peak]),
+                decibels(levels[peak + 1]),
+            );
+            let denominator = a - 2.0 * b + c;
+            if denominator.abs() > f32::EPSILON {
+                (0.5 * (a - c) / denominator).clamp(-0.5, 0.5)
+            } else {
+                0.0
+            }
+        } else {
+            0.0
+        };
+        let peak_line = format!(
+            "{:.1} Hz ({:.1} dB)",
+            (peak as f32 + offset) * self.bin_width,
+            decibels(levels[peak])
+        );
+
+        match self.spectrogram {
+            Some(width) => {
+                let row: String = (0..width)
+                    .map(|column| {
+                        let bins = last + 1 - first;
+                        let from = first + column * bins / width;
+                        let to = usize::max(from + 1, first + (column + 1) * bins / width);
+                        let level = decibels(levels[from..to].iter().copied().fold(0.0, f32::max));
+                        let shade = ((level - SPECTROGRAM_FLOOR_DB) / -SPECTROGRAM_FLOOR_DB
+                            * (SPECTROGRAM_SHADES.len() - 1) as f32)
+                            .round()
+                            .clamp(0.0, (SPECTROGRAM_SHADES.len() - 1) as f32);
+                        SPECTROGRAM_SHADES[shade as usize] as char
+                    })
+                    .collect();
+                println!("{:9.3}s |{}| {}", time, row, peak_line);
+            }
+            None => println!("{:.3}s: peak at {}", time, peak_line),
+        }
+    }
+}
+
+fn run_spectrum_command(args: &SpectrumArgs) -> anyhow::Result<ExitCode> {
+    let output_rate = args.processing.output_rate(args.sampling_rate);
+    let mut spectrum = Spectrum::new(args.fft_size as usize);
+    let bin_width = spectrum.bin_frequency(1, output_rate);
+    let nyquist = args.fft_size as usize / 2;
+    let bin_of = |frequency: f32| ((frequency / bin_width).round() as usize).min(nyquist);
+    let first = args.min_frequency.map_or(1, bin_of);
+    let last = args.max_frequency.map_or(nyquist, bin_of);
+    if first > last {
+        return Err(anyhow!(
+            "--min-frequency has to be below --max-frequency, and both within half the output rate"
+        ));
+    }
+
+    let mut printer = SpectrumPrinter {
+        sum: vec![0.0; nyquist + 1],
+        blocks: 0,
+        bins: (first, last),
+        bin_width,
+        spectrogram: args.spectrogram.then_some(args.width as usize),
+    };
+    if args.spectrogram {
+        println!(
+            "{:>10} |{:<width$}| peak",
+            "",
+            format!(
+                "{:.0} Hz to {:.0} Hz",
+                first as f32 * bin_width,
+                last as f32 * bin_width
+            ),
+            width = args.width as usize
+        );
+    }
+
+    let interval = u64::max(dsp::millis_to_samples(output_rate, args.interval) as u64, 1);
+    let mut chain = args.processing.chain::<f32>(args.sampling_rate)?;
+    let buf_size = args.input.chunk_len(
+        args.sampling_rate,
+        usize::max(rate::bytes_per(args.sampling_rate, 20), 32),
+    );
+    let mut buf = vec![0; buf_size];
+    let mut serial = args
+        .input
+        .open(args.sampling_rate, Duration::from_secs(1))?;
+    let mut total_samples: u64 = 0;
+    let mut next_line = interval;
+
+    let result = cancel::with_scope(|scope| -> anyhow::Result<()> {
+        while !scope.is_cancelled() {
+            let len = io::read_input(&mut serial, &mut buf)?;
+            if len == 0 {
+                break;
+            }
+
+            let samples = chain.process(&buf[..len], io::decode_esp32_sample_f32);
+            spectrum.process(samples, |magnitudes| printer.add(magnitudes));
+            total_samples += samples.len() as u64;
+            if total_samples >= next_line {
+                printer.print(total_samples as f32 / output_rate as f32);
+                next_line = total_samples + interval;
+            }
+        }
+        Ok(())
+    })?;
+
+    result.output?;
+    Ok(if result.interrupted {
+        ExitCode::from(128 + SIGINT as u8)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+struct ToneDetector {
+    frequencies: Vec<f32>,
+    dtmf: bool,
+    threshold: f32,
+    present: Vec<bool>,
+    digit: Option<char>,
+}
+
+impl ToneDetector {
+    fn new(frequencies: &[f32], dtmf: bool, threshold: f32) -> ToneDetector {
+        ToneDetector {
+            frequencies: frequencies.to_vec(),
+            dtmf,
+            threshold,
+            present: vec![false; frequencies.len()],
+            digit: None,
+        }
+    }
+
+    fn bank_frequencies(&self) -> Vec<f32> {
+        let mut frequencies = self.frequencies.clone();
+        if self.dtmf {
+            frequencies.extend_from_slice(&DTMF_LOW_FREQUENCIES);
+            frequencies.extend_from_slice(&DTMF_HIGH_FREQUENCIES);
+        }
+        frequencies
+    }
+
+    fn strongest(levels: &[f32]) -> (usize, f32) {
+        levels
+            .iter()
+            .copied()
+            .enumerate()
+            .fold(
+                (0, 0.0),
+                |best, (i, level)| {
+                    if level > best.1 {
+                        (i, level)
+                    } else {
+                        best
+                    }
+                },
+            )
+    }
+
+    fn on_block(&mut self, levels: &[f32], time: f32) {
+        for (i, frequency) in self.frequencies.iter().enumerate() {
+            let present = levels[i] >= self.threshold;
+            if present != self.present[i] {
+                self.present[i] = present;
+                if present {
+                    println!(
+                        "{:.3}s: {} Hz started (level {:.2})",
+                        time, frequency, levels[i]
+                    );
+                } else {
+                    println!("{:.3}s: {} Hz ended", time, frequency);
+                }
+            }
+        }
+
+        if self.dtmf {
+            let dtmf_levels = &levels[self.frequencies.len()..];
+            let (low, low_level) = Self::strongest(&dtmf_levels[..4]);
+            let (high, high_level) = Self::strongest(&dtmf_levels[4..]);
+            let digit = if low_level >= self.threshold && high_level >= self.threshold {
+                Some(DTMF_DIGITS[low][high])
+            } else {
+                None
+            };
+
+            if digit != self.digit {
+                self.digit = digit;
+                if let Some(digit) = digit {
+                    println!("{:.3}s: DTMF {}", time, digit);
+                }
+            }
+        }
+    }
+}
+
+fn run_tones_command(args: &TonesArgs) -> anyhow::Result<ExitCode> {
+    let output_rate = args.processing.output_rate(args.sampling_rate);
+    let block_len = usize::max(dsp::millis_to_samples(output_rate, args.block_duration), 1);
+
+    let mut chain = args.processing.chain::<f32>(args.sampling_rate)?;
+    let mut detector = ToneDetector::new(&args.frequencies, args.dtmf, args.threshold);
+    let mut bank = ToneBank::new(&detector.bank_frequencies(), output_rate, block_len);
+
+    // Read approx 50 msecs of data on each iteration, with a minimum
+    // of 32 bytes.
+    let buf_size = args.input.chunk_len(
+        args.sampling_rate,
+        usize::max(rate::bytes_per(args.sampling_rate, 20), 32),
+    );
+    let mut buf = vec![0; buf_size];
+    let mut serial = args
+        .input
+        .open(args.sampling_rate, Duration::from_secs(1))?;
+    let mut total_blocks: usize = 0;
+
+    let result = cancel::with_scope(|scope| -> anyhow::Result<()> {
+        while !scope.is_cancelled() {
+            let len = io::read_input(&mut serial, &mut buf)?;
+            if len == 0 {
+                break;
+            }
+
+            let samples = chain.process(&buf[..len], io::decode_esp32_sample_f32);
+            bank.process(samples, |levels| {
+                total_blocks += 1;
+                let time = (total_blocks * block_len) as f32 / output_rate as f32;
+                detector.on_block(levels, time);
+            });
+        }
+        Ok(())
+    })?;
+
+    result.output?;
+    Ok(if result.interrupted {
+        ExitCode::from(128 + SIGINT as u8)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+pub fn run_analyze_command(args: &AnalyzeArgs) -> anyhow::Result<ExitCode> {
+    match &args.command {
+        AnalyzeCommands::Tones(args) => run_tones_command(args),
+        AnalyzeCommands::Spectrum(args) => run_spectrum_command(args),
+    }
+}
@@ -0,0 +1,104 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    os::unix::net::UnixStream,
+    process::ExitCode,
+};
+
+use anyhow::anyhow;
+use clap::Parser;
+
+use crate::{
+    state::CaptureState,
+    status::{self, StatusPrinter},
+};
+
+#[derive(Parser)]
+pub struct AttachArgs {
+    /// PID of the capture to attach to. Can be omitted if there's only
+    /// one capture running.
+    pub pid: Option<u32>,
+
+    /// Show the level of each channel next to the status of the
+    /// capture. It needs to be started with --preview.
+    #[arg(long)]
+    pub preview: bool,
+}
+
+const LEVEL_METER_WIDTH: usize = 20;
+
+/// Renders the range covered by the last block of each channel of a
+/// capture preview.
+fn level_meters(preview: &File) -> std::io::Result<String> {
+    let mut blocks = status::latest_preview_blocks(preview)?;
+    if blocks.is_empty() {
+        blocks.push([128, 128]);
+    }
+
+    let meters: Vec<String> = blocks
+        .iter()
+        .map(|block| {
+            let level = block[1].saturating_sub(block[0]) as usize * LEVEL_METER_WIDTH / 255;
+            format!(
+                "[{}{}]",
+                "#".repeat(level),
+                " ".repeat(LEVEL_METER_WIDTH - level)
+            )
+        })
+        .collect();
+    Ok(meters.join(" "))
+}
+
+pub fn run_attach_command(args: &AttachArgs) -> anyhow::Result<ExitCode> {
+    let (pid, path) = match args.pid {
+        Some(pid) => (pid, status::socket_path(pid)),
+        None => {
+            let captures = status::running_captures()?;
+            match captures.as_slice() {
+                [] => return Err(anyhow!("No running capture found")),
+                [capture] => capture.clone(),
+                _ => {
+                    let pids: Vec<_> = captures.iter().map(|(pid, _)| pid.to_string()).collect();
+                    tracing::error!(
+                        "Several captures are running, give the PID of one of them: {}",
+                        pids.join(", ")
+                    );
+                    return Ok(ExitCode::FAILURE);
+                }
+            }
+        }
+    };
+
+    let stream = UnixStream::connect(&path)
+        .map_err(|e| anyhow!("Unable to attach to {}: {}", path.display(), e))?;
+    let preview = match args.preview {
+        true => Some(File::open(status::preview_path(pid)).map_err(|e| {
+            anyhow!(
+                "Unable to open the preview of the capture, which needs --preview: {}",
+                e
+            )
+        })?),
+        false => None,
+    };
+
+    // Attached clients only read, so stopping this process (with
+    // Ctrl+C or otherwise) doesn't affect the capture at all.
+    let mut printer = StatusPrinter::stderr();
+    let mut last_line = String::new();
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        match &preview {
+            Some(preview) => printer.print(&format!("{} {}", line, level_meters(preview)?)),
+            None => printer.print(&line),
+        }
+        last_line = line;
+    }
+    status::finish_line();
+    // The capture sends its final state before closing the socket.
+    if last_line.starts_with(&format!("{}:", CaptureState::Error)) {
+        tracing::error!("Capture failed.");
+        return Ok(ExitCode::FAILURE);
+    }
+    tracing::info!("Capture finished.");
+    Ok(ExitCode::SUCCESS)
+}
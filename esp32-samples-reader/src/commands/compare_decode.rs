@@ -0,0 +1,263 @@
+use std::{collections::VecDeque, io::Read, path::PathBuf, process::ExitCode};
+
+use anyhow::Context as _;
+use clap::Parser;
+
+use crate::{
+    decode::{DecodeMode, Decoder},
+    io,
+    source::dump,
+    wav::{self, FileOptions, OutputFile},
+};
+
+/// Bytes of the input decoded at once.
+const CHUNK_LEN: usize = 64 * 1024;
+/// Furthest the outputs are shifted against each other looking for
+/// the best match, in compared samples. Decoders with longer filters
+/// lag behind the rest.
+const MAX_LAG: usize = 64;
+
+#[derive(Parser)]
+pub struct CompareDecodeArgs {
+    /// Capture of the received bits, like the ones written by dump.
+    #[arg(short, long)]
+    pub input: PathBuf,
+
+    /// Sampling rate the input was captured at.
+    #[arg(short, long)]
+    pub sampling_rate: u32,
+
+    /// First decode mode: bits, envelope:<window> or pdm:<factor>.
+    #[arg(long, value_name = "MODE")]
+    pub a: DecodeMode,
+
+    /// Second decode mode, compared against the first.
+    #[arg(long, value_name = "MODE")]
+    pub b: DecodeMode,
+
+    /// Write both outputs side by side to a stereo 16-bit WAV file, A
+    /// on the left and B on the right, at the rate they are compared.
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+/// Output of one of the decoders, averaged over the windows of the
+/// coarser one so both line up sample by sample.
+struct Aligned {
+    mode: DecodeMode,
+    decoder: Decoder,
+    decoded: Vec<f32>,
+    /// Samples of each compared window, in bits of the input.
+    window: u64,
+    /// Bits decoded so far.
+    position: u64,
+    sum: f32,
+    count: u32,
+    ready: VecDeque<f32>,
+}
+
+impl Aligned {
+    fn new(mode: DecodeMode, window: u64) -> Aligned {
+        Aligned {
+            mode,
+            decoder: mode.decoder(),
+            decoded: Vec::new(),
+            window,
+            position: 0,
+            sum: 0.0,
+            count: 0,
+            ready: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, input: &[u8]) {
+        let bits = self.mode.bits_per_sample() as u64;
+        self.decoder
+            .decode(input, &mut self.decoded, io::decode_esp32_sample_f32);
+        for sample in &self.decoded {
+            self.sum += sample;
+            self.count += 1;
+            self.position += bits;
+            // Each sample goes to the window it ends in.
+            if self.position % self.window < bits {
+                self.ready.push_back(self.sum / self.count as f32);
+                self.sum = 0.0;
+                self.count = 0;
+            }
+        }
+    }
+}
+
+/// Running statistics of the compared outputs.
+struct Divergence {
+    samples: u64,
+    sum: [f64; 2],
+    sum_squares: [f64; 2],
+    difference_squares: f64,
+    max_difference: f32,
+    /// Sums of the products of A and B, with B delayed by each lag,
+    /// and then with A delayed.
+    products: [Vec<f64>; 2],
+    /// Last outputs, newest first.
+    history: [VecDeque<f32>; 2],
+}
+
+impl Divergence {
+    fn new() -> Divergence {
+        Divergence {
+            samples: 0,
+            sum: [0.0; 2],
+            sum_squares: [0.0; 2],
+            difference_squares: 0.0,
+            max_difference: 0.0,
+            products: [vec![0.0; MAX_LAG + 1], vec![0.0; MAX_LAG + 1]],
+            history: Default::default(),
+        }
+    }
+
+    fn push(&mut self, a: f32, b: f32) {
+        let pair = [a, b];
+        for (side, value) in pair.iter().enumerate() {
+            self.sum[side] += *value as f64;
+            self.sum_squares[side] += (*value as f64).powi(2);
+            self.history[side].push_front(*value);
+            self.history[side].truncate(MAX_LAG + 1);
+        }
+        let difference = (a - b).abs();
+        self.difference_squares += (difference as f64).powi(2);
+        self.max_difference = self.max_difference.max(difference);
+        for side in 0..2 {
+            let other = pair[1 - side] as f64;
+            for (lag, delayed) in self.history[side].iter().enumerate() {
+                self.products[side][lag] += other * *delayed as f64;
+            }
+        }
+        self.samples += 1;
+    }
+
+    fn mean(&self, side: usize) -> f64 {
+        self.sum[side] / self.samples as f64
+    }
+
+    fn rms(&self, side: usize) -> f64 {
+        (self.sum_squares[side] / self.samples as f64).sqrt()
+    }
+
+    /// Correlation of A and B, with `lag` samples of delay on `side`.
+    /// Leaves out the few samples at the ends that don't overlap.
+    fn correlation(&self, side: usize, lag: usize) -> f64 {
+        let n = self.samples as f64;
+        let variance = |side: usize| self.sum_squares[side] / n - self.mean(side).powi(2);
+        let covariance = self.products[side][lag] / (n - lag as f64) - self.mean(0) * self.mean(1);
+        covariance / (variance(0) * variance(1)).sqrt()
+    }
+
+    /// Lag with the highest correlation, positive when B trails A.
+    fn best_lag(&self) -> (i64, f64) {
+        let lags = (self.samples as usize).min(MAX_LAG + 1);
+        let mut best = (0, self.correlation(0, 0));
+        for lag in 1..lags {
+            // Products of B with the delayed A match B trailing A.
+            for (side, sign) in [(0, 1), (1, -1)] {
+                let correlation = self.correlation(side, lag);
+                if correlation > best.1 {
+                    best = (sign * lag as i64, correlation);
+                }
+            }
+        }
+        best
+    }
+}
+
+pub fn run_compare_decode_command(args: &CompareDecodeArgs) -> anyhow::Result<ExitCode> {
+    let mut input =
+        dump::open(&args.input).with_context(|| format!("Can't open {}", args.input.display()))?;
+
+    // Compared at the rate of the coarser decoder.
+    let window = u64::from(args.a.bits_per_sample().max(args.b.bits_per_sample()));
+    let rate = args.sampling_rate as f64 / window as f64;
+    let mut outputs = [Aligned::new(args.a, window), Aligned::new(args.b, window)];
+    let mut file = args
+        .output
+        .as_ref()
+        .map(|path| {
+            OutputFile::<i16>::create(
+                path.clone(),
+                wav::spec::<i16>(rate.round() as u32, 2),
+                &FileOptions::default(),
+            )
+        })
+        .transpose()?;
+
+    let mut divergence = Divergence::new();
+    let mut buf = vec![0; CHUNK_LEN];
+    let mut frames = Vec::new();
+    loop {
+        let len = input.read(&mut buf)?;
+        if len == 0 {
+            break;
+        }
+        for output in &mut outputs {
+            output.push(&buf[..len]);
+        }
+
+        frames.clear();
+        let [a, b] = &mut outputs;
+        while let (Some(a), Some(b)) = (a.ready.pop_front(), b.ready.pop_front()) {
+            divergence.push(a, b);
+            frames.extend([a, b].map(|value| (value.clamp(-1.0, 1.0) * i16::MAX as f32) as i16));
+        }
+        if let Some(file) = &mut file {
+            file.write(&frames)?;
+        }
+    }
+    if let Some(file) = file {
+        file.finalize()?;
+    }
+
+    if divergence.samples == 0 {
+        tracing::error!("The input is too short for any sample to be compared");
+        return Ok(ExitCode::FAILURE);
+    }
+    let (lag, best) = divergence.best_lag();
+    println!(
+        "Compared {} samples at {:.1} Hz ({:.2} seconds)",
+        divergence.samples,
+        rate,
+        divergence.samples as f64 / rate
+    );
+    println!(
+        "{:<16}{:>12}{:>12}",
+        "",
+        args.a.to_string(),
+        args.b.to_string()
+    );
+    println!(
+        "{:<16}{:>12.4}{:>12.4}",
+        "Mean",
+        divergence.mean(0),
+        divergence.mean(1)
+    );
+    println!(
+        "{:<16}{:>12.4}{:>12.4}",
+        "RMS",
+        divergence.rms(0),
+        divergence.rms(1)
+    );
+    println!(
+        "RMS difference: {:.4}",
+        (divergence.difference_squares / divergence.samples as f64).sqrt()
+    );
+    println!("Max difference: {:.4}", divergence.max_difference);
+    println!("Correlation: {:.4}", divergence.correlation(0, 0));
+    match lag {
+        0 => println!("Best correlation: {:.4}, without shifting them", best),
+        _ => println!(
+            "Best correlation: {:.4}, with B {} samples {} A",
+            best,
+            lag.abs(),
+            if lag < 0 { "ahead of" } else { "behind" }
+        ),
+    }
+    Ok(ExitCode::SUCCESS)
+}
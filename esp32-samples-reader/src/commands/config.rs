@@ -0,0 +1,110 @@
+use std::process::ExitCode;
+
+use anyhow::anyhow;
+use clap::{Parser, Subcommand};
+use toml::Value;
+
+use crate::config::{self, Config, ConfigValue};
+
+#[derive(Subcommand)]
+pub enum ConfigCommands {
+    /// Print the effective configuration values, along with where each
+    /// one comes from.
+    Show(ShowArgs),
+}
+
+#[derive(Parser)]
+pub struct ConfigArgs {
+    #[command(subcommand)]
+    pub command: ConfigCommands,
+}
+
+#[derive(Parser)]
+pub struct ShowArgs {
+    /// Also list the config files read and the sections applied, in
+    /// order of priority.
+    #[arg(long)]
+    pub origin: bool,
+
+    /// Apply the values bound to the USB device with the given serial
+    /// number, as if its port was in use.
+    #[arg(long, value_name = "SERIAL")]
+    pub device: Option<String>,
+}
+
+fn run_show_command(args: &ShowArgs, config: &Config) -> anyhow::Result<ExitCode> {
+    let device_config;
+    let config = match &args.device {
+        Some(serial) => {
+            device_config = config
+                .for_device(serial)
+                .ok_or_else(|| anyhow!("The config files have no [device.{}] table", serial))?;
+            &device_config
+        }
+        None => config,
+    };
+
+    if args.origin {
+        println!("# Config files, from lowest to highest priority:");
+        for (path, loaded) in &config.files {
+            println!(
+                "#   {} ({})",
+                path.display(),
+                if *loaded { "loaded" } else { "not found" }
+            );
+        }
+        for (section, _) in &config.sections {
+            println!("# The values of [{}] override the rest.", section);
+        }
+        println!("# Environment variables override the config files.");
+        println!("# Command line flags override any of these values.");
+        println!();
+    }
+
+    let env = config::env_values();
+    let print = |key: &str, value: &ConfigValue| match env.get(key) {
+        Some((name, env_value)) => println!(
+            "{} = {}  # environment ({}), over {}",
+            key,
+            Value::String(env_value.clone()),
+            name,
+            value.source()
+        ),
+        None => println!("{} = {}  # {}", key, value.value, value.source()),
+    };
+
+    let values = config.effective_values();
+    let top_level = values.get("").cloned().unwrap_or_default();
+    for (key, value) in &top_level {
+        print(key, value);
+    }
+    // Variables apply to the flag in every command, like the values
+    // of the top level.
+    for (key, (name, env_value)) in &env {
+        if !top_level.contains_key(key) {
+            println!(
+                "{} = {}  # environment ({})",
+                key,
+                Value::String(env_value.clone()),
+                name
+            );
+        }
+    }
+    println!();
+
+    for (command, table) in values.iter().filter(|(command, _)| !command.is_empty()) {
+        println!("[{}]", command);
+        for (key, value) in table {
+            print(key, value);
+        }
+        println!();
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+pub fn run_config_command(args: &ConfigArgs, config: &Config) -> anyhow::Result<ExitCode> {
+    match &args.command {
+        ConfigCommands::Show(args) => run_show_command(args, config),
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use anyhow::anyhow;
+use clap::{Parser, ValueEnum};
+
+use super::{InProgressArgs, ProcessingArgs, TagArgs};
+use crate::{
+    dsp::Chain,
+    edges::{EdgeExpander, EdgeReader},
+    pipeline::{DecodeSigned, SampleDecoder},
+    source::dump::DumpWriter,
+    wav::{self, FileOptions, OutputFile},
+};
+
+/// Samples generated at once while filling long spans without
+/// transitions.
+const CHUNK_SAMPLES: u64 = 1 << 20;
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ConvertFormat {
+    /// Signed 8-bit WAV file, after going through the processing.
+    Wav,
+    /// Bits packed like the ESP32 sends them, readable with --source
+    /// file:<path>.
+    Bits,
+}
+
+#[derive(Parser)]
+pub struct ConvertArgs {
+    /// Transitions written by read-raw with --format edges or
+    /// edges-bin.
+    #[arg(short, long)]
+    pub input: PathBuf,
+
+    #[arg(short, long)]
+    pub output: PathBuf,
+
+    /// Sampling rate the transitions were captured at.
+    #[arg(short, long)]
+    pub rate: u32,
+
+    #[arg(short, long, value_enum, default_value_t = ConvertFormat::Wav)]
+    pub format: ConvertFormat,
+
+    /// Write a sparse dump, with --format bits.
+    #[arg(long)]
+    pub sparse: bool,
+
+    #[command(flatten)]
+    pub tag: TagArgs,
+
+    #[command(flatten)]
+    pub in_progress: InProgressArgs,
+
+    #[command(flatten)]
+    pub processing: ProcessingArgs,
+}
+
+struct WavOutput {
+    file: OutputFile<i8>,
+    chain: Chain<i8>,
+}
+
+enum Converted {
+    Wav(Box<WavOutput>),
+    Bits {
+        dump: DumpWriter,
+        /// Where the dump is written until it's complete.
+        writing: PathBuf,
+        path: PathBuf,
+    },
+}
+
+impl Converted {
+    fn create(args: &ConvertArgs, path: &Path) -> anyhow::Result<Converted> {
+        Ok(match args.format {
+            ConvertFormat::Wav => Converted::Wav(Box::new(WavOutput {
+                file: OutputFile::create(
+                    path.to_path_buf(),
+                    wav::spec::<i8>(args.processing.output_rate(args.rate), 1),
+                    &FileOptions {
+                        tags: args.tag.tags.clone(),
+                        inprogress_suffix: args.in_progress.inprogress_suffix.clone(),
+                        ..Default::default()
+                    },
+                )?,
+                chain: args.processing.chain(args.rate)?,
+            })),
+            ConvertFormat::Bits => {
+                let writing = args.in_progress.path(path);
+                Converted::Bits {
+                    dump: DumpWriter::create(&writing, args.sparse)?,
+                    writing,
+                    path: path.to_path_buf(),
+                }
+            }
+        })
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+        match self {
+            Converted::Wav(wav) => {
+                let WavOutput { file, chain } = wav.as_mut();
+                file.write(chain.process(bytes, DecodeSigned::decode))
+            }
+            Converted::Bits { dump, .. } => Ok(dump.write(bytes)?),
+        }
+    }
+
+    fn finish(self) -> anyhow::Result<()> {
+        match self {
+            Converted::Wav(wav) => {
+                let WavOutput {
+                    mut file,
+                    mut chain,
+                } = *wav;
+                file.write(&chain.finish())?;
+                file.finalize()
+            }
+            Converted::Bits {
+                dump,
+                writing,
+                path,
+            } => {
+                dump.finish()?;
+                Ok(wav::complete_output(&writing, &path)?)
+            }
+        }
+    }
+}
+
+/// Rebuilds the full rate signal out of a list of its transitions. The
+/// result ends at the last transition.
+pub fn run_convert_command(args: &ConvertArgs) -> anyhow::Result<ExitCode> {
+    let mut reader = EdgeReader::new(BufReader::new(File::open(&args.input)?))?;
+    let mut output = Converted::create(args, &args.output)?;
+    let mut expander = EdgeExpander::new();
+    let mut bytes = Vec::new();
+    let mut transitions = 0;
+
+    while let Some((index, level)) = reader.next_edge()? {
+        if index < expander.position() {
+            return Err(anyhow!("Transitions out of order at sample {}", index));
+        }
+        if transitions == 0 {
+            // Lists starting past the first sample are taken as
+            // having been at the opposite level until then.
+            expander.set_level(!level);
+        }
+
+        while expander.position() < index {
+            let until = u64::min(index, expander.position() + CHUNK_SAMPLES);
+            expander.fill_to(until, &mut bytes);
+            output.write(&bytes)?;
+            bytes.clear();
+        }
+        expander.set_level(level);
+        transitions += 1;
+    }
+
+    expander.finish(&mut bytes);
+    output.write(&bytes)?;
+    output.finish()?;
+
+    tracing::info!(
+        "Converted {} transitions, {:.2} seconds of signal",
+        transitions,
+        expander.position() as f64 / args.rate as f64
+    );
+    Ok(ExitCode::SUCCESS)
+}
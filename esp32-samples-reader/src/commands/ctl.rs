@@ -0,0 +1,77 @@
+use std::{
+    env,
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    process::ExitCode,
+};
+
+use anyhow::{anyhow, Context as _};
+use clap::{Parser, Subcommand};
+
+use super::daemon;
+
+#[derive(Subcommand)]
+pub enum CtlCommands {
+    /// Start recording the stream into a WAV file.
+    StartRecording {
+        /// Relative paths are resolved from the current directory. The
+        /// next file of the --record-template of the daemon if not
+        /// given.
+        file: Option<PathBuf>,
+    },
+    /// Stop the recording, finishing its file.
+    Stop,
+    /// Print the state of the capture and of the recording.
+    Status,
+}
+
+#[derive(Parser)]
+pub struct CtlArgs {
+    /// Control socket of the daemon.
+    #[arg(long, value_name = "PATH")]
+    pub socket: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: CtlCommands,
+}
+
+/// Sends a request to a running daemon and prints its answer.
+pub fn run_ctl_command(args: &CtlArgs) -> anyhow::Result<ExitCode> {
+    let request = match &args.command {
+        CtlCommands::StartRecording { file: None } => "start-recording".into(),
+        CtlCommands::StartRecording { file: Some(file) } => {
+            // The daemon runs somewhere else.
+            let file = env::current_dir()?.join(file);
+            let file = file
+                .to_str()
+                .ok_or_else(|| anyhow!("Paths outside of UTF-8 aren't supported"))?
+                .to_string();
+            format!("start-recording {}", file)
+        }
+        CtlCommands::Stop => "stop".into(),
+        CtlCommands::Status => "status".into(),
+    };
+
+    let path = args
+        .socket
+        .clone()
+        .unwrap_or_else(daemon::default_socket_path);
+    let mut stream = UnixStream::connect(&path)
+        .with_context(|| format!("Unable to connect to the daemon on {}", path.display()))?;
+    writeln!(stream, "{}", request)?;
+
+    let mut answer = String::new();
+    BufReader::new(stream).read_line(&mut answer)?;
+    match answer.trim_end().split_once(": ") {
+        Some(("ok", message)) => {
+            println!("{}", message);
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(("error", message)) => {
+            eprintln!("{}", message);
+            Ok(ExitCode::FAILURE)
+        }
+        _ => Err(anyhow!("Unexpected answer from the daemon: {:?}", answer)),
+    }
+}
@@ -0,0 +1,537 @@
+use std::{
+    env,
+    ffi::OsStr,
+    fs,
+    io::{BufRead, BufReader, ErrorKind, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::mpsc::{self, Receiver, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context as _};
+use clap::Parser;
+use hound::WavSpec;
+use toml::{Table, Value};
+
+use super::pulse_stream::{self, PulseStreamArgs};
+use crate::{
+    config, rate, state, status,
+    wav::{self, numbered_path, FileOptions, OutputFile},
+};
+
+/// Longest wait for the capture to answer a request. It only looks at
+/// them between chunks, and not at all while the board is gone.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Extra wait for the answer of a request taken right before its
+/// deadline.
+const HANDLING_TIMEOUT: Duration = Duration::from_secs(1);
+/// Interval of the saves of the session while recording, keeping the
+/// seconds recorded up to date in case the daemon doesn't get to save
+/// it when exiting, like on a power cut.
+const SESSION_SAVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Socket of the daemon if none is given.
+pub fn default_socket_path() -> PathBuf {
+    status::socket_dir().join("daemon.sock")
+}
+
+/// Session file of the daemon if none is given, named after its
+/// socket. It's kept under $XDG_STATE_HOME, which unlike the directory
+/// of the socket survives reboots.
+pub fn default_state_path(socket: &Path) -> PathBuf {
+    let dir = match env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME").unwrap_or_default()).join(".local/state"),
+    };
+    let name = socket.file_stem().unwrap_or(OsStr::new("daemon"));
+    dir.join("esp32-samples-reader")
+        .join(name)
+        .with_extension("toml")
+}
+
+#[derive(Parser)]
+pub struct DaemonArgs {
+    /// Control socket, where ctl sends its requests.
+    #[arg(long, value_name = "PATH")]
+    pub socket: Option<PathBuf>,
+
+    /// Template of the files of the recordings started without one,
+    /// numbered like capture-001.wav for capture.wav. Files already
+    /// there are skipped.
+    #[arg(long, value_name = "PATH")]
+    pub record_template: Option<String>,
+
+    /// File keeping the session across restarts of the daemon: the
+    /// numbering of --record-template, whether a recording was going on
+    /// and the totals. Defaults to
+    /// $XDG_STATE_HOME/esp32-samples-reader/<name of the socket>.toml.
+    #[arg(long, value_name = "PATH")]
+    pub state: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub stream: PulseStreamArgs,
+}
+
+/// What the daemon keeps across restarts, reboots included, so it goes
+/// on numbering and recording where it left off.
+#[derive(Debug, PartialEq)]
+struct Session {
+    profile: Option<String>,
+    template: Option<String>,
+    /// Number of the next file of the template.
+    next_file: usize,
+    /// A recording was going on and no client stopped it, so it's
+    /// resumed in the next file of the template.
+    recording: bool,
+    recordings: u64,
+    recorded_seconds: f64,
+}
+
+impl Session {
+    fn new(profile: Option<String>, template: Option<String>) -> Session {
+        Session {
+            profile,
+            template,
+            next_file: 1,
+            recording: false,
+            recordings: 0,
+            recorded_seconds: 0.0,
+        }
+    }
+
+    /// The session saved in the given file, if it was saved with the
+    /// same profile and template. A new one otherwise.
+    fn resume(
+        path: &Path,
+        profile: Option<String>,
+        template: Option<String>,
+    ) -> anyhow::Result<Session> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Ok(Session::new(profile, template))
+            }
+            Err(e) => return Err(e).with_context(|| format!("Unable to read {}", path.display())),
+        };
+        let session = Session::from_table(&text.parse::<Table>()?)
+            .with_context(|| format!("Invalid session in {}", path.display()))?;
+        if session.profile != profile || session.template != template {
+            tracing::info!(
+                "The profile or the template changed since the last session, starting a new one"
+            );
+            return Ok(Session::new(profile, template));
+        }
+        Ok(session)
+    }
+
+    fn from_table(table: &Table) -> anyhow::Result<Session> {
+        let string = |key: &str| -> anyhow::Result<Option<String>> {
+            match table.get(key) {
+                None => Ok(None),
+                Some(Value::String(value)) => Ok(Some(value.clone())),
+                Some(_) => Err(anyhow!("{} isn't a string", key)),
+            }
+        };
+        let integer = |key: &str| -> anyhow::Result<u64> {
+            match table.get(key) {
+                Some(Value::Integer(value)) if *value >= 0 => Ok(*value as u64),
+                _ => Err(anyhow!("{} isn't a count", key)),
+            }
+        };
+        Ok(Session {
+            profile: string("profile")?,
+            template: string("template")?,
+            next_file: integer("next_file")? as usize,
+            recording: table
+                .get("recording")
+                .and_then(Value::as_bool)
+                .ok_or_else(|| anyhow!("recording isn't a boolean"))?,
+            recordings: integer("recordings")?,
+            recorded_seconds: table
+                .get("recorded_seconds")
+                .and_then(Value::as_float)
+                .ok_or_else(|| anyhow!("recorded_seconds isn't a number"))?,
+        })
+    }
+
+    fn to_table(&self) -> Table {
+        let mut table = Table::new();
+        if let Some(profile) = &self.profile {
+            table.insert("profile".into(), profile.clone().into());
+        }
+        if let Some(template) = &self.template {
+            table.insert("template".into(), template.clone().into());
+        }
+        table.insert("next_file".into(), (self.next_file as i64).into());
+        table.insert("recording".into(), self.recording.into());
+        table.insert("recordings".into(), (self.recordings as i64).into());
+        table.insert("recorded_seconds".into(), self.recorded_seconds.into());
+        table
+    }
+
+    /// Saves the session, replacing the file at once so a power cut
+    /// never leaves half of it.
+    fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let writing = wav::in_progress_path(path, Some(".tmp"));
+        fs::write(&writing, self.to_table().to_string())?;
+        fs::rename(&writing, path)?;
+        Ok(())
+    }
+
+    /// The next file of the template, skipping the ones already there.
+    fn next_path(&mut self) -> Option<PathBuf> {
+        let template = self.template.as_deref()?;
+        loop {
+            let path = numbered_path(template, self.next_file);
+            self.next_file += 1;
+            if !path.exists() {
+                return Some(path);
+            }
+        }
+    }
+}
+
+/// A request of a control client.
+pub enum Request {
+    /// Into the given file, or the next one of the template.
+    StartRecording(Option<PathBuf>),
+    Stop,
+    Status,
+}
+
+impl Request {
+    fn parse(line: &str) -> Result<Request, String> {
+        let line = line.trim();
+        let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+        match (command, argument.trim()) {
+            ("start-recording", "") => Ok(Request::StartRecording(None)),
+            ("start-recording", path) => Ok(Request::StartRecording(Some(path.into()))),
+            ("stop", "") => Ok(Request::Stop),
+            ("status", "") => Ok(Request::Status),
+            _ => Err(format!(
+                "Unknown request '{}'. Expected start-recording [<file>], stop or status",
+                line
+            )),
+        }
+    }
+}
+
+/// A request waiting for the capture, which the client stops waiting
+/// for at the deadline.
+pub struct Pending {
+    request: Request,
+    reply: Sender<String>,
+    deadline: Instant,
+}
+
+struct Recording {
+    path: PathBuf,
+    file: OutputFile<u8>,
+    frames: u64,
+}
+
+/// Records the samples played by the daemon into WAV files, as asked by
+/// the control clients. Requests are handled between chunks, on the
+/// thread of the capture.
+pub struct Recorder {
+    requests: Receiver<Pending>,
+    spec: WavSpec,
+    recording: Option<Recording>,
+    session: Session,
+    session_path: PathBuf,
+    last_save: Instant,
+}
+
+impl Recorder {
+    /// Handles the pending requests, then records the samples of the
+    /// latest chunk if recording. Requests whose client already gave up
+    /// on them are dropped, as it was told they failed.
+    pub fn process(&mut self, samples: &[u8]) {
+        while let Ok(pending) = self.requests.try_recv() {
+            if Instant::now() > pending.deadline {
+                tracing::debug!("Dropped a request the client stopped waiting for");
+                continue;
+            }
+            let answer = match self.handle(pending.request) {
+                Ok(message) => format!("ok: {}", message),
+                Err(e) => format!("error: {:#}", e),
+            };
+            let _ = pending.reply.send(answer);
+        }
+
+        if let Some(recording) = &mut self.recording {
+            match recording.file.write(samples) {
+                Ok(()) => recording.frames += (samples.len() / self.spec.channels as usize) as u64,
+                Err(e) => {
+                    tracing::error!("Recording to {} failed: {:#}", recording.path.display(), e);
+                    let recording = self.recording.take().unwrap();
+                    self.session.recorded_seconds += self.seconds(&recording);
+                    self.session.recording = false;
+                    self.save_session();
+                }
+            }
+        }
+        if self.recording.is_some() && self.last_save.elapsed() >= SESSION_SAVE_INTERVAL {
+            self.save_session();
+        }
+    }
+
+    fn seconds(&self, recording: &Recording) -> f64 {
+        recording.frames as f64 / self.spec.sample_rate as f64
+    }
+
+    /// Saves the session with the seconds of the recording going on
+    /// counted in. Failing to do it doesn't stop the capture.
+    fn save_session(&mut self) {
+        self.last_save = Instant::now();
+        let current = self.recording.as_ref().map_or(0.0, |r| self.seconds(r));
+        self.session.recorded_seconds += current;
+        let saved = self.session.save(&self.session_path);
+        self.session.recorded_seconds -= current;
+        if let Err(e) = saved {
+            tracing::warn!(
+                "Unable to save the session to {}: {:#}",
+                self.session_path.display(),
+                e
+            );
+        }
+    }
+
+    fn totals(&self) -> String {
+        let current = self.recording.as_ref().map_or(0.0, |r| self.seconds(r));
+        format!(
+            "{} recordings, {:.2} seconds in total",
+            self.session.recordings,
+            self.session.recorded_seconds + current
+        )
+    }
+
+    fn handle(&mut self, request: Request) -> anyhow::Result<String> {
+        match request {
+            Request::StartRecording(path) => {
+                if let Some(recording) = &self.recording {
+                    return Err(anyhow!("Already recording to {}", recording.path.display()));
+                }
+                let path = match path {
+                    Some(path) => path,
+                    None => {
+                        let path = self.session.next_path().ok_or_else(|| {
+                            anyhow!("start-recording needs the path of the file, as the daemon has no --record-template")
+                        })?;
+                        // Templates usually have a directory per board.
+                        if let Some(dir) = path.parent() {
+                            fs::create_dir_all(dir)?;
+                        }
+                        path
+                    }
+                };
+                let file = OutputFile::create(path.clone(), self.spec, &FileOptions::default())?;
+                tracing::info!("Recording to {}", path.display());
+                let message = format!("recording to {}", path.display());
+                self.recording = Some(Recording {
+                    path,
+                    file,
+                    frames: 0,
+                });
+                self.session.recording = true;
+                self.session.recordings += 1;
+                self.save_session();
+                Ok(message)
+            }
+            Request::Stop => {
+                let recording = self
+                    .recording
+                    .take()
+                    .ok_or_else(|| anyhow!("Not recording"))?;
+                self.session.recorded_seconds += self.seconds(&recording);
+                self.session.recording = false;
+                self.save_session();
+                recording.file.finalize()?;
+                tracing::info!("Recording to {} stopped", recording.path.display());
+                Ok(format!(
+                    "recorded {} seconds to {}",
+                    rate::format_seconds(self.spec.sample_rate, recording.frames),
+                    recording.path.display()
+                ))
+            }
+            Request::Status => Ok(match &self.recording {
+                Some(recording) => format!(
+                    "{}, recording {} seconds to {}; {}",
+                    state::current(),
+                    rate::format_seconds(self.spec.sample_rate, recording.frames),
+                    recording.path.display(),
+                    self.totals()
+                ),
+                None => format!("{}, not recording; {}", state::current(), self.totals()),
+            }),
+        }
+    }
+
+    /// Finishes the file being recorded, if any, once the capture
+    /// ends. The session still has it going on, as no client stopped
+    /// it, so the next run of the daemon resumes it.
+    fn finish(&mut self) -> anyhow::Result<()> {
+        match self.recording.take() {
+            Some(recording) => {
+                self.session.recorded_seconds += self.seconds(&recording);
+                self.save_session();
+                recording.file.finalize()
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+/// Answers a control client, one request per line, until it goes away.
+fn serve_client(client: UnixStream, requests: &Sender<Pending>) {
+    let mut writer = match client.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return,
+    };
+    for line in BufReader::new(client).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        let answer = match Request::parse(&line) {
+            Ok(request) => {
+                let (reply, answer) = mpsc::channel();
+                let _ = requests.send(Pending {
+                    request,
+                    reply,
+                    deadline: Instant::now() + REPLY_TIMEOUT,
+                });
+                answer
+                    .recv_timeout(REPLY_TIMEOUT + HANDLING_TIMEOUT)
+                    .unwrap_or_else(|_| {
+                        format!(
+                            "error: the capture isn't reading samples ({}), so the request was dropped",
+                            state::current()
+                        )
+                    })
+            }
+            Err(e) => format!("error: {}", e),
+        };
+        if writeln!(writer, "{}", answer).is_err() {
+            break;
+        }
+    }
+}
+
+fn listen(path: &Path, requests: Sender<Pending>) -> anyhow::Result<()> {
+    match path.parent() {
+        Some(dir) if dir == status::socket_dir() => {
+            status::create_socket_dir()?;
+        }
+        Some(dir) => fs::create_dir_all(dir)?,
+        None => {}
+    }
+    // A socket nobody answers on is left by a daemon that didn't exit
+    // cleanly.
+    if UnixStream::connect(path).is_ok() {
+        return Err(anyhow!("A daemon is already running on {}", path.display()));
+    }
+    let _ = fs::remove_file(path);
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Unable to create the control socket {}", path.display()))?;
+
+    thread::Builder::new()
+        .name("control".into())
+        .spawn(move || {
+            for client in listener.incoming().flatten() {
+                let requests = requests.clone();
+                thread::spawn(move || serve_client(client, &requests));
+            }
+        })?;
+    Ok(())
+}
+
+/// Streams to Pulse like pulse-stream, recording the stream on demand
+/// of the clients of the control socket.
+pub fn run_daemon_command(args: &DaemonArgs) -> anyhow::Result<ExitCode> {
+    let path = args.socket.clone().unwrap_or_else(default_socket_path);
+    let (sender, requests) = mpsc::channel();
+    listen(&path, sender)?;
+    tracing::info!("Listening for requests on {}", path.display());
+
+    let session_path = args
+        .state
+        .clone()
+        .unwrap_or_else(|| default_state_path(&path));
+    let session = Session::resume(
+        &session_path,
+        config::selected_profile(env::args_os()),
+        args.record_template.clone(),
+    )?;
+    let resumed = session.recording;
+
+    let stream = &args.stream;
+    let mut recorder = Recorder {
+        requests,
+        spec: wav::spec::<u8>(
+            stream.processing.output_rate(stream.sampling_rate),
+            stream.channels.channels,
+        ),
+        recording: None,
+        session,
+        session_path,
+        last_save: Instant::now(),
+    };
+    recorder.save_session();
+    if resumed {
+        match recorder.handle(Request::StartRecording(None)) {
+            Ok(message) => tracing::info!("Resuming the recording of the last run, {}", message),
+            Err(e) => {
+                tracing::warn!("Unable to resume the recording of the last run: {:#}", e);
+                recorder.session.recording = false;
+                recorder.save_session();
+            }
+        }
+    }
+    let result = pulse_stream::run_pulse_stream(stream, Some(&mut recorder));
+    let finished = recorder.finish();
+    let _ = fs::remove_file(&path);
+    finished?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sessions_resume_with_the_same_profile_and_template() {
+        let dir = env::temp_dir().join(format!(
+            "esp32-samples-reader-session-{}",
+            std::process::id()
+        ));
+        let path = dir.join("state").join("daemon.toml");
+        let template = dir.join("capture.wav").to_string_lossy().into_owned();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("capture-002.wav"), "").unwrap();
+
+        let mut session = Session::resume(&path, None, Some(template.clone())).unwrap();
+        assert_eq!(session.next_path(), Some(dir.join("capture-001.wav")));
+        assert_eq!(session.next_path(), Some(dir.join("capture-003.wav")));
+        session.recording = true;
+        session.recordings = 2;
+        session.recorded_seconds = 1.5;
+        session.save(&path).unwrap();
+
+        assert_eq!(
+            Session::resume(&path, None, Some(template.clone())).unwrap(),
+            session
+        );
+        assert_eq!(
+            Session::resume(&path, Some("lab".into()), Some(template.clone())).unwrap(),
+            Session::new(Some("lab".into()), Some(template))
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,76 @@
+use std::{
+    io::{ErrorKind, Read},
+    process::ExitCode,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+
+use super::{InputArgs, ProcessingArgs};
+use crate::{
+    dsp::silence::{ActivityDetector, DetectActivity},
+    io, rate,
+};
+
+/// Exit code when the timeout expires without activity, apart from the
+/// 1 of errors.
+const NO_ACTIVITY: u8 = 3;
+
+#[derive(Parser)]
+pub struct DetectArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[arg(short, long)]
+    pub sampling_rate: u32,
+
+    /// Seconds to wait for activity before giving up, exiting with 3.
+    #[arg(short, long, default_value_t = 10)]
+    pub timeout: u64,
+
+    /// Level of the signal (0 to 1, relative to its idle level) taken
+    /// as activity.
+    #[arg(long, default_value_t = 0.1)]
+    pub threshold: f32,
+
+    #[command(flatten)]
+    pub processing: ProcessingArgs,
+}
+
+/// Exits successfully as soon as there's activity in the signal, or
+/// with NO_ACTIVITY once the timeout expires. Nothing is recorded.
+pub fn run_detect_command(args: &DetectArgs) -> anyhow::Result<ExitCode> {
+    let mut serial = args
+        .input
+        .open(args.sampling_rate, Duration::from_millis(200))?;
+    let timeout = Duration::from_secs(args.timeout);
+    let mut buf = vec![0; usize::max(32, rate::bytes_per(args.sampling_rate, 20))];
+    let mut chain = args.processing.chain::<f32>(args.sampling_rate)?;
+    let mut detector = ActivityDetector::new(
+        args.processing.output_rate(args.sampling_rate),
+        args.threshold,
+    );
+
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        let len = match serial.read(&mut buf) {
+            Ok(len) => len,
+            // No data at all doesn't count as activity either.
+            Err(e) if e.kind() == ErrorKind::TimedOut => continue,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        let samples = chain.process(&buf[..len], io::decode_esp32_sample_f32);
+        if samples.iter().any(|sample| detector.is_active(*sample)) {
+            tracing::info!(
+                "Activity detected after {:.2} seconds",
+                start.elapsed().as_secs_f32()
+            );
+            return Ok(ExitCode::SUCCESS);
+        }
+    }
+
+    tracing::info!("No activity detected in {} seconds", args.timeout);
+    Ok(ExitCode::from(NO_ACTIVITY))
+}
@@ -0,0 +1,78 @@
+use std::{
+    path::PathBuf,
+    process::ExitCode,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use nix::libc::SIGINT;
+
+use super::{InProgressArgs, InputArgs};
+use crate::{cancel, io, rate, source::dump::DumpWriter, wav};
+
+#[derive(Parser)]
+pub struct DumpArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[arg(short, long)]
+    pub sampling_rate: u32,
+
+    /// File the received data is written to, to be read later with
+    /// --source file:<path>.
+    #[arg(short, long)]
+    pub output: PathBuf,
+
+    /// Store runs of repeated bytes instead of every byte, which makes
+    /// dumps of mostly idle signals take a fraction of the space.
+    #[arg(long)]
+    pub sparse: bool,
+
+    #[command(flatten)]
+    pub in_progress: InProgressArgs,
+}
+
+/// Saves the data received from the ESP32 as is, without decoding it.
+pub fn run_dump_command(args: &DumpArgs) -> anyhow::Result<ExitCode> {
+    let mut serial = args
+        .input
+        .open(args.sampling_rate, Duration::from_secs(1))?;
+    let writing = args.in_progress.path(&args.output);
+    let mut dump = DumpWriter::create(&writing, args.sparse)?;
+    let default_len = usize::max(1024, rate::bytes_per(args.sampling_rate, 4));
+    let mut buf = vec![0; args.input.chunk_len(args.sampling_rate, default_len)];
+    let mut status = args.input.status_line(args.sampling_rate)?;
+    let mut total_bytes = 0;
+    let mut last_flush = Instant::now();
+
+    let result = cancel::with_scope(|scope| -> anyhow::Result<()> {
+        while !scope.is_cancelled() {
+            let len = io::read_input(&mut serial, &mut buf)?;
+            if len == 0 {
+                break;
+            }
+            dump.write(&buf[..len])?;
+            if last_flush.elapsed() >= args.input.flush_interval() {
+                dump.flush()?;
+                last_flush = Instant::now();
+            }
+            total_bytes += len;
+            status.update(total_bytes * 8, args.sampling_rate);
+            if len < buf.len() {
+                break;
+            }
+        }
+        Ok(())
+    })?;
+
+    let finished = dump.finish();
+    status.finish(result.output.is_ok() && finished.is_ok());
+    finished?;
+    result.output?;
+    wav::complete_output(&writing, &args.output)?;
+    Ok(if result.interrupted {
+        ExitCode::from(128 + SIGINT as u8)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
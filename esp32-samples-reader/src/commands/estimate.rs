@@ -0,0 +1,219 @@
+use std::{io::Cursor, process::ExitCode, time::Duration};
+
+use clap::{Parser, ValueEnum};
+
+use super::{read_wav::BitDepth, InputArgs};
+use crate::{
+    flac::{self, FlacWriter},
+    io,
+    pipeline::{DecodeF32, DecodeSigned, DecodeSigned16, DecodeSigned24, SampleDecoder},
+    rate,
+    segment::{ByteSize, TimeSpan},
+    source::dump::DumpWriter,
+    wav::{self, WavSample},
+};
+
+/// Bytes of the header of WAV files without tags.
+const WAV_HEADER_LEN: u64 = 44;
+/// Largest WAV file, as its sizes are 32-bit.
+const WAV_MAX_LEN: u64 = u32::MAX as u64;
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum EstimateFormat {
+    /// Files of read-wav.
+    Wav,
+    /// Files of read-wav --format flac.
+    Flac,
+    /// Files of read-wav --format opus.
+    #[cfg(feature = "opus")]
+    Opus,
+    /// Output of read-raw, a byte per sample.
+    Raw,
+    /// Files of dump, holding the received data as is.
+    Dump,
+    /// Files of dump --sparse.
+    DumpSparse,
+}
+
+#[derive(Parser)]
+pub struct EstimateArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    /// Sampling rate of the planned capture.
+    #[arg(short, long)]
+    pub rate: u32,
+
+    /// Length of the planned capture, like 90s, 30m or 8h.
+    #[arg(short, long)]
+    pub duration: TimeSpan,
+
+    /// Outputs to estimate, separated by commas. All of them if not
+    /// given.
+    #[arg(short, long, value_delimiter = ',')]
+    pub format: Vec<EstimateFormat>,
+
+    /// Sample format of the WAV and FLAC files.
+    #[arg(long, value_name = "BITS", default_value = "8")]
+    pub bit_depth: BitDepth,
+
+    /// Bitrate of the Opus files, in kbit/s.
+    #[cfg(feature = "opus")]
+    #[arg(long, value_name = "KBPS", default_value_t = 64)]
+    pub bitrate: u32,
+
+    /// Capture the given seconds of signal from the input first, for
+    /// measuring how well it compresses. Compressed outputs are
+    /// estimated at their largest otherwise.
+    #[arg(long, value_name = "SECS")]
+    pub probe: Option<u32>,
+}
+
+/// Reads the given seconds of signal from the input.
+fn capture_probe(args: &EstimateArgs, seconds: u32) -> anyhow::Result<Vec<u8>> {
+    let mut serial = args.input.open(args.rate, Duration::from_secs(1))?;
+    let len = rate::bytes_for_samples(args.rate as u64 * seconds as u64) as usize;
+    let mut data = vec![0; len];
+
+    tracing::info!("Capturing {} second(s) of signal...", seconds);
+    let mut read = 0;
+    let chunk_len = args
+        .input
+        .chunk_len(args.rate, rate::bytes_per(args.rate, 4));
+    while read < len {
+        let end = usize::min(read + chunk_len, len);
+        let chunk = io::read_input(&mut serial, &mut data[read..end])?;
+        read += chunk;
+        if read < end {
+            break;
+        }
+    }
+    data.truncate(read);
+    Ok(data)
+}
+
+/// Fraction of the uncompressed size taken by the probe as a FLAC
+/// file.
+fn flac_ratio<D: SampleDecoder>(args: &EstimateArgs, probe: &[u8]) -> anyhow::Result<f64>
+where
+    D::Sample: WavSample,
+{
+    let spec = wav::spec::<D::Sample>(args.rate, 1);
+    let mut file = Cursor::new(Vec::new());
+    let mut writer = FlacWriter::new(&mut file, spec, &[])?;
+    let mut samples = Vec::new();
+    for chunk in probe.chunks(4096) {
+        samples.clear();
+        samples.extend(
+            chunk
+                .iter()
+                .flat_map(|byte| D::decode(*byte))
+                .filter_map(|sample| sample.to_int()),
+        );
+        writer.write(&samples)?;
+    }
+    writer.finalize()?;
+
+    let uncompressed = probe.len() as u64 * 8 * (spec.bits_per_sample as u64 / 8);
+    Ok(file.get_ref().len() as f64 / uncompressed as f64)
+}
+
+/// Fraction of the plain size taken by the probe as a sparse dump.
+fn sparse_ratio(probe: &[u8]) -> anyhow::Result<f64> {
+    let mut file = Cursor::new(Vec::new());
+    let mut dump = DumpWriter::new(&mut file, true)?;
+    dump.write(probe)?;
+    dump.finish()?;
+    Ok(file.get_ref().len() as f64 / probe.len() as f64)
+}
+
+fn print_estimate<D: SampleDecoder>(args: &EstimateArgs, probe: Option<&[u8]>) -> anyhow::Result<()>
+where
+    D::Sample: WavSample,
+{
+    let samples = rate::samples_in(args.rate, args.duration.0);
+    let sample_len = D::Sample::BITS_PER_SAMPLE as u64 / 8;
+    let wav_len = WAV_HEADER_LEN + samples * sample_len;
+    let dump_len = rate::bytes_for_samples(samples);
+    let probe = probe.filter(|probe| !probe.is_empty());
+
+    println!(
+        "{} seconds at {} Hz, {} samples:",
+        args.duration.0.as_secs(),
+        args.rate,
+        samples
+    );
+    let formats = if args.format.is_empty() {
+        EstimateFormat::value_variants()
+    } else {
+        &args.format
+    };
+    for format in formats {
+        match format {
+            EstimateFormat::Wav => {
+                print!("  wav: {}", ByteSize(wav_len));
+                if wav_len > WAV_MAX_LEN {
+                    print!(" (over the 4G limit of WAV files, split it with --segment-size)");
+                }
+                println!();
+            }
+            EstimateFormat::Flac => {
+                let spec = wav::spec::<D::Sample>(args.rate, 1);
+                if let Err(e) = flac::check_spec(&spec) {
+                    println!("  flac: {}", e);
+                    continue;
+                }
+                match probe {
+                    Some(probe) => {
+                        let ratio = flac_ratio::<D>(args, probe)?;
+                        println!(
+                            "  flac: {} ({:.1}% of the WAV size, as measured)",
+                            ByteSize((wav_len as f64 * ratio) as u64),
+                            ratio * 100.0
+                        );
+                    }
+                    None => println!("  flac: up to {}", ByteSize(wav_len)),
+                }
+            }
+            #[cfg(feature = "opus")]
+            EstimateFormat::Opus => println!(
+                "  opus: {} at {} kbit/s",
+                ByteSize(args.bitrate as u64 * 1000 / 8 * args.duration.0.as_secs()),
+                args.bitrate
+            ),
+            EstimateFormat::Raw => println!("  raw: {}", ByteSize(samples)),
+            EstimateFormat::Dump => println!("  dump: {}", ByteSize(dump_len)),
+            EstimateFormat::DumpSparse => match probe {
+                Some(probe) => {
+                    let ratio = sparse_ratio(probe)?;
+                    println!(
+                        "  dump-sparse: {} ({:.1}% of the plain size, as measured)",
+                        ByteSize((dump_len as f64 * ratio) as u64),
+                        ratio * 100.0
+                    );
+                }
+                // Every byte different from the previous one.
+                None => println!("  dump-sparse: up to {}", ByteSize(dump_len * 2)),
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Prints the expected size of each output of a capture, for planning
+/// the storage of field captures.
+pub fn run_estimate_command(args: &EstimateArgs) -> anyhow::Result<ExitCode> {
+    let probe = args
+        .probe
+        .map(|seconds| capture_probe(args, seconds))
+        .transpose()?;
+    let probe = probe.as_deref();
+
+    match args.bit_depth {
+        BitDepth::Int8 => print_estimate::<DecodeSigned>(args, probe)?,
+        BitDepth::Int16 => print_estimate::<DecodeSigned16>(args, probe)?,
+        BitDepth::Int24 => print_estimate::<DecodeSigned24>(args, probe)?,
+        BitDepth::Float32 => print_estimate::<DecodeF32>(args, probe)?,
+    }
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,54 @@
+use std::process::ExitCode;
+
+use clap::Parser;
+use serialport::SerialPortType;
+
+use crate::source::usb;
+
+#[derive(Parser)]
+pub struct ListPortsArgs {
+    /// Only list the ports that look like ESP32 boards.
+    #[arg(long)]
+    pub esp32: bool,
+}
+
+pub fn run_list_ports_command(args: &ListPortsArgs) -> anyhow::Result<ExitCode> {
+    let mut ports = serialport::available_ports()?;
+    ports.sort_by(|a, b| a.port_name.cmp(&b.port_name));
+
+    for port in ports {
+        match &port.port_type {
+            SerialPortType::UsbPort(info) => {
+                let bridge = usb::esp32_bridge(info);
+                if args.esp32 && bridge.is_none() {
+                    continue;
+                }
+
+                println!(
+                    "{}  USB {:04x}:{:04x}  {}{}",
+                    port.port_name,
+                    info.vid,
+                    info.pid,
+                    [info.manufacturer.as_deref(), info.product.as_deref()]
+                        .into_iter()
+                        .flatten()
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                    match bridge {
+                        Some(bridge) => format!("  [likely ESP32, {}]", bridge),
+                        None => String::new(),
+                    }
+                );
+                if let Some(serial) = &info.serial_number {
+                    println!("    serial number: {}", serial);
+                }
+            }
+            _ if args.esp32 => {}
+            SerialPortType::PciPort => println!("{}  PCI", port.port_name),
+            SerialPortType::BluetoothPort => println!("{}  Bluetooth", port.port_name),
+            SerialPortType::Unknown => println!("{}", port.port_name),
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
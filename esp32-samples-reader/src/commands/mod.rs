@@ -1,2 +1,896 @@
+#[cfg(feature = "alsa")]
+pub mod alsa_stream;
+#[cfg(feature = "analysis")]
+pub mod analyze;
+pub mod attach;
+pub mod compare_decode;
+pub mod config;
+pub mod convert;
+#[cfg(feature = "pulse")]
+pub mod ctl;
+#[cfg(feature = "pulse")]
+pub mod daemon;
+pub mod detect;
+pub mod dump;
+pub mod estimate;
+pub mod list_ports;
+#[cfg(feature = "network")]
+pub mod multicast_stream;
+#[cfg(feature = "pipewire")]
+pub mod pipewire_stream;
+#[cfg(feature = "analysis")]
+pub mod probe;
+#[cfg(feature = "pulse")]
 pub mod pulse_stream;
+pub mod read_raw;
 pub mod read_wav;
+pub mod round_robin;
+#[cfg(feature = "tui")]
+pub mod scope;
+pub mod status;
+pub mod stream;
+#[cfg(feature = "pulse")]
+pub mod supervise;
+pub mod verify_golden;
+
+use std::fmt::Display;
+use std::{
+    io::Read,
+    iter,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context as _};
+use clap::ValueEnum;
+use clap::{builder::BoolishValueParser, Args};
+use nix::sys::{
+    resource::{getrusage, UsageWho},
+    time::TimeVal,
+};
+
+use crate::{
+    channels::{Interleave, MultiChain},
+    decode::DecodeMode,
+    dsp::{
+        self,
+        effects::{Effect, Effects},
+        fade::{Tail, Warmup},
+        resample::{self, Resampler},
+        Chain, Processor, Sample, Stage,
+    },
+    io::{BitOrder, FlowControl, NormalizedInput, Parity, SerialConfig},
+    metrics::METRICS,
+    pipeline::StageTimes,
+    rate,
+    segment::TimeSpan,
+    source::{read_ahead::ReadAhead, usb, OpenOptions, SourceSpec},
+    state::{self, CaptureState},
+    status::{StatsTarget, StatusLine},
+    wav::{self, Alignment, Tag},
+};
+#[cfg(feature = "dsp")]
+use crate::{
+    dsp::{
+        beep::Beeper,
+        biquad::{Band, Biquad},
+        filter::{DcBlocker, Filter},
+    },
+    trigger,
+};
+
+/// Most the input is read ahead with --low-memory, in milliseconds.
+const LOW_MEMORY_READ_AHEAD_MS: u64 = 250;
+/// Most samples of silence held in memory by --trim-silence with
+/// --low-memory, before moving them to a temporary file.
+pub const LOW_MEMORY_HELD_SAMPLES: usize = 1 << 14;
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum WaveAmplitude {
+    Full,
+    Half,
+}
+
+impl Display for WaveAmplitude {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_possible_value().unwrap().get_name())
+    }
+}
+
+// Line settings of the serial port, for bridges that need other than
+// the 8N1 without flow control the firmware uses. Not a doc comment,
+// as clap would take it as the about text of every command flattening
+// it.
+#[derive(Args)]
+pub struct SerialArgs {
+    #[arg(long, value_name = "BITS", default_value_t = 8)]
+    #[arg(value_parser = clap::value_parser!(u8).range(5..=8))]
+    pub data_bits: u8,
+
+    /// none, odd or even.
+    #[arg(long, default_value = "none")]
+    pub parity: Parity,
+
+    #[arg(long, value_name = "BITS", default_value_t = 1)]
+    #[arg(value_parser = clap::value_parser!(u8).range(1..=2))]
+    pub stop_bits: u8,
+
+    /// none, hardware (RTS/CTS) or software (XON/XOFF). Some USB
+    /// bridges need RTS/CTS for keeping up with high baud rates.
+    #[arg(long, value_name = "MODE", default_value = "none")]
+    pub flow_control: FlowControl,
+
+    /// Keep the board from resetting: DTR and RTS are both released
+    /// right after opening the port, as the auto-reset circuit of most
+    /// ESP32 boards only resets the chip when they differ. Can't be
+    /// combined with hardware flow control, which drives RTS.
+    #[arg(long, conflicts_with_all = ["dtr", "rts"])]
+    pub no_reset: bool,
+
+    /// Level of the DTR line once the port is open: on or off.
+    #[arg(long, value_name = "LEVEL", value_parser = BoolishValueParser::new(), hide_possible_values = true)]
+    pub dtr: Option<bool>,
+
+    /// Level of the RTS line once the port is open: on or off. Can't be
+    /// combined with hardware flow control, which drives it.
+    #[arg(long, value_name = "LEVEL", value_parser = BoolishValueParser::new(), hide_possible_values = true)]
+    pub rts: Option<bool>,
+}
+
+impl SerialArgs {
+    pub fn config(&self) -> anyhow::Result<SerialConfig> {
+        // Releasing DTR while the flow control holds RTS would keep the
+        // board in reset instead.
+        if self.no_reset && self.flow_control == FlowControl::Hardware {
+            return Err(anyhow!(
+                "--no-reset can't be combined with hardware flow control, which drives RTS"
+            ));
+        }
+        Ok(SerialConfig {
+            data_bits: self.data_bits,
+            parity: self.parity,
+            stop_bits: self.stop_bits,
+            flow_control: self.flow_control,
+            dtr: if self.no_reset { Some(false) } else { self.dtr },
+            rts: if self.no_reset { Some(false) } else { self.rts },
+        })
+    }
+}
+
+#[derive(Args)]
+pub struct InputArgs {
+    // Requirements are checked when opening the source, as both the
+    // port and the baud rate may come from the config files.
+    /// Serial port to read from: its path, usb:<vid>:<pid> or
+    /// serial:<number> for the USB device with that ID or serial number
+    /// (as shown by list-ports).
+    #[arg(short, long, conflicts_with = "source")]
+    pub port: Option<String>,
+
+    /// Use the only ESP32 board connected, as found by list-ports.
+    #[arg(long, conflicts_with_all = ["port", "source"])]
+    pub auto: bool,
+
+    /// Where to read samples from: serial:<port>, midi:<device>,
+    /// file:<path>, pcap:<file> or stdin (also given as -).
+    #[arg(long)]
+    pub source: Option<SourceSpec>,
+
+    #[arg(short, long)]
+    pub baud_rate: Option<u32>,
+
+    #[command(flatten)]
+    pub serial: SerialArgs,
+
+    /// Log the raw traffic of the link to a pcapng file, for debugging
+    /// link-level problems with Wireshark.
+    #[arg(long, value_name = "FILE")]
+    pub debug_pcap: Option<PathBuf>,
+
+    /// Read recorded sources (file:, pcap: and stdin) as fast as possible,
+    /// instead of at the pace of a live capture.
+    #[arg(long)]
+    pub as_fast_as_possible: bool,
+
+    /// Reopen the serial port when the board is unplugged or resets,
+    /// instead of failing.
+    #[arg(long)]
+    pub reconnect: bool,
+
+    /// Fill the time the board was gone with silence, so the capture
+    /// keeps its timing.
+    #[arg(long, requires = "reconnect")]
+    pub fill_gaps: bool,
+
+    /// The board sends the samples in frames with a sequence number
+    /// and a CRC (FRAMED_PROTOCOL in the firmware), which are checked
+    /// for finding lost data.
+    #[arg(long)]
+    pub framed: bool,
+
+    /// Order in which the firmware shifts the sampled bits into each
+    /// byte: msb or lsb first.
+    #[arg(long, value_name = "ORDER", default_value = "msb")]
+    pub bit_order: BitOrder,
+
+    /// The firmware sends low levels as 1 and high levels as 0.
+    #[arg(long)]
+    pub invert: bool,
+
+    /// Keep the memory use to a minimum, for boards like OpenWrt
+    /// gateways: data is read in smaller chunks, and read ahead and
+    /// held back by --trim-silence in smaller amounts.
+    #[arg(long)]
+    pub low_memory: bool,
+
+    /// Write out what has been captured at least this often, in
+    /// milliseconds, however slow the sampling rate is.
+    #[arg(long, value_name = "MS", default_value_t = 1000)]
+    #[arg(value_parser = clap::value_parser!(u64).range(1..))]
+    pub flush_interval: u64,
+
+    /// Read the input on a thread of its own, buffering up to the
+    /// given milliseconds of data while the output is blocked, so the
+    /// serial port doesn't overflow. 0 reads it in the same thread as
+    /// the rest of the capture.
+    #[arg(long, value_name = "MS", default_value_t = 2000)]
+    pub read_ahead: u64,
+
+    /// Print where the time went once the capture ends (waiting for
+    /// data, processing it or blocked on the output), along with the
+    /// CPU time, peak memory and bytes written.
+    #[arg(long)]
+    pub stats: bool,
+
+    /// Show the duty cycle, edge rate, estimated frequency and
+    /// throughput of the signal instead of the progress line. Only for
+    /// single channel captures.
+    #[arg(long)]
+    pub meter: bool,
+
+    /// Keep a preview of the last minute of the capture next to its
+    /// status socket, for attach --preview and other tools.
+    #[arg(long)]
+    pub preview: bool,
+
+    /// Write a JSON line with the health of the capture every second
+    /// (samples read, bytes dropped, read-ahead buffer fill, duty
+    /// cycle...) to the given file, or to the given file descriptor if
+    /// a number, like 3.
+    #[arg(long, value_name = "PATH|FD")]
+    pub stats_json: Option<StatsTarget>,
+}
+
+impl InputArgs {
+    pub fn open(
+        &self,
+        sampling_rate: u32,
+        timeout: Duration,
+    ) -> anyhow::Result<Box<dyn Read + Send>> {
+        let source = match &self.source {
+            Some(source) => source.clone(),
+            None if self.auto => SourceSpec::Serial(usb::find_esp32_port()?),
+            None => match self.port.as_deref() {
+                Some("-") => SourceSpec::Stdin,
+                Some(port) => SourceSpec::Serial(usb::resolve_port(port)?),
+                None => return Err(anyhow!("One of --port, --source or --auto is required")),
+            },
+        };
+        state::set(CaptureState::Connecting);
+        let input = source.open(&OpenOptions {
+            baud_rate: self.baud_rate,
+            serial: self.serial.config()?,
+            timeout,
+            debug_pcap: self.debug_pcap.as_deref(),
+            realtime_rate: (!self.as_fast_as_possible).then_some(sampling_rate),
+            reconnect: self.reconnect,
+            gap_fill_rate: self.fill_gaps.then_some(sampling_rate),
+            framed: self.framed,
+        })?;
+        state::set(CaptureState::Prebuffering);
+        let input: Box<dyn Read + Send> = if self.bit_order == BitOrder::MsbFirst && !self.invert {
+            input
+        } else {
+            Box::new(NormalizedInput::new(input, self.bit_order, self.invert))
+        };
+        if self.read_ahead() == 0 {
+            return Ok(input);
+        }
+        // Small reads, as recorded sources are paced on each one.
+        let read_len = rate::bytes_in(sampling_rate, Duration::from_millis(10)).min(4096);
+        let capacity = rate::bytes_in(sampling_rate, Duration::from_millis(self.read_ahead()));
+        Ok(Box::new(ReadAhead::spawn(input, read_len, capacity)?))
+    }
+
+    /// Milliseconds of the input read ahead, capped in low memory mode.
+    pub fn read_ahead(&self) -> u64 {
+        if self.low_memory {
+            self.read_ahead.min(LOW_MEMORY_READ_AHEAD_MS)
+        } else {
+            self.read_ahead
+        }
+    }
+
+    /// Bytes to read at once: the given default, or 10 msecs of data
+    /// in low memory mode. Never more than what arrives within the
+    /// flush interval, so slow signals don't sit in the buffer.
+    pub fn chunk_len(&self, sampling_rate: u32, default: usize) -> usize {
+        let len = if self.low_memory {
+            usize::max(rate::bytes_per(sampling_rate, 100), 32)
+        } else {
+            default
+        };
+        len.min(rate::bytes_in(sampling_rate, self.flush_interval()))
+    }
+
+    pub fn flush_interval(&self) -> Duration {
+        Duration::from_millis(self.flush_interval)
+    }
+
+    pub fn status_line(&self, output_rate: u32) -> anyhow::Result<StatusLine> {
+        self.channels_status_line(output_rate, 1)
+    }
+
+    /// Status line of a capture of the given interleaved channels. The
+    /// meter only makes sense for a single one.
+    pub fn channels_status_line(
+        &self,
+        output_rate: u32,
+        channels: u16,
+    ) -> anyhow::Result<StatusLine> {
+        let mut status = StatusLine::create();
+        if self.meter && channels == 1 {
+            status.show_meter(output_rate);
+        }
+        self.write_stats(&mut status, output_rate)?;
+        if self.preview {
+            status
+                .write_preview(output_rate, channels)
+                .context("Unable to create the preview of --preview")?;
+        }
+        Ok(status)
+    }
+
+    fn write_stats(&self, status: &mut StatusLine, output_rate: u32) -> anyhow::Result<()> {
+        if let Some(target) = &self.stats_json {
+            status
+                .write_stats(target, output_rate)
+                .context("Unable to open the output of --stats-json")?;
+        }
+        Ok(())
+    }
+
+    /// Prints the times of the pipeline and the resources used by the
+    /// capture, if requested with --stats.
+    pub fn report_stats(&self, times: &StageTimes) {
+        if self.stats {
+            for line in times.to_string().lines() {
+                tracing::info!("{}", line);
+            }
+            for (name, waits) in METRICS.queues() {
+                if let Some(counts) = waits.snapshot() {
+                    tracing::info!("{}: {}", name, counts);
+                }
+            }
+            match getrusage(UsageWho::RUSAGE_SELF) {
+                Ok(usage) => {
+                    let seconds =
+                        |time: TimeVal| time.tv_sec() as f64 + time.tv_usec() as f64 / 1e6;
+                    tracing::info!(
+                        "CPU: {:.3} s user, {:.3} s system",
+                        seconds(usage.user_time()),
+                        seconds(usage.system_time())
+                    );
+                    // In KiB on Linux.
+                    tracing::info!("Peak RSS: {:.1} MiB", usage.max_rss() as f64 / 1024.0);
+                }
+                Err(e) => tracing::warn!("Unable to get the resource usage: {}", e),
+            }
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct ChannelArgs {
+    /// Number of signals interleaved by the firmware in the received
+    /// bits, written as the channels of the output. The sampling rate
+    /// is the one of each channel.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..=32))]
+    pub channels: u16,
+
+    /// How the channels are interleaved: bit (one bit of each channel
+    /// in turn), group (each byte split in a group of bits per
+    /// channel, like 2 channels of 4 bits) or byte (one byte of each
+    /// channel in turn).
+    #[arg(long, value_name = "LAYOUT", default_value = "bit")]
+    pub interleave: Interleave,
+}
+
+impl ChannelArgs {
+    /// Bits per second received for the given sampling rate of each
+    /// channel.
+    pub fn link_rate(&self, sampling_rate: u32) -> u32 {
+        sampling_rate * self.channels as u32
+    }
+
+    /// Rounds the given chunk length up to whole frames of the
+    /// channels.
+    pub fn chunk_len(&self, len: usize) -> usize {
+        len.div_ceil(self.channels as usize) * self.channels as usize
+    }
+
+    /// Status line of the capture, with all of its channels.
+    pub fn status_line(&self, input: &InputArgs, output_rate: u32) -> anyhow::Result<StatusLine> {
+        input.channels_status_line(output_rate, self.channels)
+    }
+
+    /// Processor of the received bytes, with its own chain for each
+    /// channel if there are several.
+    pub fn processor<S: Sample + 'static>(
+        &self,
+        mut chain: impl FnMut() -> anyhow::Result<Chain<S>>,
+    ) -> anyhow::Result<Box<dyn Processor<S>>> {
+        if self.channels == 1 {
+            return Ok(Box::new(chain()?));
+        }
+        let chains = (0..self.channels)
+            .map(|_| chain())
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Box::new(MultiChain::new(self.interleave, chains)?))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AlignMode {
+    /// Append silence up to the next multiple.
+    Pad,
+    /// Drop the samples over the previous multiple.
+    Trim,
+}
+
+#[derive(Args)]
+pub struct AlignArgs {
+    /// Make the length of the written files a multiple of the given
+    /// number of samples, for concatenating them or feeding them to
+    /// frame-based encoders.
+    #[arg(long, value_name = "SAMPLES", value_parser = clap::value_parser!(u32).range(1..))]
+    pub align_output: Option<u32>,
+
+    /// How the files are brought to a multiple of --align-output.
+    #[arg(long, default_value = "pad", requires = "align_output")]
+    pub align_mode: AlignMode,
+}
+
+#[derive(Args)]
+pub struct LimitArgs {
+    /// Stop the capture after the given time of signal, like 30s, 10m
+    /// or 2h, finishing the output as with Ctrl+C.
+    #[arg(long, value_name = "DURATION")]
+    pub duration: Option<TimeSpan>,
+
+    /// Stop the capture after the given number of samples of each
+    /// channel.
+    #[arg(long, value_name = "SAMPLES", value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_samples: Option<u64>,
+}
+
+impl LimitArgs {
+    /// Samples of each channel to capture at the given rate, the lower
+    /// of both limits if both are given.
+    pub fn samples(&self, sampling_rate: u32) -> Option<u64> {
+        let by_duration = self
+            .duration
+            .map(|TimeSpan(duration)| rate::samples_in(sampling_rate, duration));
+        match (by_duration, self.max_samples) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct InProgressArgs {
+    /// Write the output files with the given suffix appended to their
+    /// name, like .part, and rename them once complete, so tools
+    /// watching the directory never pick up a half-written file.
+    #[arg(long, value_name = "SUFFIX")]
+    pub inprogress_suffix: Option<String>,
+}
+
+impl InProgressArgs {
+    /// Where the output file of the given path is written until it's
+    /// complete.
+    pub fn path(&self, path: &Path) -> PathBuf {
+        wav::in_progress_path(path, self.inprogress_suffix.as_deref())
+    }
+}
+
+#[derive(Args)]
+pub struct TagArgs {
+    /// Metadata stored in the written files, like an experiment ID or
+    /// the name of the operator. Can be given several times.
+    #[arg(long = "tag", value_name = "KEY=VALUE")]
+    pub tags: Vec<Tag>,
+}
+
+impl AlignArgs {
+    pub fn alignment(&self) -> Option<Alignment> {
+        self.align_output.map(|block| match self.align_mode {
+            AlignMode::Pad => Alignment::Pad(block as usize),
+            AlignMode::Trim => Alignment::Trim(block as usize),
+        })
+    }
+}
+
+#[derive(Args, Clone)]
+pub struct FadeArgs {
+    /// Milliseconds of signal to drop at the start of the capture.
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    pub discard_first: u32,
+
+    /// Milliseconds of fade-in applied after the discarded signal.
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    pub fade_in: u32,
+
+    /// Milliseconds of fade-out applied when the capture is stopped.
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    pub fade_out: u32,
+
+    /// Milliseconds of silence appended when the capture is stopped.
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    pub tail_padding: u32,
+}
+
+impl FadeArgs {
+    pub fn warmup(&self, sampling_rate: u32) -> Warmup {
+        Warmup::new(
+            dsp::millis_to_samples(sampling_rate, self.discard_first),
+            dsp::millis_to_samples(sampling_rate, self.fade_in),
+        )
+    }
+
+    pub fn tail<S: Sample>(&self, sampling_rate: u32) -> Tail<S> {
+        Tail::new(
+            dsp::millis_to_samples(sampling_rate, self.fade_out),
+            dsp::millis_to_samples(sampling_rate, self.tail_padding),
+        )
+    }
+}
+
+#[cfg(feature = "dsp")]
+#[derive(Args)]
+pub struct BeepArgs {
+    /// Mix a beep into the output each time Enter is pressed or SIGUSR1
+    /// is received, for marking points of interest.
+    #[arg(long)]
+    pub beep_markers: bool,
+
+    #[arg(long, value_name = "HZ", default_value_t = 1000)]
+    pub beep_frequency: u32,
+
+    #[arg(long, value_name = "MS", default_value_t = 150)]
+    pub beep_duration: u32,
+}
+
+#[cfg(feature = "dsp")]
+impl BeepArgs {
+    pub fn beeper(&self, sampling_rate: u32) -> anyhow::Result<Option<Beeper>> {
+        if !self.beep_markers {
+            return Ok(None);
+        }
+
+        trigger::listen()?;
+        Ok(Some(Beeper::new(
+            sampling_rate,
+            self.beep_frequency,
+            dsp::millis_to_samples(sampling_rate, self.beep_duration),
+        )))
+    }
+}
+
+#[derive(Args)]
+pub struct ProcessingArgs {
+    /// How samples are decoded from the received bits: bits,
+    /// envelope:<window> for the envelope of the signal over windows of
+    /// that many bits, or pdm:<factor> (same as --decimate).
+    #[arg(long, value_name = "MODE", default_value = "bits")]
+    pub decode: DecodeMode,
+
+    /// Demodulate the bit stream as PDM, producing one PCM sample out
+    /// of every FACTOR bits through a CIC decimation filter.
+    #[cfg(feature = "dsp")]
+    #[arg(long, value_name = "FACTOR", conflicts_with = "decode", value_parser = clap::value_parser!(u32).range(1..=1 << 15))]
+    pub decimate: Option<u32>,
+
+    #[command(flatten)]
+    pub fade: FadeArgs,
+
+    /// Effects in sox syntax, like "highpass 300 gain 6 rate 48k" or
+    /// "trim 0.5 fade 0.1 0 0.2 pad 0 1". Supported effects are trim,
+    /// fade, pad, highpass, lowpass, bandpass, gain and rate. They run
+    /// in order after the other options, and trim, fade and pad take
+    /// precedence over the equivalent options.
+    #[arg(long, value_name = "EFFECTS")]
+    pub effects: Option<Effects>,
+
+    #[cfg(feature = "dsp")]
+    #[command(flatten)]
+    pub beep: BeepArgs,
+
+    /// Remove the DC offset of the decoded signal, like the one of the
+    /// unsigned encodings, through a high-pass filter with the given
+    /// cutoff (20 Hz if not given).
+    #[cfg(feature = "dsp")]
+    #[arg(long, value_name = "HZ", num_args = 0..=1, default_missing_value = "20")]
+    pub dc_block: Option<f32>,
+
+    /// Smooth the decoded signal with a low-pass filter at the given
+    /// cutoff, like 8000 for taking the edges off a square wave.
+    #[cfg(feature = "dsp")]
+    #[arg(long, value_name = "HZ")]
+    pub lowpass: Option<f32>,
+
+    /// Keep only the frequencies of the decoded signal between the
+    /// given ones, like 300:3400 for the band of a phone line.
+    #[cfg(feature = "dsp")]
+    #[arg(long, value_name = "LOW:HIGH")]
+    pub bandpass: Option<Band>,
+
+    /// Value of the output samples at the high level of the signal, in
+    /// the units of the output format: like 0 to 255 for unsigned 8-bit
+    /// samples, or -1 to 1 for float ones. Takes precedence over
+    /// --wave-amplitude.
+    #[arg(
+        long,
+        value_name = "VALUE",
+        requires = "low",
+        allow_negative_numbers = true
+    )]
+    pub high: Option<f64>,
+
+    /// Value of the output samples at the low level of the signal. See
+    /// --high.
+    #[arg(
+        long,
+        value_name = "VALUE",
+        requires = "high",
+        allow_negative_numbers = true
+    )]
+    pub low: Option<f64>,
+
+    /// Resample the output to the nearest of 44.1, 48 and 96 kHz, for
+    /// players and editors that don't take unusual sample rates.
+    #[arg(long)]
+    pub standard_rate: bool,
+
+    /// Resample the output to the given rate, like 48000 for sinks
+    /// that reject the rate of the bits.
+    #[arg(long, value_name = "HZ", conflicts_with = "standard_rate", value_parser = clap::value_parser!(u32).range(1..))]
+    pub output_rate: Option<u32>,
+}
+
+impl ProcessingArgs {
+    /// Rate of the samples written to the outputs.
+    pub fn output_rate(&self, sampling_rate: u32) -> u32 {
+        self.effects()
+            .filter_map(|effect| match effect {
+                Effect::Rate(rate) => Some(*rate),
+                _ => None,
+            })
+            .last()
+            .unwrap_or_else(|| self.resampled_rate(sampling_rate))
+    }
+
+    /// Rate of the samples after --standard-rate or --output-rate,
+    /// before the effects.
+    fn resampled_rate(&self, sampling_rate: u32) -> u32 {
+        let decoded_rate = self.decoded_rate(sampling_rate);
+        if let Some(rate) = self.output_rate {
+            rate
+        } else if self.standard_rate {
+            resample::nearest_standard_rate(decoded_rate)
+        } else {
+            decoded_rate
+        }
+    }
+
+    /// Latency added by decoding and resampling the signal received at
+    /// the given rate, by part.
+    pub fn latency(&self, sampling_rate: u32) -> Vec<(String, Duration)> {
+        let mode = self.decode_mode();
+        let mut parts = vec![(format!("Decoding ({})", mode), mode.delay(sampling_rate))];
+        let rates = self.effects().filter_map(|effect| match effect {
+            Effect::Rate(rate) => Some(*rate),
+            _ => None,
+        });
+        let mut rate = self.decoded_rate(sampling_rate);
+        for to in iter::once(self.resampled_rate(sampling_rate)).chain(rates) {
+            if to != rate {
+                // The filter waits for the input it looks ahead over.
+                let lookahead = Resampler::lookahead(rate, to);
+                parts.push((
+                    format!("Resampling to {} Hz", to),
+                    Duration::from_secs_f64(lookahead as f64 / rate as f64),
+                ));
+                rate = to;
+            }
+        }
+        parts
+    }
+
+    /// Rate of the samples coming out of the decoder.
+    fn decoded_rate(&self, sampling_rate: u32) -> u32 {
+        self.decode_mode().output_rate(sampling_rate)
+    }
+
+    fn decode_mode(&self) -> DecodeMode {
+        #[cfg(feature = "dsp")]
+        if let Some(factor) = self.decimate {
+            return DecodeMode::Pdm(factor);
+        }
+        self.decode
+    }
+
+    fn effects(&self) -> impl Iterator<Item = &Effect> {
+        self.effects.iter().flat_map(|effects| &effects.0)
+    }
+
+    /// Fade settings after applying the effects on top of the options.
+    fn fade(&self) -> FadeArgs {
+        let mut fade = self.fade.clone();
+        for effect in self.effects() {
+            match *effect {
+                Effect::Trim(start) => fade.discard_first = start,
+                Effect::Fade { fade_in, fade_out } => {
+                    fade.fade_in = fade_in;
+                    fade.fade_out = fade_out;
+                }
+                Effect::Pad(end) => fade.tail_padding = end,
+                _ => (),
+            }
+        }
+        fade
+    }
+
+    pub fn chain<S: Sample>(&self, sampling_rate: u32) -> anyhow::Result<Chain<S>> {
+        let output_rate = self.output_rate(sampling_rate);
+        let fade = self.fade();
+        let mut chain = Chain::new(
+            self.decode_mode().decoder(),
+            fade.warmup(output_rate),
+            fade.tail(output_rate),
+        );
+        if let (Some(low), Some(high)) = (self.low, self.high) {
+            let level = |value: f64, name: &str| {
+                S::from_level(value).ok_or_else(|| {
+                    anyhow!(
+                        "--{} {} doesn't fit in the samples of the output",
+                        name,
+                        value
+                    )
+                })
+            };
+            chain.levels = Some((level(low, "low")?, level(high, "high")?));
+        }
+        let decoded_rate = self.decoded_rate(sampling_rate);
+        let resampled_rate = self.resampled_rate(sampling_rate);
+        if decoded_rate != resampled_rate {
+            chain.resampler = Some(Resampler::new(decoded_rate, resampled_rate));
+        }
+        #[cfg(feature = "dsp")]
+        {
+            chain.beeper = self.beep.beeper(output_rate)?;
+            let rate = resampled_rate;
+            if let Some(cutoff) = self.dc_block {
+                chain
+                    .filters
+                    .push(Filter::DcBlock(DcBlocker::new(cutoff, rate)));
+            }
+            if let Some(band) = self.bandpass {
+                chain.filters.extend([
+                    Filter::Biquad(Biquad::highpass(
+                        cutoff(band.low, rate, "--bandpass")?,
+                        rate,
+                    )),
+                    Filter::Biquad(Biquad::lowpass(
+                        cutoff(band.high, rate, "--bandpass")?,
+                        rate,
+                    )),
+                ]);
+            }
+            if let Some(frequency) = self.lowpass {
+                chain.filters.push(Filter::Biquad(Biquad::lowpass(
+                    cutoff(frequency, rate, "--lowpass")?,
+                    rate,
+                )));
+            }
+        }
+
+        // Set the edges of the recording instead, see fade().
+        let effects: Vec<Effect> = self
+            .effects()
+            .filter(|effect| {
+                !matches!(
+                    effect,
+                    Effect::Trim(_) | Effect::Fade { .. } | Effect::Pad(_)
+                )
+            })
+            .copied()
+            .collect();
+        chain.effects = effect_stages(&effects, resampled_rate)?.0;
+        Ok(chain)
+    }
+}
+
+/// Checks a cutoff given with the option or effect of the given name
+/// is below half the rate of the samples it filters.
+#[cfg(feature = "dsp")]
+fn cutoff(value: f32, rate: u32, name: &str) -> anyhow::Result<f32> {
+    let nyquist = rate as f32 / 2.0;
+    if value > 0.0 && value < nyquist {
+        Ok(value)
+    } else {
+        Err(anyhow!(
+            "{} {} must be between 0 and half the rate of the samples ({} Hz)",
+            name,
+            value,
+            nyquist
+        ))
+    }
+}
+
+/// Stages of the given effects, for samples at the given rate, along
+/// with the rate of the samples coming out of them. The effects setting
+/// the edges of the recording (trim, fade and pad) aren't stages, so
+/// they can't be given.
+pub fn effect_stages<S: Sample>(
+    effects: &[Effect],
+    sampling_rate: u32,
+) -> anyhow::Result<(Vec<Stage<S>>, u32)> {
+    // Each stage runs at the rate left by the ones before it.
+    let mut rate = sampling_rate;
+    let mut all_stages = Vec::new();
+    for effect in effects {
+        let stages = match *effect {
+            Effect::Trim(_) | Effect::Fade { .. } | Effect::Pad(_) => {
+                return Err(anyhow!("trim, fade and pad can only be given to --effects"))
+            }
+            Effect::Gain(gain) => vec![Stage::Gain(10f32.powf(gain / 20.0))],
+            Effect::Rate(to) if to == rate => continue,
+            Effect::Rate(to) => {
+                let stage = Stage::Resample(Resampler::new(rate, to), Vec::new());
+                rate = to;
+                vec![stage]
+            }
+            #[cfg(feature = "dsp")]
+            Effect::Highpass(frequency) => vec![Stage::Filter(Filter::Biquad(Biquad::highpass(
+                cutoff(frequency, rate, "highpass")?,
+                rate,
+            )))],
+            #[cfg(feature = "dsp")]
+            Effect::Lowpass(frequency) => vec![Stage::Filter(Filter::Biquad(Biquad::lowpass(
+                cutoff(frequency, rate, "lowpass")?,
+                rate,
+            )))],
+            #[cfg(feature = "dsp")]
+            Effect::Bandpass { low, high } => vec![
+                Stage::Filter(Filter::Biquad(Biquad::highpass(
+                    cutoff(low, rate, "bandpass")?,
+                    rate,
+                ))),
+                Stage::Filter(Filter::Biquad(Biquad::lowpass(
+                    cutoff(high, rate, "bandpass")?,
+                    rate,
+                ))),
+            ],
+            #[cfg(not(feature = "dsp"))]
+            Effect::Highpass(_) | Effect::Lowpass(_) | Effect::Bandpass { .. } => {
+                return Err(anyhow!(
+                    "The filters of --effects need a build with the dsp feature"
+                ))
+            }
+        };
+        all_stages.extend(stages);
+    }
+    Ok((all_stages, rate))
+}
@@ -0,0 +1,53 @@
+use std::{iter, net::SocketAddrV4, process::ExitCode};
+
+use clap::Parser;
+
+use super::{
+    stream::{self, MonitorArgs, OutputArgs, OutputSpec, StreamInput},
+    ChannelArgs, InputArgs, ProcessingArgs, WaveAmplitude,
+};
+
+#[derive(Parser)]
+pub struct MulticastStreamArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[arg(short, long)]
+    pub sampling_rate: u32,
+
+    #[arg(short, long, default_value_t = WaveAmplitude::Full)]
+    pub wave_amplitude: WaveAmplitude,
+
+    /// Multicast group and port the samples are sent to, like 239.255.0.1:5004.
+    #[arg(short, long)]
+    pub group: SocketAddrV4,
+
+    #[command(flatten)]
+    pub monitor: MonitorArgs,
+
+    // Outputs the samples also go to, besides the group.
+    #[command(flatten)]
+    pub outputs: OutputArgs,
+
+    #[command(flatten)]
+    pub channels: ChannelArgs,
+
+    #[command(flatten)]
+    pub processing: ProcessingArgs,
+}
+
+/// Same as stream with the group as the first of its outputs.
+pub fn run_multicast_stream_command(args: &MulticastStreamArgs) -> anyhow::Result<ExitCode> {
+    let stream = StreamInput {
+        input: &args.input,
+        sampling_rate: args.sampling_rate,
+        wave_amplitude: args.wave_amplitude.clone(),
+        channels: &args.channels,
+        processing: &args.processing,
+        monitor: &args.monitor,
+    };
+    let outputs: Vec<_> = iter::once(OutputSpec::Multicast(args.group).into())
+        .chain(args.outputs.outputs.iter().cloned())
+        .collect();
+    stream::run_stream(&stream, &outputs, &args.outputs)
+}
@@ -0,0 +1,281 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    process::ExitCode,
+    sync::atomic::Ordering,
+    thread,
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use clap::Parser;
+use nix::{
+    libc::SIGINT,
+    sys::signal::{pthread_sigmask, SigSet, SigmaskHow, Signal},
+};
+use pipewire::{
+    self as pw, properties,
+    spa::{
+        self,
+        param::audio::{AudioFormat, AudioInfoRaw},
+        pod::{serialize::PodSerializer, Object, Pod, Value},
+    },
+    stream::{Stream, StreamFlags},
+};
+use rtrb::{Consumer, Producer, RingBuffer};
+
+use super::{stream::MonitorArgs, InputArgs, ProcessingArgs, WaveAmplitude};
+use crate::{
+    cancel::{self, CancellationScope},
+    dsp::Chain,
+    metrics::METRICS,
+    pipeline::{
+        self, DecodeUnsignedFullRange, DecodeUnsignedHalfRange, SampleDecoder, SinkWriter,
+        StageTimes,
+    },
+    rate,
+    status::StatusLine,
+};
+
+const NODE_NAME: &str = "esp32-signal-reader";
+const NODE_DESCRIPTION: &str = "ESP32 Signal Reader";
+
+#[derive(Parser)]
+pub struct PipewireStreamArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[arg(short, long)]
+    pub sampling_rate: u32,
+
+    #[arg(short, long, default_value_t = WaveAmplitude::Full)]
+    pub wave_amplitude: WaveAmplitude,
+
+    #[command(flatten)]
+    pub monitor: MonitorArgs,
+
+    #[command(flatten)]
+    pub processing: ProcessingArgs,
+}
+
+/// Pushes the samples into a ring holding up to a second of audio, to
+/// be taken by the PipeWire graph. The reading thread is the only
+/// producer and the PipeWire thread the only consumer, so neither of
+/// them ever waits for the other: the samples that don't fit are
+/// dropped if the graph falls behind.
+struct QueueSink {
+    producer: Producer<u8>,
+    rate: u32,
+    pushed: u64,
+}
+
+impl SinkWriter<u8> for QueueSink {
+    fn write_samples(&mut self, samples: &[u8]) -> anyhow::Result<()> {
+        let len = match self.producer.write(samples) {
+            Ok(len) => len,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => 0,
+            Err(e) => return Err(e.into()),
+        };
+        self.pushed += len as u64;
+        if len < samples.len() {
+            METRICS
+                .pipewire_waits
+                .count_full(samples_duration(samples.len() - len, self.rate));
+        }
+        Ok(())
+    }
+
+    /// Samples dropped because the queue was full aren't counted.
+    fn bytes_written(&self) -> Option<u64> {
+        Some(self.pushed)
+    }
+}
+
+fn samples_duration(samples: usize, rate: u32) -> Duration {
+    Duration::from_secs_f64(samples as f64 / rate.max(1) as f64)
+}
+
+/// Runs a PipeWire source node fed from the queue, until a message is
+/// received through quit.
+fn run_source_node(
+    queue: Consumer<u8>,
+    rate: u32,
+    quit: pw::channel::Receiver<()>,
+) -> anyhow::Result<()> {
+    pw::init();
+    let mainloop = pw::MainLoop::new()?;
+    let context = pw::Context::new(&mainloop)?;
+    let core = context.connect(None)?;
+
+    let stream = Stream::new(
+        &core,
+        NODE_DESCRIPTION,
+        properties! {
+            *pw::keys::MEDIA_TYPE => "Audio",
+            *pw::keys::MEDIA_CLASS => "Audio/Source",
+            *pw::keys::NODE_NAME => NODE_NAME,
+            *pw::keys::NODE_DESCRIPTION => NODE_DESCRIPTION,
+        },
+    )?;
+
+    // Silence sent when there are no samples ready, so apps recording
+    // from the node don't stall: 10 msecs on each cycle.
+    let silence_len = usize::max(rate as usize / 100, 1);
+    let _listener = stream
+        .add_local_listener_with_user_data(queue)
+        .process(move |stream, queue| {
+            let mut buffer = match stream.dequeue_buffer() {
+                Some(buffer) => buffer,
+                None => return,
+            };
+            let data = &mut buffer.datas_mut()[0];
+            let len = match data.data() {
+                Some(slice) => match queue.read(slice) {
+                    Ok(len) => len,
+                    Err(_) => {
+                        METRICS.underruns.fetch_add(1, Ordering::Relaxed);
+                        let len = usize::min(silence_len, slice.len());
+                        METRICS
+                            .pipewire_waits
+                            .count_empty(samples_duration(len, rate));
+                        slice[..len].fill(128);
+                        len
+                    }
+                },
+                None => 0,
+            };
+
+            let chunk = data.chunk_mut();
+            *chunk.offset_mut() = 0;
+            *chunk.stride_mut() = 1;
+            *chunk.size_mut() = len as u32;
+        })
+        .register()?;
+
+    let mut audio_info = AudioInfoRaw::new();
+    audio_info.set_format(AudioFormat::U8);
+    audio_info.set_rate(rate);
+    audio_info.set_channels(1);
+    let format = PodSerializer::serialize(
+        std::io::Cursor::new(Vec::new()),
+        &Value::Object(Object {
+            type_: spa::sys::SPA_TYPE_OBJECT_Format,
+            id: spa::sys::SPA_PARAM_EnumFormat,
+            properties: audio_info.into(),
+        }),
+    )
+    .map_err(|e| anyhow!("Can't serialize the stream format: {:?}", e))?
+    .0
+    .into_inner();
+    let mut params = [Pod::from_bytes(&format).expect("serialized format is a valid pod")];
+
+    stream.connect(
+        spa::Direction::Output,
+        None,
+        StreamFlags::AUTOCONNECT | StreamFlags::MAP_BUFFERS,
+        &mut params,
+    )?;
+
+    let _quit = quit.attach(&mainloop, {
+        let mainloop = mainloop.clone();
+        move |_| mainloop.quit()
+    });
+    mainloop.run();
+    Ok(())
+}
+
+fn stream_samples_to_queue<R: Read + Send, D: SampleDecoder<Sample = u8>>(
+    input: &mut R,
+    input_args: &InputArgs,
+    sampling_rate: u32,
+    scope: &CancellationScope,
+    chain: &mut Chain<u8>,
+    status: &mut StatusLine,
+    sink: &mut dyn SinkWriter<u8>,
+) -> anyhow::Result<StageTimes> {
+    // Same as with pulse, approx 50 msecs of data on each iteration,
+    // with a minimum of 32 bytes.
+    let buf_size = input_args.chunk_len(
+        sampling_rate,
+        usize::max(rate::bytes_per(sampling_rate, 20), 32),
+    );
+
+    pipeline::stream::<D, _, _, _>(
+        input,
+        chain,
+        sink,
+        buf_size,
+        input_args.flush_interval(),
+        |samples, total_samples| {
+            status.preview(samples);
+            status.update(total_samples, sampling_rate);
+            !scope.is_cancelled()
+        },
+    )
+}
+
+pub fn run_pipewire_stream_command(args: &PipewireStreamArgs) -> anyhow::Result<ExitCode> {
+    let output_rate = args.processing.output_rate(args.sampling_rate);
+    let (producer, consumer) = RingBuffer::new(output_rate.max(1) as usize);
+    METRICS.counts_underruns.store(true, Ordering::Relaxed);
+    METRICS.pipewire_waits.start();
+
+    let result = cancel::with_scope(|scope| -> anyhow::Result<StageTimes> {
+        // The PipeWire objects live in their own thread, which leaves
+        // SIGINT to this one.
+        let (quit_sender, quit_receiver) = pw::channel::channel();
+        let node = thread::spawn(move || {
+            let mut sigset = SigSet::empty();
+            sigset.add(Signal::SIGINT);
+            pthread_sigmask(SigmaskHow::SIG_BLOCK, Some(&sigset), None)?;
+            run_source_node(consumer, output_rate, quit_receiver)
+        });
+        scope.on_exit(move || {
+            // The node thread is gone already if it failed.
+            let _ = quit_sender.send(());
+            node.join().expect("PipeWire thread panicked")
+        });
+
+        let mut serial = args
+            .input
+            .open(args.sampling_rate, Duration::from_secs(1))?;
+        let mut chain = args.processing.chain(args.sampling_rate)?;
+        let mut status = args.input.status_line(output_rate)?;
+        let sink = QueueSink {
+            producer,
+            rate: output_rate,
+            pushed: 0,
+        };
+        let mut sink = args.monitor.monitor(Box::new(sink), output_rate, 1);
+
+        let times = match args.wave_amplitude {
+            WaveAmplitude::Full => stream_samples_to_queue::<_, DecodeUnsignedFullRange>(
+                &mut serial,
+                &args.input,
+                args.sampling_rate,
+                scope,
+                &mut chain,
+                &mut status,
+                &mut *sink,
+            ),
+            WaveAmplitude::Half => stream_samples_to_queue::<_, DecodeUnsignedHalfRange>(
+                &mut serial,
+                &args.input,
+                args.sampling_rate,
+                scope,
+                &mut chain,
+                &mut status,
+                &mut *sink,
+            ),
+        };
+        status.finish(times.is_ok());
+        times
+    })?;
+
+    let times = result.output?;
+    args.input.report_stats(&times);
+    Ok(if result.interrupted {
+        ExitCode::from(128 + SIGINT as u8)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
@@ -0,0 +1,355 @@
+use std::{
+    io::{ErrorKind, Read},
+    process::ExitCode,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+
+use super::InputArgs;
+use crate::dsp::{resample, spectrum::Spectrum};
+
+/// Baud rates commonly supported by USB-to-UART bridges.
+const STANDARD_BAUD_RATES: [u32; 12] = [
+    9600, 19200, 38400, 57600, 115200, 128000, 230400, 250000, 460800, 500000, 921600, 1000000,
+];
+
+/// Minimum amount of samples the shortest pulses should span for being
+/// reliably captured.
+const MIN_SAMPLES_PER_PULSE: u32 = 4;
+
+/// Bits in each block of the spectrum.
+const FFT_SIZE: usize = 4096;
+/// Frequencies under this are taken as the offset of the idle line
+/// rather than as part of the signal.
+const DC_HZ: f32 = 20.0;
+/// The band of the signal is taken to span the frequencies within these
+/// dB of its strongest one, up to the first octave without any.
+const BAND_RANGE_DB: f64 = 20.0;
+/// Output rates recommended, the lowest one keeping the band of the
+/// signal being picked.
+const OUTPUT_RATES: [u32; 8] = [8000, 11025, 16000, 22050, 32000, 44100, 48000, 96000];
+/// Share of the power in the DC offset over which removing it is
+/// recommended.
+const DC_BLOCK_SHARE: f64 = 0.01;
+
+#[derive(Parser)]
+pub struct ProbeArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    /// Sampling rate the ESP32 is currently configured with.
+    #[arg(short, long)]
+    pub sampling_rate: u32,
+
+    /// Seconds of signal to capture for the analysis.
+    #[arg(short, long, default_value_t = 3)]
+    pub duration: u64,
+}
+
+struct SignalStats {
+    total_bits: u64,
+    high_bits: u64,
+    runs: Vec<u32>,
+    last_bit: Option<bool>,
+    run: u32,
+}
+
+impl SignalStats {
+    fn new() -> SignalStats {
+        SignalStats {
+            total_bits: 0,
+            high_bits: 0,
+            runs: Vec::new(),
+            last_bit: None,
+            run: 0,
+        }
+    }
+
+    fn push(&mut self, input: &[u8]) {
+        for byte in input {
+            for bit in (0..8).rev() {
+                let high = (byte >> bit) & 1 != 0;
+                if self.last_bit.is_some() && self.last_bit != Some(high) {
+                    self.runs.push(self.run);
+                    self.run = 0;
+                }
+                self.last_bit = Some(high);
+                self.run += 1;
+                self.total_bits += 1;
+                self.high_bits += high as u64;
+            }
+        }
+    }
+}
+
+/// Power spectrum of the bits, averaged over blocks of `FFT_SIZE`.
+struct PowerSpectrum {
+    spectrum: Spectrum,
+    power: Vec<f64>,
+    blocks: u64,
+    samples: Vec<f32>,
+    sampling_rate: u32,
+}
+
+impl PowerSpectrum {
+    fn new(sampling_rate: u32) -> PowerSpectrum {
+        PowerSpectrum {
+            spectrum: Spectrum::new(FFT_SIZE),
+            power: vec![0.0; FFT_SIZE / 2 + 1],
+            blocks: 0,
+            samples: Vec::new(),
+            sampling_rate,
+        }
+    }
+
+    fn push(&mut self, input: &[u8]) {
+        self.samples.clear();
+        self.samples.extend(input.iter().flat_map(|byte| {
+            (0..8)
+                .rev()
+                .map(move |bit| if (byte >> bit) & 1 != 0 { 1.0 } else { -1.0 })
+        }));
+        self.spectrum.process(&self.samples, |magnitudes| {
+            for (power, magnitude) in self.power.iter_mut().zip(magnitudes) {
+                *power += (*magnitude as f64).powi(2);
+            }
+            self.blocks += 1;
+        });
+    }
+
+    fn frequency(&self, bin: usize) -> f32 {
+        self.spectrum.bin_frequency(bin, self.sampling_rate)
+    }
+
+    /// Mean power of the bins from `low` up to `high`, in dB relative
+    /// to a full scale sine.
+    fn band_db(&self, low: f32, high: f32) -> f64 {
+        let power: f64 = (0..self.power.len())
+            .filter(|bin| (low..high).contains(&self.frequency(*bin)))
+            .map(|bin| self.power[bin])
+            .sum();
+        10.0 * (power / self.blocks as f64).max(1e-12).log10()
+    }
+
+    /// Band around the strongest frequency, leaving out the DC offset,
+    /// over which the signal stays within `BAND_RANGE_DB` of it. Gaps
+    /// of an octave end the band, which keeps the noise a modulator
+    /// shapes away from the signal out of it. None if there's no power
+    /// outside of the DC offset.
+    fn occupied_band(&self) -> Option<(f32, f32)> {
+        let first = (0..self.power.len()).find(|bin| self.frequency(*bin) >= DC_HZ)?;
+        let peak =
+            (first..self.power.len()).max_by(|a, b| self.power[*a].total_cmp(&self.power[*b]))?;
+        if self.power[peak] <= 0.0 {
+            return None;
+        }
+        let threshold = self.power[peak] / 10f64.powf(BAND_RANGE_DB / 10.0);
+        let (mut low, mut high) = (peak, peak);
+        for bin in peak..self.power.len() {
+            if self.frequency(bin) > 2.0 * self.frequency(high) {
+                break;
+            }
+            if self.power[bin] >= threshold {
+                high = bin;
+            }
+        }
+        for bin in (first..peak).rev() {
+            if self.frequency(bin) < self.frequency(low) / 2.0 {
+                break;
+            }
+            if self.power[bin] >= threshold {
+                low = bin;
+            }
+        }
+        Some((self.frequency(low), self.frequency(high)))
+    }
+
+    /// Share of the power of the bits in their DC offset.
+    fn dc_share(&self) -> f64 {
+        let total: f64 = self.power.iter().sum();
+        let dc: f64 = (0..self.power.len())
+            .filter(|bin| self.frequency(*bin) < DC_HZ)
+            .map(|bin| self.power[bin])
+            .sum();
+        if total > 0.0 {
+            dc / total
+        } else {
+            0.0
+        }
+    }
+
+    fn print(&self) {
+        println!("Spectrum:");
+        println!(
+            "  Under {} Hz (DC offset): {:.1} dB",
+            DC_HZ,
+            self.band_db(0.0, DC_HZ)
+        );
+        let nyquist = self.sampling_rate as f32 / 2.0;
+        let mut low = DC_HZ;
+        while low < nyquist {
+            let high = (low * 2.0).min(nyquist);
+            println!(
+                "  {:>8.0} - {:>8.0} Hz: {:>6.1} dB",
+                low,
+                high,
+                self.band_db(low, high)
+            );
+            low = high;
+        }
+        if let Some((low, high)) = self.occupied_band() {
+            println!(
+                "  Occupied band: {:.0} - {:.0} Hz (within {} dB of its strongest frequency)",
+                low, high, BAND_RANGE_DB
+            );
+        }
+    }
+
+    /// Prints the filters and output rate that keep the band of the
+    /// signal. The filters are left out of builds without them.
+    fn print_recommendations(&self) {
+        let has_filters = cfg!(feature = "dsp");
+        if has_filters && self.dc_share() > DC_BLOCK_SHARE {
+            println!("  Remove the DC offset of the line: --dc-block");
+        }
+        let (low, high) = match self.occupied_band() {
+            Some(band) => band,
+            None => return,
+        };
+        let nyquist = self.sampling_rate as f32 / 2.0;
+        // Margins keeping the filters off the edges of the band.
+        let (highpass, lowpass) = ((low / 2.0).floor(), (high * 1.5).ceil());
+        let has_highpass = highpass > DC_HZ;
+        let has_lowpass = lowpass < nyquist * 0.9;
+        match (has_filters && has_highpass, has_filters && has_lowpass) {
+            (true, true) => println!("  Filter: --bandpass {}:{}", highpass, lowpass),
+            (true, false) => println!("  Filter: --effects \"highpass {}\"", highpass),
+            (false, true) => println!("  Filter: --lowpass {}", lowpass),
+            (false, false) => (),
+        }
+        // Rates the resampler keeps the band at.
+        let needed = (lowpass.min(nyquist) as f64 / resample::CUTOFF).ceil() as u32;
+        let output_rate = OUTPUT_RATES
+            .into_iter()
+            .find(|rate| *rate >= needed)
+            .unwrap_or(needed.div_ceil(1000) * 1000);
+        if output_rate < self.sampling_rate {
+            println!(
+                "  Output rate: --output-rate {} (keeps up to {:.0} Hz)",
+                output_rate,
+                output_rate as f64 * resample::CUTOFF
+            );
+        }
+    }
+}
+
+fn recommended_baud_rate(sampling_rate: u32) -> Option<u32> {
+    // 10 bits on the wire per byte (8N1), plus a 20% of margin.
+    let required = (sampling_rate as u64 * 10 * 12).div_ceil(8 * 10);
+    STANDARD_BAUD_RATES
+        .iter()
+        .copied()
+        .find(|baud_rate| *baud_rate as u64 >= required)
+}
+
+pub fn run_probe_command(args: &ProbeArgs) -> anyhow::Result<ExitCode> {
+    let mut serial = args
+        .input
+        .open(args.sampling_rate, Duration::from_millis(200))?;
+    let duration = Duration::from_secs(args.duration);
+    let mut buf = vec![0; 4096];
+    let mut stats = SignalStats::new();
+    let mut spectrum = PowerSpectrum::new(args.sampling_rate);
+    let mut stalls = 0;
+
+    tracing::info!("Capturing {} second(s) of signal...", args.duration);
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        match serial.read(&mut buf) {
+            Ok(len) => {
+                stats.push(&buf[..len]);
+                spectrum.push(&buf[..len]);
+            }
+            Err(e) if e.kind() == ErrorKind::TimedOut => stalls += 1,
+            Err(e) if e.kind() == ErrorKind::Interrupted => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let expected_bytes = args.sampling_rate as f64 / 8.0 * elapsed;
+    let received_bytes = (stats.total_bits / 8) as f64;
+    println!("Link:");
+    println!(
+        "  Received {:.0} bytes/s, expected {:.0} bytes/s ({:.1}%)",
+        received_bytes / elapsed,
+        expected_bytes / elapsed,
+        received_bytes * 100.0 / expected_bytes
+    );
+    println!("  Read stalls (no data for 200 ms): {}", stalls);
+    if received_bytes < expected_bytes * 0.98 {
+        println!("  The link is not keeping up with the sampling rate!");
+    }
+
+    if stats.total_bits == 0 {
+        println!("No data received. Check the port, baud rate and wiring.");
+        return Ok(ExitCode::FAILURE);
+    }
+
+    println!("Signal:");
+    println!(
+        "  Duty cycle: {:.1}%",
+        stats.high_bits as f64 * 100.0 / stats.total_bits as f64
+    );
+    println!(
+        "  Edges: {} ({:.0}/s)",
+        stats.runs.len(),
+        stats.runs.len() as f64 / elapsed
+    );
+
+    if stats.runs.len() < 2 {
+        println!("No signal activity detected; keeping the current settings is fine.");
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    stats.runs.sort_unstable();
+    // Ignore the shortest pulses, which are usually glitches.
+    let short_pulse = stats.runs[stats.runs.len() / 20].max(1);
+    let median_pulse = stats.runs[stats.runs.len() / 2];
+    let sample_us = 1e6 / args.sampling_rate as f64;
+    println!(
+        "  Pulse width: {:.1} us (shortest), {:.1} us (median)",
+        short_pulse as f64 * sample_us,
+        median_pulse as f64 * sample_us
+    );
+    println!(
+        "  Estimated bandwidth: {:.0} Hz",
+        args.sampling_rate as f64 / (2.0 * short_pulse as f64)
+    );
+    if spectrum.blocks > 0 {
+        spectrum.print();
+    }
+
+    let recommended_rate = (args.sampling_rate as u64 * MIN_SAMPLES_PER_PULSE as u64
+        / short_pulse as u64)
+        .div_ceil(1000)
+        .max(1) as u32
+        * 1000;
+    println!("Recommendations:");
+    if short_pulse <= 1 {
+        println!("  The signal looks undersampled. Try again with a higher sampling rate.");
+    }
+    println!("  Sampling rate: {} Hz", recommended_rate);
+    match recommended_baud_rate(recommended_rate) {
+        Some(baud_rate) => println!("  Baud rate: {}", baud_rate),
+        None => {
+            println!("  Baud rate: none of the standard rates is enough for this sampling rate")
+        }
+    }
+    if spectrum.blocks > 0 {
+        spectrum.print_recommendations();
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
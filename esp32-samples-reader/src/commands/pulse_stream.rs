@@ -1,10 +1,11 @@
 use anyhow::{anyhow, Context as _};
-use clap::{Parser, ValueEnum};
+use clap::Parser;
 use lazy_static::lazy_static;
 use libpulse_binding::{
     callbacks::ListResult,
     context::{introspect::Introspector, Context, FlagSet as ContextFlagSet},
     def::{BufferAttr, Retval},
+    error::Code,
     mainloop::{standard::IterateResult, standard::Mainloop},
     sample::{Format, Spec},
     stream::Direction,
@@ -13,65 +14,88 @@ use libpulse_simple_binding::Simple;
 use nix::libc::SIGINT;
 use regex::{Captures, Regex};
 use std::{
-    borrow::Cow,
-    cell::RefCell,
-    fmt::Display,
-    io::Read,
-    panic::{catch_unwind, UnwindSafe},
-    process::ExitCode,
-    rc::Rc,
+    borrow::Cow, cell::RefCell, fmt, io::Read, path::PathBuf, process::ExitCode, rc::Rc, thread,
     time::Duration,
 };
 
+use super::{
+    daemon::Recorder,
+    stream::{MonitorArgs, Output, OutputArgs, OutputSpec, StreamInput},
+    ChannelArgs, InputArgs, ProcessingArgs, WaveAmplitude,
+};
 use crate::{
-    ctrlc::{self, CtrlCIgnoredContext},
-    io,
+    cancel::{self, CancellationScope},
+    dsp::Processor,
+    pipeline::{
+        self, DecodeUnsignedFullRange, DecodeUnsignedHalfRange, Reopen, SampleDecoder, SinkWriter,
+        StageTimes, Tee,
+    },
+    rate,
 };
 
-trait DecodeSampleUnsigned {
-    fn decode_sample(input: u8) -> [u8; 8];
-}
-
-struct DecodeSampleUnsignedFullRange {}
-impl DecodeSampleUnsigned for DecodeSampleUnsignedFullRange {
-    #[inline(always)]
-    fn decode_sample(input: u8) -> [u8; 8] {
-        io::decode_esp32_sample_unsigned_full_range(input)
-    }
-}
-
-struct DecodeSampleUnsignedHalfRange {}
-impl DecodeSampleUnsigned for DecodeSampleUnsignedHalfRange {
-    #[inline(always)]
-    fn decode_sample(input: u8) -> [u8; 8] {
-        io::decode_esp32_sample_unsigned_half_range(input)
-    }
-}
-
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
-pub enum WaveAmplitude {
-    Full,
-    Half,
-}
-
-impl Display for WaveAmplitude {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.to_possible_value().unwrap().get_name())
-    }
-}
-
 #[derive(Parser)]
 pub struct PulseStreamArgs {
-    #[arg(short, long)]
-    pub port: String,
-    #[arg(short, long)]
-    pub sampling_rate: u32,
+    #[command(flatten)]
+    pub input: InputArgs,
 
     #[arg(short, long)]
-    pub baud_rate: u32,
+    pub sampling_rate: u32,
 
     #[arg(short, long, default_value_t = WaveAmplitude::Full)]
     pub wave_amplitude: WaveAmplitude,
+
+    #[command(flatten)]
+    pub monitor: MonitorArgs,
+
+    /// Also expose the stream as a source, so recording applications
+    /// list it as an input device instead of having to record from the
+    /// monitor of the sink.
+    #[arg(long)]
+    pub as_source: bool,
+
+    /// Name of the sink created for the stream, which has to differ
+    /// between captures running at once.
+    #[arg(long, value_name = "NAME", default_value = PULSE_SINK_NAME)]
+    pub sink_name: String,
+
+    /// Name of the source created with --as-source.
+    #[arg(long, value_name = "NAME", default_value = PULSE_SOURCE_NAME)]
+    pub source_name: String,
+
+    /// Times to retry connecting to the Pulse server or loading its
+    /// modules before giving up.
+    #[arg(long, value_name = "N", default_value_t = 3)]
+    pub pulse_retries: u32,
+
+    /// Delay before the first retry, doubled on every further attempt.
+    #[arg(long, value_name = "MS", default_value_t = 500)]
+    pub pulse_retry_delay: u64,
+
+    /// Target latency from the signal being sampled to it being played,
+    /// in milliseconds. The Pulse buffer gets what's left after reading
+    /// and decoding the samples.
+    #[arg(long, value_name = "MS")]
+    pub latency: Option<u64>,
+
+    /// Print where the latency of the playback comes from before
+    /// starting.
+    #[arg(long)]
+    pub explain_latency: bool,
+
+    /// Also record the stream into a WAV file, as it is played. Same as
+    /// --out wav:<FILE>.
+    #[arg(long, value_name = "FILE")]
+    pub record: Option<PathBuf>,
+
+    // Outputs the samples also go to, besides Pulse.
+    #[command(flatten)]
+    pub outputs: OutputArgs,
+
+    #[command(flatten)]
+    pub channels: ChannelArgs,
+
+    #[command(flatten)]
+    pub processing: ProcessingArgs,
 }
 
 lazy_static! {
@@ -141,13 +165,71 @@ impl SinkSpec {
     }
 }
 
+/// Returned when the server refuses to load modules, which is the case
+/// on confined sessions like flatpak or snap.
+#[derive(Debug)]
+struct ModuleLoadForbidden;
+
+impl fmt::Display for ModuleLoadForbidden {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Loading modules is not allowed by the Pulse server")
+    }
+}
+
+impl std::error::Error for ModuleLoadForbidden {}
+
+#[derive(Clone, Copy)]
+struct RetryPolicy {
+    retries: u32,
+    delay: Duration,
+}
+
+impl RetryPolicy {
+    /// Runs f until it succeeds or the retries are exhausted, waiting
+    /// an exponentially increasing delay between attempts. Errors that
+    /// won't go away by retrying are returned right away.
+    fn run<T, F: FnMut() -> anyhow::Result<T>>(&self, what: &str, mut f: F) -> anyhow::Result<T> {
+        let mut delay = self.delay;
+        let mut attempt = 0;
+        loop {
+            match f() {
+                Ok(value) => return Ok(value),
+                Err(e) if e.is::<ModuleLoadForbidden>() => return Err(e),
+                Err(e) if attempt >= self.retries => {
+                    return Err(e.context(format!(
+                        "{} failed after {} attempts",
+                        what,
+                        attempt + 1
+                    )))
+                }
+                Err(e) => {
+                    tracing::warn!("{} failed: {:#}. Retrying in {:?}...", what, e, delay);
+                    thread::sleep(delay);
+                    delay *= 2;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
 struct PulseUtil {
     context: Context,
     mainloop: Mainloop,
+    retry: RetryPolicy,
 }
 
 impl PulseUtil {
-    fn create(name: &str) -> anyhow::Result<PulseUtil> {
+    fn create(name: &str, retry: RetryPolicy) -> anyhow::Result<PulseUtil> {
+        let (context, mainloop) = retry.run("Connecting to Pulse", || Self::connect(name))?;
+        Ok(PulseUtil {
+            context,
+            mainloop,
+            retry,
+        })
+    }
+
+    fn connect(name: &str) -> anyhow::Result<(Context, Mainloop)> {
         let mut mainloop = Mainloop::new().context("Unable to create pulse main loop")?;
         let mut context =
             Context::new(&mainloop, name).context("Unable to create pulse context")?;
@@ -163,13 +245,13 @@ impl PulseUtil {
                 }
                 libpulse_binding::context::State::Failed
                 | libpulse_binding::context::State::Terminated => {
-                    return Err(anyhow!("Pulse context failed terminated!"))
+                    return Err(anyhow!("Pulse context failed: {}", context.errno()))
                 }
                 _ => {}
             }
         }
 
-        Ok(PulseUtil { context, mainloop })
+        Ok((context, mainloop))
     }
 
     fn iterate_mainloop(mainloop: &mut Mainloop, block: bool) -> anyhow::Result<()> {
@@ -232,7 +314,11 @@ impl PulseUtil {
 
         if result == u32::MAX {
             // Error
-            Err(anyhow!("Module initialization failed"))
+            if self.context.errno() == Code::Access.into() {
+                Err(ModuleLoadForbidden.into())
+            } else {
+                Err(anyhow!("Module initialization failed"))
+            }
         } else {
             Ok(result)
         }
@@ -244,131 +330,418 @@ impl PulseUtil {
         })
     }
 
-    fn using_null_sink<T, E, F: FnOnce() -> std::result::Result<T, E> + UnwindSafe>(
-        &mut self,
-        sink_spec: SinkSpec,
-        f: F,
-    ) -> anyhow::Result<std::result::Result<T, E>> {
-        let module_index =
-            self.load_module("module-null-sink", &sink_spec.build_sink_arguments())?;
-        let result = catch_unwind(|| f());
-        self.unload_module(module_index)?;
-        result.map_err(|error| panic!("Program panick'ed while using Pulse module: {:?}", error))
+    /// Loads the given modules in order, returning their indexes.
+    fn load_modules(&mut self, modules: &[(&str, String)]) -> anyhow::Result<Vec<u32>> {
+        let mut loaded = Vec::new();
+        for (name, arg) in modules {
+            let _span = tracing::info_span!("load_module", module = name).entered();
+            let retry = self.retry;
+            let result = retry.run(&format!("Loading {}", name), || self.load_module(name, arg));
+            match result {
+                Ok(index) => {
+                    tracing::debug!(index, "Module loaded");
+                    loaded.push(index);
+                }
+                Err(e) => {
+                    self.unload_modules(loaded)?;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(loaded)
+    }
+
+    /// Unloads the given modules, in reverse order.
+    fn unload_modules(&mut self, modules: Vec<u32>) -> anyhow::Result<()> {
+        for index in modules.into_iter().rev() {
+            self.unload_module(index)?;
+        }
+        Ok(())
     }
 }
 
 const PULSE_SINK_NAME: &'static str = "esp32-signal-device";
-fn stream_samples_to_pulse<R: Read, S: DecodeSampleUnsigned>(
+const PULSE_SOURCE_NAME: &str = "esp32-signal-source";
+const DEVICE_DESCRIPTION: &str = "ESP32 Signal Reader";
+
+/// Description of the devices created for the stream, naming the sink
+/// when it isn't the usual one so captures running at once can be told
+/// apart.
+fn device_description(args: &PulseStreamArgs) -> String {
+    if args.sink_name == PULSE_SINK_NAME {
+        DEVICE_DESCRIPTION.into()
+    } else {
+        format!("{} ({})", DEVICE_DESCRIPTION, args.sink_name)
+    }
+}
+
+/// Arguments of module-remap-source for exposing the monitor of the
+/// sink as a regular source.
+fn build_source_arguments(args: &PulseStreamArgs) -> String {
+    format!(
+        "master={}.monitor source_name={} source_properties=device.description='{}'",
+        args.sink_name,
+        args.source_name,
+        SinkSpec::pa_escape_string(&device_description(args))
+    )
+}
+
+/// Buffered by Pulse before starting the playback, unless a latency is
+/// requested.
+pub const DEFAULT_BUFFER: Duration = Duration::from_millis(125);
+
+/// Playback stream of unsigned 8-bit samples.
+pub struct PulseSink {
+    simple: Simple,
+    written: u64,
+}
+
+impl PulseSink {
+    /// Connects to the given sink, or to the default one if none is
+    /// given, starting the playback once `buffer_bytes` are buffered.
+    /// With `fixed_buffer` Pulse also keeps the buffer at that length,
+    /// for a steady latency.
+    pub fn connect(
+        device: Option<&str>,
+        audio_spec: &Spec,
+        buffer_bytes: u32,
+        fixed_buffer: bool,
+    ) -> anyhow::Result<PulseSink> {
+        let simple = Simple::new(
+            None,
+            "esp32-samples-reader",
+            Direction::Playback,
+            device,
+            "ESP32 Reader Stream",
+            audio_spec,
+            None,
+            Some(&BufferAttr {
+                maxlength: u32::MAX,
+                tlength: if fixed_buffer { buffer_bytes } else { u32::MAX },
+                prebuf: buffer_bytes,
+                minreq: u32::MAX,
+                fragsize: 0,
+            }),
+        )?;
+        Ok(PulseSink { simple, written: 0 })
+    }
+}
+
+impl SinkWriter<u8> for PulseSink {
+    fn write_samples(&mut self, samples: &[u8]) -> anyhow::Result<()> {
+        if !samples.is_empty() {
+            self.simple.write(samples)?;
+            self.written += samples.len() as u64;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        Ok(self.simple.drain()?)
+    }
+
+    fn bytes_written(&self) -> Option<u64> {
+        Some(self.written)
+    }
+}
+
+/// Bytes read from the input at once: approx 50 msecs of data, with a
+/// minimum of 32 bytes.
+fn chunk_len(args: &PulseStreamArgs, link_rate: u32) -> usize {
+    args.channels.chunk_len(
+        args.input
+            .chunk_len(link_rate, usize::max(rate::bytes_per(link_rate, 20), 32)),
+    )
+}
+
+/// Where the latency of the playback comes from, in the order the
+/// samples go through.
+struct LatencyBudget {
+    parts: Vec<(String, Duration)>,
+    requested: Option<Duration>,
+}
+
+impl LatencyBudget {
+    /// Budget up to the Pulse buffer, which gets the rest of the
+    /// requested latency, or a default length if there's none.
+    fn new(args: &PulseStreamArgs, audio_spec: &Spec) -> anyhow::Result<LatencyBudget> {
+        let link_rate = args.channels.link_rate(args.sampling_rate);
+        let chunk = chunk_len(args, link_rate);
+        // Reading waits for a whole chunk before decoding it.
+        let mut parts = vec![(
+            format!("Reading chunks of {} bytes", chunk),
+            rate::duration_of_bytes(link_rate, chunk as u64),
+        )];
+        if let Some(baud_rate) = args.input.baud_rate {
+            let frame_bits = args.input.serial.config()?.frame_bits();
+            parts.push((
+                format!("Serial line at {} baud", baud_rate),
+                Duration::from_secs_f64(frame_bits as f64 / baud_rate as f64),
+            ));
+        }
+        parts.extend(args.processing.latency(args.sampling_rate));
+
+        let requested = args.latency.map(Duration::from_millis);
+        let upstream: Duration = parts.iter().map(|(_, duration)| *duration).sum();
+        let buffer = match requested {
+            Some(requested) => requested.checked_sub(upstream).ok_or_else(|| {
+                anyhow!(
+                    "A latency of {} ms can't be met, as reading and decoding the samples already takes {:.1} ms. Try a shorter --flush-interval for reading smaller chunks",
+                    requested.as_millis(),
+                    upstream.as_secs_f64() * 1000.0
+                )
+            })?,
+            None => DEFAULT_BUFFER,
+        };
+        // Whole frames of u8 samples.
+        let frames = rate::samples_in(audio_spec.rate, buffer).max(1);
+        parts.push((
+            format!("Pulse buffer of {} frames", frames),
+            rate::duration_of_samples(audio_spec.rate, frames),
+        ));
+        Ok(LatencyBudget { parts, requested })
+    }
+
+    /// Length of the Pulse buffer, in bytes.
+    fn buffer_bytes(&self, audio_spec: &Spec) -> u32 {
+        let buffer = self.parts.last().unwrap().1;
+        rate::samples_in(audio_spec.rate, buffer).max(1) as u32 * audio_spec.channels as u32
+    }
+
+    fn print(&self, args: &PulseStreamArgs) {
+        eprintln!("Latency of the playback:");
+        for (part, duration) in &self.parts {
+            eprintln!("  {:<40} {:>9.2} ms", part, duration.as_secs_f64() * 1000.0);
+        }
+        let total: Duration = self.parts.iter().map(|(_, duration)| *duration).sum();
+        eprint!("  {:<40} {:>9.2} ms", "Total", total.as_secs_f64() * 1000.0);
+        match self.requested {
+            Some(requested) => eprintln!(" (of {} ms requested)", requested.as_millis()),
+            None => eprintln!(),
+        }
+        if args.input.read_ahead() > 0 {
+            eprintln!(
+                "While the output is blocked, up to {} ms more are buffered by --read-ahead.",
+                args.input.read_ahead()
+            );
+        }
+    }
+}
+
+/// Streams the input through the chain to Pulse, and to the file
+/// being recorded if any. `on_chunk` gets the samples of each chunk
+/// once played and the samples read so far, and stops the stream by
+/// returning false.
+fn stream_samples_to_pulse<R: Read + Send, D: SampleDecoder<Sample = u8>>(
     input: &mut R,
-    sampling_rate: u32,
-    ctrlc_context: &CtrlCIgnoredContext,
-    simple: &mut Simple,
-) -> anyhow::Result<()> {
-    // Adjust buffer size to hold approx 50 msecs of data, with a
-    // minimum of 32 bytes.
-    let buf_size = usize::max((sampling_rate / (8 * 20)) as usize, 32);
-
-    let mut buf = vec![0; buf_size];
-    let mut out_buf = vec![0; buf_size * 8];
-
-    let mut total_written_samples: usize = 0;
-    while !ctrlc_context.has_received_ctrlc() {
-        io::recover_if_interrupted(|| input.read_exact(&mut buf), || ())?;
-
-        for i in 0..buf.len() {
-            (&mut out_buf[i * 8..(i + 1) * 8]).copy_from_slice(&S::decode_sample(buf[i])[..])
+    args: &PulseStreamArgs,
+    link_rate: u32,
+    chain: &mut dyn Processor<u8>,
+    sink: &mut Tee<u8>,
+    on_chunk: impl FnMut(&[u8], usize) -> bool,
+) -> anyhow::Result<StageTimes> {
+    let buf_size = chunk_len(args, link_rate);
+
+    pipeline::stream::<D, _, _, _>(
+        input,
+        chain,
+        sink,
+        buf_size,
+        args.input.flush_interval(),
+        on_chunk,
+    )
+}
+
+/// Streams the input to the given Pulse sink, or to the default one if
+/// none is given.
+fn stream_to_pulse(
+    args: &PulseStreamArgs,
+    audio_spec: &Spec,
+    device: Option<&str>,
+    scope: &CancellationScope,
+    mut recorder: Option<&mut Recorder>,
+) -> anyhow::Result<StageTimes> {
+    let budget = LatencyBudget::new(args, audio_spec)?;
+    if args.explain_latency {
+        budget.print(args);
+    }
+    // Played live, unlike --record and the recordings of the daemon,
+    // so the sink is the one getting the shifted signal. It is
+    // connected again if the server goes away, like when restarted.
+    let mut pulse_sink = Reopen::new(|| {
+        Ok(args.monitor.monitor(
+            Box::new(PulseSink::connect(
+                device,
+                audio_spec,
+                budget.buffer_bytes(audio_spec),
+                args.latency.is_some(),
+            )?),
+            audio_spec.rate,
+            audio_spec.channels as u16,
+        ))
+    })?;
+
+    // Make sure to open the serial after establishing
+    // connection to pulse, for preventing delays while
+    // reading data from the port.
+    let stream = StreamInput {
+        input: &args.input,
+        sampling_rate: args.sampling_rate,
+        wave_amplitude: args.wave_amplitude.clone(),
+        channels: &args.channels,
+        processing: &args.processing,
+        monitor: &args.monitor,
+    };
+    let link_rate = args.channels.link_rate(args.sampling_rate);
+    let mut serial = args.input.open(link_rate, Duration::from_secs(1))?;
+    let mut chain = stream.processor()?;
+    let mut status = args
+        .channels
+        .status_line(&args.input, stream.output_rate())?;
+    let on_chunk = |samples: &[u8], total_samples| {
+        status.preview(samples);
+        status.update(total_samples, link_rate);
+        if let Some(recorder) = recorder.as_deref_mut() {
+            recorder.process(samples);
         }
+        !scope.is_cancelled()
+    };
 
-        simple.write(&out_buf[..])?;
-        total_written_samples += out_buf.len();
-        let written_duration = total_written_samples as f32 / sampling_rate as f32;
+    let mut outputs = args.outputs.open(
+        &extra_outputs(args),
+        audio_spec.rate,
+        audio_spec.channels as u16,
+        &args.monitor,
+    )?;
+    let mut sink = Tee::new();
+    sink.push("pulse", &mut pulse_sink, true);
+    for (output, file) in &mut outputs {
+        sink.push(&output.spec, file.as_mut(), output.required);
+    }
 
-        eprint!(
-            "Total {} samples read; {:.2} seconds of recording...\r",
-            total_written_samples, written_duration
-        );
+    let times = match args.wave_amplitude {
+        WaveAmplitude::Full => stream_samples_to_pulse::<_, DecodeUnsignedFullRange>(
+            &mut serial,
+            args,
+            link_rate,
+            &mut *chain,
+            &mut sink,
+            on_chunk,
+        ),
+        WaveAmplitude::Half => stream_samples_to_pulse::<_, DecodeUnsignedHalfRange>(
+            &mut serial,
+            args,
+            link_rate,
+            &mut *chain,
+            &mut sink,
+            on_chunk,
+        ),
+    };
+    // The outputs are finished even if the stream failed, keeping
+    // what was recorded up to then.
+    if times.is_err() {
+        let _ = sink.finish();
     }
-    simple.drain()?;
-    Ok(())
+    status.finish(times.is_ok());
+    times
+}
+
+/// Outputs besides Pulse, the file of --record included.
+fn extra_outputs(args: &PulseStreamArgs) -> Vec<Output> {
+    let record = args
+        .record
+        .as_ref()
+        .map(|path| OutputSpec::Wav(path.clone()).into());
+    record
+        .into_iter()
+        .chain(args.outputs.outputs.iter().cloned())
+        .collect()
 }
 
 pub fn run_pulse_stream_command(args: &PulseStreamArgs) -> anyhow::Result<ExitCode> {
-    let mut pulse_util = PulseUtil::create("esp32-pulse")?;
-    if let Some(existing_dev_module) = pulse_util.get_sink_owner_module_by_name(PULSE_SINK_NAME)? {
-        eprintln!("Sink '{}' already exists, probably because the program did not exit cleanly the last time.", PULSE_SINK_NAME);
-        match existing_dev_module {
-            Some(mod_number) => {
-                eprintln!(
-                    "Please remove it manually before proceeding with the following command:"
-                );
-                eprintln!();
-                eprintln!("pactl unload-module {}", mod_number);
-            }
-            None => {
-                eprintln!("Please remove it manually before proceeding.");
-            }
-        }
+    run_pulse_stream(args, None)
+}
+
+/// Streams to Pulse as pulse-stream does, handing the samples played to
+/// the recorder of the daemon if there's one.
+pub fn run_pulse_stream(
+    args: &PulseStreamArgs,
+    mut recorder: Option<&mut Recorder>,
+) -> anyhow::Result<ExitCode> {
+    let retry = RetryPolicy {
+        retries: args.pulse_retries,
+        delay: Duration::from_millis(args.pulse_retry_delay),
+    };
+    args.outputs.check(&extra_outputs(args))?;
+    let mut pulse_util = PulseUtil::create("esp32-pulse", retry)?;
+    if let Some(existing_dev_module) = pulse_util.get_sink_owner_module_by_name(&args.sink_name)? {
+        let removal = match existing_dev_module {
+            Some(mod_number) => format!(
+                "with the following command:\n\npactl unload-module {}",
+                mod_number
+            ),
+            None => "manually".into(),
+        };
+        tracing::error!(
+            "Sink '{}' already exists, probably because the program did not exit cleanly the last time. Please remove it before proceeding, {}",
+            args.sink_name,
+            removal
+        );
 
         return Ok(ExitCode::FAILURE);
     }
 
     let audio_spec = Spec {
         format: Format::U8,
-        channels: 1,
-        rate: args.sampling_rate,
+        channels: args.channels.channels as u8,
+        rate: args.processing.output_rate(args.sampling_rate),
     };
 
-    let result = ctrlc::ignoring_ctrlc(|ctrlc_context| {
+    let pulse = &mut pulse_util;
+    let result = cancel::with_scope(|scope| {
         let sink_spec = SinkSpec {
-            sink_name: PULSE_SINK_NAME.into(),
-            device_description: Some("ESP32 Signal Reader".into()),
+            sink_name: args.sink_name.clone(),
+            device_description: Some(device_description(args)),
             audio_format: audio_spec.clone(),
         };
 
-        pulse_util.using_null_sink(sink_spec, || -> anyhow::Result<()> {
-            let mut simple = Simple::new(
-                None,
-                "esp32-samples-reader",
-                Direction::Playback,
-                Some(PULSE_SINK_NAME),
-                "ESP32 Reader Stream",
-                &audio_spec,
-                None,
-                Some(&BufferAttr {
-                    maxlength: u32::MAX,
-                    tlength: u32::MAX,
-                    prebuf: args.sampling_rate / 8, // A second of prebuf.
-                    minreq: u32::MAX,
-                    fragsize: 0,
-                }),
-            )?;
-
-            // Make sure to open the serial after establishing
-            // connection to pulse, for preventing delays while
-            // reading data from the port.
-            let mut serial =
-                io::open_serial_port(&args.port, args.baud_rate, Duration::from_secs(1))?;
-
-            (match args.wave_amplitude {
-                WaveAmplitude::Full => stream_samples_to_pulse::<_, DecodeSampleUnsignedFullRange>(
-                    &mut serial,
-                    args.sampling_rate,
-                    ctrlc_context,
-                    &mut simple,
-                ),
-                WaveAmplitude::Half => stream_samples_to_pulse::<_, DecodeSampleUnsignedHalfRange>(
-                    &mut serial,
-                    args.sampling_rate,
-                    ctrlc_context,
-                    &mut simple,
-                ),
-            })?;
-            Ok(())
-        })
+        let mut modules = vec![("module-null-sink", sink_spec.build_sink_arguments())];
+        if args.as_source {
+            modules.push(("module-remap-source", build_source_arguments(args)));
+        }
+
+        // The modules are unloaded when their scope ends, even if
+        // streaming fails, before falling back to the default sink.
+        let output = scope
+            .nested(|modules_scope| {
+                let loaded = pulse.load_modules(&modules)?;
+                modules_scope.on_exit(move || pulse.unload_modules(loaded));
+                stream_to_pulse(
+                    args,
+                    &audio_spec,
+                    Some(&args.sink_name),
+                    modules_scope,
+                    recorder.as_deref_mut(),
+                )
+            })
+            .and_then(|output| output);
+        match output {
+            Err(e) if e.is::<ModuleLoadForbidden>() => {
+                tracing::warn!("{}, probably because the program is running confined.", e);
+                tracing::warn!("Falling back to playing the stream on the default sink.");
+                if args.as_source {
+                    tracing::warn!("No virtual source will be available.");
+                }
+                stream_to_pulse(args, &audio_spec, None, scope, recorder)
+            }
+            output => output,
+        }
     })?;
+    let times = result.output?;
+    args.input.report_stats(&times);
 
     pulse_util.mainloop.quit(Retval(0));
-    Ok(if result.has_received_ctrlc {
+    Ok(if result.interrupted {
         ExitCode::from(128 + SIGINT as u8)
     } else {
         ExitCode::SUCCESS
@@ -0,0 +1,403 @@
+use std::{
+    fs::File,
+    io::{stdout, BufWriter, ErrorKind, Read, Write},
+    os::unix::process::CommandExt,
+    path::PathBuf,
+    process::{Child, Command, ExitCode, Stdio},
+    time::Duration,
+};
+
+use anyhow::Context as _;
+use clap::{Parser, ValueEnum};
+use nix::libc::SIGINT;
+
+use super::{InProgressArgs, InputArgs, LimitArgs, ProcessingArgs, WaveAmplitude};
+use crate::{
+    cancel::{self, CancellationScope},
+    dsp::{Chain, Sample},
+    edges::{EdgeDetector, EdgeFormat, EdgeWriter},
+    io::{self, ByteCounter, CountingWriter},
+    pipeline::{
+        self, DecodeSigned, DecodeUnsignedFullRange, DecodeUnsignedHalfRange, Limited,
+        SampleDecoder, ShutdownToken, SinkWriter, StageTimes,
+    },
+    rate,
+    sigrok::SigrokWriter,
+    status::StatusLine,
+    wav,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RawFormat {
+    /// Signed 8-bit PCM.
+    S8,
+    /// Unsigned 8-bit PCM.
+    U8,
+    /// The transitions of the signal as CSV, with the sample index,
+    /// time and new level of each one. The processing options don't
+    /// apply.
+    Edges,
+    /// Same as edges, as binary records of the sample index (u64) and
+    /// new level (u8), little endian.
+    EdgesBin,
+    /// Same as edges, as a Value Change Dump file for logic analyzer
+    /// tools like GTKWave or PulseView.
+    Vcd,
+    /// The samples as a sigrok session, for the protocol decoders of
+    /// PulseView and sigrok-cli. The processing options don't apply.
+    Sr,
+}
+
+#[derive(Parser)]
+pub struct ReadRawArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[arg(short, long)]
+    pub sampling_rate: u32,
+
+    /// File the samples are written to. Written to the standard output
+    /// if not given.
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Shell command the samples are piped into, like "ffmpeg -f
+    /// {format} -ar {rate} -i - out.mp3". {rate} and {format} are
+    /// replaced with the sampling rate and the format of the output.
+    #[arg(long, value_name = "COMMAND", conflicts_with = "output")]
+    pub pipe_to: Option<String>,
+
+    #[arg(short, long, value_enum, default_value_t = RawFormat::S8)]
+    pub format: RawFormat,
+
+    /// Amplitude of the unsigned samples. Signed samples always use the
+    /// full range.
+    #[arg(short, long, default_value_t = WaveAmplitude::Full)]
+    pub wave_amplitude: WaveAmplitude,
+
+    #[command(flatten)]
+    pub limit: LimitArgs,
+
+    #[command(flatten)]
+    pub in_progress: InProgressArgs,
+
+    #[command(flatten)]
+    pub processing: ProcessingArgs,
+}
+
+trait RawSample: Sample {
+    fn to_raw(self) -> u8;
+}
+
+impl RawSample for i8 {
+    #[inline(always)]
+    fn to_raw(self) -> u8 {
+        self as u8
+    }
+}
+
+impl RawSample for u8 {
+    #[inline(always)]
+    fn to_raw(self) -> u8 {
+        self
+    }
+}
+
+struct RawSink<'a, W: Write> {
+    output: &'a mut W,
+    buf: Vec<u8>,
+}
+
+impl<S: RawSample, W: Write> SinkWriter<S> for RawSink<'_, W> {
+    fn write_samples(&mut self, samples: &[S]) -> anyhow::Result<()> {
+        self.buf.clear();
+        self.buf
+            .extend(samples.iter().map(|sample| sample.to_raw()));
+        self.output.write_all(&self.buf)?;
+        // Flushed right away, as whatever reads the output may be
+        // playing it live.
+        Ok(self.output.flush()?)
+    }
+}
+
+fn stream_raw<D: SampleDecoder, R: Read + Send, W: Write>(
+    input: &mut R,
+    args: &ReadRawArgs,
+    scope: &CancellationScope,
+    chain: &mut Chain<D::Sample>,
+    status: &mut StatusLine,
+    output: &mut W,
+) -> anyhow::Result<StageTimes>
+where
+    D::Sample: RawSample,
+{
+    let sampling_rate = args.sampling_rate;
+    // Same as with pulse, approx 50 msecs of data on each iteration,
+    // with a minimum of 32 bytes.
+    let buf_size = args.input.chunk_len(
+        sampling_rate,
+        usize::max(rate::bytes_per(sampling_rate, 20), 32),
+    );
+
+    let limit_reached = ShutdownToken::new();
+    let limit = args
+        .limit
+        .samples(args.processing.output_rate(sampling_rate))
+        .unwrap_or(u64::MAX);
+    let mut sink = Limited::new(
+        RawSink {
+            output,
+            buf: Vec::new(),
+        },
+        limit,
+        limit_reached.clone(),
+    );
+    pipeline::stream::<D, _, _, _>(
+        input,
+        chain,
+        &mut sink,
+        buf_size,
+        args.input.flush_interval(),
+        |samples, total_samples| {
+            status.preview(samples);
+            status.update(total_samples, sampling_rate);
+            !scope.is_cancelled() && !limit_reached.is_shutdown()
+        },
+    )
+}
+
+/// Outputs of the bit stream as received, without decoding nor
+/// processing it.
+enum BitOutput<W> {
+    Edges(EdgeDetector, EdgeWriter<W>),
+    Sigrok(SigrokWriter<W>),
+}
+
+fn stream_bits<R: Read, W: Write>(
+    input: &mut R,
+    args: &ReadRawArgs,
+    scope: &CancellationScope,
+    status: &mut StatusLine,
+    mut output: BitOutput<W>,
+) -> anyhow::Result<StageTimes> {
+    let sampling_rate = args.sampling_rate;
+    let buf_size = args.input.chunk_len(
+        sampling_rate,
+        usize::max(rate::bytes_per(sampling_rate, 20), 32),
+    );
+    let mut buf = vec![0; buf_size];
+    let limit = args.limit.samples(sampling_rate).unwrap_or(u64::MAX);
+
+    let mut total_samples: u64 = 0;
+    let mut times = StageTimes::default();
+    while !scope.is_cancelled() && total_samples < limit {
+        let read = times.reading.measure(|| io::read_input(input, &mut buf))?;
+        if read == 0 {
+            break;
+        }
+        // Only the bytes holding samples under the limit, and only
+        // the samples before it.
+        let left = rate::bytes_for_samples(limit - total_samples);
+        let len = usize::try_from(left).unwrap_or(usize::MAX).min(read);
+        let end = u64::min(total_samples + len as u64 * 8, limit);
+
+        times.writing.measure(|| -> anyhow::Result<()> {
+            match &mut output {
+                BitOutput::Edges(detector, writer) => {
+                    detector.process(&buf[..len], |index, level| {
+                        if index < end {
+                            writer.write_edge(index, level)
+                        } else {
+                            Ok(())
+                        }
+                    })?;
+                    writer.flush()?;
+                }
+                BitOutput::Sigrok(writer) => {
+                    writer.write(&buf[..len], (end - total_samples) as usize)?;
+                    writer.flush()?;
+                }
+            }
+            Ok(())
+        })?;
+        total_samples = end;
+        times.chunks += 1;
+        times
+            .reporting
+            .measure(|| status.update(total_samples as usize, sampling_rate));
+        if read < buf.len() {
+            break;
+        }
+    }
+    match output {
+        BitOutput::Edges(_, mut writer) => writer.finish(total_samples)?,
+        BitOutput::Sigrok(writer) => {
+            writer.finish()?;
+        }
+    }
+    Ok(times)
+}
+
+/// Streams the edges of the signal in the given format.
+fn stream_edges<R: Read, W: Write>(
+    input: &mut R,
+    args: &ReadRawArgs,
+    scope: &CancellationScope,
+    status: &mut StatusLine,
+    output: &mut W,
+    format: EdgeFormat,
+) -> anyhow::Result<StageTimes> {
+    let writer = EdgeWriter::new(output, format, args.sampling_rate)?;
+    let output = BitOutput::Edges(EdgeDetector::new(), writer);
+    stream_bits(input, args, scope, status, output)
+}
+
+/// Starts the command given with --pipe-to, reading from a pipe.
+fn spawn_pipe(command: &str, format: RawFormat, output_rate: u32) -> anyhow::Result<Child> {
+    let format = format.to_possible_value().unwrap();
+    let command = command
+        .replace("{rate}", &output_rate.to_string())
+        .replace("{format}", format.get_name());
+    Command::new("sh")
+        .arg("-c")
+        .arg(&command)
+        .stdin(Stdio::piped())
+        // Out of the process group, so Ctrl+C doesn't reach it and it
+        // sees the end of its input instead, finishing its output
+        // as usual.
+        .process_group(0)
+        .spawn()
+        .with_context(|| format!("Can't run '{}'", command))
+}
+
+pub fn run_read_raw_command(args: &ReadRawArgs) -> anyhow::Result<ExitCode> {
+    let mut serial = args
+        .input
+        .open(args.sampling_rate, Duration::from_secs(1))?;
+    let output_rate = match args.format {
+        RawFormat::Edges | RawFormat::EdgesBin | RawFormat::Vcd | RawFormat::Sr => {
+            args.sampling_rate
+        }
+        _ => args.processing.output_rate(args.sampling_rate),
+    };
+    let mut child = args
+        .pipe_to
+        .as_deref()
+        .map(|command| spawn_pipe(command, args.format, output_rate))
+        .transpose()?;
+    let output: Box<dyn Write> = match (&args.output, &mut child) {
+        (Some(path), _) => Box::new(File::create(args.in_progress.path(path))?),
+        (None, Some(child)) => Box::new(child.stdin.take().unwrap()),
+        (None, None) => Box::new(stdout().lock()),
+    };
+    let written = ByteCounter::default();
+    let mut output = BufWriter::new(CountingWriter::new(output, written.clone()));
+    let mut status = args.input.status_line(output_rate)?;
+
+    let result = cancel::with_scope(|scope| -> anyhow::Result<StageTimes> {
+        match args.format {
+            RawFormat::Edges => stream_edges(
+                &mut serial,
+                args,
+                scope,
+                &mut status,
+                &mut output,
+                EdgeFormat::Csv,
+            ),
+            RawFormat::EdgesBin => stream_edges(
+                &mut serial,
+                args,
+                scope,
+                &mut status,
+                &mut output,
+                EdgeFormat::Binary,
+            ),
+            RawFormat::Vcd => stream_edges(
+                &mut serial,
+                args,
+                scope,
+                &mut status,
+                &mut output,
+                EdgeFormat::Vcd,
+            ),
+            RawFormat::Sr => {
+                let writer = SigrokWriter::new(&mut output, args.sampling_rate)?;
+                stream_bits(
+                    &mut serial,
+                    args,
+                    scope,
+                    &mut status,
+                    BitOutput::Sigrok(writer),
+                )
+            }
+            RawFormat::S8 => stream_raw::<DecodeSigned, _, _>(
+                &mut serial,
+                args,
+                scope,
+                &mut args.processing.chain(args.sampling_rate)?,
+                &mut status,
+                &mut output,
+            ),
+            RawFormat::U8 => {
+                let mut chain = args.processing.chain(args.sampling_rate)?;
+                match args.wave_amplitude {
+                    WaveAmplitude::Full => stream_raw::<DecodeUnsignedFullRange, _, _>(
+                        &mut serial,
+                        args,
+                        scope,
+                        &mut chain,
+                        &mut status,
+                        &mut output,
+                    ),
+                    WaveAmplitude::Half => stream_raw::<DecodeUnsignedHalfRange, _, _>(
+                        &mut serial,
+                        args,
+                        scope,
+                        &mut chain,
+                        &mut status,
+                        &mut output,
+                    ),
+                }
+            }
+        }
+    })?;
+
+    // The reading end of the pipe going away is the usual way of
+    // stopping when piping into other tools.
+    let broken_pipe = match &result.output {
+        Err(e) => e
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == ErrorKind::BrokenPipe),
+        Ok(_) => false,
+    };
+    let flushed = if broken_pipe { Ok(()) } else { output.flush() };
+    status.finish(broken_pipe || (result.output.is_ok() && flushed.is_ok()));
+    // Closes the pipe, so the command sees the end of the samples.
+    drop(output);
+    if let Some(mut child) = child {
+        let status = child.wait()?;
+        if !status.success() {
+            tracing::error!(
+                "The command the samples were piped into failed ({})",
+                status
+            );
+            return Ok(ExitCode::FAILURE);
+        }
+    }
+    if broken_pipe {
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let mut times = result.output?;
+    flushed?;
+    times.written = Some(written.get());
+    if let Some(path) = &args.output {
+        wav::complete_output(&args.in_progress.path(path), path)?;
+    }
+    args.input.report_stats(&times);
+    Ok(if result.interrupted {
+        ExitCode::from(128 + SIGINT as u8)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
@@ -1,86 +1,565 @@
-use std::{io::Read, process::ExitCode, time::Duration};
+use std::{collections::VecDeque, io::Read, path::PathBuf, process::ExitCode, time::Duration};
 
+use super::{
+    AlignArgs, ChannelArgs, InProgressArgs, InputArgs, LimitArgs, ProcessingArgs, TagArgs,
+    WaveAmplitude, LOW_MEMORY_HELD_SAMPLES,
+};
 use crate::{
-    ctrlc::{self, CtrlCIgnoredOutput},
-    io,
+    cancel::{self, ScopeOutput},
+    dsp::silence::{
+        ActivityDetector, DetectActivity, DutyCycleDetector, Segment, SilenceSplit, Splitter,
+        Trimmer,
+    },
+    io::ByteCounter,
+    pipeline::{
+        self, DecodeF32, DecodeSigned, DecodeSigned16, DecodeSigned24, HalfRange, Limited,
+        SampleDecoder, ShutdownToken, SinkWriter, StageTimes,
+    },
+    rate,
+    segment::{ByteSize, SegmentLimit, SegmentedFile, TimeSpan},
+    trigger,
+    wav::{self, numbered_path, FileFormat, FileOptions, OutputFile, Tag, WavSample},
 };
-use clap::Parser;
-use hound::{WavSpec, WavWriter};
+use clap::{Parser, ValueEnum};
+use hound::WavSpec;
 use nix::libc::SIGINT;
 
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BitDepth {
+    /// Signed 8-bit integer samples.
+    #[value(name = "8")]
+    Int8,
+    /// Signed 16-bit integer samples.
+    #[value(name = "16")]
+    Int16,
+    /// Signed 24-bit integer samples.
+    #[value(name = "24")]
+    Int24,
+    /// 32-bit floating point samples.
+    #[value(name = "32f")]
+    Float32,
+}
+
 #[derive(Parser)]
 pub struct ReadWavArgs {
-    #[arg(short, long)]
-    pub port: String,
-    #[arg(short, long)]
-    pub sampling_rate: u32,
+    #[command(flatten)]
+    pub input: InputArgs,
 
     #[arg(short, long)]
-    pub baud_rate: u32,
+    pub sampling_rate: u32,
 
     #[arg(short, long)]
     pub output: String,
+
+    /// Sample format of the written files. Many audio editors don't
+    /// handle 8-bit files properly.
+    #[arg(long, value_name = "BITS", default_value = "8")]
+    pub bit_depth: BitDepth,
+
+    /// Levels the signal is written with: full swings between the
+    /// lowest and the highest, half between silence and the highest.
+    /// Stored as the amplitude tag of the files.
+    #[arg(short, long, default_value_t = WaveAmplitude::Full)]
+    pub wave_amplitude: WaveAmplitude,
+
+    /// Container of the written files, wav, flac or opus. FLAC files
+    /// are losslessly compressed, which suits long captures of 1-bit
+    /// signals, but can't hold 32f samples nor sampling rates over
+    /// 1048575 Hz. Opus files are lossy and much smaller, resampled to
+    /// 48 kHz.
+    #[arg(long, default_value = "wav")]
+    pub format: FileFormat,
+
+    /// Bitrate of Opus files, in kbit/s. Chosen by the encoder if not
+    /// given.
+    #[arg(long, value_name = "KBPS")]
+    pub bitrate: Option<u32>,
+
+    /// Keep only the last given seconds of recording in memory, and
+    /// save them to a new numbered file each time Enter is pressed or
+    /// SIGUSR1 is received.
+    #[arg(long, value_name = "SECS")]
+    #[cfg_attr(feature = "dsp", arg(conflicts_with = "beep_markers"))]
+    pub loop_buffer: Option<u32>,
+
+    /// Write each burst of activity to its own numbered file. A burst
+    /// ends after MIN_GAP milliseconds with the signal under THRESHOLD
+    /// (0 to 1).
+    #[arg(long, value_name = "THRESHOLD,MIN_GAP", conflicts_with_all = ["loop_buffer", "channels"])]
+    pub split_on_silence: Option<SilenceSplit>,
+
+    /// Milliseconds of silence kept before and after each burst.
+    #[arg(long, default_value_t = 100)]
+    pub split_roll: u32,
+
+    /// Only record while the signal is active, writing each burst of
+    /// activity to its own numbered file. The signal is active while
+    /// its duty cycle over --trigger-window differs from the one of the
+    /// idle line by more than the given fraction (0 to 1).
+    #[arg(long, value_name = "DEVIATION", conflicts_with_all = ["loop_buffer", "split_on_silence", "channels"])]
+    pub trigger: Option<f32>,
+
+    /// Milliseconds of signal the duty cycle is measured over.
+    #[arg(long, value_name = "MS", default_value_t = 10, requires = "trigger")]
+    pub trigger_window: u32,
+
+    /// Seconds of signal kept from before the trigger, and of silence
+    /// kept after the activity.
+    #[arg(long, value_name = "SECS", default_value_t = 1, requires = "trigger")]
+    pub pre_trigger: u32,
+
+    /// Seconds of silence that end a triggered recording.
+    #[arg(long, value_name = "SECS", default_value_t = 5, requires = "trigger")]
+    pub stop_after: u32,
+
+    /// Write the recording to consecutive files of the given length,
+    /// like 90s, 10m or 2h, named after the output with the number and
+    /// the start time of each one.
+    #[arg(long, value_name = "DURATION", conflicts_with_all = ["loop_buffer", "split_on_silence", "trigger"])]
+    pub segment_duration: Option<TimeSpan>,
+
+    /// Like --segment-duration, starting a new file once the current
+    /// one reaches the given size, like 500M or 2G. Compressed formats
+    /// end up smaller, as the size is counted before compressing.
+    #[arg(long, value_name = "SIZE", conflicts_with_all = ["loop_buffer", "split_on_silence", "trigger", "segment_duration"])]
+    pub segment_size: Option<ByteSize>,
+
+    /// Remove the silence at the start and the end of the recording,
+    /// taking as silence the signal under the given level (0 to 1).
+    #[arg(long, value_name = "THRESHOLD", conflicts_with_all = ["loop_buffer", "split_on_silence", "trigger", "channels"])]
+    pub trim_silence: Option<f32>,
+
+    /// Print a fingerprint of each output file once it's finished, for
+    /// finding duplicated or overlapping captures.
+    #[cfg(feature = "analysis")]
+    #[arg(long)]
+    pub fingerprint: bool,
+
+    /// Write a `.done` file next to each output file once it's
+    /// complete, with its size, CRC-32, length and format, so batch
+    /// processors watching the directory know when to pick it up and
+    /// can check it first.
+    #[arg(long)]
+    pub done_marker: bool,
+
+    #[command(flatten)]
+    pub limit: LimitArgs,
+
+    #[command(flatten)]
+    pub channels: ChannelArgs,
+
+    #[command(flatten)]
+    pub align: AlignArgs,
+
+    #[command(flatten)]
+    pub tag: TagArgs,
+
+    #[command(flatten)]
+    pub in_progress: InProgressArgs,
+
+    #[command(flatten)]
+    pub processing: ProcessingArgs,
 }
 
-pub fn run_write_wav_command(args: &ReadWavArgs) -> anyhow::Result<ExitCode> {
-    // Adjust the buffer size to the expected data flow, between a set
-    // of limits. Default set to a quarter of the expected data to be
-    // received in a second (Arbitrarily chosen number).
-    let buf_size = usize::max(1024, args.sampling_rate as usize / (8 * 4));
+impl ReadWavArgs {
+    fn fingerprint(&self) -> bool {
+        #[cfg(feature = "analysis")]
+        return self.fingerprint;
+        #[cfg(not(feature = "analysis"))]
+        return false;
+    }
 
-    // buf_size will be set to half of the bytes required to read 1
-    // second of recording, So a timeout of 1 second is enough.
-    let mut serial = io::open_serial_port(&args.port, args.baud_rate, Duration::from_secs(1))?;
-    let spec = WavSpec {
-        channels: 1,
-        sample_rate: args.sampling_rate,
-        bits_per_sample: 8,
-        sample_format: hound::SampleFormat::Int,
-    };
+    fn segment_limit(&self) -> Option<SegmentLimit> {
+        match (self.segment_duration, self.segment_size) {
+            (Some(TimeSpan(duration)), _) => Some(SegmentLimit::Duration(duration)),
+            (_, Some(ByteSize(size))) => Some(SegmentLimit::Size(size)),
+            _ => None,
+        }
+    }
 
-    let mut writer = WavWriter::create(&args.output, spec)?;
+    fn file_options(&self) -> FileOptions {
+        FileOptions {
+            format: self.format,
+            bitrate: self.bitrate,
+            fingerprint: self.fingerprint(),
+            alignment: self.align.alignment(),
+            tags: self
+                .tag
+                .tags
+                .iter()
+                .cloned()
+                .chain([Tag {
+                    key: "amplitude".into(),
+                    value: match (self.processing.low, self.processing.high) {
+                        (Some(low), Some(high)) => format!("{}:{}", low, high),
+                        _ => self.wave_amplitude.to_string(),
+                    },
+                }])
+                .collect(),
+            inprogress_suffix: self.in_progress.inprogress_suffix.clone(),
+            done_marker: self.done_marker,
+            written: ByteCounter::default(),
+        }
+    }
+}
 
-    let mut input_buf = vec![0; buf_size];
-    let mut total_written_samples = 0;
+/// Holds the last samples of the recording, and saves them to disk
+/// when a trigger is fired.
+struct LoopRecorder<'a, S> {
+    samples: VecDeque<S>,
+    capacity: usize,
+    spec: WavSpec,
+    output: &'a str,
+    options: FileOptions,
+    saved: usize,
+}
 
-    let result: CtrlCIgnoredOutput<anyhow::Result<()>> = ctrlc::ignoring_ctrlc(|context| {
-        while !context.has_received_ctrlc() {
-            io::recover_if_interrupted(|| serial.read_exact(&mut input_buf), || ())?;
+impl<'a, S: WavSample> LoopRecorder<'a, S> {
+    fn new(
+        spec: WavSpec,
+        seconds: u32,
+        output: &'a str,
+        options: FileOptions,
+    ) -> anyhow::Result<Self> {
+        trigger::listen()?;
+        let capacity = spec.sample_rate as usize * spec.channels as usize * seconds as usize;
+        Ok(LoopRecorder {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+            spec,
+            output,
+            options,
+            saved: 0,
+        })
+    }
 
-            for i in 0..buf_size {
-                for sample in io::decode_esp32_sample(input_buf[i]) {
-                    io::retry_if_interrupted(
-                        || writer.write_sample(sample),
-                        |e| match e {
-                            hound::Error::IoError(e) => Some(e),
-                            _ => None,
-                        },
-                    )?;
+    fn push(&mut self, samples: &[S]) -> anyhow::Result<()> {
+        self.samples.extend(samples);
+        if self.samples.len() > self.capacity {
+            self.samples.drain(..self.samples.len() - self.capacity);
+        }
+
+        if trigger::take() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    fn save(&mut self) -> anyhow::Result<()> {
+        self.saved += 1;
+        let path = numbered_path(self.output, self.saved);
+        tracing::info!(
+            "Saving last {} seconds of recording to {}",
+            rate::format_seconds(
+                self.spec.sample_rate,
+                (self.samples.len() / self.spec.channels as usize) as u64
+            ),
+            path.display()
+        );
+
+        let mut file = OutputFile::create(path, self.spec, &self.options)?;
+        let (front, back) = self.samples.as_slices();
+        file.write(front)?;
+        file.write(back)?;
+        file.finalize()
+    }
+}
+
+/// Writes each burst of activity of the signal to its own file.
+struct SplitRecorder<'a, S, D = ActivityDetector> {
+    splitter: Splitter<S, D>,
+    file: Option<OutputFile<S>>,
+    spec: WavSpec,
+    output: &'a str,
+    options: FileOptions,
+    saved: usize,
+}
+
+impl<'a, S: WavSample, D: DetectActivity> SplitRecorder<'a, S, D> {
+    fn push(&mut self, samples: &[S]) -> anyhow::Result<()> {
+        let SplitRecorder {
+            splitter,
+            file,
+            spec,
+            output,
+            options,
+            saved,
+        } = self;
+
+        splitter.process(samples, |segment| {
+            match segment {
+                Segment::Start => {
+                    *saved += 1;
+                    let path = numbered_path(output, *saved);
+                    tracing::info!("Activity detected. Writing to {}", path.display());
+                    *file = Some(OutputFile::create(path, *spec, options)?);
+                }
+                Segment::Samples(samples) => {
+                    if let Some(file) = file {
+                        file.write(samples)?;
+                    }
+                }
+                Segment::End => {
+                    if let Some(file) = file.take() {
+                        file.finalize()?;
+                    }
                 }
             }
+            Ok(())
+        })
+    }
 
-            total_written_samples += buf_size * 8;
-            let written_duration = total_written_samples as f32 / args.sampling_rate as f32;
+    fn flush(&mut self) -> anyhow::Result<()> {
+        if let Some(file) = &mut self.file {
+            file.flush()?;
+        }
+        Ok(())
+    }
 
-            eprint!(
-                "Total {} samples read; {:.2} seconds of recording...\r",
-                total_written_samples, written_duration
-            );
+    fn finalize(self) -> anyhow::Result<()> {
+        if let Some(file) = self.file {
+            file.finalize()?;
+        }
+        Ok(())
+    }
+}
+
+enum Output<'a, S> {
+    Wav(Box<OutputFile<S>>),
+    Loop(LoopRecorder<'a, S>),
+    Split(Box<SplitRecorder<'a, S>>),
+    Triggered(Box<SplitRecorder<'a, S, DutyCycleDetector>>),
+    Segmented(Box<SegmentedFile<S>>),
+}
+
+impl<'a, S: WavSample> Output<'a, S> {
+    fn write(&mut self, samples: &[S]) -> anyhow::Result<()> {
+        match self {
+            Output::Wav(file) => file.write(samples)?,
+            Output::Loop(recorder) => recorder.push(samples)?,
+            Output::Split(recorder) => recorder.push(samples)?,
+            Output::Triggered(recorder) => recorder.push(samples)?,
+            Output::Segmented(file) => file.write(samples)?,
         }
+        Ok(())
+    }
 
+    fn flush(&mut self) -> anyhow::Result<()> {
+        match self {
+            Output::Wav(file) => file.flush()?,
+            // Only written when triggered, all at once.
+            Output::Loop(_) => (),
+            Output::Split(recorder) => recorder.flush()?,
+            Output::Triggered(recorder) => recorder.flush()?,
+            Output::Segmented(file) => file.flush()?,
+        }
         Ok(())
+    }
+
+    fn finalize(self) -> anyhow::Result<()> {
+        match self {
+            Output::Wav(file) => file.finalize()?,
+            Output::Loop(_) => (),
+            Output::Split(recorder) => recorder.finalize()?,
+            Output::Triggered(recorder) => recorder.finalize()?,
+            Output::Segmented(file) => file.finalize()?,
+        }
+        Ok(())
+    }
+}
+
+/// Output of the recording, after trimming its silence if requested.
+struct Recording<'a, S> {
+    output: Output<'a, S>,
+    trimmer: Option<Trimmer<S>>,
+    sample_rate: u32,
+}
+
+impl<S: WavSample> SinkWriter<S> for Recording<'_, S> {
+    fn write_samples(&mut self, samples: &[S]) -> anyhow::Result<()> {
+        match &mut self.trimmer {
+            Some(trimmer) => {
+                let output = &mut self.output;
+                trimmer.process(samples, |samples| output.write(samples))
+            }
+            None => self.output.write(samples),
+        }
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        self.output.flush()
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        if let Some(trimmer) = &mut self.trimmer {
+            let (leading, trailing) = trimmer.finish();
+            tracing::info!(
+                "Trimmed {:.2} seconds of silence from the start and {:.2} from the end",
+                leading as f32 / self.sample_rate as f32,
+                trailing as f32 / self.sample_rate as f32
+            );
+        }
+        Ok(())
+    }
+}
+
+fn record<D: SampleDecoder>(
+    args: &ReadWavArgs,
+    serial: &mut (impl Read + Send + ?Sized),
+    buf_size: usize,
+) -> anyhow::Result<ExitCode>
+where
+    D::Sample: WavSample + 'static,
+{
+    let spec = wav::spec::<D::Sample>(
+        args.processing.output_rate(args.sampling_rate),
+        args.channels.channels,
+    );
+
+    let mut chain = args
+        .channels
+        .processor(|| args.processing.chain(args.sampling_rate))?;
+
+    // Shared by every file of the recording, so they all add to the
+    // same count of bytes written.
+    let options = args.file_options();
+    let output = if let Some(seconds) = args.loop_buffer {
+        Output::Loop(LoopRecorder::new(
+            spec,
+            seconds,
+            &args.output,
+            options.clone(),
+        )?)
+    } else if let Some(split) = args.split_on_silence {
+        Output::Split(Box::new(SplitRecorder {
+            splitter: Splitter::new(spec.sample_rate, split, args.split_roll),
+            file: None,
+            spec,
+            output: &args.output,
+            options: options.clone(),
+            saved: 0,
+        }))
+    } else if let Some(threshold) = args.trigger {
+        Output::Triggered(Box::new(SplitRecorder {
+            splitter: Splitter::with_detector(
+                DutyCycleDetector::new(spec.sample_rate, threshold, args.trigger_window),
+                spec.sample_rate,
+                args.stop_after * 1000,
+                args.pre_trigger * 1000,
+            ),
+            file: None,
+            spec,
+            output: &args.output,
+            options: options.clone(),
+            saved: 0,
+        }))
+    } else if let Some(limit) = args.segment_limit() {
+        Output::Segmented(Box::new(SegmentedFile::new(
+            &args.output,
+            spec,
+            limit,
+            options.clone(),
+        )?))
+    } else {
+        Output::Wav(Box::new(OutputFile::create(
+            PathBuf::from(&args.output),
+            spec,
+            &options,
+        )?))
+    };
+
+    let recording = Recording {
+        output,
+        trimmer: args.trim_silence.map(|threshold| {
+            let trimmer = Trimmer::new(spec.sample_rate, threshold);
+            if args.input.low_memory {
+                trimmer.with_max_held(LOW_MEMORY_HELD_SAMPLES)
+            } else {
+                trimmer
+            }
+        }),
+        sample_rate: spec.sample_rate,
+    };
+    let limit_reached = ShutdownToken::new();
+    let limit = args
+        .limit
+        .samples(spec.sample_rate)
+        .map_or(u64::MAX, |frames| frames * spec.channels as u64);
+    let mut recording = Limited::new(recording, limit, limit_reached.clone());
+
+    let link_rate = args.channels.link_rate(args.sampling_rate);
+    let mut status = args.channels.status_line(&args.input, spec.sample_rate)?;
+
+    let result: ScopeOutput<anyhow::Result<StageTimes>> = cancel::with_scope(|scope| {
+        pipeline::stream::<D, _, _, _>(
+            serial,
+            &mut *chain,
+            &mut recording,
+            buf_size,
+            args.input.flush_interval(),
+            |samples, total_samples| {
+                status.preview(samples);
+                status.update(total_samples, link_rate);
+                !scope.is_cancelled() && !limit_reached.is_shutdown()
+            },
+        )
     })?;
 
-    let exit_code = if result.has_received_ctrlc {
-        eprintln!();
-        eprintln!("Ctrl+C handled. Stopping...");
-        writer.finalize()?;
+    if result.interrupted {
+        tracing::info!("Ctrl+C handled. Stopping...");
+    }
+    let finalized = recording.inner.output.finalize();
+    status.finish(result.output.is_ok() && finalized.is_ok());
+    finalized?;
 
+    let mut times = result.output?;
+    times.written = Some(options.written.get());
+    args.input.report_stats(&times);
+    Ok(if result.interrupted {
         ExitCode::from((128 + SIGINT) as u8)
     } else {
         ExitCode::SUCCESS
-    };
+    })
+}
+
+pub fn run_write_wav_command(args: &ReadWavArgs) -> anyhow::Result<ExitCode> {
+    // Adjust the buffer size to the expected data flow, between a set
+    // of limits. Default set to a quarter of the expected data to be
+    // received in a second (Arbitrarily chosen number).
+    let link_rate = args.channels.link_rate(args.sampling_rate);
+    let buf_size = args.channels.chunk_len(
+        args.input
+            .chunk_len(link_rate, usize::max(1024, rate::bytes_per(link_rate, 4))),
+    );
 
-    result.output?;
-    Ok(exit_code)
+    // buf_size will be set to half of the bytes required to read 1
+    // second of recording, So a timeout of 1 second is enough.
+    let mut serial = args.input.open(link_rate, Duration::from_secs(1))?;
+
+    match (args.bit_depth, &args.wave_amplitude) {
+        (BitDepth::Int8, WaveAmplitude::Full) => {
+            record::<DecodeSigned>(args, &mut serial, buf_size)
+        }
+        (BitDepth::Int8, WaveAmplitude::Half) => {
+            record::<HalfRange<DecodeSigned>>(args, &mut serial, buf_size)
+        }
+        (BitDepth::Int16, WaveAmplitude::Full) => {
+            record::<DecodeSigned16>(args, &mut serial, buf_size)
+        }
+        (BitDepth::Int16, WaveAmplitude::Half) => {
+            record::<HalfRange<DecodeSigned16>>(args, &mut serial, buf_size)
+        }
+        (BitDepth::Int24, WaveAmplitude::Full) => {
+            record::<DecodeSigned24>(args, &mut serial, buf_size)
+        }
+        (BitDepth::Int24, WaveAmplitude::Half) => {
+            record::<HalfRange<DecodeSigned24>>(args, &mut serial, buf_size)
+        }
+        (BitDepth::Float32, WaveAmplitude::Full) => {
+            record::<DecodeF32>(args, &mut serial, buf_size)
+        }
+        (BitDepth::Float32, WaveAmplitude::Half) => {
+            record::<HalfRange<DecodeF32>>(args, &mut serial, buf_size)
+        }
+    }
 }
@@ -0,0 +1,177 @@
+use std::{path::Path, process::ExitCode, time::Duration};
+
+use super::{AlignArgs, ProcessingArgs, SerialArgs, TagArgs};
+use crate::{
+    cancel::{self, CancellationScope},
+    io, rate,
+    source::{usb, OpenOptions, SourceSpec},
+    state::{self, CaptureState},
+    status::{self, StatusLine},
+    wav::{tagged_path, FileOptions, OutputFile},
+};
+use anyhow::anyhow;
+use clap::Parser;
+use hound::WavSpec;
+use nix::libc::SIGINT;
+
+#[derive(Parser)]
+pub struct RoundRobinArgs {
+    /// Comma-separated list of the ports to capture from, in order. USB
+    /// ports can be given as usb:<vid>:<pid> or serial:<number>, as
+    /// with --port.
+    #[arg(short, long, value_delimiter = ',', required = true)]
+    pub ports: Vec<String>,
+
+    #[arg(short, long)]
+    pub baud_rate: u32,
+
+    #[command(flatten)]
+    pub serial: SerialArgs,
+
+    #[arg(short, long)]
+    pub sampling_rate: u32,
+
+    /// Seconds captured from each port on its turn.
+    #[arg(short, long, default_value_t = 10)]
+    pub duration: u64,
+
+    /// Times to go through the list of ports. Runs until stopped if not
+    /// given.
+    #[arg(long)]
+    pub cycles: Option<u32>,
+
+    /// Output file, which gets the name of the port and the cycle
+    /// number appended for each capture.
+    #[arg(short, long)]
+    pub output: String,
+
+    #[command(flatten)]
+    pub align: AlignArgs,
+
+    #[command(flatten)]
+    pub tag: TagArgs,
+
+    #[command(flatten)]
+    pub processing: ProcessingArgs,
+}
+
+fn capture_port(
+    args: &RoundRobinArgs,
+    port: &str,
+    cycle: u32,
+    scope: &CancellationScope,
+    status: &mut StatusLine,
+) -> anyhow::Result<()> {
+    // Resolved on each turn, as boards may have been plugged again
+    // under another device.
+    let _span = tracing::info_span!("capture", port, cycle).entered();
+    let port = &usb::resolve_port(port)?;
+    state::set(CaptureState::Connecting);
+    let mut serial = SourceSpec::Serial(port.clone()).open(&OpenOptions {
+        baud_rate: Some(args.baud_rate),
+        serial: args.serial.config()?,
+        timeout: Duration::from_secs(1),
+        debug_pcap: None,
+        realtime_rate: None,
+        reconnect: false,
+        gap_fill_rate: None,
+        framed: false,
+    })?;
+    state::set(CaptureState::Prebuffering);
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate: args.processing.output_rate(args.sampling_rate),
+        bits_per_sample: 8,
+        sample_format: hound::SampleFormat::Int,
+    };
+
+    let port_name = Path::new(port)
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let path = tagged_path(&args.output, &format!("{}-{:03}", port_name, cycle));
+    tracing::info!(
+        "Capturing {} seconds from {} into {}",
+        args.duration,
+        port,
+        path.display()
+    );
+    let options = FileOptions {
+        fingerprint: false,
+        alignment: args.align.alignment(),
+        tags: args.tag.tags.clone(),
+        ..Default::default()
+    };
+    let mut file = OutputFile::create(path, spec, &options)?;
+
+    let total_bytes = rate::bytes_for_samples(args.sampling_rate as u64 * args.duration) as usize;
+    let mut buf = vec![0; usize::max(1024, rate::bytes_per(args.sampling_rate, 4))];
+    let mut chain = args.processing.chain(args.sampling_rate)?;
+    let mut read_bytes = 0;
+
+    while read_bytes < total_bytes && !scope.is_cancelled() {
+        let wanted = usize::min(buf.len(), total_bytes - read_bytes);
+        let len = io::read_input(&mut serial, &mut buf[..wanted])?;
+        if len == 0 {
+            break;
+        }
+        state::set(CaptureState::Streaming);
+        let samples = chain.process(&buf[..len], io::decode_esp32_sample);
+        status.preview(samples);
+        file.write(samples)?;
+
+        read_bytes += len;
+        status.update(read_bytes * 8, args.sampling_rate);
+        if len < wanted {
+            break;
+        }
+    }
+
+    file.write(&chain.finish())?;
+    file.finalize()?;
+    status::finish_line();
+    Ok(())
+}
+
+pub fn run_round_robin_command(args: &RoundRobinArgs) -> anyhow::Result<ExitCode> {
+    let mut status = StatusLine::create();
+
+    let result = cancel::with_scope(|scope| {
+        let mut cycle = 0;
+        while !scope.is_cancelled() {
+            if args.cycles == Some(cycle) {
+                break;
+            }
+            cycle += 1;
+
+            // A board that fails doesn't stop the rest from being
+            // captured, unless all of them fail.
+            let mut failed = 0;
+            for port in &args.ports {
+                if scope.is_cancelled() {
+                    break;
+                }
+                if let Err(e) = capture_port(args, port, cycle, scope, &mut status) {
+                    tracing::warn!("Capture from {} failed: {}", port, e);
+                    failed += 1;
+                }
+            }
+
+            if failed == args.ports.len() {
+                return Err(anyhow!("Unable to capture from any of the ports"));
+            }
+        }
+        Ok(())
+    })?;
+    status.finish(result.output.is_ok());
+
+    let exit_code = if result.interrupted {
+        tracing::info!("Ctrl+C handled. Stopping...");
+        ExitCode::from((128 + SIGINT) as u8)
+    } else {
+        ExitCode::SUCCESS
+    };
+
+    result.output?;
+    Ok(exit_code)
+}
@@ -0,0 +1,291 @@
+use std::{
+    collections::VecDeque,
+    io::{ErrorKind, Read},
+    process::ExitCode,
+    time::{Duration, Instant},
+};
+
+use clap::{Parser, ValueEnum};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::{
+    style::{Color, Style},
+    symbols::Marker,
+    widgets::{Axis, Block, Chart, Dataset, GraphType},
+    DefaultTerminal, Frame,
+};
+
+use super::{InputArgs, ProcessingArgs};
+use crate::{cancel, io, rate};
+
+/// Interval between redraws of the screen.
+const FRAME_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Time bases the display steps through with + and -, in
+/// milliseconds.
+const TIME_BASES: [f64; 16] = [
+    0.05, 0.1, 0.2, 0.5, 1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0, 1000.0, 2000.0,
+    5000.0,
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TriggerEdge {
+    Rising,
+    Falling,
+    /// Free running, showing the latest samples.
+    None,
+}
+
+impl TriggerEdge {
+    fn next(self) -> TriggerEdge {
+        match self {
+            TriggerEdge::Rising => TriggerEdge::Falling,
+            TriggerEdge::Falling => TriggerEdge::None,
+            TriggerEdge::None => TriggerEdge::Rising,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            TriggerEdge::Rising => "rising",
+            TriggerEdge::Falling => "falling",
+            TriggerEdge::None => "none",
+        }
+    }
+}
+
+#[derive(Parser)]
+pub struct ScopeArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[arg(short, long)]
+    pub sampling_rate: u32,
+
+    /// Time shown across the screen, in milliseconds. Changed with +
+    /// and - while running.
+    #[arg(long, value_name = "MS", default_value_t = 10.0)]
+    pub time_base: f64,
+
+    /// Edge the display is aligned to: rising, falling or none. Changed
+    /// with t while running.
+    #[arg(long, value_name = "EDGE", default_value = "rising")]
+    pub trigger: TriggerEdge,
+
+    /// Level the signal crosses for triggering, from -1 to 1.
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pub trigger_level: f32,
+
+    #[command(flatten)]
+    pub processing: ProcessingArgs,
+}
+
+/// Decoded samples of the latest stretch of the signal, and what's
+/// shown of them.
+struct Scope {
+    rate: u32,
+    time_base: f64,
+    trigger: TriggerEdge,
+    level: f32,
+    samples: VecDeque<f32>,
+    shown: Vec<f32>,
+    triggered: bool,
+    frozen: bool,
+    ended: bool,
+}
+
+impl Scope {
+    /// Samples across the screen.
+    fn window(&self) -> usize {
+        usize::max(2, (self.rate as f64 * self.time_base / 1000.0) as usize)
+    }
+
+    fn push(&mut self, samples: &[f32]) {
+        if self.frozen {
+            return;
+        }
+        self.samples.extend(samples);
+        // Enough for finding a trigger before the latest full window.
+        let keep = self.window() * 3;
+        if self.samples.len() > keep {
+            self.samples.drain(..self.samples.len() - keep);
+        }
+    }
+
+    /// Start of the latest window beginning at the trigger edge.
+    fn trigger_point(&self) -> Option<usize> {
+        let last = self.samples.len().checked_sub(self.window())?;
+        (1..=last).rev().find(|&i| {
+            let (before, after) = (self.samples[i - 1], self.samples[i]);
+            match self.trigger {
+                TriggerEdge::Rising => before <= self.level && after > self.level,
+                TriggerEdge::Falling => before >= self.level && after < self.level,
+                TriggerEdge::None => false,
+            }
+        })
+    }
+
+    /// Picks the samples shown on the next frame. Without a trigger
+    /// edge in sight it runs free, like the auto mode of scopes.
+    fn capture(&mut self) {
+        if self.frozen {
+            return;
+        }
+        let window = self.window();
+        let point = self.trigger_point();
+        self.triggered = point.is_some();
+        let start = point.unwrap_or(self.samples.len().saturating_sub(window));
+        self.shown = self.samples.range(start..).take(window).copied().collect();
+    }
+
+    fn step_time_base(&mut self, longer: bool) {
+        let next = if longer {
+            TIME_BASES.iter().find(|&&base| base > self.time_base)
+        } else {
+            TIME_BASES.iter().rev().find(|&&base| base < self.time_base)
+        };
+        if let Some(next) = next {
+            self.time_base = *next;
+        }
+    }
+
+    /// The shown samples as the minimum and the maximum of each column
+    /// of the chart, which draws the same as every sample would.
+    fn points(&self, columns: usize) -> Vec<(f64, f64)> {
+        let len = self.shown.len();
+        let columns = columns.clamp(1, usize::max(1, len));
+        let millis_per_sample = 1000.0 / self.rate as f64;
+        let mut points = Vec::with_capacity(columns * 2);
+        for column in 0..columns {
+            let from = column * len / columns;
+            let to = usize::max(from + 1, (column + 1) * len / columns).min(len);
+            let block = &self.shown[from..to];
+            let min = block.iter().copied().fold(f32::MAX, f32::min);
+            let max = block.iter().copied().fold(f32::MIN, f32::max);
+            let x = from as f64 * millis_per_sample;
+            points.push((x, min as f64));
+            points.push((x, max as f64));
+        }
+        points
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let area = frame.area();
+        // Braille characters hold two dots across.
+        let points = self.points(area.width as usize * 2);
+        let status = if self.frozen {
+            "held"
+        } else if self.ended {
+            "input ended"
+        } else if self.triggered || self.trigger == TriggerEdge::None {
+            "running"
+        } else {
+            "auto"
+        };
+        let title = format!(
+            " {} Hz  {} ms  trigger {} at {:.2}  {}  (+/- time base, t trigger, space hold, q quit) ",
+            self.rate,
+            self.time_base,
+            self.trigger.name(),
+            self.level,
+            status
+        );
+
+        let dataset = Dataset::default()
+            .marker(Marker::Braille)
+            .graph_type(GraphType::Line)
+            .style(Style::default().fg(Color::Green))
+            .data(&points);
+        let chart = Chart::new(vec![dataset])
+            .block(Block::bordered().title(title))
+            .x_axis(Axis::default().bounds([0.0, self.time_base]).labels([
+                "0".to_string(),
+                format!("{} ms", self.time_base / 2.0),
+                format!("{} ms", self.time_base),
+            ]))
+            .y_axis(
+                Axis::default()
+                    .bounds([-1.05, 1.05])
+                    .labels(["-1", "0", "1"]),
+            );
+        frame.render_widget(chart, area);
+    }
+}
+
+/// Handles the pending key presses, returning false once the user
+/// asked to quit.
+fn handle_keys(scope: &mut Scope) -> anyhow::Result<bool> {
+    while event::poll(Duration::ZERO)? {
+        let key = match event::read()? {
+            Event::Key(key) if key.kind == KeyEventKind::Press => key,
+            _ => continue,
+        };
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return Ok(false),
+            // The terminal is in raw mode, so Ctrl+C arrives as a key.
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Ok(false)
+            }
+            KeyCode::Char('+') => scope.step_time_base(false),
+            KeyCode::Char('-') => scope.step_time_base(true),
+            KeyCode::Char('t') => scope.trigger = scope.trigger.next(),
+            KeyCode::Char(' ') => scope.frozen = !scope.frozen,
+            _ => {}
+        }
+    }
+    Ok(true)
+}
+
+fn run(args: &ScopeArgs, terminal: &mut DefaultTerminal) -> anyhow::Result<ExitCode> {
+    let mut serial = args
+        .input
+        .open(args.sampling_rate, Duration::from_millis(20))?;
+    let mut buf = vec![0; usize::max(32, rate::bytes_per(args.sampling_rate, 10))];
+    let mut chain = args.processing.chain::<f32>(args.sampling_rate)?;
+    let mut scope = Scope {
+        rate: args.processing.output_rate(args.sampling_rate),
+        time_base: args.time_base,
+        trigger: args.trigger,
+        level: args.trigger_level,
+        samples: VecDeque::new(),
+        shown: Vec::new(),
+        triggered: false,
+        frozen: false,
+        ended: false,
+    };
+
+    let result = cancel::with_scope(|cancel| -> anyhow::Result<()> {
+        let mut last_frame = Instant::now() - FRAME_INTERVAL;
+        while !cancel.is_cancelled() {
+            if scope.ended {
+                std::thread::sleep(FRAME_INTERVAL);
+            } else {
+                match serial.read(&mut buf) {
+                    Ok(0) => scope.ended = true,
+                    Ok(len) => scope.push(chain.process(&buf[..len], io::decode_esp32_sample_f32)),
+                    Err(e) if matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::Interrupted) => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+
+            if last_frame.elapsed() >= FRAME_INTERVAL {
+                if !handle_keys(&mut scope)? {
+                    break;
+                }
+                scope.capture();
+                terminal.draw(|frame| scope.draw(frame))?;
+                last_frame = Instant::now();
+            }
+        }
+        Ok(())
+    })?;
+    result.output?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Shows the decoded signal on the terminal, like an oscilloscope.
+pub fn run_scope_command(args: &ScopeArgs) -> anyhow::Result<ExitCode> {
+    let mut terminal = ratatui::init();
+    let result = run(args, &mut terminal);
+    ratatui::restore();
+    result
+}
@@ -0,0 +1,84 @@
+use std::{
+    io::{BufRead, BufReader},
+    os::unix::net::UnixStream,
+    path::Path,
+    process::ExitCode,
+    time::Duration,
+};
+
+use clap::{Parser, ValueEnum};
+
+use crate::status;
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StatusFormat {
+    /// One line per running capture.
+    Text,
+    /// A single JSON object for waybar or polybar custom modules.
+    Waybar,
+}
+
+#[derive(Parser)]
+pub struct StatusArgs {
+    #[arg(short, long, default_value = "text")]
+    pub format: StatusFormat,
+}
+
+/// Reads the next status line of a capture, if it sends one in time.
+fn query_capture(path: &Path) -> Option<String> {
+    let stream = UnixStream::connect(path).ok()?;
+    stream.set_read_timeout(Some(Duration::from_secs(1))).ok()?;
+    BufReader::new(stream).lines().next()?.ok()
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+pub fn run_status_command(args: &StatusArgs) -> anyhow::Result<ExitCode> {
+    let captures: Vec<(u32, Option<String>)> = status::running_captures()?
+        .into_iter()
+        .map(|(pid, path)| (pid, query_capture(&path)))
+        .collect();
+    let lines: Vec<String> = captures
+        .iter()
+        .map(|(pid, line)| format!("{}: {}", pid, line.as_deref().unwrap_or("waiting for data")))
+        .collect();
+
+    match args.format {
+        StatusFormat::Text => {
+            if lines.is_empty() {
+                println!("No running capture");
+            }
+            for line in &lines {
+                println!("{}", line);
+            }
+        }
+        StatusFormat::Waybar => {
+            let (text, tooltip, class) = match captures.len() {
+                0 => (String::new(), "No running capture".into(), "idle"),
+                1 => ("REC".into(), lines.join("\n"), "recording"),
+                count => (format!("REC {}", count), lines.join("\n"), "recording"),
+            };
+            println!(
+                "{{\"text\":{},\"tooltip\":{},\"class\":{}}}",
+                json_string(&text),
+                json_string(&tooltip),
+                json_string(class)
+            );
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,635 @@
+use std::{
+    fmt::{self, Display},
+    io::{self, Read, Stdout, Write},
+    path::PathBuf,
+    process::ExitCode,
+    str::FromStr,
+    time::Duration,
+};
+#[cfg(feature = "network")]
+use std::{
+    fs,
+    net::{SocketAddr, SocketAddrV4},
+};
+
+use anyhow::anyhow;
+use clap::{Args, Parser};
+#[cfg(feature = "pulse")]
+use libpulse_binding::sample::{Format, Spec};
+use nix::libc::SIGINT;
+
+#[cfg(feature = "alsa")]
+use super::alsa_stream::AlsaSink;
+#[cfg(feature = "pulse")]
+use super::pulse_stream::{self, PulseSink};
+use super::{ChannelArgs, InputArgs, ProcessingArgs, WaveAmplitude};
+#[cfg(feature = "dsp")]
+use crate::dsp::shift::{FrequencyShift, Shifted};
+use crate::dsp::{effects::Effects, Staged};
+#[cfg(feature = "network")]
+use crate::sink::{
+    multicast::{MulticastCodec, MulticastSink},
+    rtp::{RtpOptions, RtpPayload, RtpSink},
+    tcp::TcpSink,
+};
+use crate::{
+    cancel::{self, CancellationScope},
+    dsp::Processor,
+    pipeline::{
+        self, DecodeUnsignedFullRange, DecodeUnsignedHalfRange, Reopen, SampleDecoder, SinkWriter,
+        StageTimes, Tee,
+    },
+    rate,
+    status::StatusLine,
+    wav::{self, FileOptions, OutputFile},
+};
+
+/// Where the samples of the stream go.
+#[derive(Clone)]
+pub enum OutputSpec {
+    Wav(PathBuf),
+    /// Played on the given Pulse sink, or on the default one.
+    #[cfg(feature = "pulse")]
+    Pulse(Option<String>),
+    /// Played on the given ALSA PCM device.
+    #[cfg(feature = "alsa")]
+    Alsa(String),
+    /// Raw PCM on the standard output.
+    Stdout,
+    /// Raw PCM served to the clients of a TCP port.
+    #[cfg(feature = "network")]
+    Tcp(SocketAddr),
+    /// Raw PCM sent to a multicast group.
+    #[cfg(feature = "network")]
+    Multicast(SocketAddrV4),
+    /// RTP stream sent to a host and port.
+    #[cfg(feature = "network")]
+    Rtp(String),
+}
+
+impl FromStr for OutputSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "-" || s == "stdout" {
+            return Ok(OutputSpec::Stdout);
+        }
+        match s.split_once(':').unwrap_or((s, "")) {
+            ("wav", path) if !path.is_empty() => Ok(OutputSpec::Wav(path.into())),
+            #[cfg(feature = "pulse")]
+            ("pulse", "") => Ok(OutputSpec::Pulse(None)),
+            #[cfg(feature = "pulse")]
+            ("pulse", device) => Ok(OutputSpec::Pulse(Some(device.into()))),
+            #[cfg(feature = "alsa")]
+            ("alsa", "") => Ok(OutputSpec::Alsa("default".into())),
+            #[cfg(feature = "alsa")]
+            ("alsa", device) => Ok(OutputSpec::Alsa(device.into())),
+            #[cfg(feature = "network")]
+            ("tcp", addr) => addr
+                .parse()
+                .map(OutputSpec::Tcp)
+                .map_err(|e| format!("Invalid address '{}': {}", addr, e)),
+            #[cfg(feature = "network")]
+            ("multicast", group) => group
+                .parse()
+                .map(OutputSpec::Multicast)
+                .map_err(|e| format!("Invalid group '{}': {}", group, e)),
+            #[cfg(feature = "network")]
+            ("rtp", destination) if !destination.is_empty() => Ok(OutputSpec::Rtp(
+                destination
+                    .strip_prefix("//")
+                    .unwrap_or(destination)
+                    .into(),
+            )),
+            _ => Err(format!(
+                "Invalid output '{}'. Expected wav:<path>, pulse[:<sink>], alsa[:<device>], stdout, tcp:<address>, multicast:<group> or rtp://<host>:<port>",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for OutputSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputSpec::Wav(path) => write!(f, "wav:{}", path.display()),
+            #[cfg(feature = "pulse")]
+            OutputSpec::Pulse(None) => f.write_str("pulse"),
+            #[cfg(feature = "pulse")]
+            OutputSpec::Pulse(Some(device)) => write!(f, "pulse:{}", device),
+            #[cfg(feature = "alsa")]
+            OutputSpec::Alsa(device) => write!(f, "alsa:{}", device),
+            OutputSpec::Stdout => f.write_str("stdout"),
+            #[cfg(feature = "network")]
+            OutputSpec::Tcp(addr) => write!(f, "tcp:{}", addr),
+            #[cfg(feature = "network")]
+            OutputSpec::Multicast(group) => write!(f, "multicast:{}", group),
+            #[cfg(feature = "network")]
+            OutputSpec::Rtp(destination) => write!(f, "rtp://{}", destination),
+        }
+    }
+}
+
+/// An output given with --out, along with whether the stream goes on
+/// without it if it fails and the effects only it gets.
+#[derive(Clone)]
+pub struct Output {
+    pub spec: OutputSpec,
+    pub required: bool,
+    pub effects: Option<Effects>,
+}
+
+impl From<OutputSpec> for Output {
+    fn from(spec: OutputSpec) -> Output {
+        Output {
+            spec,
+            required: true,
+            effects: None,
+        }
+    }
+}
+
+impl FromStr for Output {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut spec = s;
+        let mut required = true;
+        let mut effects = None;
+        // The options follow the spec, in any order.
+        while let Some((rest, option)) = spec.rsplit_once(',') {
+            match option.split_once('=') {
+                Some(("required", "true")) => required = true,
+                Some(("required", "false")) => required = false,
+                Some(("required", value)) => {
+                    return Err(format!(
+                        "Invalid value '{}' for required. Expected true or false",
+                        value
+                    ))
+                }
+                Some(("effects", value)) => effects = Some(value.parse()?),
+                _ => break,
+            }
+            spec = rest;
+        }
+        Ok(Output {
+            spec: spec.parse()?,
+            required,
+            effects,
+        })
+    }
+}
+
+impl Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.spec)?;
+        if let Some(effects) = &self.effects {
+            write!(f, ",effects={}", effects)?;
+        }
+        if !self.required {
+            f.write_str(",required=false")?;
+        }
+        Ok(())
+    }
+}
+
+/// Outputs of the stream commands, and the options of each kind of
+/// output.
+#[derive(Args)]
+pub struct OutputArgs {
+    /// Where the samples go, as wav:<path>, pulse[:<sink>],
+    /// alsa[:<device>], stdout, tcp:<address>, multicast:<group> or
+    /// rtp://<host>:<port>. Repeat it for sending them to several
+    /// outputs at once. An output ending in ,required=false can fail
+    /// without stopping the stream; the stream stops once no required
+    /// output is left. One ending in ,effects=<effects> gets the
+    /// stream through effects of its own, in the syntax of --effects,
+    /// like ,effects=lowpass 7000 rate 16k for sending a narrower
+    /// version of it over the network.
+    #[arg(long = "out", value_name = "OUTPUT")]
+    pub outputs: Vec<Output>,
+
+    /// Time-to-live of the packets of multicast outputs. Increase it
+    /// for reaching hosts behind routers.
+    #[cfg(feature = "network")]
+    #[arg(long, default_value_t = 1)]
+    pub ttl: u32,
+
+    /// Encoding of the samples sent to multicast outputs: pcm, raw
+    /// unsigned 8-bit samples, or opus, one packet per datagram.
+    #[cfg(feature = "network")]
+    #[arg(long, default_value = "pcm")]
+    pub codec: MulticastCodec,
+
+    #[cfg(feature = "network")]
+    #[command(flatten)]
+    pub rtp: RtpArgs,
+}
+
+/// How the stream is monitored live.
+#[derive(Args)]
+pub struct MonitorArgs {
+    #[cfg(feature = "dsp")]
+    /// Shift the signal into the audible range, as heterodyne:<hz> for
+    /// mixing it with a local oscillator or divide:<n> for dividing its
+    /// frequency. Only the outputs played live are shifted; WAV files,
+    /// --record and the standard output get the signal as captured.
+    #[arg(long, value_name = "MODE")]
+    pub monitor_shift: Option<FrequencyShift>,
+}
+
+impl MonitorArgs {
+    /// Wraps the sink of an output played live, shifting what it gets
+    /// if asked to.
+    pub fn monitor<'a>(
+        &self,
+        sink: Box<dyn SinkWriter<u8> + 'a>,
+        #[cfg_attr(not(feature = "dsp"), allow(unused_variables))] rate: u32,
+        #[cfg_attr(not(feature = "dsp"), allow(unused_variables))] channels: u16,
+    ) -> Box<dyn SinkWriter<u8> + 'a> {
+        #[cfg(feature = "dsp")]
+        if let Some(shift) = self.monitor_shift {
+            return Box::new(Shifted::new(sink, shift, rate, channels));
+        }
+        sink
+    }
+}
+
+#[derive(Parser)]
+pub struct StreamArgs {
+    #[command(flatten)]
+    pub input: InputArgs,
+
+    #[arg(short, long)]
+    pub sampling_rate: u32,
+
+    #[arg(short, long, default_value_t = WaveAmplitude::Full)]
+    pub wave_amplitude: WaveAmplitude,
+
+    #[command(flatten)]
+    pub monitor: MonitorArgs,
+
+    #[command(flatten)]
+    pub outputs: OutputArgs,
+
+    #[command(flatten)]
+    pub channels: ChannelArgs,
+
+    #[command(flatten)]
+    pub processing: ProcessingArgs,
+}
+
+#[cfg(feature = "network")]
+#[derive(Args)]
+pub struct RtpArgs {
+    /// Encoding of the samples sent to rtp outputs: l16, uncompressed,
+    /// or opus.
+    #[arg(long, value_name = "PAYLOAD", default_value = "l16")]
+    pub rtp_payload: RtpPayload,
+
+    /// Payload type of the RTP packets. The dynamic ones (96 to 127)
+    /// need the SDP of --sdp for being played.
+    #[arg(long, value_name = "TYPE", default_value_t = 96, value_parser = clap::value_parser!(u8).range(0..=127))]
+    pub rtp_payload_type: u8,
+
+    /// Audio carried by each RTP packet, in milliseconds. Opus only
+    /// takes 5, 10, 20, 40 or 60.
+    #[arg(long, value_name = "MS", default_value_t = 10, value_parser = clap::value_parser!(u64).range(1..))]
+    pub rtp_packet_duration: u64,
+
+    /// Write the session description of the rtp output to this file,
+    /// for playing it with VLC or GStreamer.
+    #[arg(long, value_name = "FILE")]
+    pub sdp: Option<PathBuf>,
+}
+
+/// A WAV file finished along with the stream.
+struct WavOutput(Option<OutputFile<u8>>);
+
+impl SinkWriter<u8> for WavOutput {
+    fn write_samples(&mut self, samples: &[u8]) -> anyhow::Result<()> {
+        match &mut self.0 {
+            Some(file) => file.write(samples),
+            None => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        match &mut self.0 {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        match self.0.take() {
+            Some(file) => file.finalize(),
+            None => Ok(()),
+        }
+    }
+}
+
+struct StdoutOutput {
+    stdout: Stdout,
+    written: u64,
+}
+
+impl SinkWriter<u8> for StdoutOutput {
+    fn write_samples(&mut self, samples: &[u8]) -> anyhow::Result<()> {
+        let mut stdout = self.stdout.lock();
+        stdout.write_all(samples)?;
+        // Flushed right away, as whatever reads the output may be
+        // playing it live.
+        stdout.flush()?;
+        self.written += samples.len() as u64;
+        Ok(())
+    }
+
+    fn bytes_written(&self) -> Option<u64> {
+        Some(self.written)
+    }
+}
+
+impl OutputSpec {
+    /// Whether the output is listened to as the samples arrive, as
+    /// opposed to WAV files and the standard output, which keep them
+    /// or pass them on to something else.
+    pub fn is_played(&self) -> bool {
+        !matches!(self, OutputSpec::Wav(_) | OutputSpec::Stdout)
+    }
+
+    /// Whether the output can be opened again after failing. Opening a
+    /// WAV file again would truncate it, and the standard output
+    /// doesn't come back once closed.
+    pub fn can_reopen(&self) -> bool {
+        !matches!(self, OutputSpec::Wav(_) | OutputSpec::Stdout)
+    }
+
+    /// Opens the output for samples of the given rate and channels.
+    pub fn open(
+        &self,
+        rate: u32,
+        channels: u16,
+        #[cfg_attr(not(feature = "network"), allow(unused_variables))] options: &OutputArgs,
+    ) -> anyhow::Result<Box<dyn SinkWriter<u8>>> {
+        Ok(match self {
+            OutputSpec::Wav(path) => {
+                let spec = wav::spec::<u8>(rate, channels);
+                Box::new(WavOutput(Some(OutputFile::create(
+                    path.clone(),
+                    spec,
+                    &FileOptions::default(),
+                )?)))
+            }
+            #[cfg(feature = "pulse")]
+            OutputSpec::Pulse(device) => {
+                let audio_spec = Spec {
+                    format: Format::U8,
+                    channels: channels as u8,
+                    rate,
+                };
+                let buffer_bytes = rate::samples_in(rate, pulse_stream::DEFAULT_BUFFER).max(1)
+                    as u32
+                    * channels as u32;
+                Box::new(PulseSink::connect(
+                    device.as_deref(),
+                    &audio_spec,
+                    buffer_bytes,
+                    false,
+                )?)
+            }
+            #[cfg(feature = "alsa")]
+            OutputSpec::Alsa(device) => Box::new(AlsaSink::open(device, rate, channels)?),
+            OutputSpec::Stdout => Box::new(StdoutOutput {
+                stdout: io::stdout(),
+                written: 0,
+            }),
+            #[cfg(feature = "network")]
+            OutputSpec::Tcp(addr) => Box::new(TcpSink::bind(*addr)?),
+            #[cfg(feature = "network")]
+            OutputSpec::Multicast(group) => Box::new(MulticastSink::new(
+                *group,
+                options.ttl,
+                options.codec,
+                rate,
+                channels,
+            )?),
+            #[cfg(feature = "network")]
+            OutputSpec::Rtp(destination) => {
+                let options = &options.rtp;
+                let rtp_options = RtpOptions {
+                    payload: options.rtp_payload,
+                    payload_type: options.rtp_payload_type,
+                    packet_duration: Duration::from_millis(options.rtp_packet_duration),
+                };
+                let sink = RtpSink::connect(destination, rate, channels, rtp_options)?;
+                if let Some(path) = &options.sdp {
+                    fs::write(path, sink.sdp()?)?;
+                }
+                Box::new(sink)
+            }
+        })
+    }
+}
+
+/// An output along with its sink, once open.
+pub type OpenOutput<'a> = (Output, Box<dyn SinkWriter<u8> + 'a>);
+
+impl OutputArgs {
+    /// Checks the given outputs can be used together.
+    pub fn check(&self, outputs: &[Output]) -> anyhow::Result<()> {
+        let count =
+            |f: fn(&OutputSpec) -> bool| outputs.iter().filter(|output| f(&output.spec)).count();
+        if count(|spec| matches!(spec, OutputSpec::Stdout)) > 1 {
+            return Err(anyhow!("The standard output can only be used once"));
+        }
+        #[cfg(feature = "network")]
+        if self.rtp.sdp.is_some() && count(|spec| matches!(spec, OutputSpec::Rtp(_))) != 1 {
+            return Err(anyhow!("--sdp needs a single rtp output to describe"));
+        }
+        Ok(())
+    }
+
+    /// Opens the given outputs, for samples of the given rate and
+    /// channels. The ones that can be opened again are reconnected
+    /// when they fail.
+    pub fn open<'a>(
+        &'a self,
+        outputs: &[Output],
+        rate: u32,
+        channels: u16,
+        monitor: &'a MonitorArgs,
+    ) -> anyhow::Result<Vec<OpenOutput<'a>>> {
+        outputs
+            .iter()
+            .map(|output| {
+                tracing::info!("Streaming to {}", output.spec);
+                // The stages are kept across reconnections, so they go
+                // on from where they were.
+                let (stages, rate) = match &output.effects {
+                    Some(_) if channels > 1 => {
+                        return Err(anyhow!(
+                            "The effects of output {} need a single channel",
+                            output.spec
+                        ))
+                    }
+                    Some(effects) => super::effect_stages(&effects.0, rate)
+                        .map_err(|e| e.context(format!("Invalid effects of {}", output.spec)))?,
+                    None => (Vec::new(), rate),
+                };
+                let spec = output.spec.clone();
+                let open = move || {
+                    let sink = spec.open(rate, channels, self)?;
+                    Ok(if spec.is_played() {
+                        monitor.monitor(sink, rate, channels)
+                    } else {
+                        sink
+                    })
+                };
+                let mut sink: Box<dyn SinkWriter<u8> + 'a> = if output.spec.can_reopen() {
+                    Box::new(Reopen::new(open)?)
+                } else {
+                    open()?
+                };
+                if !stages.is_empty() {
+                    sink = Box::new(Staged::new(sink, stages));
+                }
+                Ok((output.clone(), sink))
+            })
+            .collect()
+    }
+}
+
+/// Hands the samples on to all the opened outputs.
+fn tee<'a>(outputs: &'a mut [OpenOutput<'_>]) -> Tee<'a, u8> {
+    let mut tee = Tee::new();
+    for (output, sink) in outputs {
+        tee.push(&output.spec, sink.as_mut(), output.required);
+    }
+    tee
+}
+
+/// What the stream commands read and how they process it, whatever
+/// their outputs are.
+pub struct StreamInput<'a> {
+    pub input: &'a InputArgs,
+    pub sampling_rate: u32,
+    pub wave_amplitude: WaveAmplitude,
+    pub channels: &'a ChannelArgs,
+    pub processing: &'a ProcessingArgs,
+    pub monitor: &'a MonitorArgs,
+}
+
+impl StreamInput<'_> {
+    pub fn output_rate(&self) -> u32 {
+        self.processing.output_rate(self.sampling_rate)
+    }
+
+    /// Processor of the decoded samples.
+    pub fn processor(&self) -> anyhow::Result<Box<dyn Processor<u8>>> {
+        self.channels
+            .processor(|| self.processing.chain(self.sampling_rate))
+    }
+}
+
+fn stream_samples<R: Read + Send, D: SampleDecoder<Sample = u8>>(
+    input: &mut R,
+    stream: &StreamInput,
+    scope: &CancellationScope,
+    chain: &mut dyn Processor<u8>,
+    status: &mut StatusLine,
+    sink: &mut Tee<u8>,
+) -> anyhow::Result<StageTimes> {
+    let link_rate = stream.channels.link_rate(stream.sampling_rate);
+    // Same as with pulse, approx 50 msecs of data on each iteration,
+    // with a minimum of 32 bytes.
+    let buf_size = stream.channels.chunk_len(
+        stream
+            .input
+            .chunk_len(link_rate, usize::max(rate::bytes_per(link_rate, 20), 32)),
+    );
+
+    pipeline::stream::<D, _, _, _>(
+        input,
+        chain,
+        sink,
+        buf_size,
+        stream.input.flush_interval(),
+        |samples, total_samples| {
+            status.preview(samples);
+            status.update(total_samples, link_rate);
+            !scope.is_cancelled()
+        },
+    )
+}
+
+/// Streams the input to the given outputs. The input is opened first,
+/// so nothing is created or bound if it can't be read.
+pub fn run_stream(
+    stream: &StreamInput,
+    outputs: &[Output],
+    options: &OutputArgs,
+) -> anyhow::Result<ExitCode> {
+    if outputs.is_empty() {
+        return Err(anyhow!("At least one --out is required"));
+    }
+    options.check(outputs)?;
+
+    let link_rate = stream.channels.link_rate(stream.sampling_rate);
+    let mut serial = stream.input.open(link_rate, Duration::from_secs(1))?;
+    let mut chain = stream.processor()?;
+    let output_rate = stream.output_rate();
+    let mut status = stream.channels.status_line(stream.input, output_rate)?;
+    let mut outputs = options.open(
+        outputs,
+        output_rate,
+        stream.channels.channels,
+        stream.monitor,
+    )?;
+    let mut sink = tee(&mut outputs);
+
+    let result = cancel::with_scope(|scope| match stream.wave_amplitude {
+        WaveAmplitude::Full => stream_samples::<_, DecodeUnsignedFullRange>(
+            &mut serial,
+            stream,
+            scope,
+            &mut *chain,
+            &mut status,
+            &mut sink,
+        ),
+        WaveAmplitude::Half => stream_samples::<_, DecodeUnsignedHalfRange>(
+            &mut serial,
+            stream,
+            scope,
+            &mut *chain,
+            &mut status,
+            &mut sink,
+        ),
+    })?;
+
+    // The outputs are finished even if the stream failed, keeping
+    // what was written up to then.
+    if result.output.is_err() {
+        let _ = sink.finish();
+    }
+    status.finish(result.output.is_ok());
+    let times = result.output?;
+    stream.input.report_stats(&times);
+    Ok(if result.interrupted {
+        ExitCode::from(128 + SIGINT as u8)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+pub fn run_stream_command(args: &StreamArgs) -> anyhow::Result<ExitCode> {
+    let stream = StreamInput {
+        input: &args.input,
+        sampling_rate: args.sampling_rate,
+        wave_amplitude: args.wave_amplitude.clone(),
+        channels: &args.channels,
+        processing: &args.processing,
+        monitor: &args.monitor,
+    };
+    run_stream(&stream, &args.outputs.outputs, &args.outputs)
+}
@@ -0,0 +1,213 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    path::Path,
+    process::{Child, Command, ExitCode},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context as _};
+use clap::Parser;
+use nix::{
+    libc::SIGINT,
+    sys::signal::{kill, Signal},
+    unistd::Pid,
+};
+use serialport::SerialPortType;
+
+use crate::{cancel, config, source::usb, status};
+
+/// Interval of the checks for boards plugged or unplugged.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Wait before starting the daemon of a board again after it exited
+/// while the board was still plugged.
+const RESTART_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Parser)]
+pub struct SuperviseArgs {
+    /// Only the boards with the given USB vendor and product ID, like
+    /// 10c4:ea60, instead of any that looks like an ESP32 board.
+    #[arg(long, value_name = "VID:PID")]
+    pub usb: Option<String>,
+
+    /// Arguments of the daemon of each board, besides --port and
+    /// --socket, after --. {device} is replaced by the name of the
+    /// board: its USB serial number, or the name of its port if it has
+    /// none.
+    #[arg(last = true, required = true, value_name = "DAEMON ARGS")]
+    pub daemon_args: Vec<String>,
+}
+
+/// A board plugged in, and the name its daemon is known by.
+struct Board {
+    port: String,
+    name: String,
+}
+
+fn plugged_boards(usb_id: Option<(u16, u16)>) -> anyhow::Result<Vec<Board>> {
+    let ports = match usb_id {
+        Some(_) => serialport::available_ports()?,
+        None => usb::esp32_ports()?,
+    };
+    Ok(ports
+        .into_iter()
+        .filter_map(|info| {
+            let usb = match info.port_type {
+                SerialPortType::UsbPort(usb) => usb,
+                _ => return None,
+            };
+            if usb_id.is_some_and(|(vid, pid)| (usb.vid, usb.pid) != (vid, pid)) {
+                return None;
+            }
+            // Used in file names, so anything else is left out.
+            let name = match usb.serial_number {
+                Some(serial) => serial
+                    .chars()
+                    .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+                    .collect(),
+                None => Path::new(&info.port_name)
+                    .file_name()?
+                    .to_string_lossy()
+                    .into_owned(),
+            };
+            Some(Board {
+                port: info.port_name,
+                name,
+            })
+        })
+        .collect())
+}
+
+/// A daemon started for a board.
+struct Daemon {
+    port: String,
+    child: Child,
+}
+
+impl Daemon {
+    fn spawn(board: &Board, daemon_args: &[String]) -> anyhow::Result<Daemon> {
+        let socket = status::socket_dir().join(format!("daemon-{}.sock", board.name));
+        let mut command = Command::new(env::current_exe()?);
+        if let Some(profile) = config::selected_profile(env::args_os()) {
+            command.arg("--profile").arg(profile);
+        }
+        command
+            .arg("daemon")
+            .arg("--port")
+            .arg(&board.port)
+            .arg("--socket")
+            .arg(&socket)
+            .args(
+                daemon_args
+                    .iter()
+                    .map(|arg| arg.replace("{device}", &board.name)),
+            );
+        let child = command
+            .spawn()
+            .with_context(|| format!("Unable to start the daemon of {}", board.port))?;
+        tracing::info!(
+            "Started the daemon of {} ({}), listening on {}",
+            board.port,
+            board.name,
+            socket.display()
+        );
+        Ok(Daemon {
+            port: board.port.clone(),
+            child,
+        })
+    }
+
+    /// Stops the daemon as Ctrl+C would, so it finishes its recording,
+    /// and waits for it.
+    fn stop(mut self) {
+        if let Ok(None) = self.child.try_wait() {
+            let _ = kill(Pid::from_raw(self.child.id() as i32), Signal::SIGINT);
+        }
+        let _ = self.child.wait();
+    }
+}
+
+/// Runs a daemon for every board plugged in, started as boards are
+/// plugged and stopped as they're unplugged.
+pub fn run_supervise_command(args: &SuperviseArgs) -> anyhow::Result<ExitCode> {
+    let usb_id = args
+        .usb
+        .as_deref()
+        .map(|id| {
+            usb::parse_usb_id(id).map_err(|_| anyhow!("Expected --usb <vid>:<pid>, like 10c4:ea60"))
+        })
+        .transpose()?;
+    let mut daemons: BTreeMap<String, Daemon> = BTreeMap::new();
+    let mut exited: BTreeMap<String, Instant> = BTreeMap::new();
+
+    let result = cancel::with_scope(|scope| -> anyhow::Result<()> {
+        while !scope.is_cancelled() {
+            let boards = match plugged_boards(usb_id) {
+                Ok(boards) => boards,
+                Err(e) => {
+                    tracing::warn!("Unable to list the serial ports: {:#}", e);
+                    thread::sleep(POLL_INTERVAL);
+                    continue;
+                }
+            };
+
+            let unplugged: Vec<String> = daemons
+                .iter()
+                .filter(|(name, daemon)| {
+                    !boards
+                        .iter()
+                        .any(|board| &board.name == *name && board.port == daemon.port)
+                })
+                .map(|(name, _)| name.clone())
+                .collect();
+            for name in unplugged {
+                let daemon = daemons.remove(&name).unwrap();
+                tracing::info!("{} ({}) unplugged, stopping its daemon", daemon.port, name);
+                daemon.stop();
+            }
+
+            for (name, daemon) in &mut daemons {
+                if let Some(status) = daemon.child.try_wait()? {
+                    tracing::warn!(
+                        "The daemon of {} ({}) exited ({}), starting it again in {:?}",
+                        daemon.port,
+                        name,
+                        status,
+                        RESTART_DELAY
+                    );
+                    exited.insert(name.clone(), Instant::now());
+                }
+            }
+            daemons.retain(|name, _| !exited.contains_key(name));
+            exited.retain(|_, at| at.elapsed() < RESTART_DELAY);
+
+            for board in &boards {
+                if daemons.contains_key(&board.name) || exited.contains_key(&board.name) {
+                    continue;
+                }
+                match Daemon::spawn(board, &args.daemon_args) {
+                    Ok(daemon) => {
+                        daemons.insert(board.name.clone(), daemon);
+                    }
+                    Err(e) => {
+                        tracing::error!("{:#}", e);
+                        exited.insert(board.name.clone(), Instant::now());
+                    }
+                }
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        Ok(())
+    })?;
+
+    for (_, daemon) in std::mem::take(&mut daemons) {
+        daemon.stop();
+    }
+    result.output?;
+    Ok(if result.interrupted {
+        ExitCode::from(128 + SIGINT as u8)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
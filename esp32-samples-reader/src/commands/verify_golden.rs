@@ -0,0 +1,351 @@
+use std::{
+    fmt::Write as _,
+    fs,
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context as _};
+use clap::{Parser, ValueEnum};
+use toml::{Table, Value};
+
+use super::{ChannelArgs, ProcessingArgs};
+use crate::{
+    dsp::I24,
+    io::{BitOrder, NormalizedInput},
+    pipeline::{
+        self, DecodeF32, DecodeSigned, DecodeSigned16, DecodeSigned24, DecodeUnsignedFullRange,
+        DecodeUnsignedHalfRange, SampleDecoder, SinkWriter,
+    },
+    source::framed::Deframer,
+};
+
+/// Samples per line of the golden files.
+const SAMPLES_PER_LINE: usize = 16;
+/// Bytes decoded at once, a multiple of any number of channels the
+/// cases use.
+const CHUNK_LEN: usize = 64;
+
+#[derive(Parser)]
+pub struct VerifyGoldenArgs {
+    /// Directory holding cases.toml, the inputs and the golden files.
+    #[arg(long, default_value = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata"))]
+    pub dir: PathBuf,
+
+    /// Overwrite the golden files with the current output, instead of
+    /// comparing them.
+    #[arg(long)]
+    pub bless: bool,
+
+    /// Only run the cases whose name contains the given text.
+    pub filter: Option<String>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum GoldenFormat {
+    S8,
+    U8,
+    U8Half,
+    S16,
+    S24,
+    F32,
+}
+
+/// Decoding options of a case, given with the same flags as the
+/// recording commands.
+#[derive(Parser)]
+#[command(no_binary_name = true)]
+struct CaseArgs {
+    #[arg(short, long, default_value_t = 8000)]
+    sampling_rate: u32,
+
+    #[arg(long, default_value = "s8")]
+    format: GoldenFormat,
+
+    #[arg(long, default_value = "msb")]
+    bit_order: BitOrder,
+
+    #[arg(long)]
+    invert: bool,
+
+    #[arg(long)]
+    framed: bool,
+
+    #[command(flatten)]
+    channels: ChannelArgs,
+
+    #[command(flatten)]
+    processing: ProcessingArgs,
+}
+
+struct Case {
+    name: String,
+    input: String,
+    args: Vec<String>,
+    /// Features of the build the case needs.
+    requires: Vec<String>,
+}
+
+/// Sample written to the golden files, as text so changes show up in
+/// diffs.
+trait GoldenSample: Copy {
+    fn render(self) -> String;
+}
+
+impl GoldenSample for i8 {
+    fn render(self) -> String {
+        self.to_string()
+    }
+}
+
+impl GoldenSample for u8 {
+    fn render(self) -> String {
+        self.to_string()
+    }
+}
+
+impl GoldenSample for i16 {
+    fn render(self) -> String {
+        self.to_string()
+    }
+}
+
+impl GoldenSample for I24 {
+    fn render(self) -> String {
+        self.0.to_string()
+    }
+}
+
+impl GoldenSample for f32 {
+    fn render(self) -> String {
+        self.to_string()
+    }
+}
+
+struct Collect<S>(Vec<S>);
+
+impl<S: Copy> SinkWriter<S> for Collect<S> {
+    fn write_samples(&mut self, samples: &[S]) -> anyhow::Result<()> {
+        self.0.extend_from_slice(samples);
+        Ok(())
+    }
+}
+
+fn feature_enabled(feature: &str) -> bool {
+    (feature == "dsp" && cfg!(feature = "dsp"))
+        || (feature == "analysis" && cfg!(feature = "analysis"))
+}
+
+fn string_list(table: &Table, key: &str) -> anyhow::Result<Vec<String>> {
+    match table.get(key) {
+        None => Ok(Vec::new()),
+        Some(Value::Array(values)) => values
+            .iter()
+            .map(|value| match value {
+                Value::String(s) => Ok(s.clone()),
+                _ => Err(anyhow!("Values of '{}' must be strings", key)),
+            })
+            .collect(),
+        Some(_) => Err(anyhow!("'{}' must be an array of strings", key)),
+    }
+}
+
+fn load_cases(path: &Path) -> anyhow::Result<Vec<Case>> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Unable to read {}", path.display()))?;
+    let table: Table = content
+        .parse()
+        .with_context(|| format!("Invalid cases file {}", path.display()))?;
+    let cases = match table.get("case") {
+        Some(Value::Array(cases)) => cases,
+        _ => return Err(anyhow!("{} has no [[case]] entries", path.display())),
+    };
+
+    cases
+        .iter()
+        .map(|case| {
+            let case = case
+                .as_table()
+                .ok_or_else(|| anyhow!("Cases must be tables"))?;
+            let string = |key: &str| {
+                case.get(key)
+                    .and_then(Value::as_str)
+                    .map(String::from)
+                    .ok_or_else(|| anyhow!("A case is missing its '{}'", key))
+            };
+            Ok(Case {
+                name: string("name")?,
+                input: string("input")?,
+                args: string_list(case, "args")?,
+                requires: string_list(case, "requires")?,
+            })
+        })
+        .collect()
+}
+
+fn decode_as<D: SampleDecoder>(args: &CaseArgs, input: &[u8]) -> anyhow::Result<String>
+where
+    D::Sample: GoldenSample + 'static,
+{
+    let input: Box<dyn Read + Send + '_> = if args.framed {
+        Box::new(Deframer::new(Cursor::new(input)))
+    } else {
+        Box::new(Cursor::new(input))
+    };
+    let mut source = NormalizedInput::new(input, args.bit_order, args.invert);
+    let mut chain = args
+        .channels
+        .processor(|| args.processing.chain(args.sampling_rate))?;
+    let mut sink = Collect(Vec::new());
+    pipeline::stream::<D, _, _, _>(
+        &mut source,
+        &mut *chain,
+        &mut sink,
+        args.channels.chunk_len(CHUNK_LEN),
+        Duration::MAX,
+        |_, _| true,
+    )?;
+
+    let mut output = String::new();
+    for line in sink.0.chunks(SAMPLES_PER_LINE) {
+        let line: Vec<String> = line.iter().map(|sample| sample.render()).collect();
+        writeln!(output, "{}", line.join(" "))?;
+    }
+    Ok(output)
+}
+
+/// Decodes the input of a case, rendered as the content of its golden
+/// file.
+fn render_case(case: &Case, dir: &Path) -> anyhow::Result<String> {
+    let args = CaseArgs::try_parse_from(&case.args)?;
+    let input = fs::read(dir.join(&case.input))
+        .with_context(|| format!("Unable to read input {}", case.input))?;
+
+    let samples = match args.format {
+        GoldenFormat::S8 => decode_as::<DecodeSigned>(&args, &input)?,
+        GoldenFormat::U8 => decode_as::<DecodeUnsignedFullRange>(&args, &input)?,
+        GoldenFormat::U8Half => decode_as::<DecodeUnsignedHalfRange>(&args, &input)?,
+        GoldenFormat::S16 => decode_as::<DecodeSigned16>(&args, &input)?,
+        GoldenFormat::S24 => decode_as::<DecodeSigned24>(&args, &input)?,
+        GoldenFormat::F32 => decode_as::<DecodeF32>(&args, &input)?,
+    };
+    Ok(format!(
+        "# {} {}\n{}",
+        case.input,
+        case.args.join(" "),
+        samples
+    ))
+}
+
+/// Describes the first difference between the expected and the actual
+/// output.
+fn first_difference(expected: &str, actual: &str) -> String {
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    let mut line = 1;
+    loop {
+        match (expected_lines.next(), actual_lines.next()) {
+            (Some(e), Some(a)) if e == a => line += 1,
+            (Some(e), Some(a)) => {
+                return format!(
+                    "line {} differs:\n  expected: {}\n  actual:   {}",
+                    line, e, a
+                )
+            }
+            (Some(_), None) => return format!("output ends early, at line {}", line),
+            (None, Some(_)) => return format!("output has extra lines, from line {}", line),
+            (None, None) => return "outputs only differ in whitespace".into(),
+        }
+    }
+}
+
+/// Decodes every input of the test data with the options of its case,
+/// comparing the result with the golden file of the case, or updating
+/// it with --bless.
+pub fn run_verify_golden_command(args: &VerifyGoldenArgs) -> anyhow::Result<ExitCode> {
+    let cases = load_cases(&args.dir.join("cases.toml"))?;
+    if let Some(filter) = &args.filter {
+        if !cases.iter().any(|case| case.name.contains(filter.as_str())) {
+            return Err(anyhow!("No case matches '{}'", filter));
+        }
+    }
+    let golden_dir = args.dir.join("golden");
+    if args.bless {
+        fs::create_dir_all(&golden_dir)?;
+    }
+
+    let (mut passed, mut failed, mut skipped) = (0, 0, 0);
+    for case in &cases {
+        if let Some(filter) = &args.filter {
+            if !case.name.contains(filter.as_str()) {
+                continue;
+            }
+        }
+        if let Some(feature) = case.requires.iter().find(|f| !feature_enabled(f)) {
+            println!("skipped {} (requires the {} feature)", case.name, feature);
+            skipped += 1;
+            continue;
+        }
+
+        let actual = match render_case(case, &args.dir) {
+            Ok(actual) => actual,
+            Err(e) => {
+                println!("FAILED {}: {:#}", case.name, e);
+                failed += 1;
+                continue;
+            }
+        };
+        let path = golden_dir.join(format!("{}.txt", case.name));
+        if args.bless {
+            fs::write(&path, actual)?;
+            println!("updated {}", case.name);
+            passed += 1;
+            continue;
+        }
+
+        match fs::read_to_string(&path) {
+            Ok(expected) if expected == actual => {
+                println!("ok {}", case.name);
+                passed += 1;
+            }
+            Ok(expected) => {
+                println!(
+                    "FAILED {}: {}",
+                    case.name,
+                    first_difference(&expected, &actual)
+                );
+                failed += 1;
+            }
+            Err(e) => {
+                println!(
+                    "FAILED {}: unable to read {}: {}",
+                    case.name,
+                    path.display(),
+                    e
+                );
+                failed += 1;
+            }
+        }
+    }
+
+    println!();
+    println!("{} passed, {} failed, {} skipped", passed, failed, skipped);
+    Ok(if failed == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_case_matches_its_golden_file() {
+        let args = VerifyGoldenArgs::try_parse_from(["verify-golden"]).unwrap();
+        assert_eq!(run_verify_golden_command(&args).unwrap(), ExitCode::SUCCESS);
+    }
+}
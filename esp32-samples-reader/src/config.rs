@@ -0,0 +1,479 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env,
+    ffi::OsString,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Context as _};
+use clap::{builder::BoolishValueParser, Arg, ArgAction, Command};
+use toml::{Table, Value};
+
+pub const SYSTEM_CONFIG_PATH: &str = "/etc/esp32-signal-reader/config.toml";
+pub const PROJECT_CONFIG_NAME: &str = ".esp32reader.toml";
+/// Prefix of the environment variables setting the flags, followed by
+/// the long name of the flag in upper case, like ESP32SR_BAUD_RATE.
+pub const ENV_PREFIX: &str = "ESP32SR_";
+
+#[derive(Clone)]
+pub struct ConfigValue {
+    pub value: Value,
+    pub origin: PathBuf,
+    /// Section the value was taken from, like `profile.<name>`, if
+    /// it's not from the plain tables of the file.
+    pub section: Option<String>,
+}
+
+impl ConfigValue {
+    /// Where the value comes from, for showing it to the user.
+    pub fn source(&self) -> String {
+        match &self.section {
+            Some(section) => format!("[{}] in {}", section, self.origin.display()),
+            None => self.origin.display().to_string(),
+        }
+    }
+}
+
+/// Configuration merged from the config files of the system, the user
+/// and the current project, in that order, each one overriding the
+/// values of the previous ones. Command line flags override all of
+/// them.
+///
+/// Top level keys are named after the long flag they set, and apply
+/// to every command having that flag. Keys inside a table named after
+/// a command (like `[read-wav]` or `[analyze.tones]`) only apply to
+/// that command.
+///
+/// Values inside a `[profile.<name>]` table only apply when that
+/// profile is picked with --profile, and take precedence over the rest,
+/// even over the command tables outside of the profile. Values inside
+/// a `[device.<serial>]` table only apply when the port in use belongs
+/// to the USB device with that serial number, and take precedence over
+/// the rest, profiles included.
+#[derive(Clone, Default)]
+pub struct Config {
+    pub files: Vec<(PathBuf, bool)>,
+    pub values: Values,
+    /// Sections picked for this run, like `profile.<name>`, with their
+    /// values keyed by command. Each one overrides `values` and the
+    /// sections before it.
+    pub sections: Vec<(String, Values)>,
+}
+
+/// Values keyed by command (empty for the top level ones) and flag
+/// name.
+pub type Values = BTreeMap<String, BTreeMap<String, ConfigValue>>;
+
+/// Profile picked in the given command line arguments, which has to
+/// be known before parsing them with the values of the config.
+pub fn selected_profile(args: impl IntoIterator<Item = OsString>) -> Option<String> {
+    let mut args = args.into_iter().skip(1);
+    let mut profile = None;
+    while let Some(arg) = args.next() {
+        let arg = arg.to_string_lossy();
+        if arg == "--" {
+            break;
+        } else if arg == "--profile" {
+            profile = args.next().map(|name| name.to_string_lossy().into_owned());
+        } else if let Some(name) = arg.strip_prefix("--profile=") {
+            profile = Some(name.to_string());
+        }
+    }
+    profile.or_else(|| env::var(env_name("profile")).ok())
+}
+
+fn env_name(long: &str) -> String {
+    format!("{}{}", ENV_PREFIX, long.to_uppercase().replace('-', "_"))
+}
+
+/// Flags set through the environment, keyed by their long name, along
+/// with the variable setting them.
+pub fn env_values() -> BTreeMap<String, (String, String)> {
+    env::vars()
+        .filter_map(|(name, value)| {
+            let flag = name
+                .strip_prefix(ENV_PREFIX)?
+                .to_lowercase()
+                .replace('_', "-");
+            Some((flag, (name, value)))
+        })
+        .collect()
+}
+
+/// Lets every flag of the command and its subcommands be set through
+/// an environment variable, which overrides the config files but not
+/// the command line.
+pub fn apply_env(mut command: Command) -> Command {
+    let ids: Vec<(String, String)> = command
+        .get_arguments()
+        .filter(|arg| !matches!(arg.get_action(), ArgAction::Help | ArgAction::Version))
+        .filter_map(|arg| Some((arg.get_id().to_string(), env_name(arg.get_long()?))))
+        .collect();
+    for (id, name) in ids {
+        command = command.mut_arg(id, |arg| {
+            // Switches set through the environment take the usual ways
+            // of saying yes or no, like 1 or off, besides true and false.
+            if matches!(arg.get_action(), ArgAction::SetTrue | ArgAction::SetFalse) {
+                arg.env(name).value_parser(BoolishValueParser::new())
+            } else {
+                arg.env(name)
+            }
+        });
+    }
+
+    let subcommands: Vec<String> = command
+        .get_subcommands()
+        .map(|subcommand| subcommand.get_name().to_string())
+        .collect();
+    for name in subcommands {
+        command = command.mut_subcommand(name, apply_env);
+    }
+    command
+}
+
+pub fn user_config_path() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .map(|dir| dir.join("esp32-signal-reader").join("config.toml"))
+}
+
+/// Looks for a project config file in the current directory and its
+/// parents.
+pub fn project_config_path() -> Option<PathBuf> {
+    let cwd = env::current_dir().ok()?;
+    cwd.ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_NAME))
+        .find(|path| path.is_file())
+}
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+impl Config {
+    pub fn load() -> anyhow::Result<Config> {
+        Config::load_from(
+            [
+                Some(PathBuf::from(SYSTEM_CONFIG_PATH)),
+                user_config_path(),
+                project_config_path(),
+            ]
+            .into_iter()
+            .flatten(),
+        )
+    }
+
+    /// Loads the given files, from lowest to highest priority. Missing
+    /// ones are skipped.
+    pub fn load_from(paths: impl IntoIterator<Item = PathBuf>) -> anyhow::Result<Config> {
+        let mut config = Config::default();
+        for path in paths {
+            let loaded = config.load_file(&path)?;
+            config.files.push((path, loaded));
+        }
+        Ok(config)
+    }
+
+    fn load_file(&mut self, path: &Path) -> anyhow::Result<bool> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e).with_context(|| format!("Unable to read {}", path.display())),
+        };
+
+        let table = content
+            .parse::<Table>()
+            .with_context(|| format!("Invalid config file {}", path.display()))?;
+        self.merge("", table, path);
+        Ok(true)
+    }
+
+    fn merge(&mut self, command: &str, table: Table, origin: &Path) {
+        for (key, value) in table {
+            match value {
+                Value::Table(table) => {
+                    let command = if command.is_empty() {
+                        key
+                    } else {
+                        format!("{}.{}", command, key)
+                    };
+                    self.merge(&command, table, origin);
+                }
+                value => {
+                    self.values.entry(command.into()).or_default().insert(
+                        key,
+                        ConfigValue {
+                            value,
+                            origin: origin.into(),
+                            section: None,
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    /// Returns the values applying to the given command, keyed by flag
+    /// name. Global values go first, then the ones of each enclosing
+    /// command table, so the most specific ones win. The values of the
+    /// picked sections go last, in the same order.
+    fn command_values(&self, command: &str) -> BTreeMap<&str, &ConfigValue> {
+        let mut scopes = vec![String::new()];
+        let mut scope = String::new();
+        for name in command.split('.').filter(|name| !name.is_empty()) {
+            if !scope.is_empty() {
+                scope.push('.');
+            }
+            scope.push_str(name);
+            scopes.push(scope.clone());
+        }
+
+        let mut values = BTreeMap::new();
+        let layers = std::iter::once(&self.values).chain(self.sections.iter().map(|(_, v)| v));
+        for layer in layers {
+            for scope in &scopes {
+                if let Some(table) = layer.get(scope) {
+                    for (key, value) in table {
+                        values.insert(key.as_str(), value);
+                    }
+                }
+            }
+        }
+        values
+    }
+
+    fn apply_to_arg(arg: Arg, value: &ConfigValue) -> Arg {
+        let values: Vec<String> = match &value.value {
+            Value::Array(items) => items.iter().map(value_to_string).collect(),
+            value => vec![value_to_string(value)],
+        };
+        arg.default_values(values).required(false)
+    }
+
+    fn apply_to_command(&self, mut command: Command, path: &str) -> Command {
+        let values = self.command_values(path);
+        let ids: Vec<(String, &ConfigValue)> = command
+            .get_arguments()
+            .filter_map(|arg| {
+                let value = values.get(arg.get_long()?)?;
+                Some((arg.get_id().to_string(), *value))
+            })
+            .collect();
+
+        for (id, value) in ids {
+            command = command.mut_arg(id, |arg| Self::apply_to_arg(arg, value));
+        }
+
+        let subcommands: Vec<String> = command
+            .get_subcommands()
+            .map(|subcommand| subcommand.get_name().to_string())
+            .collect();
+        for name in subcommands {
+            let sub_path = if path.is_empty() {
+                name.clone()
+            } else {
+                format!("{}.{}", path, name)
+            };
+            command = command.mut_subcommand(name, |subcommand| {
+                self.apply_to_command(subcommand, &sub_path)
+            });
+        }
+        command
+    }
+
+    /// Returns the config with the values of the `[<section>.<name>]`
+    /// table and its command tables on top, if there's any.
+    fn with_section(&self, section: &str, name: &str) -> Option<Config> {
+        let prefix = format!("{}.{}", section, name);
+        let mut values = Values::new();
+        let mut found = false;
+
+        for (scope, table) in &self.values {
+            let command = match scope.strip_prefix(&prefix) {
+                Some("") => "",
+                Some(rest) => match rest.strip_prefix('.') {
+                    Some(command) => command,
+                    None => continue,
+                },
+                None => continue,
+            };
+
+            found = true;
+            let table = table
+                .iter()
+                .map(|(key, value)| {
+                    let value = ConfigValue {
+                        section: Some(prefix.clone()),
+                        ..value.clone()
+                    };
+                    (key.clone(), value)
+                })
+                .collect();
+            values.insert(command.to_string(), table);
+        }
+        found.then(|| {
+            let mut config = self.clone();
+            config.sections.push((prefix, values));
+            config
+        })
+    }
+
+    /// Returns the config with the values bound to the given USB
+    /// serial number on top, if there's any.
+    pub fn for_device(&self, serial: &str) -> Option<Config> {
+        self.with_section("device", serial)
+    }
+
+    /// Names of the profiles defined in the config files.
+    pub fn profiles(&self) -> BTreeSet<&str> {
+        self.values
+            .keys()
+            .filter_map(|scope| scope.strip_prefix("profile."))
+            .map(|rest| rest.split('.').next().unwrap_or(rest))
+            .collect()
+    }
+
+    /// Returns the config with the values of the given profile on top.
+    pub fn for_profile(&self, name: &str) -> anyhow::Result<Config> {
+        self.with_section("profile", name).ok_or_else(|| {
+            let profiles = self.profiles();
+            if profiles.is_empty() {
+                anyhow!("Unknown profile '{}': the config files define none", name)
+            } else {
+                anyhow!(
+                    "Unknown profile '{}'. The config files define: {}",
+                    name,
+                    profiles.into_iter().collect::<Vec<_>>().join(", ")
+                )
+            }
+        })
+    }
+
+    /// Returns the values in effect for each command table, after
+    /// applying the picked sections over the rest. Only the keys set in
+    /// a table (or in the same table of a section) are listed for it,
+    /// with the value that wins for that command.
+    pub fn effective_values(&self) -> Values {
+        let is_section =
+            |scope: &str| scope.starts_with("profile.") || scope.starts_with("device.");
+        let mut keys: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+        let base = self.values.iter().filter(|(scope, _)| !is_section(scope));
+        let sections = self.sections.iter().flat_map(|(_, values)| values.iter());
+        for (scope, table) in base.chain(sections) {
+            keys.entry(scope)
+                .or_default()
+                .extend(table.keys().map(String::as_str));
+        }
+
+        let mut effective = Values::new();
+        for (scope, keys) in keys {
+            let values = self.command_values(scope);
+            let table = effective.entry(scope.to_string()).or_default();
+            for key in keys {
+                table.insert(key.to_string(), values[key].clone());
+            }
+        }
+        effective
+    }
+
+    /// Sets the config values as the defaults of the matching flags of
+    /// the command and its subcommands.
+    pub fn apply(&self, command: Command) -> Command {
+        self.apply_to_command(command, "")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> Config {
+        let mut config = Config::default();
+        config.merge("", content.parse().unwrap(), Path::new("config.toml"));
+        config
+    }
+
+    fn value(config: &Config, command: &str, key: &str) -> Option<Value> {
+        let values = config.command_values(command);
+        values.get(key).map(|value| value.value.clone())
+    }
+
+    #[test]
+    fn sections_override_command_tables() {
+        let config = parse(
+            r#"
+            baud-rate = 1
+            [read-raw]
+            baud-rate = 2
+            [profile.fast]
+            baud-rate = 3
+            [profile.fast.read-wav]
+            baud-rate = 4
+            [device.ABC]
+            baud-rate = 5
+            "#,
+        );
+        assert_eq!(
+            value(&config, "read-raw", "baud-rate"),
+            Some(Value::Integer(2))
+        );
+
+        let profile = config.for_profile("fast").unwrap();
+        assert_eq!(
+            value(&profile, "read-raw", "baud-rate"),
+            Some(Value::Integer(3))
+        );
+        assert_eq!(
+            value(&profile, "read-wav", "baud-rate"),
+            Some(Value::Integer(4))
+        );
+        assert_eq!(
+            value(&profile, "dump", "baud-rate"),
+            Some(Value::Integer(3))
+        );
+
+        let device = profile.for_device("ABC").unwrap();
+        assert_eq!(
+            value(&device, "read-raw", "baud-rate"),
+            Some(Value::Integer(5))
+        );
+        assert_eq!(
+            value(&device, "read-wav", "baud-rate"),
+            Some(Value::Integer(5))
+        );
+        assert!(config.for_device("XYZ").is_none());
+        assert!(config.for_profile("slow").is_err());
+    }
+
+    #[test]
+    fn effective_values_name_their_section() {
+        let config = parse(
+            r#"
+            baud-rate = 1
+            [read-raw]
+            baud-rate = 2
+            fade-in = 10
+            [profile.fast]
+            baud-rate = 3
+            "#,
+        )
+        .for_profile("fast")
+        .unwrap();
+        let values = config.effective_values();
+        let source = |command: &str, key: &str| values[command][key].source();
+
+        assert_eq!(source("", "baud-rate"), "[profile.fast] in config.toml");
+        assert_eq!(
+            source("read-raw", "baud-rate"),
+            "[profile.fast] in config.toml"
+        );
+        assert_eq!(source("read-raw", "fade-in"), "config.toml");
+        assert!(!values.contains_key("profile.fast"));
+    }
+}
@@ -3,60 +3,64 @@ use nix::sys::{
     signalfd::SigSet,
 };
 use std::{
-    marker::PhantomData,
-    sync::atomic::{AtomicBool, Ordering},
+    ptr,
+    sync::{
+        atomic::{AtomicBool, AtomicPtr, Ordering},
+        Arc,
+    },
 };
 
-thread_local! {
-    static CTRLC_HANDLED: AtomicBool = AtomicBool::new(false);
-}
-
-pub struct CtrlCIgnoredContext {
-    inner: PhantomData<()>,
-}
+/// Flag set when SIGINT is received, if any.
+static CTRLC_FLAG: AtomicPtr<AtomicBool> = AtomicPtr::new(ptr::null_mut());
 
-impl CtrlCIgnoredContext {
-    pub fn has_received_ctrlc(&self) -> bool {
-        CTRLC_HANDLED.with(|value| value.load(Ordering::Relaxed))
+#[no_mangle]
+pub extern "C" fn handle_ignore_sigint(_signal: i32) {
+    let flag = CTRLC_FLAG.load(Ordering::SeqCst);
+    if !flag.is_null() {
+        // Safe as the flag is kept alive by the guard that published
+        // it for as long as it stays published.
+        unsafe { (*flag).store(true, Ordering::Relaxed) };
     }
 }
 
-pub struct CtrlCIgnoredOutput<A> {
-    pub has_received_ctrlc: bool,
-    pub output: A,
+/// Keeps SIGINT setting a flag instead of terminating the program,
+/// until dropped.
+pub struct CtrlCGuard {
+    _flag: Arc<AtomicBool>,
+    previous_flag: *mut AtomicBool,
+    previous_action: SigAction,
 }
 
-#[no_mangle]
-pub extern "C" fn handle_ignore_sigint(_signal: i32) {
-    CTRLC_HANDLED.with(|value| value.store(true, Ordering::Relaxed));
+impl Drop for CtrlCGuard {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = sigaction(SIGINT, &self.previous_action);
+        }
+        CTRLC_FLAG.store(self.previous_flag, Ordering::SeqCst);
+    }
 }
 
-fn disable_ctrlc() -> anyhow::Result<SigAction> {
+/// Sets `flag` on SIGINT instead of terminating the program, until
+/// the returned guard is dropped.
+pub fn catch_ctrlc(flag: Arc<AtomicBool>) -> anyhow::Result<CtrlCGuard> {
+    let previous_flag = CTRLC_FLAG.swap(Arc::as_ptr(&flag) as *mut _, Ordering::SeqCst);
+
     let mut sigset = SigSet::empty();
     sigset.add(SIGINT);
-
     let action = SigAction::new(
         SigHandler::Handler(handle_ignore_sigint),
         SaFlags::empty(),
         sigset,
     );
-    unsafe { Ok(sigaction(SIGINT, &action)?) }
-}
-
-pub fn ignoring_ctrlc<A, F: FnMut(&CtrlCIgnoredContext) -> A>(
-    mut f: F,
-) -> anyhow::Result<CtrlCIgnoredOutput<A>> {
-    CTRLC_HANDLED.with(|value| {
-        value.store(false, Ordering::Relaxed);
-    });
-    let previous_action = disable_ctrlc()?;
-    let context = CtrlCIgnoredContext { inner: PhantomData };
-    let result = f(&context);
-    unsafe {
-        sigaction(SIGINT, &previous_action)?;
+    match unsafe { sigaction(SIGINT, &action) } {
+        Ok(previous_action) => Ok(CtrlCGuard {
+            _flag: flag,
+            previous_flag,
+            previous_action,
+        }),
+        Err(e) => {
+            CTRLC_FLAG.store(previous_flag, Ordering::SeqCst);
+            Err(e.into())
+        }
     }
-    Ok(CtrlCIgnoredOutput {
-        has_received_ctrlc: context.has_received_ctrlc(),
-        output: result,
-    })
 }
@@ -0,0 +1,188 @@
+use std::{fmt, str::FromStr, time::Duration};
+
+#[cfg(feature = "dsp")]
+use crate::dsp::pdm::PdmDecoder;
+use crate::{dsp::Sample, rate};
+
+#[derive(Clone, Copy, Debug)]
+pub enum DecodeMode {
+    /// One output sample per received bit.
+    Bits,
+    /// One output sample per window of N bits, holding the rectified
+    /// and smoothed envelope of the signal.
+    #[cfg(feature = "dsp")]
+    Envelope(u32),
+    /// One output sample per N bits, demodulating the bit stream as
+    /// PDM.
+    #[cfg(feature = "dsp")]
+    Pdm(u32),
+}
+
+impl FromStr for DecodeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "bits" => Ok(DecodeMode::Bits),
+            #[cfg(feature = "dsp")]
+            Some(("envelope", window)) => window
+                .parse::<u32>()
+                .ok()
+                .filter(|window| *window > 0)
+                .map(DecodeMode::Envelope)
+                .ok_or_else(|| format!("Invalid envelope window '{}'", window)),
+            #[cfg(feature = "dsp")]
+            Some(("pdm", factor)) => factor
+                .parse::<u32>()
+                .ok()
+                .filter(|factor| (1..=1 << 15).contains(factor))
+                .map(DecodeMode::Pdm)
+                .ok_or_else(|| format!("Invalid decimation factor '{}'", factor)),
+            _ => Err(format!(
+                "Invalid decode mode '{}'. Expected bits, envelope:<window> or pdm:<factor>",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for DecodeMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeMode::Bits => write!(f, "bits"),
+            #[cfg(feature = "dsp")]
+            DecodeMode::Envelope(window) => write!(f, "envelope:{}", window),
+            #[cfg(feature = "dsp")]
+            DecodeMode::Pdm(factor) => write!(f, "pdm:{}", factor),
+        }
+    }
+}
+
+impl DecodeMode {
+    /// Rate of the samples produced when decoding a stream sampled at
+    /// the given rate.
+    pub fn output_rate(&self, sampling_rate: u32) -> u32 {
+        match self {
+            DecodeMode::Bits => sampling_rate,
+            #[cfg(feature = "dsp")]
+            DecodeMode::Envelope(window) => sampling_rate / window,
+            #[cfg(feature = "dsp")]
+            DecodeMode::Pdm(factor) => sampling_rate / factor,
+        }
+    }
+
+    /// Received bits making up each output sample.
+    pub fn bits_per_sample(&self) -> u32 {
+        match self {
+            DecodeMode::Bits => 1,
+            #[cfg(feature = "dsp")]
+            DecodeMode::Envelope(window) => *window,
+            #[cfg(feature = "dsp")]
+            DecodeMode::Pdm(factor) => *factor,
+        }
+    }
+
+    /// How far the decoded samples lag behind the signal received at
+    /// the given rate.
+    pub fn delay(&self, sampling_rate: u32) -> Duration {
+        let bits = match self {
+            DecodeMode::Bits => 0,
+            // Each sample is the average of its window.
+            #[cfg(feature = "dsp")]
+            DecodeMode::Envelope(window) => *window as u64 / 2,
+            #[cfg(feature = "dsp")]
+            DecodeMode::Pdm(factor) => PdmDecoder::delay_bits(*factor),
+        };
+        rate::duration_of_samples(sampling_rate, bits)
+    }
+
+    pub fn decoder(&self) -> Decoder {
+        match self {
+            DecodeMode::Bits => Decoder::Bits,
+            #[cfg(feature = "dsp")]
+            DecodeMode::Envelope(window) => Decoder::Envelope(EnvelopeDecoder::new(*window)),
+            #[cfg(feature = "dsp")]
+            DecodeMode::Pdm(factor) => Decoder::Pdm(PdmDecoder::new(*factor)),
+        }
+    }
+}
+
+/// Follows the envelope of an amplitude-modulated carrier carried on
+/// the bit stream: the DC level of the signal is removed, and the
+/// rectified result is averaged over windows of N bits.
+///
+/// The rectified signal reaches 2 when a carrier starts after a long
+/// idle stretch, before the DC level catches up, so it's halved to fit
+/// the output: a steady full-scale carrier gives half the full scale.
+#[cfg(feature = "dsp")]
+pub struct EnvelopeDecoder {
+    window: u32,
+    count: u32,
+    sum: f32,
+    dc: f32,
+    dc_alpha: f32,
+}
+
+#[cfg(feature = "dsp")]
+impl EnvelopeDecoder {
+    pub fn new(window: u32) -> EnvelopeDecoder {
+        EnvelopeDecoder {
+            window,
+            count: 0,
+            sum: 0.0,
+            dc: 0.0,
+            dc_alpha: 1.0 / (4 * window) as f32,
+        }
+    }
+
+    pub fn decode<S: Sample>(&mut self, input: &[u8], output: &mut Vec<S>) {
+        for byte in input {
+            for bit in (0..8).rev() {
+                let value = if (byte >> bit) & 1 != 0 { 1.0 } else { -1.0 };
+                self.dc += self.dc_alpha * (value - self.dc);
+                self.sum += (value - self.dc).abs();
+                self.count += 1;
+
+                if self.count == self.window {
+                    output.push(S::from_f32(self.sum / (2 * self.window) as f32));
+                    self.count = 0;
+                    self.sum = 0.0;
+                }
+            }
+        }
+    }
+}
+
+pub enum Decoder {
+    Bits,
+    #[cfg(feature = "dsp")]
+    Envelope(EnvelopeDecoder),
+    #[cfg(feature = "dsp")]
+    Pdm(PdmDecoder),
+}
+
+impl Decoder {
+    /// Decodes the received bytes into output, replacing its previous
+    /// contents. decode_bits gives the samples of each byte when
+    /// decoding bit by bit, only called for the levels of zeros and
+    /// ones, as the bits are expanded in bulk.
+    pub fn decode<S: Sample, F: Fn(u8) -> [S; 8]>(
+        &mut self,
+        input: &[u8],
+        output: &mut Vec<S>,
+        decode_bits: F,
+    ) {
+        output.clear();
+        match self {
+            Decoder::Bits => {
+                // Every bit maps to one of two levels, expanded in bulk.
+                let (low, high) = (decode_bits(0x00)[0], decode_bits(0xff)[0]);
+                S::expand_bits(input, low, high, output);
+            }
+            #[cfg(feature = "dsp")]
+            Decoder::Envelope(decoder) => decoder.decode(input, output),
+            #[cfg(feature = "dsp")]
+            Decoder::Pdm(decoder) => decoder.decode(input, output),
+        }
+    }
+}
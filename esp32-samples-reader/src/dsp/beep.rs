@@ -0,0 +1,42 @@
+use std::f32::consts::TAU;
+
+use super::Sample;
+use crate::trigger;
+
+/// Level of the beep once mixed; the signal is attenuated by the same
+/// amount while the beep lasts so the result doesn't clip.
+const BEEP_LEVEL: f32 = 0.5;
+
+/// Mixes a short sine beep into the stream each time a trigger is
+/// fired, as an audible marker of that point of the recording.
+pub struct Beeper {
+    phase_step: f32,
+    len: usize,
+    pos: usize,
+}
+
+impl Beeper {
+    pub fn new(sampling_rate: u32, frequency: u32, len: usize) -> Beeper {
+        Beeper {
+            phase_step: TAU * frequency as f32 / sampling_rate as f32,
+            len,
+            pos: len,
+        }
+    }
+
+    pub fn process<S: Sample>(&mut self, samples: &mut [S]) {
+        if trigger::take() {
+            self.pos = 0;
+        }
+
+        for sample in samples.iter_mut() {
+            if self.pos >= self.len {
+                break;
+            }
+
+            let beep = (self.phase_step * self.pos as f32).sin();
+            *sample = S::from_f32(sample.to_f32() * (1.0 - BEEP_LEVEL) + beep * BEEP_LEVEL);
+            self.pos += 1;
+        }
+    }
+}
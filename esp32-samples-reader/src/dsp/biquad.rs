@@ -0,0 +1,100 @@
+//! Second order IIR filters, with the coefficients of the Audio EQ
+//! Cookbook.
+
+use std::{f64::consts::TAU, str::FromStr};
+
+/// Q of a second order Butterworth filter, flat in the passband.
+const BUTTERWORTH_Q: f64 = std::f64::consts::FRAC_1_SQRT_2;
+
+/// A biquad filter in transposed direct form II. It works on f64, as
+/// cutoffs far below the sampling rate of the bits need the precision.
+pub struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    s1: f64,
+    s2: f64,
+}
+
+impl Biquad {
+    pub fn lowpass(cutoff: f32, sampling_rate: u32) -> Biquad {
+        let (cos, alpha) = Self::params(cutoff, sampling_rate);
+        Self::normalized(
+            (1.0 - cos) / 2.0,
+            1.0 - cos,
+            (1.0 - cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+
+    pub fn highpass(cutoff: f32, sampling_rate: u32) -> Biquad {
+        let (cos, alpha) = Self::params(cutoff, sampling_rate);
+        Self::normalized(
+            (1.0 + cos) / 2.0,
+            -(1.0 + cos),
+            (1.0 + cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+
+    /// Cosine and alpha of the cutoff frequency.
+    fn params(cutoff: f32, sampling_rate: u32) -> (f64, f64) {
+        let w0 = TAU * cutoff as f64 / sampling_rate as f64;
+        (w0.cos(), w0.sin() / (2.0 * BUTTERWORTH_Q))
+    }
+
+    fn normalized(b0: f64, b1: f64, b2: f64, a0: f64, a1: f64, a2: f64) -> Biquad {
+        Biquad {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            s1: 0.0,
+            s2: 0.0,
+        }
+    }
+
+    #[inline(always)]
+    pub fn filter(&mut self, input: f32) -> f32 {
+        let input = input as f64;
+        let output = self.b0 * input + self.s1;
+        self.s1 = self.b1 * input - self.a1 * output + self.s2;
+        self.s2 = self.b2 * input - self.a2 * output;
+        output as f32
+    }
+}
+
+/// Range of frequencies let through by a band-pass filter, given as
+/// <low>:<high> in Hz.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Band {
+    pub low: f32,
+    pub high: f32,
+}
+
+impl FromStr for Band {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid band '{}'. Expected <low>:<high> in Hz", s);
+        let (low, high) = s.split_once(':').ok_or_else(invalid)?;
+        let (low, high) = (
+            low.parse::<f32>().map_err(|_| invalid())?,
+            high.parse::<f32>().map_err(|_| invalid())?,
+        );
+        if !(low > 0.0 && high > low) {
+            return Err(format!(
+                "Invalid band '{}'. The low frequency must be above 0 and below the high one",
+                s
+            ));
+        }
+        Ok(Band { low, high })
+    }
+}
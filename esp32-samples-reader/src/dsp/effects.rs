@@ -0,0 +1,212 @@
+use std::{
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+/// An effect written in the syntax of sox, mapped onto the processing
+/// stages available. Times are in milliseconds and frequencies in Hz.
+#[derive(Clone, Copy, Debug)]
+pub enum Effect {
+    /// `trim <start>`: drops the start of the signal.
+    Trim(u32),
+    /// `fade [<type>] <in> [<stop> [<out>]]`.
+    Fade { fade_in: u32, fade_out: u32 },
+    /// `pad 0 <end>`: appends silence at the end of the signal.
+    Pad(u32),
+    /// `highpass <frequency>`.
+    Highpass(f32),
+    /// `lowpass <frequency>`.
+    Lowpass(f32),
+    /// `bandpass <frequency> <width>`: keeps the band of the given
+    /// width around the frequency. The width is in Hz, or a Q factor
+    /// when followed by q, like 2q.
+    Bandpass { low: f32, high: f32 },
+    /// `gain <dB>`.
+    Gain(f32),
+    /// `rate <rate>`: resamples the signal to the given rate.
+    Rate(u32),
+}
+
+const EFFECTS: [&str; 8] = [
+    "trim", "fade", "pad", "highpass", "lowpass", "bandpass", "gain", "rate",
+];
+
+/// Parses a sox time specification, `[[hh:]mm:]ss[.frac]`, into
+/// milliseconds.
+fn parse_time(s: &str) -> Result<u32, String> {
+    let mut seconds = 0.0;
+    for part in s.split(':') {
+        let value = part
+            .parse::<f64>()
+            .ok()
+            .filter(|value| *value >= 0.0)
+            .ok_or_else(|| format!("Invalid time '{}'", s))?;
+        seconds = seconds * 60.0 + value;
+    }
+    Ok((seconds * 1000.0).round() as u32)
+}
+
+/// Parses a frequency in Hz, or in kHz when followed by k, like 3.4k.
+fn parse_frequency(s: &str) -> Result<f32, String> {
+    let (value, scale) = match s.strip_suffix('k') {
+        Some(value) => (value, 1000.0),
+        None => (s, 1.0),
+    };
+    value
+        .parse::<f32>()
+        .ok()
+        .map(|value| value * scale)
+        .filter(|value| *value > 0.0)
+        .ok_or_else(|| format!("Invalid frequency '{}'", s))
+}
+
+/// Formats a time in milliseconds the way parse_time reads it.
+fn format_time(millis: u32) -> f64 {
+    millis as f64 / 1000.0
+}
+
+impl Display for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Effect::Trim(start) => write!(f, "trim {}", format_time(start)),
+            Effect::Fade { fade_in, fade_out } => write!(
+                f,
+                "fade {} 0 {}",
+                format_time(fade_in),
+                format_time(fade_out)
+            ),
+            Effect::Pad(end) => write!(f, "pad 0 {}", format_time(end)),
+            Effect::Highpass(frequency) => write!(f, "highpass {}", frequency),
+            Effect::Lowpass(frequency) => write!(f, "lowpass {}", frequency),
+            Effect::Bandpass { low, high } => {
+                write!(f, "bandpass {} {}", (low + high) / 2.0, high - low)
+            }
+            Effect::Gain(gain) => write!(f, "gain {}", gain),
+            Effect::Rate(rate) => write!(f, "rate {}", rate),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Effects(pub Vec<Effect>);
+
+impl Display for Effects {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, effect) in self.0.iter().enumerate() {
+            if index > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{}", effect)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Effects {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = s.split_whitespace().peekable();
+        let mut effects = Vec::new();
+
+        while let Some(name) = tokens.next() {
+            if !EFFECTS.contains(&name) {
+                return Err(format!(
+                    "Unsupported effect '{}'. Supported effects: {}",
+                    name,
+                    EFFECTS.join(", ")
+                ));
+            }
+            let mut params = Vec::new();
+            while let Some(param) = tokens.next_if(|token| !EFFECTS.contains(token)) {
+                params.push(param);
+            }
+            // The fade type is accepted for compatibility, but the
+            // shape of the fades is always the same.
+            if name == "fade"
+                && params
+                    .first()
+                    .is_some_and(|kind| ["q", "h", "t", "l", "p"].contains(kind))
+            {
+                params.remove(0);
+            }
+            // So are the quality options of rate.
+            if name == "rate" {
+                params.retain(|param| !param.starts_with('-'));
+            }
+
+            let effect = match (name, params.as_slice()) {
+                ("trim", [start]) => Effect::Trim(parse_time(start)?),
+                ("trim", _) => return Err("Usage: trim <start>".to_string()),
+                ("fade", [fade_in]) => Effect::Fade {
+                    fade_in: parse_time(fade_in)?,
+                    fade_out: 0,
+                },
+                ("fade", [fade_in, stop]) => {
+                    parse_time(stop)?;
+                    let fade_in = parse_time(fade_in)?;
+                    Effect::Fade {
+                        fade_in,
+                        fade_out: fade_in,
+                    }
+                }
+                ("fade", [fade_in, stop, fade_out]) => {
+                    parse_time(stop)?;
+                    Effect::Fade {
+                        fade_in: parse_time(fade_in)?,
+                        fade_out: parse_time(fade_out)?,
+                    }
+                }
+                ("fade", _) => return Err("Usage: fade [<type>] <in> [<stop> [<out>]]".to_string()),
+                ("pad", [start, end]) if parse_time(start)? == 0 => Effect::Pad(parse_time(end)?),
+                ("pad", _) => return Err("Usage: pad 0 <end>".to_string()),
+                ("highpass", [frequency]) => Effect::Highpass(parse_frequency(frequency)?),
+                ("highpass", _) => return Err("Usage: highpass <frequency>".to_string()),
+                ("lowpass", [frequency]) => Effect::Lowpass(parse_frequency(frequency)?),
+                ("lowpass", _) => return Err("Usage: lowpass <frequency>".to_string()),
+                ("bandpass", [frequency, width]) => {
+                    let frequency = parse_frequency(frequency)?;
+                    let width = match width.strip_suffix('q') {
+                        Some(q) => q
+                            .parse::<f32>()
+                            .ok()
+                            .filter(|q| *q > 0.0)
+                            .map(|q| frequency / q)
+                            .ok_or_else(|| format!("Invalid width '{}'", width))?,
+                        None => parse_frequency(width)?,
+                    };
+                    if width / 2.0 >= frequency {
+                        return Err(format!(
+                            "The band of bandpass {} {} goes under 0 Hz",
+                            frequency, width
+                        ));
+                    }
+                    Effect::Bandpass {
+                        low: frequency - width / 2.0,
+                        high: frequency + width / 2.0,
+                    }
+                }
+                ("bandpass", _) => return Err("Usage: bandpass <frequency> <width>[q]".to_string()),
+                ("gain", [gain]) => Effect::Gain(
+                    gain.parse::<f32>()
+                        .ok()
+                        .filter(|gain| gain.is_finite())
+                        .ok_or_else(|| format!("Invalid gain '{}'", gain))?,
+                ),
+                ("gain", _) => return Err("Usage: gain <dB>".to_string()),
+                ("rate", [rate]) => {
+                    let rate = parse_frequency(rate)?;
+                    if rate.fract() != 0.0 || rate > u32::MAX as f32 {
+                        return Err(format!("Invalid rate '{}'", rate));
+                    }
+                    Effect::Rate(rate as u32)
+                }
+                ("rate", _) => return Err("Usage: rate <rate>".to_string()),
+                _ => unreachable!(),
+            };
+            effects.push(effect);
+        }
+
+        Ok(Effects(effects))
+    }
+}
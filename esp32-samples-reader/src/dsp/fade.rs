@@ -0,0 +1,100 @@
+use std::collections::VecDeque;
+
+use super::Sample;
+
+/// Drops the first samples of a capture and fades in the ones coming
+/// right after, so the transients produced while the ESP32 boots or
+/// the signal settles don't end up in the output.
+pub struct Warmup {
+    discard_remaining: usize,
+    fade_in_len: usize,
+    fade_in_pos: usize,
+}
+
+impl Warmup {
+    pub fn new(discard: usize, fade_in: usize) -> Warmup {
+        Warmup {
+            discard_remaining: discard,
+            fade_in_len: fade_in,
+            fade_in_pos: 0,
+        }
+    }
+
+    pub fn process<'a, S: Sample>(&mut self, samples: &'a mut [S]) -> &'a mut [S] {
+        let discarded = usize::min(self.discard_remaining, samples.len());
+        self.discard_remaining -= discarded;
+        let samples = &mut samples[discarded..];
+
+        for sample in samples.iter_mut() {
+            if self.fade_in_pos >= self.fade_in_len {
+                break;
+            }
+
+            *sample = sample.scale(self.fade_in_pos as f32 / self.fade_in_len as f32);
+            self.fade_in_pos += 1;
+        }
+
+        samples
+    }
+}
+
+/// Holds back the most recent samples of the stream so they can be
+/// faded out when the capture is stopped, and appends some silence
+/// after them.
+pub struct Tail<S> {
+    held: VecDeque<S>,
+    fade_out_len: usize,
+    padding_len: usize,
+    out_buf: Vec<S>,
+}
+
+impl<S: Sample> Tail<S> {
+    pub fn new(fade_out: usize, padding: usize) -> Tail<S> {
+        Tail {
+            held: VecDeque::with_capacity(fade_out),
+            fade_out_len: fade_out,
+            padding_len: padding,
+            out_buf: Vec::new(),
+        }
+    }
+
+    pub fn process<'a>(&'a mut self, samples: &'a [S]) -> &'a [S] {
+        if self.fade_out_len == 0 {
+            return samples;
+        }
+
+        self.held.extend(samples);
+        let excess = self.held.len().saturating_sub(self.fade_out_len);
+        self.out_buf.clear();
+        self.out_buf.extend(self.held.drain(..excess));
+        &self.out_buf
+    }
+
+    pub fn finish(&mut self) -> Vec<S> {
+        let len = self.held.len();
+        let mut result: Vec<S> = self
+            .held
+            .drain(..)
+            .enumerate()
+            .map(|(i, sample)| sample.scale((len - i) as f32 / len as f32))
+            .collect();
+        result.resize(len + self.padding_len, S::SILENCE);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsigned_fades_are_centred_on_the_midpoint() {
+        let mut samples = [0u8, 255, 0, 255];
+        Warmup::new(0, 4).process(&mut samples);
+        assert_eq!(samples, [128, 160, 64, 223]);
+
+        let mut tail = Tail::new(4, 2);
+        assert!(tail.process(&[255u8, 0, 255, 0]).is_empty());
+        assert_eq!(tail.finish(), [255, 32, 192, 96, 128, 128]);
+    }
+}
@@ -0,0 +1,54 @@
+use std::f32::consts::TAU;
+
+use super::{biquad::Biquad, Sample};
+
+/// Single-pole high-pass filter removing the DC offset of the signal,
+/// like the one of the 0/255 unsigned encoding.
+pub struct DcBlocker {
+    pole: f32,
+    last_input: Option<f32>,
+    last_output: f32,
+}
+
+impl DcBlocker {
+    pub fn new(cutoff: f32, sampling_rate: u32) -> DcBlocker {
+        DcBlocker {
+            pole: (-TAU * cutoff / sampling_rate as f32).exp(),
+            last_input: None,
+            last_output: 0.0,
+        }
+    }
+
+    #[inline(always)]
+    fn filter(&mut self, input: f32) -> f32 {
+        // Starting from the first sample avoids a step at the start.
+        let last_input = self.last_input.unwrap_or(input);
+        let output = input - last_input + self.pole * self.last_output;
+        self.last_input = Some(input);
+        self.last_output = output;
+        output
+    }
+}
+
+/// A filter applied to the decoded samples.
+pub enum Filter {
+    DcBlock(DcBlocker),
+    Biquad(Biquad),
+}
+
+impl Filter {
+    pub fn process<S: Sample>(&mut self, samples: &mut [S]) {
+        match self {
+            Filter::DcBlock(blocker) => {
+                for sample in samples.iter_mut() {
+                    *sample = S::from_f32(blocker.filter(sample.to_f32()));
+                }
+            }
+            Filter::Biquad(biquad) => {
+                for sample in samples.iter_mut() {
+                    *sample = S::from_f32(biquad.filter(sample.to_f32()));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use super::{goertzel::ToneBank, millis_to_samples, Sample};
+
+const FRAME_MS: u32 = 20;
+const BANDS: usize = 9;
+const LOWEST_BAND_HZ: f32 = 100.0;
+const HIGHEST_BAND_HZ: f32 = 8000.0;
+
+/// Frames quieter than this (summing the level of all bands) don't
+/// contribute to the fingerprint, so the amount of silence around a
+/// capture doesn't change it.
+const SILENCE_LEVEL: f32 = 1e-3;
+
+/// Computes a 64-bit fingerprint of a signal, meant for finding
+/// duplicated or overlapping captures. Each frame is reduced to the
+/// way the energy differences between adjacent bands change over time,
+/// and the sequences of those are combined with a simhash: the more
+/// content two captures share, the fewer bits their fingerprints differ
+/// in.
+pub struct Fingerprinter {
+    bank: ToneBank,
+    buf: Vec<f32>,
+    previous: Option<[f32; BANDS - 1]>,
+    history: [u8; 2],
+    weights: [i64; 64],
+}
+
+/// FNV-1a, good enough for spreading the features over the simhash.
+fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl Fingerprinter {
+    pub fn new(sampling_rate: u32) -> Fingerprinter {
+        let highest = f32::max(
+            HIGHEST_BAND_HZ.min(sampling_rate as f32 * 0.45),
+            LOWEST_BAND_HZ * 2.0,
+        );
+        let ratio = (highest / LOWEST_BAND_HZ).powf(1.0 / (BANDS - 1) as f32);
+        let bands: Vec<f32> = (0..BANDS)
+            .map(|band| LOWEST_BAND_HZ * ratio.powi(band as i32))
+            .collect();
+
+        Fingerprinter {
+            bank: ToneBank::new(
+                &bands,
+                sampling_rate,
+                millis_to_samples(sampling_rate, FRAME_MS).max(1),
+            ),
+            buf: Vec::new(),
+            previous: None,
+            history: [0; 2],
+            weights: [0; 64],
+        }
+    }
+
+    pub fn push<S: Sample>(&mut self, samples: &[S]) {
+        let Fingerprinter {
+            bank,
+            buf,
+            previous,
+            history,
+            weights,
+        } = self;
+
+        buf.clear();
+        buf.extend(samples.iter().map(|sample| sample.to_f32()));
+        bank.process(buf, |levels| {
+            if levels.iter().sum::<f32>() < SILENCE_LEVEL {
+                return;
+            }
+
+            let mut differences = [0.0; BANDS - 1];
+            for (band, difference) in differences.iter_mut().enumerate() {
+                *difference = levels[band] - levels[band + 1];
+            }
+
+            if let Some(previous) = previous {
+                let mut bits = 0u8;
+                for band in 0..BANDS - 1 {
+                    if differences[band] > previous[band] {
+                        bits |= 1 << band;
+                    }
+                }
+
+                let feature = hash(&[history[0], history[1], bits]);
+                for (bit, weight) in weights.iter_mut().enumerate() {
+                    *weight += if feature & (1 << bit) != 0 { 1 } else { -1 };
+                }
+                *history = [history[1], bits];
+            }
+            *previous = Some(differences);
+        });
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.weights
+            .iter()
+            .enumerate()
+            .filter(|(_, weight)| **weight > 0)
+            .fold(0, |fingerprint, (bit, _)| fingerprint | (1 << bit))
+    }
+}
@@ -0,0 +1,77 @@
+use std::f32::consts::TAU;
+
+/// Measures the power of a single frequency over a block of samples,
+/// cheaper than a full FFT when only a few frequencies matter.
+pub struct Goertzel {
+    coeff: f32,
+    s1: f32,
+    s2: f32,
+}
+
+impl Goertzel {
+    pub fn new(frequency: f32, sampling_rate: u32) -> Goertzel {
+        Goertzel {
+            coeff: 2.0 * (TAU * frequency / sampling_rate as f32).cos(),
+            s1: 0.0,
+            s2: 0.0,
+        }
+    }
+
+    #[inline(always)]
+    pub fn push(&mut self, sample: f32) {
+        let s0 = sample + self.coeff * self.s1 - self.s2;
+        self.s2 = self.s1;
+        self.s1 = s0;
+    }
+
+    /// Returns the amplitude of the frequency over the last block of
+    /// block_len samples, and resets the filter for the next one.
+    pub fn finish_block(&mut self, block_len: usize) -> f32 {
+        let power = self.s1 * self.s1 + self.s2 * self.s2 - self.coeff * self.s1 * self.s2;
+        self.s1 = 0.0;
+        self.s2 = 0.0;
+        2.0 * power.max(0.0).sqrt() / block_len as f32
+    }
+}
+
+/// Runs a set of Goertzel filters over consecutive blocks of samples.
+pub struct ToneBank {
+    filters: Vec<Goertzel>,
+    block_len: usize,
+    block_pos: usize,
+    levels: Vec<f32>,
+}
+
+impl ToneBank {
+    pub fn new(frequencies: &[f32], sampling_rate: u32, block_len: usize) -> ToneBank {
+        ToneBank {
+            filters: frequencies
+                .iter()
+                .map(|frequency| Goertzel::new(*frequency, sampling_rate))
+                .collect(),
+            block_len,
+            block_pos: 0,
+            levels: vec![0.0; frequencies.len()],
+        }
+    }
+
+    /// Feeds samples to the filters, calling on_block with the level
+    /// of each frequency, in the order they were given, every time a
+    /// block is completed.
+    pub fn process<F: FnMut(&[f32])>(&mut self, samples: &[f32], mut on_block: F) {
+        for sample in samples {
+            for filter in self.filters.iter_mut() {
+                filter.push(*sample);
+            }
+
+            self.block_pos += 1;
+            if self.block_pos == self.block_len {
+                for (level, filter) in self.levels.iter_mut().zip(self.filters.iter_mut()) {
+                    *level = filter.finish_block(self.block_len);
+                }
+                self.block_pos = 0;
+                on_block(&self.levels);
+            }
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use crate::dsp::Sample;
+
+/// Statistics of the signal over a stretch of samples.
+pub struct MeterReading {
+    /// Fraction of the samples at the high level.
+    pub duty_cycle: f32,
+    /// Level changes per second of signal.
+    pub edge_rate: f64,
+    /// Rising edges per second of signal, which is the frequency of
+    /// the signal when it's mostly a single tone.
+    pub frequency: f64,
+}
+
+/// Accumulates the levels of the output samples between readings, for
+/// the live meter. Samples above zero count as the high level.
+#[derive(Default)]
+pub struct SignalMeter {
+    total: u64,
+    high: u64,
+    edges: u64,
+    rising: u64,
+    last_high: Option<bool>,
+}
+
+impl SignalMeter {
+    pub fn push<S: Sample>(&mut self, samples: &[S]) {
+        for sample in samples {
+            let high = sample.to_f32() > 0.0;
+            if let Some(last_high) = self.last_high {
+                if high != last_high {
+                    self.edges += 1;
+                    self.rising += high as u64;
+                }
+            }
+            self.last_high = Some(high);
+            self.high += high as u64;
+        }
+        self.total += samples.len() as u64;
+    }
+
+    /// Statistics of the samples pushed since the last reading, which
+    /// run at the given rate. None if there are none.
+    pub fn take(&mut self, sampling_rate: u32) -> Option<MeterReading> {
+        if self.total == 0 {
+            return None;
+        }
+        let seconds = self.total as f64 / sampling_rate as f64;
+        let reading = MeterReading {
+            duty_cycle: self.high as f32 / self.total as f32,
+            edge_rate: self.edges as f64 / seconds,
+            frequency: self.rising as f64 / seconds,
+        };
+        // The last level stays, so an edge across readings counts.
+        *self = SignalMeter {
+            last_high: self.last_high,
+            ..Default::default()
+        };
+        Some(reading)
+    }
+}
@@ -0,0 +1,466 @@
+#[cfg(feature = "dsp")]
+pub mod beep;
+#[cfg(feature = "dsp")]
+pub mod biquad;
+pub mod effects;
+pub mod fade;
+#[cfg(feature = "dsp")]
+pub mod filter;
+#[cfg(feature = "analysis")]
+pub mod fingerprint;
+#[cfg(feature = "analysis")]
+pub mod goertzel;
+pub mod meter;
+#[cfg(feature = "dsp")]
+pub mod pdm;
+pub mod resample;
+#[cfg(feature = "dsp")]
+pub mod shift;
+pub mod silence;
+#[cfg(feature = "analysis")]
+pub mod spectrum;
+
+use crate::{decode::Decoder, io, pipeline::SinkWriter};
+#[cfg(feature = "dsp")]
+use beep::Beeper;
+use fade::{Tail, Warmup};
+#[cfg(feature = "dsp")]
+use filter::Filter;
+use resample::Resampler;
+
+/// A PCM sample value that can be handled by the processing stages.
+pub trait Sample: Copy + Send {
+    const SILENCE: Self;
+
+    /// Scales the distance of the sample to `SILENCE`, so unsigned
+    /// samples fade towards their midpoint rather than towards 0.
+    fn scale(self, gain: f32) -> Self;
+
+    /// Returns the sample as a value in the [-1, 1] range.
+    fn to_f32(self) -> f32;
+
+    fn from_f32(value: f32) -> Self;
+
+    /// The sample holding the given value, in the units of the sample
+    /// format, if it can hold it.
+    fn from_level(value: f64) -> Option<Self>;
+
+    /// Appends a sample for each bit of input, most significant bit
+    /// first: `high` for ones and `low` for zeros.
+    #[inline(always)]
+    fn expand_bits(input: &[u8], low: Self, high: Self, output: &mut Vec<Self>) {
+        let levels = [low, high];
+        for byte in input {
+            output.extend((0..8).rev().map(|bit| levels[((byte >> bit) & 1) as usize]));
+        }
+    }
+}
+
+impl Sample for i8 {
+    const SILENCE: i8 = 0;
+
+    #[inline(always)]
+    fn scale(self, gain: f32) -> i8 {
+        (self as f32 * gain).round() as i8
+    }
+
+    #[inline(always)]
+    fn to_f32(self) -> f32 {
+        self as f32 / 128.0
+    }
+
+    #[inline(always)]
+    fn from_f32(value: f32) -> i8 {
+        (value * 128.0).round().clamp(-128.0, 127.0) as i8
+    }
+
+    fn from_level(value: f64) -> Option<i8> {
+        integer_level(value, i8::MIN as f64, i8::MAX as f64).map(|value| value as i8)
+    }
+
+    fn expand_bits(input: &[u8], low: i8, high: i8, output: &mut Vec<i8>) {
+        let start = output.len();
+        output.resize(start + input.len() * 8, low);
+        let samples = &mut output[start..];
+        // SAFETY: i8 and u8 have the same size and alignment, and any
+        // bit pattern is valid for both.
+        let samples = unsafe {
+            std::slice::from_raw_parts_mut(samples.as_mut_ptr() as *mut u8, samples.len())
+        };
+        io::decode_block(input, samples, low as u8, high as u8);
+    }
+}
+
+impl Sample for i16 {
+    const SILENCE: i16 = 0;
+
+    #[inline(always)]
+    fn scale(self, gain: f32) -> i16 {
+        (self as f32 * gain).round() as i16
+    }
+
+    #[inline(always)]
+    fn to_f32(self) -> f32 {
+        self as f32 / 32768.0
+    }
+
+    #[inline(always)]
+    fn from_f32(value: f32) -> i16 {
+        (value * 32768.0).round().clamp(-32768.0, 32767.0) as i16
+    }
+
+    fn from_level(value: f64) -> Option<i16> {
+        integer_level(value, i16::MIN as f64, i16::MAX as f64).map(|value| value as i16)
+    }
+}
+
+/// A 24-bit signed sample, held in the low bits of an i32.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct I24(pub i32);
+
+impl I24 {
+    pub const MAX: I24 = I24((1 << 23) - 1);
+    pub const MIN: I24 = I24(-(1 << 23));
+}
+
+impl Sample for I24 {
+    const SILENCE: I24 = I24(0);
+
+    #[inline(always)]
+    fn scale(self, gain: f32) -> I24 {
+        I24((self.0 as f32 * gain).round() as i32)
+    }
+
+    #[inline(always)]
+    fn to_f32(self) -> f32 {
+        self.0 as f32 / 8388608.0
+    }
+
+    #[inline(always)]
+    fn from_f32(value: f32) -> I24 {
+        I24((value * 8388608.0).round().clamp(-8388608.0, 8388607.0) as i32)
+    }
+
+    fn from_level(value: f64) -> Option<I24> {
+        integer_level(value, I24::MIN.0 as f64, I24::MAX.0 as f64).map(|value| I24(value as i32))
+    }
+}
+
+impl Sample for u8 {
+    const SILENCE: u8 = 128;
+
+    #[inline(always)]
+    fn scale(self, gain: f32) -> u8 {
+        ((self as f32 - 128.0) * gain + 128.0).round() as u8
+    }
+
+    #[inline(always)]
+    fn to_f32(self) -> f32 {
+        (self as f32 - 128.0) / 128.0
+    }
+
+    #[inline(always)]
+    fn from_f32(value: f32) -> u8 {
+        (value * 128.0 + 128.0).round().clamp(0.0, 255.0) as u8
+    }
+
+    fn from_level(value: f64) -> Option<u8> {
+        integer_level(value, u8::MIN as f64, u8::MAX as f64).map(|value| value as u8)
+    }
+
+    fn expand_bits(input: &[u8], low: u8, high: u8, output: &mut Vec<u8>) {
+        let start = output.len();
+        output.resize(start + input.len() * 8, low);
+        io::decode_block(input, &mut output[start..], low, high);
+    }
+}
+
+impl Sample for f32 {
+    const SILENCE: f32 = 0.0;
+
+    #[inline(always)]
+    fn scale(self, gain: f32) -> f32 {
+        self * gain
+    }
+
+    #[inline(always)]
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    #[inline(always)]
+    fn from_f32(value: f32) -> f32 {
+        value
+    }
+
+    fn from_level(value: f64) -> Option<f32> {
+        value.is_finite().then_some(value as f32)
+    }
+}
+
+/// The value, if it's a whole number within the range.
+fn integer_level(value: f64, min: f64, max: f64) -> Option<f64> {
+    (value.fract() == 0.0 && (min..=max).contains(&value)).then_some(value)
+}
+
+pub fn millis_to_samples(sampling_rate: u32, millis: u32) -> usize {
+    (sampling_rate as u64 * millis as u64 / 1000) as usize
+}
+
+/// Turns the received bytes into the samples written to the output.
+pub trait Processor<S>: Send {
+    /// Decodes and processes the given bytes. decode_bits converts
+    /// each byte into samples when decoding bit by bit.
+    fn process(&mut self, input: &[u8], decode_bits: fn(u8) -> [S; 8]) -> &[S];
+
+    /// Returns the samples to be written once the capture is stopped.
+    fn finish(&mut self) -> Vec<S>;
+}
+
+/// A stage of the effects, run in the order they were given.
+pub enum Stage<S> {
+    #[cfg(feature = "dsp")]
+    Filter(Filter),
+    /// Multiplies the signal by the given factor.
+    Gain(f32),
+    /// Converts the signal to another rate, into its own buffer.
+    Resample(Resampler, Vec<S>),
+}
+
+impl<S: Sample> Stage<S> {
+    /// Runs the stages in order over the samples, returning the ones
+    /// coming out of the last of them.
+    fn run<'a>(stages: &'a mut [Stage<S>], mut samples: &'a mut [S]) -> &'a mut [S] {
+        for stage in stages {
+            match stage {
+                #[cfg(feature = "dsp")]
+                Stage::Filter(filter) => filter.process(samples),
+                Stage::Gain(gain) => {
+                    for sample in samples.iter_mut() {
+                        *sample = S::from_f32(sample.to_f32() * *gain);
+                    }
+                }
+                Stage::Resample(resampler, resampled) => {
+                    resampled.clear();
+                    resampler.process(samples, resampled);
+                    samples = resampled;
+                }
+            }
+        }
+        samples
+    }
+
+    /// Flushes the input held back by the resamplers among the stages,
+    /// returning what comes out of the last of them.
+    fn finish(stages: &mut [Stage<S>]) -> Vec<S> {
+        let mut output = Vec::new();
+        for index in 0..stages.len() {
+            let (stage, rest) = stages[index..].split_first_mut().unwrap();
+            if let Stage::Resample(resampler, _) = stage {
+                let mut flushed = Vec::new();
+                resampler.finish(&mut flushed);
+                output.extend_from_slice(Stage::run(rest, &mut flushed));
+            }
+        }
+        output
+    }
+}
+
+/// Runs stages of its own over the samples on their way to `inner`,
+/// for an output getting the stream processed differently from the
+/// others, like at a lower rate. The samples must be of a single
+/// channel.
+pub struct Staged<S, Snk> {
+    pub inner: Snk,
+    stages: Vec<Stage<S>>,
+    staged: Vec<S>,
+}
+
+impl<S, Snk> Staged<S, Snk> {
+    pub fn new(inner: Snk, stages: Vec<Stage<S>>) -> Self {
+        Staged {
+            inner,
+            stages,
+            staged: Vec::new(),
+        }
+    }
+}
+
+impl<S: Sample, Snk: SinkWriter<S>> SinkWriter<S> for Staged<S, Snk> {
+    fn write_samples(&mut self, samples: &[S]) -> anyhow::Result<()> {
+        self.staged.clear();
+        self.staged.extend_from_slice(samples);
+        let samples = Stage::run(&mut self.stages, &mut self.staged);
+        if samples.is_empty() {
+            return Ok(());
+        }
+        self.inner.write_samples(samples)
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        self.inner.flush()
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        let rest = Stage::finish(&mut self.stages);
+        if !rest.is_empty() {
+            self.inner.write_samples(&rest)?;
+        }
+        self.inner.finish()
+    }
+
+    fn bytes_written(&self) -> Option<u64> {
+        self.inner.bytes_written()
+    }
+
+    fn can_reconnect(&self) -> bool {
+        self.inner.can_reconnect()
+    }
+
+    fn reconnect(&mut self) -> anyhow::Result<()> {
+        self.inner.reconnect()
+    }
+}
+
+/// Decodes the received bytes and applies the processing stages to
+/// the resulting samples before being written to the output.
+pub struct Chain<S> {
+    pub decoder: Decoder,
+    /// Values of the output at the low and high levels of the signal,
+    /// in place of the ones of the sample decoder.
+    pub levels: Option<(S, S)>,
+    /// Converts the decoded samples to the output rate, if they
+    /// differ.
+    pub resampler: Option<Resampler>,
+    /// Run over the decoded samples in order, before the fades.
+    #[cfg(feature = "dsp")]
+    pub filters: Vec<Filter>,
+    /// Stages of the effects, run in order after the filters.
+    pub effects: Vec<Stage<S>>,
+    pub warmup: Warmup,
+    #[cfg(feature = "dsp")]
+    pub beeper: Option<Beeper>,
+    pub tail: Tail<S>,
+    decoded: Vec<S>,
+    resampled: Vec<S>,
+}
+
+impl<S: Sample> Chain<S> {
+    pub fn new(decoder: Decoder, warmup: Warmup, tail: Tail<S>) -> Chain<S> {
+        Chain {
+            decoder,
+            levels: None,
+            resampler: None,
+            #[cfg(feature = "dsp")]
+            filters: Vec::new(),
+            effects: Vec::new(),
+            warmup,
+            #[cfg(feature = "dsp")]
+            beeper: None,
+            tail,
+            decoded: Vec::new(),
+            resampled: Vec::new(),
+        }
+    }
+
+    /// Decodes and processes the given bytes. decode_bits converts
+    /// each byte into samples when decoding bit by bit.
+    pub fn process<F: Fn(u8) -> [S; 8]>(&mut self, input: &[u8], decode_bits: F) -> &[S] {
+        match self.levels {
+            Some((low, high)) => {
+                self.decoder.decode(input, &mut self.decoded, |byte| {
+                    io::decode_esp32_sample_levels(byte, low, high)
+                });
+                // Other decoders give values in between, scaled from
+                // the full range to the one between the levels.
+                if !matches!(self.decoder, Decoder::Bits) {
+                    let (low, high) = (low.to_f32(), high.to_f32());
+                    for sample in &mut self.decoded {
+                        let position = (sample.to_f32() + 1.0) / 2.0;
+                        *sample = S::from_f32(low + position * (high - low));
+                    }
+                }
+            }
+            None => self.decoder.decode(input, &mut self.decoded, decode_bits),
+        }
+
+        if let Some(resampler) = &mut self.resampler {
+            self.resampled.clear();
+            resampler.process(&self.decoded, &mut self.resampled);
+        }
+        let samples = match self.resampler {
+            Some(_) => &mut self.resampled[..],
+            None => &mut self.decoded[..],
+        };
+        #[cfg(feature = "dsp")]
+        for filter in &mut self.filters {
+            filter.process(samples);
+        }
+        let samples = Stage::run(&mut self.effects, samples);
+        Self::process_output(
+            &mut self.warmup,
+            #[cfg(feature = "dsp")]
+            &mut self.beeper,
+            &mut self.tail,
+            samples,
+        )
+    }
+
+    /// Returns the samples to be written once the capture is stopped.
+    pub fn finish(&mut self) -> Vec<S> {
+        let mut output = Vec::new();
+        // Each resampler holds back the input its filter looks ahead
+        // over, which still goes through the stages after it.
+        if let Some(resampler) = &mut self.resampler {
+            let mut flushed = Vec::new();
+            resampler.finish(&mut flushed);
+            #[cfg(feature = "dsp")]
+            for filter in &mut self.filters {
+                filter.process(&mut flushed);
+            }
+            let samples = Stage::run(&mut self.effects, &mut flushed);
+            output.extend_from_slice(Self::process_output(
+                &mut self.warmup,
+                #[cfg(feature = "dsp")]
+                &mut self.beeper,
+                &mut self.tail,
+                samples,
+            ));
+        }
+        let mut flushed = Stage::finish(&mut self.effects);
+        output.extend_from_slice(Self::process_output(
+            &mut self.warmup,
+            #[cfg(feature = "dsp")]
+            &mut self.beeper,
+            &mut self.tail,
+            &mut flushed,
+        ));
+        output.extend(self.tail.finish());
+        output
+    }
+
+    /// Applies the stages run at the output rate, the last ones.
+    fn process_output<'a>(
+        warmup: &mut Warmup,
+        #[cfg(feature = "dsp")] beeper: &mut Option<Beeper>,
+        tail: &'a mut Tail<S>,
+        samples: &'a mut [S],
+    ) -> &'a [S] {
+        let samples = warmup.process(samples);
+        #[cfg(feature = "dsp")]
+        if let Some(beeper) = beeper {
+            beeper.process(samples);
+        }
+        tail.process(samples)
+    }
+}
+
+impl<S: Sample> Processor<S> for Chain<S> {
+    fn process(&mut self, input: &[u8], decode_bits: fn(u8) -> [S; 8]) -> &[S] {
+        Chain::process(self, input, decode_bits)
+    }
+
+    fn finish(&mut self) -> Vec<S> {
+        Chain::finish(self)
+    }
+}
@@ -0,0 +1,58 @@
+use super::Sample;
+
+/// Order of the CIC filter. Each stage adds about 13 dB of rejection
+/// of the aliased noise.
+const CIC_ORDER: usize = 4;
+
+/// Converts a pulse-density modulated bit stream into PCM at a
+/// fraction of its rate, through a CIC decimation filter.
+///
+/// The integrators and combs are run with wrapping arithmetic, which
+/// gives the right result as long as the output fits in the
+/// accumulators: up to a decimation factor of 2^15 with 64 bits.
+pub struct PdmDecoder {
+    factor: u32,
+    count: u32,
+    integrators: [i64; CIC_ORDER],
+    combs: [i64; CIC_ORDER],
+    gain: f32,
+}
+
+impl PdmDecoder {
+    pub fn new(factor: u32) -> PdmDecoder {
+        PdmDecoder {
+            factor,
+            count: 0,
+            integrators: [0; CIC_ORDER],
+            combs: [0; CIC_ORDER],
+            gain: (factor as f32).powi(CIC_ORDER as i32),
+        }
+    }
+
+    /// Group delay of the filter, in received bits.
+    pub fn delay_bits(factor: u32) -> u64 {
+        CIC_ORDER as u64 * (factor as u64 - 1) / 2
+    }
+
+    pub fn decode<S: Sample>(&mut self, input: &[u8], output: &mut Vec<S>) {
+        for byte in input {
+            for bit in (0..8).rev() {
+                let mut value: i64 = if (byte >> bit) & 1 != 0 { 1 } else { -1 };
+                for integrator in &mut self.integrators {
+                    *integrator = integrator.wrapping_add(value);
+                    value = *integrator;
+                }
+
+                self.count += 1;
+                if self.count == self.factor {
+                    self.count = 0;
+                    for comb in &mut self.combs {
+                        let previous = std::mem::replace(comb, value);
+                        value = value.wrapping_sub(previous);
+                    }
+                    output.push(S::from_f32(value as f32 / self.gain));
+                }
+            }
+        }
+    }
+}
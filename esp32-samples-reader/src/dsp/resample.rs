@@ -0,0 +1,233 @@
+use crate::dsp::Sample;
+
+/// Rates most players and editors handle, for --standard-rate.
+pub const STANDARD_RATES: [u32; 3] = [44100, 48000, 96000];
+
+/// Zero crossings of the sinc on each side of the filter, at the lower
+/// of the two rates. More make the transition band narrower.
+const ZERO_CROSSINGS: usize = 32;
+/// Cutoff of the filter, as a fraction of the lower of the two rates.
+/// With the Kaiser window below, the stopband starts right at half
+/// that rate, so nothing above it folds back into the output.
+pub const CUTOFF: f64 = 0.46;
+/// Shape of the Kaiser window, giving about 80 dB of stopband
+/// attenuation.
+const KAISER_BETA: f64 = 8.0;
+/// Largest number of coefficients kept. Rates without a small common
+/// divisor have more phases than fit, so the position of each output
+/// sample is rounded to the nearest of the phases that do.
+const MAX_COEFFICIENTS: usize = 1 << 18;
+
+/// The standard rate closest to the given one.
+pub fn nearest_standard_rate(rate: u32) -> u32 {
+    STANDARD_RATES
+        .into_iter()
+        .min_by_key(|standard| standard.abs_diff(rate))
+        .unwrap()
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Modified Bessel function of the first kind and order 0.
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let mut k = 1.0;
+    while term > sum * 1e-12 {
+        term *= (x / (2.0 * k)).powi(2);
+        sum += term;
+        k += 1.0;
+    }
+    sum
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    // Separate sums let the compiler vectorize the loop.
+    let mut sums = [0.0f32; 8];
+    let (a_chunks, b_chunks) = (a.chunks_exact(8), b.chunks_exact(8));
+    let (a_rest, b_rest) = (a_chunks.remainder(), b_chunks.remainder());
+    for (a, b) in a_chunks.zip(b_chunks) {
+        for (sum, (a, b)) in sums.iter_mut().zip(a.iter().zip(b)) {
+            *sum += a * b;
+        }
+    }
+    let rest: f32 = a_rest.iter().zip(b_rest).map(|(a, b)| a * b).sum();
+    sums.iter().sum::<f32>() + rest
+}
+
+/// Converts a stream of samples to another rate through a windowed
+/// sinc filter, which low-passes the signal below half the lower of
+/// the two rates: when decimating, what the output can't hold is
+/// removed instead of folding back into it, and when interpolating,
+/// the images of the input are. Positions are kept as exact counts, so
+/// the output doesn't drift from the input however long it runs.
+pub struct Resampler {
+    /// Input samples advanced per output sample, as down / up.
+    up: u64,
+    down: u64,
+    /// Coefficients for each of the phases, `taps` per phase.
+    coefficients: Vec<f32>,
+    phases: u64,
+    taps: usize,
+    /// Input samples waiting to be used, the first `taps / 2` of the
+    /// stream being silence for the filter to start on.
+    history: Vec<f32>,
+    /// Position of the next output sample in `history`, in 1/up of
+    /// an input sample.
+    position: u64,
+    consumed: u64,
+    produced: u64,
+}
+
+impl Resampler {
+    pub fn new(from: u32, to: u32) -> Resampler {
+        let divisor = gcd(from as u64, to as u64).max(1);
+        let (up, down) = (to as u64 / divisor, from as u64 / divisor);
+        // Lowering the rate stretches the filter over more input
+        // samples, with its cutoff scaled down along with it.
+        let scale = f64::min(1.0, to as f64 / from as f64);
+        let half = (ZERO_CROSSINGS as f64 / scale).ceil() as usize;
+        let taps = 2 * half;
+        let phases = up.min((MAX_COEFFICIENTS / taps).max(1) as u64);
+
+        let cutoff = CUTOFF * scale;
+        let support = half as f64;
+        let mut coefficients = Vec::with_capacity(phases as usize * taps);
+        for phase in 0..phases {
+            let fraction = phase as f64 / phases as f64;
+            let start = coefficients.len();
+            for tap in 0..taps {
+                // Distance from the output sample to the input sample
+                // of this tap.
+                let distance = fraction + (half - 1) as f64 - tap as f64;
+                let x = 2.0 * cutoff * distance;
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (std::f64::consts::PI * x).sin() / (std::f64::consts::PI * x)
+                };
+                let ratio = distance / support;
+                let window = if ratio.abs() < 1.0 {
+                    bessel_i0(KAISER_BETA * (1.0 - ratio * ratio).sqrt()) / bessel_i0(KAISER_BETA)
+                } else {
+                    0.0
+                };
+                coefficients.push(sinc * window);
+            }
+            // Unity gain at DC for every phase.
+            let sum: f64 = coefficients[start..].iter().sum();
+            for coefficient in &mut coefficients[start..] {
+                *coefficient /= sum;
+            }
+        }
+
+        Resampler {
+            up,
+            down,
+            coefficients: coefficients.into_iter().map(|c| c as f32).collect(),
+            phases,
+            taps,
+            history: vec![0.0; half],
+            position: half as u64 * up,
+            consumed: 0,
+            produced: 0,
+        }
+    }
+
+    /// Input samples the filter looks ahead of each output sample,
+    /// which is the latency it adds.
+    pub fn lookahead(from: u32, to: u32) -> u64 {
+        let scale = f64::min(1.0, to as f64 / from as f64);
+        (ZERO_CROSSINGS as f64 / scale).ceil() as u64
+    }
+
+    pub fn process<S: Sample>(&mut self, input: &[S], output: &mut Vec<S>) {
+        self.history
+            .extend(input.iter().map(|sample| sample.to_f32()));
+        self.consumed += input.len() as u64;
+        self.resample(u64::MAX, output);
+    }
+
+    /// Produces the output up to the end of the input, taking the
+    /// signal as silent after it.
+    pub fn finish<S: Sample>(&mut self, output: &mut Vec<S>) {
+        // Output samples falling within the input.
+        let total = (self.consumed as u128 * self.up as u128).div_ceil(self.down as u128) as u64;
+        self.history.resize(self.history.len() + self.taps, 0.0);
+        self.resample(total, output);
+    }
+
+    fn resample<S: Sample>(&mut self, total: u64, output: &mut Vec<S>) {
+        let half = self.taps / 2;
+        while self.produced < total {
+            let mut center = (self.position / self.up) as usize;
+            let fraction = self.position % self.up;
+            let mut phase = if self.phases == self.up {
+                fraction
+            } else {
+                // Rounded to the nearest phase kept.
+                ((fraction as u128 * self.phases as u128 + self.up as u128 / 2) / self.up as u128)
+                    as u64
+            };
+            if phase == self.phases {
+                phase = 0;
+                center += 1;
+            }
+            if center + half >= self.history.len() {
+                break;
+            }
+
+            let start = phase as usize * self.taps;
+            let window = &self.history[center + 1 - half..=center + half];
+            let value = dot(window, &self.coefficients[start..start + self.taps]);
+            output.push(S::from_f32(value));
+            self.position += self.down;
+            self.produced += 1;
+        }
+
+        // Drops the input no output sample needs anymore.
+        let needed = (self.position / self.up) as usize + 1 - half;
+        let unused = needed.min(self.history.len());
+        self.history.drain(..unused);
+        self.position -= unused as u64 * self.up;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resample_tone(from: u32, to: u32, frequency: f64, length: usize) -> Vec<f32> {
+        let tone: Vec<f32> = (0..length)
+            .map(|i| (2.0 * std::f64::consts::PI * frequency * i as f64 / from as f64).sin() as f32)
+            .collect();
+        let mut resampler = Resampler::new(from, to);
+        let mut output = Vec::new();
+        for chunk in tone.chunks(1000) {
+            resampler.process(chunk, &mut output);
+        }
+        resampler.finish(&mut output);
+        output
+    }
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|s| s * s).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn removes_what_the_output_rate_cannot_hold() {
+        let passed = resample_tone(613000, 48000, 1000.0, 61300);
+        assert_eq!(passed.len(), 4800);
+        assert!((rms(&passed[200..4600]) - 0.5f32.sqrt()).abs() < 0.01);
+
+        // Would fold back to 18 kHz.
+        let folded = resample_tone(613000, 48000, 30000.0, 61300);
+        assert!(rms(&folded[200..4600]) < 1e-3);
+    }
+}
@@ -0,0 +1,184 @@
+use std::{f32::consts::TAU, str::FromStr};
+
+use super::Sample;
+use crate::pipeline::SinkWriter;
+
+/// Cutoff of the low-pass filter that keeps only the difference
+/// frequencies after heterodyning.
+const HETERODYNE_CUTOFF_HZ: f32 = 4000.0;
+
+/// Amplitude of the output of the frequency divider.
+const DIVIDER_LEVEL: f32 = 0.5;
+
+#[derive(Clone, Copy, Debug)]
+pub enum FrequencyShift {
+    /// Mixes the signal with a local oscillator of the given frequency,
+    /// moving the band around it down to the audible range.
+    Heterodyne(u32),
+    /// Outputs a square wave toggling every N cycles of the input.
+    Divide(u32),
+}
+
+impl FromStr for FrequencyShift {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mode, value) = s.split_once(':').ok_or_else(|| {
+            format!(
+                "Invalid shift '{}'. Expected heterodyne:<hz> or divide:<n>",
+                s
+            )
+        })?;
+        let value = value
+            .parse::<u32>()
+            .ok()
+            .filter(|value| *value > 0)
+            .ok_or_else(|| format!("Invalid shift value '{}'", value))?;
+
+        match mode {
+            "heterodyne" => Ok(FrequencyShift::Heterodyne(value)),
+            "divide" => Ok(FrequencyShift::Divide(value)),
+            _ => Err(format!("Unknown shift mode '{}'", mode)),
+        }
+    }
+}
+
+enum State {
+    Heterodyne {
+        phase: f32,
+        phase_step: f32,
+        alpha: f32,
+        stages: [f32; 2],
+    },
+    Divide {
+        ratio: u32,
+        edges: u32,
+        last_high: bool,
+        output_high: bool,
+    },
+}
+
+/// Moves high frequency signals (like ultrasound) into the audible
+/// range, for monitoring them live.
+pub struct Shifter {
+    state: State,
+}
+
+impl Shifter {
+    pub fn new(shift: FrequencyShift, sampling_rate: u32) -> Shifter {
+        let state = match shift {
+            FrequencyShift::Heterodyne(frequency) => {
+                let dt = 1.0 / sampling_rate as f32;
+                let rc = 1.0 / (TAU * HETERODYNE_CUTOFF_HZ);
+                State::Heterodyne {
+                    phase: 0.0,
+                    phase_step: TAU * frequency as f32 / sampling_rate as f32,
+                    alpha: dt / (rc + dt),
+                    stages: [0.0; 2],
+                }
+            }
+            FrequencyShift::Divide(ratio) => State::Divide {
+                ratio,
+                edges: 0,
+                last_high: false,
+                output_high: false,
+            },
+        };
+        Shifter { state }
+    }
+
+    /// Shifts the next sample of the signal.
+    pub fn shift(&mut self, value: f32) -> f32 {
+        match &mut self.state {
+            State::Heterodyne {
+                phase,
+                phase_step,
+                alpha,
+                stages,
+            } => {
+                let mut value = value * phase.cos();
+                for stage in stages.iter_mut() {
+                    *stage += *alpha * (value - *stage);
+                    value = *stage;
+                }
+                *phase = (*phase + *phase_step) % TAU;
+                // The mixing halves the amplitude of the signal.
+                value * 2.0
+            }
+            State::Divide {
+                ratio,
+                edges,
+                last_high,
+                output_high,
+            } => {
+                let high = value > 0.0;
+                if high && !*last_high {
+                    *edges += 1;
+                    if *edges >= *ratio {
+                        *edges = 0;
+                        *output_high = !*output_high;
+                    }
+                }
+                *last_high = high;
+                if *output_high {
+                    DIVIDER_LEVEL
+                } else {
+                    -DIVIDER_LEVEL
+                }
+            }
+        }
+    }
+}
+
+/// Shifts the samples on their way to `inner`, so the stream is only
+/// shifted where it's listened to while the other outputs get it as
+/// captured. Each channel has its own shifter.
+pub struct Shifted<S, Snk> {
+    pub inner: Snk,
+    shifters: Vec<Shifter>,
+    shifted: Vec<S>,
+}
+
+impl<S, Snk> Shifted<S, Snk> {
+    pub fn new(inner: Snk, shift: FrequencyShift, sampling_rate: u32, channels: u16) -> Self {
+        Shifted {
+            inner,
+            shifters: (0..channels.max(1))
+                .map(|_| Shifter::new(shift, sampling_rate))
+                .collect(),
+            shifted: Vec::new(),
+        }
+    }
+}
+
+impl<S: Sample, Snk: SinkWriter<S>> SinkWriter<S> for Shifted<S, Snk> {
+    fn write_samples(&mut self, samples: &[S]) -> anyhow::Result<()> {
+        let channels = self.shifters.len();
+        self.shifted.clear();
+        self.shifted
+            .extend(samples.iter().enumerate().map(|(index, sample)| {
+                S::from_f32(self.shifters[index % channels].shift(sample.to_f32()))
+            }));
+        self.inner.write_samples(&self.shifted)
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        self.inner.flush()
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        self.inner.finish()
+    }
+
+    fn bytes_written(&self) -> Option<u64> {
+        self.inner.bytes_written()
+    }
+
+    fn can_reconnect(&self) -> bool {
+        self.inner.can_reconnect()
+    }
+
+    fn reconnect(&mut self) -> anyhow::Result<()> {
+        self.inner.reconnect()
+    }
+}
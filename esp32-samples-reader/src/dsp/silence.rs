@@ -0,0 +1,434 @@
+use std::{
+    collections::VecDeque,
+    env,
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    mem, process,
+    str::FromStr,
+};
+
+use anyhow::Context;
+
+use super::{millis_to_samples, Sample};
+
+/// Time constant of the DC tracker, in milliseconds. The idle level of
+/// the line, whether high or low, must count as silence.
+const DC_TRACKING_MS: u32 = 10;
+/// Time constant of the tracker of the idle duty cycle, in
+/// milliseconds. Much slower than the window, so activity doesn't get
+/// learned as idle before it's noticed.
+const IDLE_TRACKING_MS: u32 = 1000;
+/// Samples of silence the trimmer holds in memory before moving them
+/// to a temporary file.
+const MAX_HELD_SAMPLES: usize = 1 << 20;
+/// Samples read back at once from the temporary file.
+const SPILL_CHUNK_SAMPLES: usize = 1 << 16;
+
+#[derive(Clone, Copy, Debug)]
+pub struct SilenceSplit {
+    /// Level, in the [0, 1] range, under which the signal is considered
+    /// silent.
+    pub threshold: f32,
+    /// Milliseconds of silence that end an activity burst.
+    pub min_gap: u32,
+}
+
+impl FromStr for SilenceSplit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (threshold, min_gap) = s
+            .split_once(',')
+            .ok_or_else(|| format!("Invalid split '{}'. Expected <threshold>,<min-gap-ms>", s))?;
+        let threshold = threshold
+            .parse::<f32>()
+            .ok()
+            .filter(|threshold| (0.0..=1.0).contains(threshold))
+            .ok_or_else(|| format!("Invalid threshold '{}'", threshold))?;
+        let min_gap = min_gap
+            .parse::<u32>()
+            .ok()
+            .filter(|min_gap| *min_gap > 0)
+            .ok_or_else(|| format!("Invalid gap '{}'", min_gap))?;
+
+        Ok(SilenceSplit { threshold, min_gap })
+    }
+}
+
+/// Tells apart activity from silence, sample by sample.
+pub trait DetectActivity {
+    fn is_active<S: Sample>(&mut self, sample: S) -> bool;
+}
+
+/// Tells apart activity from silence, measuring the level of the
+/// signal relative to its DC offset.
+pub struct ActivityDetector {
+    threshold: f32,
+    dc_alpha: f32,
+    dc: Option<f32>,
+}
+
+impl ActivityDetector {
+    pub fn new(sampling_rate: u32, threshold: f32) -> Self {
+        ActivityDetector {
+            threshold,
+            dc_alpha: 1.0 / millis_to_samples(sampling_rate, DC_TRACKING_MS).max(1) as f32,
+            dc: None,
+        }
+    }
+}
+
+impl DetectActivity for ActivityDetector {
+    #[inline(always)]
+    fn is_active<S: Sample>(&mut self, sample: S) -> bool {
+        let value = sample.to_f32();
+        // Starting from the first sample, so the idle level of the line
+        // isn't taken as activity while the tracker settles.
+        let dc = self.dc.get_or_insert(value);
+        *dc += self.dc_alpha * (value - *dc);
+        (value - *dc).abs() >= self.threshold
+    }
+}
+
+/// Tells apart activity from silence by the duty cycle of the signal,
+/// the fraction of a sliding window it spends high. The signal is
+/// active while its duty cycle differs from the one of the idle line
+/// by more than the threshold. The idle duty cycle is taken from the
+/// first window, and then tracked slowly while the signal is silent.
+pub struct DutyCycleDetector {
+    threshold: f32,
+    window: VecDeque<bool>,
+    window_len: usize,
+    high: usize,
+    idle: Option<f32>,
+    idle_alpha: f32,
+}
+
+impl DutyCycleDetector {
+    pub fn new(sampling_rate: u32, threshold: f32, window_millis: u32) -> Self {
+        let window_len = millis_to_samples(sampling_rate, window_millis).max(1);
+        DutyCycleDetector {
+            threshold,
+            window: VecDeque::with_capacity(window_len + 1),
+            window_len,
+            high: 0,
+            idle: None,
+            idle_alpha: 1.0 / millis_to_samples(sampling_rate, IDLE_TRACKING_MS).max(1) as f32,
+        }
+    }
+}
+
+impl DetectActivity for DutyCycleDetector {
+    #[inline(always)]
+    fn is_active<S: Sample>(&mut self, sample: S) -> bool {
+        let level = sample.to_f32() > 0.0;
+        self.window.push_back(level);
+        self.high += level as usize;
+        if self.window.len() > self.window_len {
+            self.high -= self.window.pop_front().unwrap_or(false) as usize;
+        } else if self.window.len() < self.window_len {
+            return false;
+        }
+
+        let duty = self.high as f32 / self.window_len as f32;
+        let idle = self.idle.get_or_insert(duty);
+        let active = (duty - *idle).abs() > self.threshold;
+        if !active {
+            *idle += self.idle_alpha * (duty - *idle);
+        }
+        active
+    }
+}
+
+pub enum Segment<'a, S> {
+    Start,
+    Samples(&'a [S]),
+    End,
+}
+
+/// Splits a stream into the bursts of activity it contains, each of
+/// them surrounded by a bit of the silence around it.
+pub struct Splitter<S, D = ActivityDetector> {
+    detector: D,
+    gap: usize,
+    roll: usize,
+    active: bool,
+    silent_for: usize,
+    pre_roll: VecDeque<S>,
+    pending: Vec<S>,
+    output: Vec<S>,
+}
+
+impl<S: Sample> Splitter<S> {
+    pub fn new(sampling_rate: u32, split: SilenceSplit, roll_millis: u32) -> Self {
+        Splitter::with_detector(
+            ActivityDetector::new(sampling_rate, split.threshold),
+            sampling_rate,
+            split.min_gap,
+            roll_millis,
+        )
+    }
+}
+
+impl<S: Sample, D: DetectActivity> Splitter<S, D> {
+    /// Splits the stream with the given detector. Bursts end after
+    /// `gap_millis` of silence, and keep up to `roll_millis` of it
+    /// before and after.
+    pub fn with_detector(
+        detector: D,
+        sampling_rate: u32,
+        gap_millis: u32,
+        roll_millis: u32,
+    ) -> Self {
+        Splitter {
+            detector,
+            gap: millis_to_samples(sampling_rate, gap_millis).max(1),
+            roll: millis_to_samples(sampling_rate, roll_millis),
+            active: false,
+            silent_for: 0,
+            pre_roll: VecDeque::new(),
+            pending: Vec::new(),
+            output: Vec::new(),
+        }
+    }
+
+    /// Feeds samples into the splitter, reporting the start and end of
+    /// each burst and the samples belonging to it.
+    pub fn process<F>(&mut self, samples: &[S], mut on_segment: F) -> anyhow::Result<()>
+    where
+        F: FnMut(Segment<'_, S>) -> anyhow::Result<()>,
+    {
+        let mut output = mem::take(&mut self.output);
+
+        for &sample in samples {
+            let loud = self.detector.is_active(sample);
+
+            if !self.active {
+                if loud {
+                    self.active = true;
+                    self.silent_for = 0;
+                    on_segment(Segment::Start)?;
+                    output.extend(self.pre_roll.drain(..));
+                    output.push(sample);
+                } else {
+                    self.pre_roll.push_back(sample);
+                    if self.pre_roll.len() > self.roll {
+                        self.pre_roll.pop_front();
+                    }
+                }
+                continue;
+            }
+
+            if loud {
+                self.silent_for = 0;
+                output.append(&mut self.pending);
+                output.push(sample);
+                continue;
+            }
+
+            // Up to the post roll, silence is written right away. The
+            // rest is held back until it's known whether the burst goes
+            // on or not.
+            self.silent_for += 1;
+            if self.silent_for <= self.roll {
+                output.push(sample);
+            } else {
+                self.pending.push(sample);
+            }
+
+            if self.silent_for >= self.gap {
+                on_segment(Segment::Samples(&output))?;
+                output.clear();
+                on_segment(Segment::End)?;
+
+                self.active = false;
+                let skip = self.pending.len().saturating_sub(self.roll);
+                self.pre_roll.extend(self.pending.drain(..).skip(skip));
+            }
+        }
+
+        if !output.is_empty() {
+            on_segment(Segment::Samples(&output))?;
+            output.clear();
+        }
+        self.output = output;
+        Ok(())
+    }
+}
+
+/// Removes the silence at the start and the end of a stream. Silence
+/// in the middle of the stream is held back until the signal is active
+/// again, in memory up to `MAX_HELD_SAMPLES` and in a temporary file
+/// past that, so long gaps don't use up the memory.
+pub struct Trimmer<S> {
+    detector: ActivityDetector,
+    started: bool,
+    leading: usize,
+    pending: Vec<S>,
+    max_pending: usize,
+    /// Held silence that didn't fit in `pending`, older than it.
+    spilled: Option<File>,
+    spilled_len: usize,
+    output: Vec<S>,
+}
+
+impl<S: Sample> Trimmer<S> {
+    pub fn new(sampling_rate: u32, threshold: f32) -> Self {
+        Trimmer {
+            detector: ActivityDetector::new(sampling_rate, threshold),
+            started: false,
+            leading: 0,
+            pending: Vec::new(),
+            max_pending: MAX_HELD_SAMPLES,
+            spilled: None,
+            spilled_len: 0,
+            output: Vec::new(),
+        }
+    }
+
+    /// Holds at most the given samples of silence in memory, instead of
+    /// `MAX_HELD_SAMPLES`.
+    pub fn with_max_held(mut self, samples: usize) -> Self {
+        self.max_pending = samples.max(1);
+        self
+    }
+
+    /// Feeds samples into the trimmer, handing the ones to be kept to
+    /// `write`.
+    pub fn process<F>(&mut self, samples: &[S], mut write: F) -> anyhow::Result<()>
+    where
+        F: FnMut(&[S]) -> anyhow::Result<()>,
+    {
+        self.output.clear();
+        for &sample in samples {
+            let loud = self.detector.is_active(sample);
+            if !self.started {
+                if loud {
+                    self.started = true;
+                    self.output.push(sample);
+                } else {
+                    self.leading += 1;
+                }
+            } else if loud {
+                if self.spilled_len > 0 {
+                    write(&self.output)?;
+                    self.output.clear();
+                    self.unspill(&mut write)?;
+                }
+                self.output.append(&mut self.pending);
+                self.output.push(sample);
+            } else {
+                self.pending.push(sample);
+                if self.pending.len() >= self.max_pending {
+                    self.spill()?;
+                }
+            }
+        }
+        write(&self.output)
+    }
+
+    /// Moves the silence held in memory to the end of the temporary
+    /// file.
+    fn spill(&mut self) -> anyhow::Result<()> {
+        let file = match &mut self.spilled {
+            Some(file) => file,
+            None => self.spilled.insert(spill_file()?),
+        };
+        let bytes: Vec<u8> = self
+            .pending
+            .iter()
+            .flat_map(|sample| sample.to_f32().to_le_bytes())
+            .collect();
+        file.write_all(&bytes)
+            .context("Couldn't hold the silence in a temporary file")?;
+        self.spilled_len += self.pending.len();
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Writes the silence of the temporary file, emptying it.
+    fn unspill<F>(&mut self, write: &mut F) -> anyhow::Result<()>
+    where
+        F: FnMut(&[S]) -> anyhow::Result<()>,
+    {
+        let file = match &mut self.spilled {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+        file.seek(SeekFrom::Start(0))?;
+        let chunk = SPILL_CHUNK_SAMPLES.min(self.max_pending);
+        let mut bytes = vec![0; chunk * 4];
+        let mut left = self.spilled_len;
+        while left > 0 {
+            let len = left.min(chunk);
+            file.read_exact(&mut bytes[..len * 4])
+                .context("Couldn't read back the silence held in a temporary file")?;
+            self.output.clear();
+            self.output.extend(
+                bytes[..len * 4]
+                    .chunks_exact(4)
+                    .map(|value| S::from_f32(f32::from_le_bytes(value.try_into().unwrap()))),
+            );
+            write(&self.output)?;
+            left -= len;
+        }
+        self.output.clear();
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        self.spilled_len = 0;
+        Ok(())
+    }
+
+    /// Discards the trailing silence, returning the number of samples
+    /// trimmed from the start and the end of the stream.
+    pub fn finish(&mut self) -> (usize, usize) {
+        let trailing = self.spilled_len + self.pending.len();
+        self.pending.clear();
+        self.spilled = None;
+        self.spilled_len = 0;
+        (self.leading, trailing)
+    }
+}
+
+/// Opens a temporary file for the held silence, removed right away so
+/// it's gone once closed, even if the program is killed.
+fn spill_file() -> anyhow::Result<File> {
+    let path = env::temp_dir().join(format!("esp32-samples-reader-silence-{}", process::id()));
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .with_context(|| format!("Couldn't create {}", path.display()))?;
+    fs::remove_file(&path)?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trimmer_spills_long_gaps_keeping_their_order() {
+        let mut trimmer = Trimmer::new(1000, 0.5).with_max_held(4);
+        // Leading silence, a burst, a gap longer than what's held in
+        // memory, another burst and trailing silence.
+        let mut input = vec![0i16; 5];
+        input.extend([32000, -32000]);
+        input.extend((1..=10).map(|i| i * 100));
+        input.extend([32000, -32000]);
+        input.extend([0; 9]);
+
+        let mut output = Vec::new();
+        for chunk in input.chunks(3) {
+            trimmer
+                .process(chunk, |samples| {
+                    output.extend_from_slice(samples);
+                    Ok(())
+                })
+                .unwrap();
+        }
+        assert_eq!(output, input[5..input.len() - 9]);
+        assert_eq!(trimmer.finish(), (5, 9));
+    }
+}
@@ -0,0 +1,70 @@
+use std::{f32::consts::TAU, sync::Arc};
+
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+
+/// Magnitude spectrum of consecutive blocks of samples, through a Hann
+/// windowed FFT.
+pub struct Spectrum {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    block: Vec<f32>,
+    buffer: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+    magnitudes: Vec<f32>,
+    /// Scales the bins to the amplitude of a full scale sine.
+    gain: f32,
+}
+
+impl Spectrum {
+    pub fn new(block_len: usize) -> Spectrum {
+        let fft = FftPlanner::new().plan_fft_forward(block_len);
+        let window: Vec<f32> = (0..block_len)
+            .map(|i| 0.5 - 0.5 * (TAU * i as f32 / block_len as f32).cos())
+            .collect();
+        let gain = 2.0 / window.iter().sum::<f32>();
+        Spectrum {
+            scratch: vec![Complex::default(); fft.get_inplace_scratch_len()],
+            fft,
+            window,
+            block: Vec::with_capacity(block_len),
+            buffer: Vec::with_capacity(block_len),
+            magnitudes: vec![0.0; block_len / 2 + 1],
+            gain,
+        }
+    }
+
+    pub fn block_len(&self) -> usize {
+        self.window.len()
+    }
+
+    /// Frequency of the given bin for samples at the given rate.
+    pub fn bin_frequency(&self, bin: usize, sampling_rate: u32) -> f32 {
+        bin as f32 * sampling_rate as f32 / self.block_len() as f32
+    }
+
+    /// Calls on_block with the amplitude of every bin, from 0 Hz up to
+    /// half the sampling rate, for each block completed by the samples.
+    pub fn process<F: FnMut(&[f32])>(&mut self, samples: &[f32], mut on_block: F) {
+        for sample in samples {
+            self.block.push(*sample);
+            if self.block.len() < self.block_len() {
+                continue;
+            }
+
+            self.buffer.clear();
+            self.buffer.extend(
+                self.block
+                    .iter()
+                    .zip(&self.window)
+                    .map(|(sample, weight)| Complex::new(sample * weight, 0.0)),
+            );
+            self.fft
+                .process_with_scratch(&mut self.buffer, &mut self.scratch);
+            for (magnitude, bin) in self.magnitudes.iter_mut().zip(&self.buffer) {
+                *magnitude = bin.norm() * self.gain;
+            }
+            self.block.clear();
+            on_block(&self.magnitudes);
+        }
+    }
+}
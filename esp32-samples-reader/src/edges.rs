@@ -0,0 +1,259 @@
+use std::io::{BufRead, ErrorKind, Write};
+
+use crate::rate;
+
+const CSV_HEADER: &str = "sample,time,level";
+/// Identifier of the signal in VCD files.
+const VCD_ID: char = '!';
+
+/// How the transitions are written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeFormat {
+    /// One `sample,time,level` line per transition, after a header
+    /// line. The time is given in seconds.
+    Csv,
+    /// One record per transition: the index of the sample as an u64
+    /// followed by the new level as an u8 (0 or 1), little endian and
+    /// without any header.
+    Binary,
+    /// Value Change Dump, as read by GTKWave or PulseView, with times
+    /// in nanoseconds.
+    Vcd,
+}
+
+/// Finds the transitions of the level of the received bit stream.
+#[derive(Default)]
+pub struct EdgeDetector {
+    index: u64,
+    level: Option<bool>,
+}
+
+impl EdgeDetector {
+    pub fn new() -> EdgeDetector {
+        EdgeDetector::default()
+    }
+
+    /// Calls on_edge with the index of the sample and the new level of
+    /// each transition found in the given bytes. The level of the very
+    /// first sample is reported as a transition too.
+    pub fn process<E, F>(&mut self, input: &[u8], mut on_edge: F) -> Result<(), E>
+    where
+        F: FnMut(u64, bool) -> Result<(), E>,
+    {
+        for byte in input {
+            for bit in (0..8).rev() {
+                let level = (byte >> bit) & 1 != 0;
+                if self.level != Some(level) {
+                    self.level = Some(level);
+                    on_edge(self.index, level)?;
+                }
+                self.index += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes transitions in the given format.
+pub struct EdgeWriter<W> {
+    output: W,
+    format: EdgeFormat,
+    sampling_rate: u32,
+}
+
+impl<W: Write> EdgeWriter<W> {
+    pub fn new(
+        mut output: W,
+        format: EdgeFormat,
+        sampling_rate: u32,
+    ) -> std::io::Result<EdgeWriter<W>> {
+        match format {
+            EdgeFormat::Csv => writeln!(output, "{}", CSV_HEADER)?,
+            EdgeFormat::Binary => (),
+            EdgeFormat::Vcd => {
+                writeln!(output, "$version esp32-samples-reader $end")?;
+                writeln!(output, "$comment Sampled at {} Hz $end", sampling_rate)?;
+                writeln!(output, "$timescale 1 ns $end")?;
+                writeln!(output, "$scope module esp32 $end")?;
+                writeln!(output, "$var wire 1 {} signal $end", VCD_ID)?;
+                writeln!(output, "$upscope $end")?;
+                writeln!(output, "$enddefinitions $end")?;
+            }
+        }
+        Ok(EdgeWriter {
+            output,
+            format,
+            sampling_rate,
+        })
+    }
+
+    pub fn write_edge(&mut self, index: u64, level: bool) -> std::io::Result<()> {
+        match self.format {
+            EdgeFormat::Csv => writeln!(
+                self.output,
+                "{},{:.9},{}",
+                index,
+                index as f64 / self.sampling_rate as f64,
+                level as u8
+            ),
+            EdgeFormat::Binary => {
+                self.output.write_all(&index.to_le_bytes())?;
+                self.output.write_all(&[level as u8])
+            }
+            EdgeFormat::Vcd => writeln!(
+                self.output,
+                "#{}\n{}{}",
+                self.vcd_time(index),
+                level as u8,
+                VCD_ID
+            ),
+        }
+    }
+
+    /// Ends the output after the given number of samples. VCD files
+    /// get the time of the end, so viewers show the signal up to it.
+    pub fn finish(&mut self, samples: u64) -> std::io::Result<()> {
+        if self.format == EdgeFormat::Vcd {
+            writeln!(self.output, "#{}", self.vcd_time(samples))?;
+        }
+        self.output.flush()
+    }
+
+    fn vcd_time(&self, index: u64) -> u128 {
+        rate::duration_of_samples(self.sampling_rate, index).as_nanos()
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// Reads transitions written by [`EdgeWriter`], telling the format
+/// apart by the CSV header.
+pub struct EdgeReader<R> {
+    input: R,
+    format: EdgeFormat,
+    line: String,
+    line_number: usize,
+}
+
+fn invalid_data(message: String) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, message)
+}
+
+impl<R: BufRead> EdgeReader<R> {
+    pub fn new(mut input: R) -> std::io::Result<EdgeReader<R>> {
+        let start = input.fill_buf()?;
+        let format = if start.starts_with(CSV_HEADER.as_bytes()) {
+            input.read_line(&mut String::new())?;
+            EdgeFormat::Csv
+        } else if start.starts_with(b"$") {
+            return Err(invalid_data("VCD files can't be read back".into()));
+        } else {
+            EdgeFormat::Binary
+        };
+
+        Ok(EdgeReader {
+            input,
+            format,
+            line: String::new(),
+            line_number: 1,
+        })
+    }
+
+    pub fn format(&self) -> EdgeFormat {
+        self.format
+    }
+
+    /// Returns the index of the sample and the new level of the next
+    /// transition, or None at the end of the input.
+    pub fn next_edge(&mut self) -> std::io::Result<Option<(u64, bool)>> {
+        match self.format {
+            EdgeFormat::Csv => {
+                self.line.clear();
+                if self.input.read_line(&mut self.line)? == 0 {
+                    return Ok(None);
+                }
+                self.line_number += 1;
+
+                let fields: Vec<&str> = self.line.trim_end().split(',').collect();
+                match fields[..] {
+                    [index, _, "0"] | [index, _, "1"] => {
+                        let index = index.parse::<u64>().map_err(|_| {
+                            invalid_data(format!(
+                                "Invalid sample index on line {}",
+                                self.line_number
+                            ))
+                        })?;
+                        Ok(Some((index, fields[2] == "1")))
+                    }
+                    _ => Err(invalid_data(format!(
+                        "Invalid transition on line {}",
+                        self.line_number
+                    ))),
+                }
+            }
+            EdgeFormat::Binary | EdgeFormat::Vcd => {
+                let mut record = [0; 9];
+                match self.input.read_exact(&mut record) {
+                    Ok(()) => (),
+                    Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                    Err(e) => return Err(e),
+                }
+                let index = u64::from_le_bytes(record[..8].try_into().unwrap());
+                Ok(Some((index, record[8] != 0)))
+            }
+        }
+    }
+}
+
+/// Rebuilds the bit stream of a signal out of its transitions, packed
+/// in bytes like the ESP32 sends it.
+#[derive(Default)]
+pub struct EdgeExpander {
+    level: bool,
+    position: u64,
+    byte: u8,
+}
+
+impl EdgeExpander {
+    pub fn new() -> EdgeExpander {
+        EdgeExpander::default()
+    }
+
+    /// Index of the next sample to be generated.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn set_level(&mut self, level: bool) {
+        self.level = level;
+    }
+
+    /// Generates the samples up to the given index (not included) at
+    /// the current level, appending the complete bytes to output.
+    pub fn fill_to(&mut self, index: u64, output: &mut Vec<u8>) {
+        while self.position < index {
+            // Whole bytes at once when possible, for long idle spans.
+            if self.position & 7 == 0 && index - self.position >= 8 {
+                let bytes = ((index - self.position) / 8) as usize;
+                output.resize(output.len() + bytes, if self.level { 0xff } else { 0 });
+                self.position += bytes as u64 * 8;
+                continue;
+            }
+
+            self.byte = (self.byte << 1) | self.level as u8;
+            self.position += 1;
+            if self.position & 7 == 0 {
+                output.push(self.byte);
+                self.byte = 0;
+            }
+        }
+    }
+
+    /// Completes the last byte, repeating the current level.
+    pub fn finish(&mut self, output: &mut Vec<u8>) {
+        let end = self.position.div_ceil(8) * 8;
+        self.fill_to(end, output);
+    }
+}
@@ -0,0 +1,433 @@
+use std::io::{Seek, SeekFrom, Write};
+
+use anyhow::anyhow;
+use hound::{SampleFormat, WavSpec};
+
+use crate::{
+    source::framed::crc8,
+    wav::{vorbis_comments, Tag},
+};
+
+/// Samples of each channel per frame.
+const BLOCK_SIZE: usize = 4096;
+/// Highest order of the fixed predictors.
+const MAX_FIXED_ORDER: usize = 4;
+/// Highest order of the partitions of the residual.
+const MAX_PARTITION_ORDER: u32 = 8;
+/// Bytes of the STREAMINFO block.
+const STREAMINFO_LEN: usize = 34;
+
+/// CRC-16 with polynomial 0x8005, protecting each frame.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter {
+            bytes: Vec::new(),
+            acc: 0,
+            bits: 0,
+        }
+    }
+
+    /// Writes the lowest `bits` bits of value, up to 32.
+    fn write(&mut self, value: u64, bits: u32) {
+        self.acc = (self.acc << bits) | (value & ((1 << bits) - 1));
+        self.bits += bits;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.bytes.push((self.acc >> self.bits) as u8);
+        }
+    }
+
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64, bits);
+    }
+
+    /// Writes value in unary, as that many zeros followed by a one.
+    fn write_unary(&mut self, mut value: u64) {
+        while value >= 32 {
+            self.write(0, 32);
+            value -= 32;
+        }
+        self.write(1, value as u32 + 1);
+    }
+
+    fn write_rice(&mut self, value: i32, parameter: u32) {
+        let folded = zigzag(value);
+        self.write_unary(folded >> parameter);
+        self.write(folded, parameter);
+    }
+
+    /// Pads the last byte with zeros.
+    fn align(&mut self) {
+        if self.bits > 0 {
+            self.write(0, 8 - self.bits);
+        }
+    }
+}
+
+fn zigzag(value: i32) -> u64 {
+    ((value << 1) ^ (value >> 31)) as u32 as u64
+}
+
+/// Frame numbers are coded like UTF-8 characters, extended to 36 bits.
+fn write_utf8_number(writer: &mut BitWriter, value: u64) {
+    if value < 0x80 {
+        writer.write(value, 8);
+        return;
+    }
+    // With n continuation bytes, the lead byte holds 6 - n bits.
+    let mut continuation = 1;
+    while value >= 1 << (5 * continuation + 6) {
+        continuation += 1;
+    }
+    let prefix = (0xff00u64 >> (continuation + 1)) & 0xff;
+    writer.write(prefix | (value >> (6 * continuation)), 8);
+    for index in (0..continuation).rev() {
+        writer.write(0x80 | ((value >> (6 * index)) & 0x3f), 8);
+    }
+}
+
+const SUBFRAME_CONSTANT: u64 = 0b000000;
+const SUBFRAME_VERBATIM: u64 = 0b000001;
+/// Combined with the order of the predictor.
+const SUBFRAME_FIXED: u64 = 0b001000;
+
+fn write_subframe_header(frame: &mut BitWriter, kind: u64) {
+    // Zero padding bit, and no wasted bits per sample.
+    frame.write(0, 1);
+    frame.write(kind, 6);
+    frame.write(0, 1);
+}
+
+/// A block coded with a fixed predictor.
+struct FixedEncoding {
+    bits: u64,
+    order: usize,
+    partition_order: u32,
+    parameters: Vec<u32>,
+    residual: Vec<i64>,
+}
+
+/// Residual of the fixed predictor of the given order.
+fn fixed_residual(samples: &[i64], order: usize, residual: &mut Vec<i64>) {
+    residual.clear();
+    residual.extend((order..samples.len()).map(|i| {
+        let s = |back: usize| samples[i - back];
+        match order {
+            0 => s(0),
+            1 => s(0) - s(1),
+            2 => s(0) - 2 * s(1) + s(2),
+            3 => s(0) - 3 * s(1) + 3 * s(2) - s(3),
+            _ => s(0) - 4 * s(1) + 6 * s(2) - 4 * s(3) + s(4),
+        }
+    }));
+}
+
+/// Bits taken by the given values coded with the best Rice parameter,
+/// along with the parameter.
+fn best_rice_parameter(values: &[i64]) -> (u64, u32) {
+    let sum: u64 = values.iter().map(|value| zigzag(*value as i32)).sum();
+    let mean = sum / values.len().max(1) as u64;
+    let estimate = (64 - mean.leading_zeros()).min(14);
+    (estimate.saturating_sub(1)..=estimate.min(13) + 1)
+        .map(|parameter| {
+            let bits: u64 = values
+                .iter()
+                .map(|value| (zigzag(*value as i32) >> parameter) + 1 + parameter as u64)
+                .sum();
+            (bits, parameter)
+        })
+        .min()
+        .unwrap_or((0, 0))
+}
+
+/// Partition order and Rice parameters taking the least bits for the
+/// residual of a block, along with the bits taken.
+fn best_partitions(residual: &[i64], order: usize) -> (u64, u32, Vec<u32>) {
+    let block_size = residual.len() + order;
+    let mut best: Option<(u64, u32, Vec<u32>)> = None;
+    for partition_order in 0..=MAX_PARTITION_ORDER {
+        let partitions = 1 << partition_order;
+        let partition_len = block_size >> partition_order;
+        if partition_len << partition_order != block_size || partition_len <= order {
+            break;
+        }
+
+        let mut bits = 0;
+        let mut parameters = Vec::with_capacity(partitions);
+        let mut start = 0;
+        for index in 0..partitions {
+            let len = if index == 0 {
+                partition_len - order
+            } else {
+                partition_len
+            };
+            let (partition_bits, parameter) = best_rice_parameter(&residual[start..start + len]);
+            bits += partition_bits + 4;
+            parameters.push(parameter);
+            start += len;
+        }
+        if best.as_ref().map(|(best_bits, _, _)| bits < *best_bits) != Some(false) {
+            best = Some((bits, partition_order, parameters));
+        }
+    }
+    best.unwrap_or((u64::MAX, 0, Vec::new()))
+}
+
+/// Fails if FLAC files can't hold samples as described by spec.
+pub fn check_spec(spec: &WavSpec) -> anyhow::Result<()> {
+    if spec.sample_format == SampleFormat::Float {
+        return Err(anyhow!(
+            "FLAC files can't hold float samples. Use a bit depth of 8, 16 or 24"
+        ));
+    }
+    if !(4..=24).contains(&spec.bits_per_sample) {
+        return Err(anyhow!(
+            "FLAC files can't hold {}-bit samples",
+            spec.bits_per_sample
+        ));
+    }
+    if !(1..=8).contains(&spec.channels) {
+        return Err(anyhow!("FLAC files hold up to 8 channels"));
+    }
+    if spec.sample_rate == 0 || spec.sample_rate >= 1 << 20 {
+        return Err(anyhow!(
+            "FLAC files can't have a sample rate of {} Hz. Decimate the signal to 1048575 Hz or less",
+            spec.sample_rate
+        ));
+    }
+    Ok(())
+}
+
+/// Writes FLAC files, with the samples of each channel predicted by
+/// the best fixed predictor and the residual Rice coded. Long runs of a
+/// constant level, usual in captures of 1-bit signals, take almost no
+/// space.
+pub struct FlacWriter<W: Write + Seek> {
+    writer: W,
+    channels: usize,
+    bits_per_sample: u32,
+    sample_rate: u32,
+    /// Interleaved samples waiting for a whole block.
+    pending: Vec<i32>,
+    frame_number: u64,
+    total_samples: u64,
+    min_frame_size: u32,
+    max_frame_size: u32,
+    streaminfo_offset: u64,
+}
+
+impl<W: Write + Seek> FlacWriter<W> {
+    /// Starts a file with integer samples as described by spec, storing
+    /// the given tags as Vorbis comments.
+    pub fn new(mut writer: W, spec: WavSpec, tags: &[Tag]) -> anyhow::Result<FlacWriter<W>> {
+        check_spec(&spec)?;
+        writer.write_all(b"fLaC")?;
+        let streaminfo_offset = writer.stream_position()? + 4;
+        let mut flac = FlacWriter {
+            writer,
+            channels: spec.channels as usize,
+            bits_per_sample: spec.bits_per_sample as u32,
+            sample_rate: spec.sample_rate,
+            pending: Vec::new(),
+            frame_number: 0,
+            total_samples: 0,
+            min_frame_size: u32::MAX,
+            max_frame_size: 0,
+            streaminfo_offset,
+        };
+        flac.write_metadata_block(0, false, &flac.streaminfo())?;
+        flac.write_metadata_block(4, true, &vorbis_comments(tags))?;
+        Ok(flac)
+    }
+
+    fn write_metadata_block(&mut self, kind: u8, last: bool, data: &[u8]) -> anyhow::Result<()> {
+        let len = (data.len() as u32).to_be_bytes();
+        self.writer
+            .write_all(&[(last as u8) << 7 | kind, len[1], len[2], len[3]])?;
+        self.writer.write_all(data)?;
+        Ok(())
+    }
+
+    fn streaminfo(&self) -> Vec<u8> {
+        let mut info = BitWriter::new();
+        info.write(BLOCK_SIZE as u64, 16);
+        info.write(BLOCK_SIZE as u64, 16);
+        let (min_frame_size, max_frame_size) = match self.max_frame_size {
+            0 => (0, 0),
+            max => (self.min_frame_size, max),
+        };
+        info.write(min_frame_size as u64, 24);
+        info.write(max_frame_size as u64, 24);
+        info.write(self.sample_rate as u64, 20);
+        info.write(self.channels as u64 - 1, 3);
+        info.write(self.bits_per_sample as u64 - 1, 5);
+        info.write(self.total_samples >> 32, 4);
+        info.write(self.total_samples & 0xffff_ffff, 32);
+        // MD5 of the audio, left as unknown.
+        info.bytes.resize(STREAMINFO_LEN, 0);
+        info.bytes
+    }
+
+    /// Adds interleaved samples to the file.
+    pub fn write(&mut self, samples: &[i32]) -> anyhow::Result<()> {
+        self.pending.extend_from_slice(samples);
+        let frame_len = BLOCK_SIZE * self.channels;
+        let whole = self.pending.len() / frame_len * frame_len;
+        if whole > 0 {
+            let pending = std::mem::take(&mut self.pending);
+            for block in pending[..whole].chunks(frame_len) {
+                self.write_frame(block)?;
+            }
+            self.pending = pending[whole..].to_vec();
+        }
+        Ok(())
+    }
+
+    fn write_frame(&mut self, block: &[i32]) -> anyhow::Result<()> {
+        let block_size = block.len() / self.channels;
+        let mut frame = BitWriter::new();
+        frame.write(0b11111111111110, 14);
+        // Reserved bit and fixed block size.
+        frame.write(0, 2);
+        // Block size stored at the end of the header, and the sample
+        // rate and size taken from STREAMINFO.
+        frame.write(0b0111, 4);
+        frame.write(0b0000, 4);
+        frame.write(self.channels as u64 - 1, 4);
+        frame.write(0b000, 3);
+        frame.write(0, 1);
+        write_utf8_number(&mut frame, self.frame_number);
+        frame.write(block_size as u64 - 1, 16);
+        let header_crc = crc8(&frame.bytes);
+        frame.write(header_crc as u64, 8);
+
+        let mut channel = Vec::with_capacity(block_size);
+        for index in 0..self.channels {
+            channel.clear();
+            channel.extend(
+                block
+                    .iter()
+                    .skip(index)
+                    .step_by(self.channels)
+                    .map(|sample| *sample as i64),
+            );
+            self.write_subframe(&mut frame, &channel);
+        }
+        frame.align();
+        let crc = crc16(&frame.bytes);
+        frame.write(crc as u64, 16);
+
+        self.writer.write_all(&frame.bytes)?;
+        let size = frame.bytes.len() as u32;
+        self.min_frame_size = self.min_frame_size.min(size);
+        self.max_frame_size = self.max_frame_size.max(size);
+        self.frame_number += 1;
+        self.total_samples += block_size as u64;
+        Ok(())
+    }
+
+    fn write_subframe(&self, frame: &mut BitWriter, samples: &[i64]) {
+        let bits = self.bits_per_sample;
+        if samples.iter().all(|sample| *sample == samples[0]) {
+            write_subframe_header(frame, SUBFRAME_CONSTANT);
+            frame.write_signed(samples[0], bits);
+            return;
+        }
+
+        let mut residual = Vec::with_capacity(samples.len());
+        let mut best: Option<FixedEncoding> = None;
+        for order in 0..=MAX_FIXED_ORDER.min(samples.len() - 1) {
+            fixed_residual(samples, order, &mut residual);
+            let (residual_bits, partition_order, parameters) = best_partitions(&residual, order);
+            let bits = residual_bits.saturating_add(order as u64 * bits as u64);
+            if best.as_ref().map(|best| bits < best.bits) != Some(false) {
+                best = Some(FixedEncoding {
+                    bits,
+                    order,
+                    partition_order,
+                    parameters,
+                    residual: residual.clone(),
+                });
+            }
+        }
+
+        match best {
+            Some(FixedEncoding {
+                bits: total,
+                order,
+                partition_order,
+                parameters,
+                residual,
+            }) if total < samples.len() as u64 * bits as u64 => {
+                write_subframe_header(frame, SUBFRAME_FIXED | order as u64);
+                for sample in &samples[..order] {
+                    frame.write_signed(*sample, bits);
+                }
+                // Rice coding with 4-bit parameters.
+                frame.write(0b00, 2);
+                frame.write(partition_order as u64, 4);
+                let partition_len = samples.len() >> partition_order;
+                let mut start = 0;
+                for (index, parameter) in parameters.iter().enumerate() {
+                    let len = if index == 0 {
+                        partition_len - order
+                    } else {
+                        partition_len
+                    };
+                    frame.write(*parameter as u64, 4);
+                    for value in &residual[start..start + len] {
+                        frame.write_rice(*value as i32, *parameter);
+                    }
+                    start += len;
+                }
+            }
+            _ => {
+                write_subframe_header(frame, SUBFRAME_VERBATIM);
+                for sample in samples {
+                    frame.write_signed(*sample, bits);
+                }
+            }
+        }
+    }
+
+    /// Pushes the frames written so far to the file. Samples that don't
+    /// fill a whole block yet wait for the next ones.
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the samples left and completes the STREAMINFO block.
+    pub fn finalize(mut self) -> anyhow::Result<()> {
+        let pending = std::mem::take(&mut self.pending);
+        if !pending.is_empty() {
+            self.write_frame(&pending)?;
+        }
+        let streaminfo = self.streaminfo();
+        self.writer.seek(SeekFrom::Start(self.streaminfo_offset))?;
+        self.writer.write_all(&streaminfo)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
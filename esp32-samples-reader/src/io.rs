@@ -1,9 +1,17 @@
 use std::{
-    io::ErrorKind,
+    io::{ErrorKind, Read, Seek, SeekFrom, Write},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     time::Duration,
 };
 
-use serialport::TTYPort;
+use anyhow::{anyhow, Context as _};
+use serialport::{SerialPort, TTYPort};
+
+use crate::dsp::I24;
 
 #[inline(always)]
 pub fn bit_sample_to_signed8(sample: bool) -> i8 {
@@ -14,6 +22,24 @@ pub fn bit_sample_to_signed8(sample: bool) -> i8 {
     }
 }
 
+#[inline(always)]
+pub fn bit_sample_to_signed16(sample: bool) -> i16 {
+    if sample {
+        i16::MAX
+    } else {
+        i16::MIN
+    }
+}
+
+#[inline(always)]
+pub fn bit_sample_to_signed24(sample: bool) -> I24 {
+    if sample {
+        I24::MAX
+    } else {
+        I24::MIN
+    }
+}
+
 #[inline(always)]
 pub fn bit_sample_to_unsigned8_full_range(sample: bool) -> u8 {
     if sample {
@@ -32,6 +58,15 @@ pub fn bit_sample_to_unsigned8_half_range(sample: bool) -> u8 {
     }
 }
 
+#[inline(always)]
+pub fn bit_sample_to_f32(sample: bool) -> f32 {
+    if sample {
+        1.0
+    } else {
+        -1.0
+    }
+}
+
 #[inline(always)]
 pub fn decode_esp32_sample(input: u8) -> [i8; 8] {
     [
@@ -42,10 +77,51 @@ pub fn decode_esp32_sample(input: u8) -> [i8; 8] {
         bit_sample_to_signed8(((input >> 3) & 1) != 0),
         bit_sample_to_signed8(((input >> 2) & 1) != 0),
         bit_sample_to_signed8(((input >> 1) & 1) != 0),
-        bit_sample_to_signed8(((input >> 0) & 1) != 0),
+        bit_sample_to_signed8((input & 1) != 0),
     ]
 }
 
+#[inline(always)]
+pub fn decode_esp32_sample_signed16(input: u8) -> [i16; 8] {
+    [
+        bit_sample_to_signed16(((input >> 7) & 1) != 0),
+        bit_sample_to_signed16(((input >> 6) & 1) != 0),
+        bit_sample_to_signed16(((input >> 5) & 1) != 0),
+        bit_sample_to_signed16(((input >> 4) & 1) != 0),
+        bit_sample_to_signed16(((input >> 3) & 1) != 0),
+        bit_sample_to_signed16(((input >> 2) & 1) != 0),
+        bit_sample_to_signed16(((input >> 1) & 1) != 0),
+        bit_sample_to_signed16((input & 1) != 0),
+    ]
+}
+
+#[inline(always)]
+pub fn decode_esp32_sample_signed24(input: u8) -> [I24; 8] {
+    [
+        bit_sample_to_signed24(((input >> 7) & 1) != 0),
+        bit_sample_to_signed24(((input >> 6) & 1) != 0),
+        bit_sample_to_signed24(((input >> 5) & 1) != 0),
+        bit_sample_to_signed24(((input >> 4) & 1) != 0),
+        bit_sample_to_signed24(((input >> 3) & 1) != 0),
+        bit_sample_to_signed24(((input >> 2) & 1) != 0),
+        bit_sample_to_signed24(((input >> 1) & 1) != 0),
+        bit_sample_to_signed24((input & 1) != 0),
+    ]
+}
+
+/// Decodes the bits of a byte into the given levels, for outputs with
+/// user-chosen amplitudes.
+#[inline(always)]
+pub fn decode_esp32_sample_levels<S: Copy>(input: u8, low: S, high: S) -> [S; 8] {
+    std::array::from_fn(|bit| {
+        if (input >> (7 - bit)) & 1 != 0 {
+            high
+        } else {
+            low
+        }
+    })
+}
+
 #[inline(always)]
 pub fn decode_esp32_sample_unsigned_full_range(input: u8) -> [u8; 8] {
     [
@@ -56,7 +132,7 @@ pub fn decode_esp32_sample_unsigned_full_range(input: u8) -> [u8; 8] {
         bit_sample_to_unsigned8_full_range(((input >> 3) & 1) != 0),
         bit_sample_to_unsigned8_full_range(((input >> 2) & 1) != 0),
         bit_sample_to_unsigned8_full_range(((input >> 1) & 1) != 0),
-        bit_sample_to_unsigned8_full_range(((input >> 0) & 1) != 0),
+        bit_sample_to_unsigned8_full_range((input & 1) != 0),
     ]
 }
 
@@ -70,18 +146,363 @@ pub fn decode_esp32_sample_unsigned_half_range(input: u8) -> [u8; 8] {
         bit_sample_to_unsigned8_half_range(((input >> 3) & 1) != 0),
         bit_sample_to_unsigned8_half_range(((input >> 2) & 1) != 0),
         bit_sample_to_unsigned8_half_range(((input >> 1) & 1) != 0),
-        bit_sample_to_unsigned8_half_range(((input >> 0) & 1) != 0),
+        bit_sample_to_unsigned8_half_range((input & 1) != 0),
+    ]
+}
+
+#[inline(always)]
+pub fn decode_esp32_sample_f32(input: u8) -> [f32; 8] {
+    [
+        bit_sample_to_f32(((input >> 7) & 1) != 0),
+        bit_sample_to_f32(((input >> 6) & 1) != 0),
+        bit_sample_to_f32(((input >> 5) & 1) != 0),
+        bit_sample_to_f32(((input >> 4) & 1) != 0),
+        bit_sample_to_f32(((input >> 3) & 1) != 0),
+        bit_sample_to_f32(((input >> 2) & 1) != 0),
+        bit_sample_to_f32(((input >> 1) & 1) != 0),
+        bit_sample_to_f32((input & 1) != 0),
     ]
 }
 
-pub fn open_serial_port(path: &str, baud_rate: u32, timeout: Duration) -> anyhow::Result<TTYPort> {
-    Ok(serialport::new(path, baud_rate)
-        .data_bits(serialport::DataBits::Eight)
-        .stop_bits(serialport::StopBits::One)
-        .parity(serialport::Parity::None)
-        .flow_control(serialport::FlowControl::None)
+/// Expands each bit of input into a byte of output, most significant
+/// bit first: `high` for ones and `low` for zeros. The bulk version of
+/// the `decode_esp32_sample*` functions for 8-bit samples, expanding
+/// two bytes at once with SSE2 (x86_64) or NEON (aarch64).
+///
+/// Panics if output isn't 8 times as long as input.
+pub fn decode_block(input: &[u8], output: &mut [u8], low: u8, high: u8) {
+    assert_eq!(output.len(), input.len() * 8);
+    let done = decode_block_simd(input, output, low, high);
+    decode_block_scalar(&input[done..], &mut output[done * 8..], low, high);
+}
+
+fn decode_block_scalar(input: &[u8], output: &mut [u8], low: u8, high: u8) {
+    for (byte, samples) in input.iter().zip(output.chunks_exact_mut(8)) {
+        for (bit, sample) in (0..8).rev().zip(samples) {
+            *sample = if (byte >> bit) & 1 != 0 { high } else { low };
+        }
+    }
+}
+
+/// Expands as many pairs of bytes as there are in input, returning
+/// how many bytes were expanded.
+#[cfg(target_arch = "x86_64")]
+fn decode_block_simd(input: &[u8], output: &mut [u8], low: u8, high: u8) -> usize {
+    use std::arch::x86_64::*;
+
+    // The mask of the bit of each sample, most significant first
+    // within each byte.
+    // SAFETY: SSE2 is part of the x86_64 baseline, and the stores are
+    // unaligned ones of 16 bytes, the length of each chunk of samples.
+    unsafe {
+        let bits = _mm_set_epi8(1, 2, 4, 8, 16, 32, 64, -128, 1, 2, 4, 8, 16, 32, 64, -128);
+        let low_v = _mm_set1_epi8(low as i8);
+        let flip = _mm_set1_epi8((low ^ high) as i8);
+        for (pair, samples) in input.chunks_exact(2).zip(output.chunks_exact_mut(16)) {
+            // The first byte repeated in the lower 8 lanes and the
+            // second one in the upper 8.
+            let v = _mm_cvtsi32_si128(u16::from_le_bytes([pair[0], pair[1]]) as i32);
+            let v = _mm_unpacklo_epi8(v, v);
+            let v = _mm_unpacklo_epi16(v, v);
+            let v = _mm_unpacklo_epi32(v, v);
+            let set = _mm_cmpeq_epi8(_mm_and_si128(v, bits), bits);
+            let v = _mm_xor_si128(low_v, _mm_and_si128(set, flip));
+            _mm_storeu_si128(samples.as_mut_ptr() as *mut __m128i, v);
+        }
+    }
+    input.len() / 2 * 2
+}
+
+#[cfg(target_arch = "aarch64")]
+fn decode_block_simd(input: &[u8], output: &mut [u8], low: u8, high: u8) -> usize {
+    use std::arch::aarch64::*;
+
+    const BITS: [u8; 16] = [128, 64, 32, 16, 8, 4, 2, 1, 128, 64, 32, 16, 8, 4, 2, 1];
+    // SAFETY: NEON is part of the aarch64 baseline, and the loads and
+    // stores are of 16 bytes, the length of BITS and of each chunk of
+    // samples.
+    unsafe {
+        let bits = vld1q_u8(BITS.as_ptr());
+        let low_v = vdupq_n_u8(low);
+        let high_v = vdupq_n_u8(high);
+        for (pair, samples) in input.chunks_exact(2).zip(output.chunks_exact_mut(16)) {
+            let v = vcombine_u8(vdup_n_u8(pair[0]), vdup_n_u8(pair[1]));
+            let set = vtstq_u8(v, bits);
+            vst1q_u8(samples.as_mut_ptr(), vbslq_u8(set, high_v, low_v));
+        }
+    }
+    input.len() / 2 * 2
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn decode_block_simd(_input: &[u8], _output: &mut [u8], _low: u8, _high: u8) -> usize {
+    0
+}
+
+/// Order in which the firmware shifts the sampled bits into each byte.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BitOrder {
+    MsbFirst,
+    LsbFirst,
+}
+
+impl FromStr for BitOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "msb" => Ok(BitOrder::MsbFirst),
+            "lsb" => Ok(BitOrder::LsbFirst),
+            _ => Err(format!("Invalid bit order '{}'. Expected msb or lsb", s)),
+        }
+    }
+}
+
+/// Turns a byte sent with the given bit order, and with inverted logic
+/// levels if requested, into the MSB-first and active-high byte the
+/// decode functions expect.
+#[inline(always)]
+pub fn normalize_esp32_byte(input: u8, bit_order: BitOrder, invert: bool) -> u8 {
+    let byte = match bit_order {
+        BitOrder::MsbFirst => input,
+        BitOrder::LsbFirst => input.reverse_bits(),
+    };
+    if invert {
+        !byte
+    } else {
+        byte
+    }
+}
+
+/// Reader normalizing the bytes of firmware that doesn't send them
+/// MSB-first and active-high, so they can go through the usual
+/// decoders.
+pub struct NormalizedInput<R> {
+    inner: R,
+    bit_order: BitOrder,
+    invert: bool,
+}
+
+impl<R: Read> NormalizedInput<R> {
+    pub fn new(inner: R, bit_order: BitOrder, invert: bool) -> NormalizedInput<R> {
+        NormalizedInput {
+            inner,
+            bit_order,
+            invert,
+        }
+    }
+}
+
+impl<R: Read> Read for NormalizedInput<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        for byte in &mut buf[..len] {
+            *byte = normalize_esp32_byte(*byte, self.bit_order, self.invert);
+        }
+        Ok(len)
+    }
+}
+
+/// Count of bytes shared by the writers of a sink, like the files of
+/// a split recording.
+#[derive(Clone, Default)]
+pub struct ByteCounter(Arc<AtomicU64>);
+
+impl ByteCounter {
+    pub fn add(&self, len: u64) {
+        self.0.fetch_add(len, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Writer adding the bytes written to the inner writer to a counter.
+pub struct CountingWriter<W> {
+    inner: W,
+    counter: ByteCounter,
+}
+
+impl<W> CountingWriter<W> {
+    pub fn new(inner: W, counter: ByteCounter) -> CountingWriter<W> {
+        CountingWriter { inner, counter }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.counter.add(len as u64);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Seek> Seek for CountingWriter<W> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// CRC-32 with polynomial 0xEDB88320 (reflected), as used by zip and
+/// gzip. Continues the given CRC, 0 for the first data.
+pub fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    crc = !crc;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+}
+
+impl FromStr for Parity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Parity::None),
+            "odd" => Ok(Parity::Odd),
+            "even" => Ok(Parity::Even),
+            _ => Err(format!(
+                "Invalid parity '{}'. Expected none, odd or even",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlowControl {
+    None,
+    /// RTS/CTS.
+    Hardware,
+    /// XON/XOFF.
+    Software,
+}
+
+impl FromStr for FlowControl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(FlowControl::None),
+            "hardware" => Ok(FlowControl::Hardware),
+            "software" => Ok(FlowControl::Software),
+            _ => Err(format!(
+                "Invalid flow control '{}'. Expected none, hardware or software",
+                s
+            )),
+        }
+    }
+}
+
+/// Line settings of the serial port, other than the baud rate. 8N1
+/// without flow control by default, as the firmware uses.
+#[derive(Clone, Copy, Debug)]
+pub struct SerialConfig {
+    /// From 5 to 8.
+    pub data_bits: u8,
+    pub parity: Parity,
+    /// 1 or 2.
+    pub stop_bits: u8,
+    pub flow_control: FlowControl,
+    /// Levels the DTR and RTS lines are set to right after opening the
+    /// port. Left as the driver sets them if not given.
+    pub dtr: Option<bool>,
+    pub rts: Option<bool>,
+}
+
+impl Default for SerialConfig {
+    fn default() -> SerialConfig {
+        SerialConfig {
+            data_bits: 8,
+            parity: Parity::None,
+            stop_bits: 1,
+            flow_control: FlowControl::None,
+            dtr: None,
+            rts: None,
+        }
+    }
+}
+
+impl SerialConfig {
+    /// Bits sent on the line for each byte, counting the start, parity
+    /// and stop bits.
+    pub fn frame_bits(&self) -> u32 {
+        1 + self.data_bits as u32 + (self.parity != Parity::None) as u32 + self.stop_bits as u32
+    }
+}
+
+pub fn open_serial_port(
+    path: &str,
+    baud_rate: u32,
+    timeout: Duration,
+    config: &SerialConfig,
+) -> anyhow::Result<TTYPort> {
+    let data_bits = match config.data_bits {
+        5 => serialport::DataBits::Five,
+        6 => serialport::DataBits::Six,
+        7 => serialport::DataBits::Seven,
+        8 => serialport::DataBits::Eight,
+        bits => return Err(anyhow!("Invalid number of data bits: {}", bits)),
+    };
+    let stop_bits = match config.stop_bits {
+        1 => serialport::StopBits::One,
+        2 => serialport::StopBits::Two,
+        bits => return Err(anyhow!("Invalid number of stop bits: {}", bits)),
+    };
+    let parity = match config.parity {
+        Parity::None => serialport::Parity::None,
+        Parity::Odd => serialport::Parity::Odd,
+        Parity::Even => serialport::Parity::Even,
+    };
+    if config.flow_control == FlowControl::Hardware && config.rts.is_some() {
+        return Err(anyhow!(
+            "RTS can't be set with hardware flow control, which drives it"
+        ));
+    }
+    let flow_control = match config.flow_control {
+        FlowControl::None => serialport::FlowControl::None,
+        FlowControl::Hardware => serialport::FlowControl::Hardware,
+        FlowControl::Software => serialport::FlowControl::Software,
+    };
+    let mut port = serialport::new(path, baud_rate)
+        .data_bits(data_bits)
+        .stop_bits(stop_bits)
+        .parity(parity)
+        .flow_control(flow_control)
         .timeout(timeout)
-        .open_native()?)
+        .open_native()?;
+    // Linux raises both lines on open, which the auto-reset circuit of
+    // most ESP32 boards ignores as long as they stay equal. RTS goes
+    // first: RTS raised with DTR released pulls EN low and resets the
+    // chip, while the opposite only holds GPIO0 low for a moment.
+    if let Some(level) = config.rts {
+        port.write_request_to_send(level)
+            .with_context(|| format!("Can't set RTS of {}", path))?;
+    }
+    if let Some(level) = config.dtr {
+        port.write_data_terminal_ready(level)
+            .with_context(|| format!("Can't set DTR of {}", path))?;
+    }
+    Ok(port)
 }
 
 pub fn recover_if_interrupted<A, F: FnOnce() -> std::io::Result<A>, R: FnOnce() -> A>(
@@ -97,6 +518,22 @@ pub fn recover_if_interrupted<A, F: FnOnce() -> std::io::Result<A>, R: FnOnce()
     }
 }
 
+/// Reads from the input until the buffer is full or the input ends,
+/// returning the bytes read. Fewer than the length of the buffer are
+/// only read at the end of the input, and none once it has ended.
+pub fn read_input<R: Read + ?Sized>(input: &mut R, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match input.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(len) => filled += len,
+            Err(e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
 pub fn retry_if_interrupted<
     A,
     E,
@@ -110,14 +547,12 @@ pub fn retry_if_interrupted<
     while {
         result = f();
 
-        let should_retry = match &result {
+        match &result {
             Ok(_) => false,
             Err(e) => error_mapper(e)
                 .map(|e| e.kind() == ErrorKind::Interrupted)
                 .unwrap_or(false),
-        };
-
-        should_retry
+        }
     } {}
 
     result
@@ -0,0 +1,28 @@
+//! Reading of the 1-bit signals sampled by the ESP32 firmware.
+//!
+//! The firmware sends the sampled bits packed in bytes, most
+//! significant bit first. [`pipeline`] ties together the three steps
+//! of reading them: a [`pipeline::SerialSampleSource`] provides the
+//! raw bytes, a [`pipeline::SampleDecoder`] turns each byte into
+//! samples, which go through a [`dsp::Chain`] and end up in a
+//! [`pipeline::SinkWriter`].
+
+pub mod channels;
+pub mod decode;
+pub mod dsp;
+pub mod edges;
+pub mod flac;
+pub mod io;
+pub mod metrics;
+#[cfg(feature = "opus")]
+pub mod opus;
+pub mod pipeline;
+pub mod rate;
+pub mod segment;
+pub mod sigrok;
+#[cfg(feature = "network")]
+pub mod sink;
+pub mod source;
+pub mod state;
+pub mod trigger;
+pub mod wav;
@@ -0,0 +1,65 @@
+//! Diagnostics of the program, written to stderr through tracing,
+//! either as text for people or as JSON lines for the services running
+//! it.
+
+use std::io::{self, IsTerminal};
+
+use clap::ValueEnum;
+use tracing::level_filters::LevelFilter;
+
+use crate::status;
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> LevelFilter {
+        match level {
+            LogLevel::Off => LevelFilter::OFF,
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// One line per message, like the rest of the output of the
+    /// program.
+    Text,
+    /// One JSON object per line, with the time, the level and the
+    /// spans each message comes from.
+    Json,
+}
+
+pub fn init(level: LogLevel, format: LogFormat) {
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(LevelFilter::from(level))
+        // Messages start on a line of their own, instead of over the
+        // status line of the capture.
+        .with_writer(|| {
+            status::finish_line();
+            io::stderr()
+        });
+    match format {
+        // The time and the origin of each message are left to the
+        // JSON output, as they only get in the way of reading the
+        // progress of a capture.
+        LogFormat::Text => builder
+            .without_time()
+            .with_target(false)
+            .with_ansi(io::stderr().is_terminal())
+            .init(),
+        LogFormat::Json => builder.json().init(),
+    }
+}
@@ -1,28 +1,293 @@
+pub mod cancel;
 pub mod commands;
+pub mod config;
 pub mod ctrlc;
-pub mod io;
+pub mod logging;
+#[cfg(feature = "network")]
+pub mod prometheus;
+pub mod status;
 
-use clap::{Parser, Subcommand};
-use commands::{pulse_stream::PulseStreamArgs, read_wav::ReadWavArgs};
-use std::process::ExitCode;
+#[cfg(feature = "network")]
+use esp32_signal::sink;
+use esp32_signal::{
+    channels, decode, dsp, edges, flac, io, metrics, pipeline, rate, segment, sigrok, source,
+    state, trigger, wav,
+};
+
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+#[cfg(feature = "alsa")]
+use commands::alsa_stream::AlsaStreamArgs;
+#[cfg(feature = "network")]
+use commands::multicast_stream::MulticastStreamArgs;
+#[cfg(feature = "pipewire")]
+use commands::pipewire_stream::PipewireStreamArgs;
+#[cfg(feature = "tui")]
+use commands::scope::ScopeArgs;
+#[cfg(feature = "pulse")]
+use commands::{
+    ctl::CtlArgs, daemon::DaemonArgs, pulse_stream::PulseStreamArgs, supervise::SuperviseArgs,
+};
+
+#[cfg(feature = "analysis")]
+use commands::{analyze::AnalyzeArgs, probe::ProbeArgs};
+use commands::{
+    attach::AttachArgs, compare_decode::CompareDecodeArgs, config::ConfigArgs,
+    convert::ConvertArgs, detect::DetectArgs, dump::DumpArgs, estimate::EstimateArgs,
+    list_ports::ListPortsArgs, read_raw::ReadRawArgs, read_wav::ReadWavArgs,
+    round_robin::RoundRobinArgs, status::StatusArgs, stream::StreamArgs,
+    verify_golden::VerifyGoldenArgs,
+};
+use logging::{LogFormat, LogLevel};
+use source::{usb, SourceSpec};
+use state::CaptureState;
+#[cfg(feature = "network")]
+use std::net::SocketAddr;
+use std::{ffi::OsString, process::ExitCode};
+
+#[cfg(all(
+    feature = "offline-capable",
+    any(
+        feature = "pulse",
+        feature = "alsa",
+        feature = "pipewire",
+        feature = "udev",
+        feature = "opus"
+    )
+))]
+compile_error!(
+    "offline-capable builds can't enable features with C dependencies (pulse, alsa, pipewire, udev, opus)"
+);
 
 #[derive(Subcommand)]
 enum Commands {
     ReadWav(ReadWavArgs),
+    ReadRaw(ReadRawArgs),
+    Dump(DumpArgs),
+    Convert(ConvertArgs),
+    CompareDecode(CompareDecodeArgs),
+    Estimate(EstimateArgs),
+    RoundRobin(RoundRobinArgs),
+    /// Streams the samples to one or more outputs at once.
+    Stream(StreamArgs),
+    #[cfg(feature = "pulse")]
     PulseStream(PulseStreamArgs),
+    /// Streams to Pulse like pulse-stream, recording the stream when
+    /// asked to through ctl.
+    #[cfg(feature = "pulse")]
+    Daemon(DaemonArgs),
+    /// Controls a running daemon.
+    #[cfg(feature = "pulse")]
+    Ctl(CtlArgs),
+    /// Runs a daemon for every board plugged in, as they come and go.
+    #[cfg(feature = "pulse")]
+    Supervise(SuperviseArgs),
+    #[cfg(feature = "alsa")]
+    AlsaStream(AlsaStreamArgs),
+    #[cfg(feature = "pipewire")]
+    PipewireStream(PipewireStreamArgs),
+    #[cfg(feature = "network")]
+    MulticastStream(MulticastStreamArgs),
+    #[cfg(feature = "analysis")]
+    Analyze(AnalyzeArgs),
+    #[cfg(feature = "analysis")]
+    Probe(ProbeArgs),
+    Detect(DetectArgs),
+    #[cfg(feature = "tui")]
+    Scope(ScopeArgs),
+    ListPorts(ListPortsArgs),
+    Attach(AttachArgs),
+    Status(StatusArgs),
+    Config(ConfigArgs),
+    /// Checks the decoders against the golden files of the test data.
+    #[command(hide = true)]
+    VerifyGolden(VerifyGoldenArgs),
 }
 
 #[derive(Parser)]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Take the values of the given profile of the config files.
+    #[arg(long, global = true, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Least severe messages shown.
+    #[arg(long, global = true, value_name = "LEVEL", default_value = "info")]
+    log_level: LogLevel,
+
+    /// Format of the messages: text, or JSON lines for services.
+    #[arg(long, global = true, value_name = "FORMAT", default_value = "text")]
+    log_format: LogFormat,
+
+    /// Serve the counters of the capture for Prometheus on the given
+    /// address, like 0.0.0.0:9100.
+    #[cfg(feature = "network")]
+    #[arg(long, global = true, value_name = "ADDRESS")]
+    metrics_listen: Option<SocketAddr>,
+}
+
+/// Port used by the command being run, if it has any.
+fn selected_port(matches: &ArgMatches) -> Option<String> {
+    let mut matches = matches;
+    while let Some((_, subcommand)) = matches.subcommand() {
+        matches = subcommand;
+    }
+
+    if let Ok(Some(true)) = matches.try_get_one::<bool>("auto") {
+        return usb::find_esp32_port().ok();
+    }
+    if let Ok(Some(port)) = matches.try_get_one::<String>("port") {
+        return usb::resolve_port(port).ok();
+    }
+    match matches.try_get_one::<SourceSpec>("source") {
+        Ok(Some(SourceSpec::Serial(path) | SourceSpec::Midi(path))) => Some(path.clone()),
+        _ => None,
+    }
+}
+
+/// Parses the command line with the values of the config applied,
+/// including the ones bound to the board in use. `usb_serial_number`
+/// looks up the serial number of the USB device behind a port.
+fn parse_args(
+    config: &config::Config,
+    args: &[OsString],
+    usb_serial_number: impl Fn(&str) -> Option<String>,
+) -> Result<ArgMatches, clap::Error> {
+    let command = |config: &config::Config| config::apply_env(config.apply(Cli::command()));
+
+    // The values bound to the board in use can only be known once the
+    // port is, so it's looked up in a first pass that lets through the
+    // flags missing until those values are applied.
+    let device_config = command(config)
+        .ignore_errors(true)
+        .try_get_matches_from(args)
+        .ok()
+        .and_then(|matches| selected_port(&matches))
+        .and_then(|port| usb_serial_number(&port))
+        .and_then(|serial| config.for_device(&serial));
+    command(device_config.as_ref().unwrap_or(config)).try_get_matches_from(args)
 }
 
 fn main() -> anyhow::Result<ExitCode> {
-    let cli = <Cli as Parser>::parse();
+    let mut config = config::Config::load()?;
+    if let Some(profile) = config::selected_profile(std::env::args_os()) {
+        config = config.for_profile(&profile)?;
+    }
+    let args: Vec<OsString> = std::env::args_os().collect();
+    let matches =
+        parse_args(&config, &args, source::usb_serial_number).unwrap_or_else(|e| e.exit());
 
-    match &cli.command {
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    logging::init(cli.log_level, cli.log_format);
+    #[cfg(feature = "network")]
+    if let Some(address) = cli.metrics_listen {
+        prometheus::serve(address)?;
+    }
+
+    let result = match &cli.command {
         Commands::ReadWav(args) => commands::read_wav::run_write_wav_command(args),
+        Commands::ReadRaw(args) => commands::read_raw::run_read_raw_command(args),
+        Commands::Dump(args) => commands::dump::run_dump_command(args),
+        Commands::Convert(args) => commands::convert::run_convert_command(args),
+        Commands::CompareDecode(args) => commands::compare_decode::run_compare_decode_command(args),
+        Commands::Estimate(args) => commands::estimate::run_estimate_command(args),
+        Commands::RoundRobin(args) => commands::round_robin::run_round_robin_command(args),
+        Commands::Stream(args) => commands::stream::run_stream_command(args),
+        #[cfg(feature = "pulse")]
         Commands::PulseStream(args) => commands::pulse_stream::run_pulse_stream_command(args),
+        #[cfg(feature = "pulse")]
+        Commands::Daemon(args) => commands::daemon::run_daemon_command(args),
+        #[cfg(feature = "pulse")]
+        Commands::Ctl(args) => commands::ctl::run_ctl_command(args),
+        #[cfg(feature = "pulse")]
+        Commands::Supervise(args) => commands::supervise::run_supervise_command(args),
+        #[cfg(feature = "alsa")]
+        Commands::AlsaStream(args) => commands::alsa_stream::run_alsa_stream_command(args),
+        #[cfg(feature = "pipewire")]
+        Commands::PipewireStream(args) => {
+            commands::pipewire_stream::run_pipewire_stream_command(args)
+        }
+        #[cfg(feature = "network")]
+        Commands::MulticastStream(args) => {
+            commands::multicast_stream::run_multicast_stream_command(args)
+        }
+        #[cfg(feature = "analysis")]
+        Commands::Analyze(args) => commands::analyze::run_analyze_command(args),
+        #[cfg(feature = "analysis")]
+        Commands::Probe(args) => commands::probe::run_probe_command(args),
+        Commands::Detect(args) => commands::detect::run_detect_command(args),
+        #[cfg(feature = "tui")]
+        Commands::Scope(args) => commands::scope::run_scope_command(args),
+        Commands::ListPorts(args) => commands::list_ports::run_list_ports_command(args),
+        Commands::Attach(args) => commands::attach::run_attach_command(args),
+        Commands::Status(args) => commands::status::run_status_command(args),
+        Commands::Config(args) => commands::config::run_config_command(args, &config),
+        Commands::VerifyGolden(args) => commands::verify_golden::run_verify_golden_command(args),
+    };
+    state::set(match result {
+        Ok(_) => CaptureState::Stopped,
+        Err(_) => CaptureState::Error,
+    });
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::*;
+
+    /// Packs the data into SysEx messages, as read by midi: sources.
+    /// Bytes have to be below 0x80, so their high bits are all 0.
+    fn sysex(data: &[u8]) -> Vec<u8> {
+        let mut packed = Vec::new();
+        for message in data.chunks(7 * 8) {
+            packed.extend([0xF0, 0x7D]);
+            for group in message.chunks(7) {
+                packed.push(0);
+                packed.extend(group);
+            }
+            packed.push(0xF7);
+        }
+        packed
+    }
+
+    #[test]
+    fn device_tables_provide_required_flags() {
+        let dir = std::env::temp_dir().join(format!("esp32-device-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("board.syx");
+        fs::write(&input, sysex(&[0x55; 7 * 40])).unwrap();
+        let config_path = dir.join("config.toml");
+        fs::write(&config_path, "[device.ABC]\nsampling-rate = 8000\n").unwrap();
+        let output = dir.join("capture.wav");
+
+        let config = config::Config::load_from([config_path]).unwrap();
+        let source = format!("midi:{}", input.display());
+        let args: Vec<OsString> = [
+            "esp32-samples-reader",
+            "read-wav",
+            "--source",
+            &source,
+            "--output",
+            output.to_str().unwrap(),
+        ]
+        .map(OsString::from)
+        .into();
+        let matches = parse_args(&config, &args, |port| {
+            (input == Path::new(port)).then(|| "ABC".to_string())
+        })
+        .unwrap();
+        let args = match Cli::from_arg_matches(&matches).unwrap().command {
+            Commands::ReadWav(args) => args,
+            _ => panic!("Parsed as another command"),
+        };
+        commands::read_wav::run_write_wav_command(&args).unwrap();
+
+        let reader = hound::WavReader::open(&output).unwrap();
+        assert_eq!(reader.spec().sample_rate, 8000);
+        assert_eq!(reader.len(), 7 * 40 * 8);
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
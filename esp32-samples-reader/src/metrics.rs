@@ -0,0 +1,176 @@
+//! Counters of the capture run by the process, kept by the parts of
+//! the pipeline where things happen, for whatever reports on the
+//! health of the capture to read them, like --stats-json and the
+//! Prometheus endpoint.
+
+use std::{
+    fmt::{self, Display},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::Duration,
+};
+
+pub struct Metrics {
+    /// Bytes received from the source, and the bits in them.
+    pub bytes_read: AtomicU64,
+    pub samples_read: AtomicU64,
+    /// Bytes of signal lost on the way, like the frames missing from a
+    /// framed link.
+    pub bytes_dropped: AtomicU64,
+    /// Bytes waiting in the read-ahead buffer.
+    pub buffered: AtomicU64,
+    /// Size of the read-ahead buffer, zero if there's none.
+    pub buffer_capacity: AtomicU64,
+    /// Times the board came back after going away.
+    pub reconnects: AtomicU64,
+    /// Times the output ran out of samples to play.
+    pub underruns: AtomicU64,
+    /// Whether the output in use counts its underruns, which only the
+    /// ALSA and PipeWire ones do.
+    pub counts_underruns: AtomicBool,
+    /// Waits on the queues between the threads of the capture: the
+    /// read-ahead buffer, the blocks decoded for the outputs and the
+    /// samples taken by the PipeWire graph.
+    pub read_ahead_waits: QueueWaits,
+    pub decoded_waits: QueueWaits,
+    pub pipewire_waits: QueueWaits,
+}
+
+pub static METRICS: Metrics = Metrics {
+    bytes_read: AtomicU64::new(0),
+    samples_read: AtomicU64::new(0),
+    bytes_dropped: AtomicU64::new(0),
+    buffered: AtomicU64::new(0),
+    buffer_capacity: AtomicU64::new(0),
+    reconnects: AtomicU64::new(0),
+    underruns: AtomicU64::new(0),
+    counts_underruns: AtomicBool::new(false),
+    read_ahead_waits: QueueWaits::new(),
+    decoded_waits: QueueWaits::new(),
+    pipewire_waits: QueueWaits::new(),
+};
+
+/// Times a queue between two threads was found full or empty, and how
+/// long the side using it waited for the other. Queues that never
+/// wait, like the one of PipeWire, count the audio dropped or filled
+/// with silence instead.
+pub struct QueueWaits {
+    used: AtomicBool,
+    full: AtomicU64,
+    full_nanos: AtomicU64,
+    empty: AtomicU64,
+    empty_nanos: AtomicU64,
+}
+
+impl QueueWaits {
+    const fn new() -> QueueWaits {
+        QueueWaits {
+            used: AtomicBool::new(false),
+            full: AtomicU64::new(0),
+            full_nanos: AtomicU64::new(0),
+            empty: AtomicU64::new(0),
+            empty_nanos: AtomicU64::new(0),
+        }
+    }
+
+    /// Marks the queue as part of the capture, for reporting it even
+    /// if it never had to wait.
+    pub fn start(&self) {
+        self.used.store(true, Ordering::Relaxed);
+    }
+
+    pub fn count_full(&self, waited: Duration) {
+        self.full.fetch_add(1, Ordering::Relaxed);
+        self.full_nanos
+            .fetch_add(waited.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub fn count_empty(&self, waited: Duration) {
+        self.empty.fetch_add(1, Ordering::Relaxed);
+        self.empty_nanos
+            .fetch_add(waited.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// The counts so far, if the queue was used.
+    pub fn snapshot(&self) -> Option<WaitCounts> {
+        self.used.load(Ordering::Relaxed).then(|| WaitCounts {
+            full: self.full.load(Ordering::Relaxed),
+            full_time: Duration::from_nanos(self.full_nanos.load(Ordering::Relaxed)),
+            empty: self.empty.load(Ordering::Relaxed),
+            empty_time: Duration::from_nanos(self.empty_nanos.load(Ordering::Relaxed)),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct WaitCounts {
+    pub full: u64,
+    pub full_time: Duration,
+    pub empty: u64,
+    pub empty_time: Duration,
+}
+
+impl Display for WaitCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "full {} times for {:.3} s, empty {} times for {:.3} s",
+            self.full,
+            self.full_time.as_secs_f64(),
+            self.empty,
+            self.empty_time.as_secs_f64()
+        )
+    }
+}
+
+/// Values of the counters at some point.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Snapshot {
+    pub bytes_read: u64,
+    pub samples_read: u64,
+    pub bytes_dropped: u64,
+    pub buffered: u64,
+    pub buffer_capacity: u64,
+    pub reconnects: u64,
+    /// None if the output doesn't count them.
+    pub underruns: Option<u64>,
+}
+
+impl Metrics {
+    /// Counts a chunk of bytes received from the source.
+    pub fn count_read(&self, bytes: usize) {
+        self.bytes_read.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.samples_read
+            .fetch_add(bytes as u64 * 8, Ordering::Relaxed);
+    }
+
+    /// The queues of the capture, named for the statistics.
+    pub fn queues(&self) -> [(&'static str, &QueueWaits); 3] {
+        [
+            ("Read-ahead buffer", &self.read_ahead_waits),
+            ("Decoded blocks", &self.decoded_waits),
+            ("PipeWire queue", &self.pipewire_waits),
+        ]
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            samples_read: self.samples_read.load(Ordering::Relaxed),
+            bytes_dropped: self.bytes_dropped.load(Ordering::Relaxed),
+            buffered: self.buffered.load(Ordering::Relaxed),
+            buffer_capacity: self.buffer_capacity.load(Ordering::Relaxed),
+            reconnects: self.reconnects.load(Ordering::Relaxed),
+            underruns: self
+                .counts_underruns
+                .load(Ordering::Relaxed)
+                .then(|| self.underruns.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl Snapshot {
+    /// Fraction of the read-ahead buffer in use, if there's one.
+    pub fn buffer_fill(&self) -> Option<f64> {
+        (self.buffer_capacity > 0).then(|| self.buffered as f64 / self.buffer_capacity as f64)
+    }
+}
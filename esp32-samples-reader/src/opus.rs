@@ -0,0 +1,331 @@
+use std::{io::Write, time::Duration};
+
+use anyhow::anyhow;
+use audiopus::{
+    coder::Encoder, Application, Bitrate, Channels as OpusChannels, SampleRate as OpusRate,
+};
+use hound::WavSpec;
+use ogg::writing::{PacketWriteEndInfo, PacketWriter};
+
+use crate::wav::{vorbis_comments, Tag};
+
+/// Rate Opus streams are encoded and timed at.
+const OPUS_RATE: u32 = 48000;
+/// Samples of each channel per packet, 20 ms.
+const FRAME_LEN: usize = OPUS_RATE as usize / 50;
+/// Largest packet the encoder may produce, as recommended by libopus.
+const MAX_PACKET_LEN: usize = 4000;
+/// Serial number of the only logical stream of the files.
+const STREAM_SERIAL: u32 = 0x4553_5033;
+
+/// Durations of the packets Opus can encode, in milliseconds, leaving
+/// out the 2.5 ms ones.
+const PACKET_DURATIONS: [u64; 5] = [5, 10, 20, 40, 60];
+
+/// Bitrates Opus can encode at, in kbit/s.
+const BITRATES: std::ops::RangeInclusive<u32> = 6..=510;
+
+/// Linear interpolation between two rates. Positions are kept as
+/// exact sample counts, so the output doesn't drift from the input
+/// however long it runs.
+struct Resampler {
+    from: u64,
+    to: u64,
+    channels: usize,
+    /// Interleaved input frames not used yet, the first one being
+    /// frame number `base` of the input.
+    frames: Vec<f32>,
+    base: u64,
+    produced: u64,
+}
+
+impl Resampler {
+    fn new(from: u32, to: u32, channels: usize) -> Resampler {
+        Resampler {
+            from: from as u64,
+            to: to as u64,
+            channels,
+            frames: Vec::new(),
+            base: 0,
+            produced: 0,
+        }
+    }
+
+    fn push(&mut self, input: &[f32], output: &mut Vec<f32>) {
+        let channels = self.channels;
+        self.frames.extend_from_slice(input);
+        let available = self.base + (self.frames.len() / channels) as u64;
+
+        loop {
+            // Position of the next output frame in the input, as a
+            // whole frame and a fraction of it.
+            let position = self.produced * self.from;
+            let index = position / self.to;
+            if index + 1 >= available {
+                break;
+            }
+            let fraction = (position % self.to) as f32 / self.to as f32;
+            let offset = (index - self.base) as usize * channels;
+            for channel in 0..channels {
+                let a = self.frames[offset + channel];
+                let b = self.frames[offset + channels + channel];
+                output.push(a + (b - a) * fraction);
+            }
+            self.produced += 1;
+        }
+
+        let next = (self.produced * self.from / self.to).min(available);
+        self.frames.drain(..(next - self.base) as usize * channels);
+        self.base = next;
+    }
+
+    /// Produces the output up to the end of the input, holding the
+    /// last frame.
+    fn finish(&mut self, output: &mut Vec<f32>) {
+        let channels = self.channels;
+        if self.frames.len() >= channels {
+            let last = self.frames[self.frames.len() - channels..].to_vec();
+            self.push(&last, output);
+        }
+    }
+}
+
+fn opus_channels(channels: u16) -> anyhow::Result<OpusChannels> {
+    match channels {
+        1 => Ok(OpusChannels::Mono),
+        2 => Ok(OpusChannels::Stereo),
+        _ => Err(anyhow!(
+            "Opus can't encode {} channels, only 1 or 2",
+            channels
+        )),
+    }
+}
+
+/// Fails if Opus files can't hold the signal described by spec, or be
+/// encoded at the given bitrate.
+pub fn check_spec(spec: &WavSpec, bitrate: Option<u32>) -> anyhow::Result<()> {
+    if !(1..=2).contains(&spec.channels) {
+        return Err(anyhow!(
+            "Opus files can't hold {} channels, only 1 or 2",
+            spec.channels
+        ));
+    }
+    match bitrate {
+        Some(bitrate) if !BITRATES.contains(&bitrate) => Err(anyhow!(
+            "Invalid bitrate {} kbit/s. Opus supports {} to {} kbit/s",
+            bitrate,
+            BITRATES.start(),
+            BITRATES.end()
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Writes Ogg Opus files, resampling the signal to 48 kHz, the rate
+/// of every Opus stream.
+pub struct OpusWriter<W: Write> {
+    packets: PacketWriter<W>,
+    encoder: Encoder,
+    resampler: Resampler,
+    channels: usize,
+    /// Resampled samples waiting for a whole packet.
+    pending: Vec<f32>,
+    packet: Vec<u8>,
+    /// Samples the decoder has to drop from the start of the stream.
+    pre_skip: u64,
+    encoded: u64,
+    /// Granule position of the end of the signal, known once
+    /// finalizing.
+    end_granule: Option<u64>,
+}
+
+impl<W: Write> OpusWriter<W> {
+    /// Starts a file with the signal described by spec, encoded at the
+    /// given bitrate in kbit/s, or the one chosen by the encoder if
+    /// none is given.
+    pub fn new(
+        writer: W,
+        spec: WavSpec,
+        bitrate: Option<u32>,
+        tags: &[Tag],
+    ) -> anyhow::Result<OpusWriter<W>> {
+        check_spec(&spec, bitrate)?;
+        let mut encoder = Encoder::new(
+            OpusRate::Hz48000,
+            opus_channels(spec.channels)?,
+            Application::Audio,
+        )?;
+        if let Some(bitrate) = bitrate {
+            encoder.set_bitrate(Bitrate::BitsPerSecond(bitrate as i32 * 1000))?;
+        }
+        let pre_skip = encoder.lookahead()? as u64;
+
+        let mut head = b"OpusHead".to_vec();
+        head.push(1);
+        head.push(spec.channels as u8);
+        head.extend_from_slice(&(pre_skip as u16).to_le_bytes());
+        // Rate of the original signal, for decoders that want to
+        // restore it.
+        head.extend_from_slice(&spec.sample_rate.to_le_bytes());
+        // No output gain, and mono or stereo channel mapping.
+        head.extend_from_slice(&0u16.to_le_bytes());
+        head.push(0);
+
+        let mut comments = b"OpusTags".to_vec();
+        comments.extend_from_slice(&vorbis_comments(tags));
+
+        // Each header goes in a page of its own.
+        let mut packets = PacketWriter::new(writer);
+        packets.write_packet(head.into(), STREAM_SERIAL, PacketWriteEndInfo::EndPage, 0)?;
+        packets.write_packet(
+            comments.into(),
+            STREAM_SERIAL,
+            PacketWriteEndInfo::EndPage,
+            0,
+        )?;
+
+        Ok(OpusWriter {
+            packets,
+            encoder,
+            resampler: Resampler::new(spec.sample_rate, OPUS_RATE, spec.channels as usize),
+            channels: spec.channels as usize,
+            pending: Vec::new(),
+            packet: vec![0; MAX_PACKET_LEN],
+            pre_skip,
+            encoded: 0,
+            end_granule: None,
+        })
+    }
+
+    /// Adds interleaved samples, in the [-1, 1] range, to the file.
+    pub fn write(&mut self, samples: &[f32]) -> anyhow::Result<()> {
+        self.resampler.push(samples, &mut self.pending);
+        self.encode_pending(false)
+    }
+
+    /// Encodes the pending samples in whole packets, padding the last
+    /// one with silence when ending the stream.
+    fn encode_pending(&mut self, end: bool) -> anyhow::Result<()> {
+        let packet_samples = FRAME_LEN * self.channels;
+        let mut start = 0;
+        while self.pending.len() - start >= packet_samples || (end && start < self.pending.len()) {
+            let available = usize::min(packet_samples, self.pending.len() - start);
+            let mut frame = self.pending[start..start + available].to_vec();
+            frame.resize(packet_samples, 0.0);
+            start += available;
+
+            let len = self.encoder.encode_float(&frame, &mut self.packet)?;
+            self.encoded += (available / self.channels) as u64;
+            let last = end && start == self.pending.len();
+            let info = if last {
+                PacketWriteEndInfo::EndStream
+            } else {
+                PacketWriteEndInfo::NormalPacket
+            };
+            // Granule positions count the samples decoded up to the end
+            // of the packet, leaving out the padding at the end.
+            let granule = (self.pre_skip + self.encoded).min(self.end_granule.unwrap_or(u64::MAX));
+            self.packets
+                .write_packet(self.packet[..len].into(), STREAM_SERIAL, info, granule)?;
+        }
+        self.pending.drain(..start);
+        Ok(())
+    }
+
+    /// Pushes the pages written so far to the file.
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.packets.inner_mut().flush()?;
+        Ok(())
+    }
+
+    /// Encodes the samples left and ends the stream.
+    pub fn finalize(mut self) -> anyhow::Result<()> {
+        self.resampler.finish(&mut self.pending);
+        let total = self.encoded + (self.pending.len() / self.channels) as u64;
+        self.end_granule = Some(self.pre_skip + total);
+        // The encoder holds back as many samples as the decoder drops
+        // at the start, which silence pushes out.
+        let flush = self.pre_skip.max(1) as usize * self.channels;
+        self.pending.resize(self.pending.len() + flush, 0.0);
+        self.encode_pending(true)?;
+        self.packets.inner_mut().flush()?;
+        Ok(())
+    }
+}
+
+/// Encodes a signal into bare Opus packets, for sending them on their
+/// own like over RTP, resampling it to 48 kHz.
+pub struct OpusEncoder {
+    encoder: Encoder,
+    resampler: Resampler,
+    channels: usize,
+    frame_len: usize,
+    /// Resampled samples waiting for a whole packet.
+    pending: Vec<f32>,
+    packet: Vec<u8>,
+}
+
+impl OpusEncoder {
+    /// Encoder of packets lasting `duration`: 5, 10, 20, 40 or 60 ms.
+    pub fn new(sample_rate: u32, channels: u16, duration: Duration) -> anyhow::Result<OpusEncoder> {
+        let ms = duration.as_millis() as u64;
+        if Duration::from_millis(ms) != duration || !PACKET_DURATIONS.contains(&ms) {
+            return Err(anyhow!(
+                "Invalid packet duration {:?}. Opus packets last 5, 10, 20, 40 or 60 ms",
+                duration
+            ));
+        }
+        let encoder = Encoder::new(
+            OpusRate::Hz48000,
+            opus_channels(channels)?,
+            Application::Audio,
+        )?;
+        Ok(OpusEncoder {
+            encoder,
+            resampler: Resampler::new(sample_rate, OPUS_RATE, channels as usize),
+            channels: channels as usize,
+            frame_len: (OPUS_RATE as u64 * ms / 1000) as usize,
+            pending: Vec::new(),
+            packet: vec![0; MAX_PACKET_LEN],
+        })
+    }
+
+    /// Samples of each channel per packet, at 48 kHz.
+    pub fn frame_len(&self) -> usize {
+        self.frame_len
+    }
+
+    /// Adds interleaved samples, in the [-1, 1] range, handing each
+    /// whole packet encoded to `send`.
+    pub fn push(
+        &mut self,
+        samples: &[f32],
+        send: impl FnMut(&[u8]) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        self.resampler.push(samples, &mut self.pending);
+        self.encode_pending(send)
+    }
+
+    /// Encodes the samples left, padding the last packet with silence.
+    pub fn finish(&mut self, send: impl FnMut(&[u8]) -> anyhow::Result<()>) -> anyhow::Result<()> {
+        self.resampler.finish(&mut self.pending);
+        let packet_samples = self.frame_len * self.channels;
+        let padded = self.pending.len().div_ceil(packet_samples) * packet_samples;
+        self.pending.resize(padded, 0.0);
+        self.encode_pending(send)
+    }
+
+    fn encode_pending(
+        &mut self,
+        mut send: impl FnMut(&[u8]) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let packet_samples = self.frame_len * self.channels;
+        let whole = self.pending.len() / packet_samples * packet_samples;
+        for frame in self.pending[..whole].chunks(packet_samples) {
+            let len = self.encoder.encode_float(frame, &mut self.packet)?;
+            send(&self.packet[..len])?;
+        }
+        self.pending.drain(..whole);
+        Ok(())
+    }
+}
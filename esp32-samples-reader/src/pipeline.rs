@@ -0,0 +1,783 @@
+use std::{
+    fmt,
+    io::Read,
+    marker::PhantomData,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, SyncSender, TryRecvError, TrySendError},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    dsp::{Processor, Sample, I24},
+    io,
+    metrics::METRICS,
+    state::{self, CaptureState},
+};
+
+/// Turns each byte sent by the ESP32 into the 8 samples it holds.
+pub trait SampleDecoder {
+    type Sample: Sample;
+
+    fn decode(input: u8) -> [Self::Sample; 8];
+}
+
+/// Decodes bits as signed samples, -128 or 127.
+pub struct DecodeSigned;
+impl SampleDecoder for DecodeSigned {
+    type Sample = i8;
+
+    #[inline(always)]
+    fn decode(input: u8) -> [i8; 8] {
+        io::decode_esp32_sample(input)
+    }
+}
+
+/// Decodes bits as signed 16-bit samples, full scale.
+pub struct DecodeSigned16;
+impl SampleDecoder for DecodeSigned16 {
+    type Sample = i16;
+
+    #[inline(always)]
+    fn decode(input: u8) -> [i16; 8] {
+        io::decode_esp32_sample_signed16(input)
+    }
+}
+
+/// Decodes bits as signed 24-bit samples, full scale.
+pub struct DecodeSigned24;
+impl SampleDecoder for DecodeSigned24 {
+    type Sample = I24;
+
+    #[inline(always)]
+    fn decode(input: u8) -> [I24; 8] {
+        io::decode_esp32_sample_signed24(input)
+    }
+}
+
+/// Decodes bits as unsigned samples, 0 or 255.
+pub struct DecodeUnsignedFullRange;
+impl SampleDecoder for DecodeUnsignedFullRange {
+    type Sample = u8;
+
+    #[inline(always)]
+    fn decode(input: u8) -> [u8; 8] {
+        io::decode_esp32_sample_unsigned_full_range(input)
+    }
+}
+
+/// Decodes bits as unsigned samples, 127 or 255.
+pub struct DecodeUnsignedHalfRange;
+impl SampleDecoder for DecodeUnsignedHalfRange {
+    type Sample = u8;
+
+    #[inline(always)]
+    fn decode(input: u8) -> [u8; 8] {
+        io::decode_esp32_sample_unsigned_half_range(input)
+    }
+}
+
+/// Decodes bits as float samples, -1.0 or 1.0.
+pub struct DecodeF32;
+impl SampleDecoder for DecodeF32 {
+    type Sample = f32;
+
+    #[inline(always)]
+    fn decode(input: u8) -> [f32; 8] {
+        io::decode_esp32_sample_f32(input)
+    }
+}
+
+/// Decodes bits like `D`, with zeros as silence instead of the lowest
+/// level, so the signal only takes the upper half of the range.
+pub struct HalfRange<D>(PhantomData<D>);
+impl<D: SampleDecoder> SampleDecoder for HalfRange<D> {
+    type Sample = D::Sample;
+
+    #[inline(always)]
+    fn decode(input: u8) -> [D::Sample; 8] {
+        let high = D::decode(0xff)[0];
+        std::array::from_fn(|bit| {
+            if (input >> (7 - bit)) & 1 != 0 {
+                high
+            } else {
+                D::Sample::SILENCE
+            }
+        })
+    }
+}
+
+/// Provider of the raw bytes sent by the ESP32. Implemented for every
+/// reader, including the ones returned by [`crate::source::SourceSpec::open`].
+pub trait SerialSampleSource {
+    /// Fills `buf`, returning the bytes read. Fewer than its length
+    /// are only read at the end of the source, and none once it has
+    /// ended.
+    fn read_bytes(&mut self, buf: &mut [u8]) -> std::io::Result<usize>;
+}
+
+impl<R: Read + ?Sized> SerialSampleSource for R {
+    fn read_bytes(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        io::read_input(self, buf)
+    }
+}
+
+/// Destination of the decoded and processed samples.
+pub trait SinkWriter<S> {
+    fn write_samples(&mut self, samples: &[S]) -> anyhow::Result<()>;
+
+    /// Pushes what has been written so far to its destination, like
+    /// the disk, called every flush interval of [`stream`].
+    fn flush(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Called once after the last samples have been written.
+    fn finish(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Bytes handed to the destination so far, for the stats of the
+    /// capture. None if the sink doesn't keep count.
+    fn bytes_written(&self) -> Option<u64> {
+        None
+    }
+
+    /// Whether [`SinkWriter::reconnect`] can bring the sink back after
+    /// it failed.
+    fn can_reconnect(&self) -> bool {
+        false
+    }
+
+    /// Opens the destination again after a failure, like a sound
+    /// server that was restarted.
+    fn reconnect(&mut self) -> anyhow::Result<()> {
+        Err(anyhow::anyhow!("The output can't be reconnected"))
+    }
+}
+
+impl<S, Snk: SinkWriter<S> + ?Sized> SinkWriter<S> for Box<Snk> {
+    fn write_samples(&mut self, samples: &[S]) -> anyhow::Result<()> {
+        (**self).write_samples(samples)
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        (**self).flush()
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        (**self).finish()
+    }
+
+    fn bytes_written(&self) -> Option<u64> {
+        (**self).bytes_written()
+    }
+
+    fn can_reconnect(&self) -> bool {
+        (**self).can_reconnect()
+    }
+
+    fn reconnect(&mut self) -> anyhow::Result<()> {
+        (**self).reconnect()
+    }
+}
+
+/// Passes the first `limit` samples on to `inner`, dropping the rest,
+/// and shuts `token` down once they have all been written.
+pub struct Limited<Snk> {
+    pub inner: Snk,
+    left: u64,
+    token: ShutdownToken,
+}
+
+impl<Snk> Limited<Snk> {
+    pub fn new(inner: Snk, limit: u64, token: ShutdownToken) -> Limited<Snk> {
+        Limited {
+            inner,
+            left: limit,
+            token,
+        }
+    }
+}
+
+impl<S, Snk: SinkWriter<S>> SinkWriter<S> for Limited<Snk> {
+    fn write_samples(&mut self, samples: &[S]) -> anyhow::Result<()> {
+        let len = self.left.min(samples.len() as u64) as usize;
+        self.left -= len as u64;
+        if self.left == 0 {
+            self.token.shutdown();
+        }
+        self.inner.write_samples(&samples[..len])
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        self.inner.flush()
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        self.inner.finish()
+    }
+
+    fn bytes_written(&self) -> Option<u64> {
+        self.inner.bytes_written()
+    }
+
+    fn can_reconnect(&self) -> bool {
+        self.inner.can_reconnect()
+    }
+
+    fn reconnect(&mut self) -> anyhow::Result<()> {
+        self.inner.reconnect()
+    }
+}
+
+/// A sink opened by `open`, and opened again by it when asked to
+/// reconnect.
+pub struct Reopen<'a, S> {
+    sink: Box<dyn SinkWriter<S> + 'a>,
+    open: Box<dyn FnMut() -> anyhow::Result<Box<dyn SinkWriter<S> + 'a>> + 'a>,
+}
+
+impl<'a, S> Reopen<'a, S> {
+    pub fn new(
+        mut open: impl FnMut() -> anyhow::Result<Box<dyn SinkWriter<S> + 'a>> + 'a,
+    ) -> anyhow::Result<Reopen<'a, S>> {
+        Ok(Reopen {
+            sink: open()?,
+            open: Box::new(open),
+        })
+    }
+}
+
+impl<S> SinkWriter<S> for Reopen<'_, S> {
+    fn write_samples(&mut self, samples: &[S]) -> anyhow::Result<()> {
+        self.sink.write_samples(samples)
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        self.sink.flush()
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        self.sink.finish()
+    }
+
+    fn bytes_written(&self) -> Option<u64> {
+        self.sink.bytes_written()
+    }
+
+    fn can_reconnect(&self) -> bool {
+        true
+    }
+
+    fn reconnect(&mut self) -> anyhow::Result<()> {
+        self.sink = (self.open)()?;
+        Ok(())
+    }
+}
+
+/// Time before trying to reconnect a failed output again, doubled after
+/// every attempt that fails up to [`MAX_RECONNECT_BACKOFF`].
+const RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Hands the samples on to several sinks, like playing them while
+/// recording them to a file. A sink that fails is reported and left
+/// out, while the rest go on. Sinks that can reconnect are tried again
+/// right away, and then with a growing backoff until they come back.
+/// The stream only fails once none of the required sinks is working,
+/// or none at all if none is required. The count of bytes reported is
+/// the one of the first sink still working.
+pub struct Tee<'a, S> {
+    sinks: Vec<TeeSink<'a, S>>,
+}
+
+struct TeeSink<'a, S> {
+    name: String,
+    sink: &'a mut dyn SinkWriter<S>,
+    required: bool,
+    failed: bool,
+    /// When to try reconnecting the failed sink next, and the backoff
+    /// after that.
+    retry: Option<(Instant, Duration)>,
+}
+
+impl<S> TeeSink<'_, S> {
+    /// Tries to reconnect the failed sink, scheduling the next attempt
+    /// if it doesn't come back.
+    fn reconnect(&mut self, backoff: Duration) {
+        match self.sink.reconnect() {
+            Ok(()) => {
+                tracing::info!("Output {} reconnected", self.name);
+                self.failed = false;
+                self.retry = None;
+            }
+            Err(e) => {
+                tracing::debug!(
+                    "Unable to reconnect output {}, trying again in {:?}: {:#}",
+                    self.name,
+                    backoff,
+                    e
+                );
+                self.retry = Some((Instant::now() + backoff, backoff));
+            }
+        }
+    }
+}
+
+impl<S> Default for Tee<'_, S> {
+    fn default() -> Self {
+        Tee { sinks: Vec::new() }
+    }
+}
+
+impl<'a, S> Tee<'a, S> {
+    pub fn new() -> Tee<'a, S> {
+        Self::default()
+    }
+
+    /// Adds a sink, named in the errors reported about it.
+    pub fn push(
+        &mut self,
+        name: impl fmt::Display,
+        sink: &'a mut dyn SinkWriter<S>,
+        required: bool,
+    ) {
+        self.sinks.push(TeeSink {
+            name: name.to_string(),
+            sink,
+            required,
+            failed: false,
+            retry: None,
+        });
+    }
+
+    /// Runs `f` on every sink still working, leaving out the ones it
+    /// fails on.
+    fn each(
+        &mut self,
+        mut f: impl FnMut(&mut dyn SinkWriter<S>) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let mut last_error = None;
+        for sink in &mut self.sinks {
+            if let Some((at, backoff)) = sink.retry {
+                if Instant::now() >= at {
+                    sink.reconnect((backoff * 2).min(MAX_RECONNECT_BACKOFF));
+                }
+            }
+            if sink.failed {
+                continue;
+            }
+            if let Err(e) = f(&mut *sink.sink) {
+                tracing::error!("Output {} failed, going on without it: {:#}", sink.name, e);
+                sink.failed = true;
+                last_error = Some(e.context(format!("Output {} failed", sink.name)));
+                if sink.sink.can_reconnect() {
+                    sink.reconnect(RECONNECT_BACKOFF);
+                }
+            }
+        }
+
+        let any_required = self.sinks.iter().any(|sink| sink.required);
+        let working = self
+            .sinks
+            .iter()
+            .any(|sink| !sink.failed && (sink.required || !any_required));
+        match last_error {
+            Some(e) if !working => Err(e.context("No required output is left")),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<S> SinkWriter<S> for Tee<'_, S> {
+    fn write_samples(&mut self, samples: &[S]) -> anyhow::Result<()> {
+        self.each(|sink| sink.write_samples(samples))
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        self.each(|sink| sink.flush())
+    }
+
+    /// Finishes every sink, the failed ones too for keeping what they
+    /// got, like the samples written to a file before it filled the
+    /// disk.
+    fn finish(&mut self) -> anyhow::Result<()> {
+        let mut result = Ok(());
+        for sink in &mut self.sinks {
+            let finished = sink.sink.finish();
+            match finished {
+                Err(e) if sink.failed => {
+                    tracing::debug!("Unable to finish the failed output {}: {:#}", sink.name, e)
+                }
+                Err(e) if result.is_ok() => {
+                    result = Err(e.context(format!("Unable to finish output {}", sink.name)))
+                }
+                _ => {}
+            }
+        }
+        result
+    }
+
+    fn bytes_written(&self) -> Option<u64> {
+        self.sinks
+            .iter()
+            .find(|sink| !sink.failed)
+            .and_then(|sink| sink.sink.bytes_written())
+    }
+}
+
+/// Flag shared with a running pipeline for asking it to stop, usually
+/// checked from the `on_chunk` callback of [`stream`]. Tokens made with
+/// [`ShutdownToken::child`] are also shut down along with their parent.
+#[derive(Clone, Default)]
+pub struct ShutdownToken {
+    flag: Arc<AtomicBool>,
+    parent: Option<Box<ShutdownToken>>,
+}
+
+impl ShutdownToken {
+    pub fn new() -> ShutdownToken {
+        Self::default()
+    }
+
+    /// Creates a token backed by the given flag, for setting it from
+    /// places that can only deal with atomics, like signal handlers.
+    pub fn from_flag(flag: Arc<AtomicBool>) -> ShutdownToken {
+        ShutdownToken { flag, parent: None }
+    }
+
+    pub fn child(&self) -> ShutdownToken {
+        ShutdownToken {
+            flag: Arc::default(),
+            parent: Some(Box::new(self.clone())),
+        }
+    }
+
+    pub fn shutdown(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.flag.load(Ordering::Relaxed)
+            || matches!(&self.parent, Some(parent) if parent.is_shutdown())
+    }
+}
+
+/// Time spent on one of the stages of [`stream`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StageTime {
+    pub total: Duration,
+    pub longest: Duration,
+}
+
+impl StageTime {
+    /// Runs f, adding the time it took to the stage.
+    pub fn measure<T>(&mut self, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        let elapsed = start.elapsed();
+        self.total += elapsed;
+        self.longest = self.longest.max(elapsed);
+        result
+    }
+}
+
+impl fmt::Display for StageTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.3} s in total, {:.1} ms at most",
+            self.total.as_secs_f64(),
+            self.longest.as_secs_f64() * 1000.0
+        )
+    }
+}
+
+/// Where the time of [`stream`] went, for attributing latency spikes
+/// to the source, the processing or the sink.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StageTimes {
+    pub chunks: u64,
+    /// Waiting for the source to provide each chunk.
+    pub reading: StageTime,
+    pub processing: StageTime,
+    /// Blocked on the sink, like when the sound server or the disk
+    /// don't keep up.
+    pub writing: StageTime,
+    /// Spent in the `on_chunk` callback.
+    pub reporting: StageTime,
+    /// Bytes written by the sink, if it keeps count.
+    pub written: Option<u64>,
+}
+
+impl fmt::Display for StageTimes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Chunks: {}", self.chunks)?;
+        writeln!(f, "Reading: {}", self.reading)?;
+        writeln!(f, "Processing: {}", self.processing)?;
+        writeln!(f, "Writing: {}", self.writing)?;
+        write!(f, "Reporting: {}", self.reporting)?;
+        if let Some(written) = self.written {
+            write!(f, "\nWritten: {} bytes", written)?;
+        }
+        Ok(())
+    }
+}
+
+/// Blocks of samples decoded ahead of the one being written, bounding
+/// the memory taken when the sink falls behind.
+const DECODED_BLOCKS: usize = 4;
+
+/// Samples handed from the decoding thread of [`stream`] to the
+/// writing one.
+enum Block<S> {
+    /// The samples of a chunk, and the count of samples read so far.
+    Chunk(Vec<S>, usize),
+    /// The samples the chain held back, once the input is over.
+    Finish(Vec<S>),
+}
+
+/// Sends a block to the writing side, counting the wait if its queue
+/// is full. Fails if the writing side went away.
+fn send_block<S>(blocks: &SyncSender<Block<S>>, block: Block<S>) -> Result<(), ()> {
+    match blocks.try_send(block) {
+        Ok(()) => Ok(()),
+        Err(TrySendError::Disconnected(_)) => Err(()),
+        Err(TrySendError::Full(block)) => {
+            let since = Instant::now();
+            let sent = blocks.send(block).map_err(|_| ());
+            METRICS.decoded_waits.count_full(since.elapsed());
+            sent
+        }
+    }
+}
+
+/// Receives the next block from the decoding side, counting the wait
+/// if none is ready. None once the decoding side is over.
+fn receive_block<S>(decoded: &Receiver<Block<S>>) -> Option<Block<S>> {
+    match decoded.try_recv() {
+        Ok(block) => Some(block),
+        Err(TryRecvError::Disconnected) => None,
+        Err(TryRecvError::Empty) => {
+            let since = Instant::now();
+            let block = decoded.recv().ok();
+            METRICS.decoded_waits.count_empty(since.elapsed());
+            block
+        }
+    }
+}
+
+/// Reads `source` in chunks of `chunk_len` bytes, decoding them with
+/// `D`, running them through `chain` (usually a [`crate::dsp::Chain`]) and writing the result to
+/// `sink`. After each chunk `on_chunk` receives the samples written
+/// and the count of samples read so far, and stops the reading by
+/// returning false. Otherwise the reading goes on until the source
+/// ends. The sink is flushed whenever `flush_interval` has passed
+/// since the last time. Returns the time spent on each stage.
+///
+/// The reading and decoding run on a thread of their own, a few
+/// blocks ahead of the writing, which stays on the calling thread
+/// along with `on_chunk`. So a sink blocking for a while, like a disk
+/// or a sound server busy with something else, doesn't hold up the
+/// decoding. The blocks decoded by the time `on_chunk` stops the
+/// reading are still written.
+pub fn stream<D, Src, Snk, F>(
+    source: &mut Src,
+    chain: &mut dyn Processor<D::Sample>,
+    sink: &mut Snk,
+    chunk_len: usize,
+    flush_interval: Duration,
+    mut on_chunk: F,
+) -> anyhow::Result<StageTimes>
+where
+    D: SampleDecoder,
+    Src: SerialSampleSource + Send + ?Sized,
+    Snk: SinkWriter<D::Sample> + ?Sized,
+    F: FnMut(&[D::Sample], usize) -> bool,
+{
+    let _span = tracing::info_span!("stream", chunk_len).entered();
+    let stop = AtomicBool::new(false);
+    let (blocks, decoded) = mpsc::sync_channel(DECODED_BLOCKS);
+    let (recycle, recycled) = mpsc::channel::<Vec<D::Sample>>();
+    METRICS.decoded_waits.start();
+
+    thread::scope(|scope| {
+        let decoder = thread::Builder::new()
+            .name("decoder".into())
+            .spawn_scoped(scope, {
+                let span = tracing::info_span!("decode");
+                let stop = &stop;
+                move || {
+                    let _span = span.entered();
+                    decode::<D, _>(source, chain, chunk_len, stop, blocks, recycled)
+                }
+            })?;
+
+        let mut times = StageTimes::default();
+        let mut last_flush = Instant::now();
+        let mut finished = false;
+        let written = (|| -> anyhow::Result<()> {
+            while let Some(block) = receive_block(&decoded) {
+                let (samples, total_samples) = match block {
+                    Block::Chunk(samples, total_samples) => (samples, total_samples),
+                    Block::Finish(samples) => {
+                        sink.write_samples(&samples)?;
+                        times.writing.measure(|| sink.finish())?;
+                        finished = true;
+                        return Ok(());
+                    }
+                };
+                times.writing.measure(|| sink.write_samples(&samples))?;
+                if last_flush.elapsed() >= flush_interval {
+                    times.writing.measure(|| sink.flush())?;
+                    last_flush = Instant::now();
+                }
+                times.chunks += 1;
+                if !stop.load(Ordering::Relaxed)
+                    && !times
+                        .reporting
+                        .measure(|| on_chunk(&samples, total_samples))
+                {
+                    stop.store(true, Ordering::Relaxed);
+                }
+                // The decoder is gone if it failed.
+                let _ = recycle.send(samples);
+            }
+            Ok(())
+        })();
+        // Lets the decoder go if the sink failed.
+        stop.store(true, Ordering::Relaxed);
+        drop(decoded);
+
+        let (reading, processing) = match decoder.join() {
+            Ok(decoded) => decoded?,
+            Err(panic) => std::panic::resume_unwind(panic),
+        };
+        written?;
+        times.reading = reading;
+        times.processing = processing;
+        if finished {
+            times.written = sink.bytes_written();
+        }
+        Ok(times)
+    })
+}
+
+/// Decoding side of [`stream`], sending the blocks of samples to the
+/// writing side until the source ends, the writing side stops it or
+/// goes away. Returns the time spent reading and processing.
+fn decode<D, Src>(
+    source: &mut Src,
+    chain: &mut dyn Processor<D::Sample>,
+    chunk_len: usize,
+    stop: &AtomicBool,
+    blocks: SyncSender<Block<D::Sample>>,
+    recycled: Receiver<Vec<D::Sample>>,
+) -> anyhow::Result<(StageTime, StageTime)>
+where
+    D: SampleDecoder,
+    Src: SerialSampleSource + ?Sized,
+{
+    let mut buf = vec![0; chunk_len];
+    let mut total_samples: usize = 0;
+    let (mut reading, mut processing) = (StageTime::default(), StageTime::default());
+    while !stop.load(Ordering::Relaxed) {
+        let len = reading.measure(|| source.read_bytes(&mut buf))?;
+        if len == 0 {
+            break;
+        }
+        state::set(CaptureState::Streaming);
+        METRICS.count_read(len);
+        let mut block = recycled.try_recv().unwrap_or_default();
+        processing.measure(|| {
+            block.clear();
+            block.extend_from_slice(chain.process(&buf[..len], D::decode));
+        });
+        total_samples += len * 8;
+        if send_block(&blocks, Block::Chunk(block, total_samples)).is_err() {
+            return Ok((reading, processing));
+        }
+        // A short chunk is the last one of the source.
+        if len < buf.len() {
+            break;
+        }
+    }
+
+    state::set(CaptureState::Draining);
+    tracing::debug!(samples = total_samples, "Input finished");
+    let _ = send_block(&blocks, Block::Finish(chain.finish()));
+    Ok((reading, processing))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Keeps the samples written, failing on the writes while `down`.
+    #[derive(Default)]
+    struct Flaky {
+        written: Vec<u8>,
+        down: bool,
+        reconnects: bool,
+    }
+
+    impl SinkWriter<u8> for Flaky {
+        fn write_samples(&mut self, samples: &[u8]) -> anyhow::Result<()> {
+            if self.down {
+                return Err(anyhow::anyhow!("Down"));
+            }
+            self.written.extend_from_slice(samples);
+            Ok(())
+        }
+
+        fn can_reconnect(&self) -> bool {
+            self.reconnects
+        }
+
+        fn reconnect(&mut self) -> anyhow::Result<()> {
+            self.down = false;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn failed_outputs_are_left_out_or_reconnected() {
+        let mut required = Flaky::default();
+        let mut lost = Flaky::default();
+        let mut reconnected = Flaky {
+            reconnects: true,
+            ..Flaky::default()
+        };
+        {
+            let mut tee = Tee::new();
+            tee.push("required", &mut required, true);
+            tee.push("lost", &mut lost, false);
+            tee.push("reconnected", &mut reconnected, false);
+            tee.write_samples(&[1]).unwrap();
+            tee.write_samples(&[2]).unwrap();
+        }
+        lost.down = true;
+        reconnected.down = true;
+        {
+            let mut tee = Tee::new();
+            tee.push("required", &mut required, true);
+            tee.push("lost", &mut lost, false);
+            tee.push("reconnected", &mut reconnected, false);
+            tee.write_samples(&[3]).unwrap();
+            tee.write_samples(&[4]).unwrap();
+        }
+        assert_eq!(required.written, [1, 2, 3, 4]);
+        assert_eq!(lost.written, [1, 2]);
+        assert_eq!(reconnected.written, [1, 2, 4]);
+
+        required.down = true;
+        let mut tee = Tee::new();
+        tee.push("required", &mut required, true);
+        tee.push("reconnected", &mut reconnected, false);
+        assert!(tee.write_samples(&[5]).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+//! Endpoint serving the counters of the capture in the text format of
+//! Prometheus, for monitoring long running captures.
+
+use std::{
+    fmt::Write as _,
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    thread,
+    time::Duration,
+};
+
+use anyhow::Context as _;
+
+use crate::{metrics::METRICS, state};
+
+/// Longest wait for a scraper to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The counters of the capture, in the text exposition format.
+fn render() -> String {
+    let metrics = METRICS.snapshot();
+    let mut text = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+        let _ = writeln!(text, "# HELP esp32sr_{} {}", name, help);
+        let _ = writeln!(text, "# TYPE esp32sr_{} {}", name, kind);
+        let _ = writeln!(text, "esp32sr_{} {}", name, value);
+    };
+    metric(
+        "samples_read_total",
+        "counter",
+        "Samples received from the source.",
+        metrics.samples_read,
+    );
+    metric(
+        "bytes_read_total",
+        "counter",
+        "Bytes received from the source.",
+        metrics.bytes_read,
+    );
+    metric(
+        "bytes_dropped_total",
+        "counter",
+        "Bytes of signal lost on the way.",
+        metrics.bytes_dropped,
+    );
+    metric(
+        "reconnects_total",
+        "counter",
+        "Times the board came back after going away.",
+        metrics.reconnects,
+    );
+    // Left out for the outputs that don't count them, rather than
+    // always reading zero.
+    if let Some(underruns) = metrics.underruns {
+        metric(
+            "underruns_total",
+            "counter",
+            "Times the output ran out of samples to play.",
+            underruns,
+        );
+    }
+    metric(
+        "buffered_bytes",
+        "gauge",
+        "Bytes waiting in the read-ahead buffer.",
+        metrics.buffered,
+    );
+    metric(
+        "buffer_capacity_bytes",
+        "gauge",
+        "Size of the read-ahead buffer.",
+        metrics.buffer_capacity,
+    );
+
+    let _ = writeln!(text, "# HELP esp32sr_state State of the capture.");
+    let _ = writeln!(text, "# TYPE esp32sr_state gauge");
+    let _ = writeln!(text, "esp32sr_state{{state=\"{}\"}} 1", state::current());
+    text
+}
+
+fn respond(stream: TcpStream) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // The headers say nothing of interest, but are read so the client
+    // doesn't see the connection reset.
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or("");
+    let (status, body) = match path {
+        "/metrics" | "/" => ("200 OK", render()),
+        _ => ("404 Not Found", "Not found\n".into()),
+    };
+    write!(
+        &stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+/// Starts serving the counters on the given address, on a thread of
+/// its own that lasts as long as the process.
+pub fn serve(address: SocketAddr) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(address)
+        .with_context(|| format!("Unable to listen for metrics on {}", address))?;
+    tracing::info!("Serving metrics on http://{}/metrics", address);
+    thread::Builder::new()
+        .name("metrics".into())
+        .spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(e) = respond(stream) {
+                    tracing::debug!("Metrics request failed: {}", e);
+                }
+            }
+        })?;
+    Ok(())
+}
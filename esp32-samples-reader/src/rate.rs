@@ -0,0 +1,62 @@
+//! Conversions between sample counts, bytes of the stream and time.
+//!
+//! Sampling rates don't need to be a multiple of 8, nor of any other
+//! number, so everything is computed from whole sample counts with
+//! integer arithmetic, rounding only at the end. Long captures don't
+//! drift however odd the rate is.
+
+use std::time::Duration;
+
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+
+/// Time taken by the given number of samples.
+pub fn duration_of_samples(sampling_rate: u32, samples: u64) -> Duration {
+    if sampling_rate == 0 {
+        return Duration::ZERO;
+    }
+    let nanos = samples as u128 * NANOS_PER_SECOND / sampling_rate as u128;
+    Duration::new(
+        (nanos / NANOS_PER_SECOND) as u64,
+        (nanos % NANOS_PER_SECOND) as u32,
+    )
+}
+
+/// Time taken by the given number of bytes of the stream, each one
+/// holding 8 samples.
+pub fn duration_of_bytes(sampling_rate: u32, bytes: u64) -> Duration {
+    duration_of_samples(sampling_rate, bytes * 8)
+}
+
+/// Whole samples taken in the given time.
+pub fn samples_in(sampling_rate: u32, duration: Duration) -> u64 {
+    (sampling_rate as u128 * duration.as_nanos() / NANOS_PER_SECOND) as u64
+}
+
+/// Whole bytes of the stream received in the given time. At least
+/// one byte.
+pub fn bytes_in(sampling_rate: u32, duration: Duration) -> usize {
+    (samples_in(sampling_rate, duration) / 8).max(1) as usize
+}
+
+/// Bytes of the stream needed to hold the given number of samples.
+pub fn bytes_for_samples(samples: u64) -> u64 {
+    samples.div_ceil(8)
+}
+
+/// Bytes of the stream received per given fraction of a second, like
+/// 20 for 50 ms, rounded up so a chunk never takes less than its
+/// share of time. At least one byte.
+pub fn bytes_per(sampling_rate: u32, fraction: u32) -> usize {
+    (sampling_rate as u64).div_ceil(8 * fraction as u64).max(1) as usize
+}
+
+/// Formats the time taken by the given number of samples as seconds
+/// with two decimals, truncated.
+pub fn format_seconds(sampling_rate: u32, samples: u64) -> String {
+    let duration = duration_of_samples(sampling_rate, samples);
+    format!(
+        "{}.{:02}",
+        duration.as_secs(),
+        duration.subsec_millis() / 10
+    )
+}
@@ -0,0 +1,245 @@
+//! Recording split into consecutive files, so long captures don't end
+//! up in a single huge file that a crash could leave unreadable.
+
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use hound::WavSpec;
+
+use crate::{
+    rate,
+    wav::{tagged_path, FileOptions, OutputFile, WavSample},
+};
+
+/// Bytes of the header of a WAV file without tags, at most, when
+/// written in the extensible format.
+const WAV_HEADER_LEN: u64 = 68;
+
+/// A length of time given with a unit, like `90s`, `10m` or `2h`.
+/// Plain numbers are seconds.
+#[derive(Clone, Copy, Debug)]
+pub struct TimeSpan(pub Duration);
+
+impl FromStr for TimeSpan {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (value, unit) = s.split_at(s.trim_end_matches(char::is_alphabetic).len());
+        let seconds = match unit {
+            "" | "s" => 1,
+            "m" => 60,
+            "h" => 3600,
+            _ => return Err(format!("Invalid unit '{}'. Expected s, m or h", unit)),
+        };
+        value
+            .parse::<u64>()
+            .ok()
+            .filter(|value| *value > 0)
+            .map(|value| TimeSpan(Duration::from_secs(value * seconds)))
+            .ok_or_else(|| format!("Invalid duration '{}'", s))
+    }
+}
+
+/// An amount of bytes given with an optional binary unit, like `500M`
+/// or `2G`.
+#[derive(Clone, Copy, Debug)]
+pub struct ByteSize(pub u64);
+
+impl FromStr for ByteSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (value, unit) = s.split_at(s.trim_end_matches(char::is_alphabetic).len());
+        let multiplier: u64 = match unit.to_ascii_uppercase().as_str() {
+            "" | "B" => 1,
+            "K" | "KB" => 1 << 10,
+            "M" | "MB" => 1 << 20,
+            "G" | "GB" => 1 << 30,
+            _ => return Err(format!("Invalid unit '{}'. Expected K, M or G", unit)),
+        };
+        value
+            .parse::<u64>()
+            .ok()
+            .and_then(|value| value.checked_mul(multiplier))
+            .filter(|value| *value > 0)
+            .map(ByteSize)
+            .ok_or_else(|| format!("Invalid size '{}'", s))
+    }
+}
+
+impl fmt::Display for ByteSize {
+    /// In the largest unit the size reaches, with a decimal, like
+    /// 1.5G.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut value = self.0 as f64;
+        for unit in ["B", "K", "M"] {
+            if value < 1024.0 {
+                return match unit {
+                    "B" => write!(f, "{}B", self.0),
+                    _ => write!(f, "{:.1}{}", value, unit),
+                };
+            }
+            value /= 1024.0;
+        }
+        write!(f, "{:.1}G", value)
+    }
+}
+
+/// When a segment is finished and the next one started.
+#[derive(Clone, Copy, Debug)]
+pub enum SegmentLimit {
+    Duration(Duration),
+    /// Size of each file, as uncompressed samples. Compressed formats
+    /// end up smaller.
+    Size(u64),
+}
+
+impl SegmentLimit {
+    /// Frames, holding a sample of each channel, of each segment.
+    fn frames(self, spec: &WavSpec) -> u64 {
+        match self {
+            SegmentLimit::Duration(duration) => rate::samples_in(spec.sample_rate, duration),
+            SegmentLimit::Size(size) => {
+                let frame_len = (spec.bits_per_sample as u64 / 8) * spec.channels as u64;
+                size.saturating_sub(WAV_HEADER_LEN) / frame_len
+            }
+        }
+        .max(1)
+    }
+}
+
+/// Formats a point in time as a UTC timestamp fit for file names, like
+/// `20240131T235959Z`.
+pub fn file_timestamp(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, time_of_day) = (seconds / 86400, seconds % 86400);
+
+    // Civil date of a day count since 1970-01-01, as in
+    // http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        time_of_day / 3600,
+        time_of_day / 60 % 60,
+        time_of_day % 60
+    )
+}
+
+/// Writes the recording to consecutive files of the given length,
+/// named after the output path with the number and the start time of
+/// each segment. Segments are cut at exact sample counts, so joining
+/// them gives back the whole recording.
+pub struct SegmentedFile<S> {
+    file: Option<OutputFile<S>>,
+    output: String,
+    spec: WavSpec,
+    options: FileOptions,
+    /// Samples per segment, counting every channel.
+    segment_len: u64,
+    /// Samples of the current segment, and of the whole recording.
+    in_segment: u64,
+    written: u64,
+    started: SystemTime,
+    segments: usize,
+}
+
+impl<S: WavSample> SegmentedFile<S> {
+    pub fn new(
+        output: &str,
+        spec: WavSpec,
+        limit: SegmentLimit,
+        options: FileOptions,
+    ) -> anyhow::Result<SegmentedFile<S>> {
+        let mut segmented = SegmentedFile {
+            file: None,
+            output: output.into(),
+            spec,
+            options,
+            segment_len: limit.frames(&spec) * spec.channels as u64,
+            in_segment: 0,
+            written: 0,
+            started: SystemTime::now(),
+            segments: 0,
+        };
+        segmented.start_segment()?;
+        Ok(segmented)
+    }
+
+    fn start_segment(&mut self) -> anyhow::Result<()> {
+        // Named after the time of the first sample of the segment,
+        // counted from the start of the recording.
+        let offset = rate::duration_of_samples(
+            self.spec.sample_rate,
+            self.written / self.spec.channels as u64,
+        );
+        self.segments += 1;
+        let path = tagged_path(
+            &self.output,
+            &format!(
+                "{:03}-{}",
+                self.segments,
+                file_timestamp(self.started + offset)
+            ),
+        );
+        tracing::info!("Writing segment {}", path.display());
+        self.file = Some(OutputFile::create(path, self.spec, &self.options)?);
+        Ok(())
+    }
+
+    pub fn write(&mut self, mut samples: &[S]) -> anyhow::Result<()> {
+        while !samples.is_empty() {
+            if self.file.is_none() {
+                self.start_segment()?;
+            }
+            let left = self.segment_len - self.in_segment;
+            let len = usize::min(samples.len(), left as usize);
+            if let Some(file) = &mut self.file {
+                file.write(&samples[..len])?;
+            }
+            self.in_segment += len as u64;
+            self.written += len as u64;
+            samples = &samples[len..];
+
+            if self.in_segment == self.segment_len {
+                self.in_segment = 0;
+                if let Some(file) = self.file.take() {
+                    file.finalize()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        if let Some(file) = &mut self.file {
+            file.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn finalize(self) -> anyhow::Result<()> {
+        if let Some(file) = self.file {
+            file.finalize()?;
+        }
+        Ok(())
+    }
+}
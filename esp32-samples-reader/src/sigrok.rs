@@ -0,0 +1,256 @@
+//! Sigrok session files (.sr), for analyzing captures with the
+//! protocol decoders of PulseView and sigrok-cli.
+//!
+//! Sessions are zip archives holding the metadata of the capture and
+//! its samples as logic data, a byte per sample with the signal in its
+//! lowest bit, split in several files. The archive is written as it
+//! goes, without seeking, so it can be piped too.
+
+use std::io::Write;
+
+use crate::io::crc32;
+
+/// Samples of each logic data file, as in the sessions saved by
+/// sigrok.
+const CHUNK_LEN: u64 = 4 << 20;
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const DATA_DESCRIPTOR: u32 = 0x08074b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY: u32 = 0x06064b50;
+const ZIP64_LOCATOR: u32 = 0x07064b50;
+/// Sizes and CRC of the entries follow their data.
+const FLAG_DATA_DESCRIPTOR: u16 = 0x0008;
+const METHOD_STORED: u16 = 0;
+/// Modification time and date of the entries, the earliest there is
+/// (1980-01-01 00:00), as the files of a session have no meaningful
+/// one.
+const DOS_TIME: u32 = 0x0021 << 16;
+/// Version of the zip format needed for reading stored entries, and
+/// for Zip64 records.
+const VERSION: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+
+struct ZipEntry {
+    name: String,
+    offset: u64,
+    crc: u32,
+    len: u64,
+}
+
+/// Writes zip archives of uncompressed files, one after the other.
+/// Archives over 4 GiB get the Zip64 records.
+struct ZipWriter<W> {
+    output: W,
+    offset: u64,
+    entries: Vec<ZipEntry>,
+}
+
+impl<W: Write> ZipWriter<W> {
+    fn put(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.output.write_all(data)?;
+        self.offset += data.len() as u64;
+        Ok(())
+    }
+
+    fn start_file(&mut self, name: &str) -> std::io::Result<()> {
+        self.entries.push(ZipEntry {
+            name: name.into(),
+            offset: self.offset,
+            crc: 0,
+            len: 0,
+        });
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&LOCAL_HEADER.to_le_bytes());
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        header.extend_from_slice(&FLAG_DATA_DESCRIPTOR.to_le_bytes());
+        header.extend_from_slice(&METHOD_STORED.to_le_bytes());
+        header.extend_from_slice(&DOS_TIME.to_le_bytes());
+        // The CRC and sizes are in the data descriptor.
+        header.extend_from_slice(&[0; 12]);
+        header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes());
+        header.extend_from_slice(name.as_bytes());
+        self.put(&header)
+    }
+
+    fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        if let Some(entry) = self.entries.last_mut() {
+            entry.crc = crc32(entry.crc, data);
+            entry.len += data.len() as u64;
+        }
+        self.put(data)
+    }
+
+    fn end_file(&mut self) -> std::io::Result<()> {
+        let entry = match self.entries.last() {
+            Some(entry) => entry,
+            None => return Ok(()),
+        };
+        let mut descriptor = Vec::new();
+        descriptor.extend_from_slice(&DATA_DESCRIPTOR.to_le_bytes());
+        descriptor.extend_from_slice(&entry.crc.to_le_bytes());
+        descriptor.extend_from_slice(&(entry.len as u32).to_le_bytes());
+        descriptor.extend_from_slice(&(entry.len as u32).to_le_bytes());
+        self.put(&descriptor)
+    }
+
+    /// Writes the central directory, ending the archive.
+    fn finish(mut self) -> std::io::Result<W> {
+        let directory_offset = self.offset;
+        let mut directory = Vec::new();
+        for entry in &self.entries {
+            let zip64 = entry.offset >= u32::MAX as u64;
+            directory.extend_from_slice(&CENTRAL_HEADER.to_le_bytes());
+            let version = if zip64 { VERSION_ZIP64 } else { VERSION };
+            directory.extend_from_slice(&version.to_le_bytes());
+            directory.extend_from_slice(&version.to_le_bytes());
+            directory.extend_from_slice(&FLAG_DATA_DESCRIPTOR.to_le_bytes());
+            directory.extend_from_slice(&METHOD_STORED.to_le_bytes());
+            directory.extend_from_slice(&DOS_TIME.to_le_bytes());
+            directory.extend_from_slice(&entry.crc.to_le_bytes());
+            directory.extend_from_slice(&(entry.len as u32).to_le_bytes());
+            directory.extend_from_slice(&(entry.len as u32).to_le_bytes());
+            directory.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+            directory.extend_from_slice(&(if zip64 { 12u16 } else { 0 }).to_le_bytes());
+            // No comment, disk number nor attributes.
+            directory.extend_from_slice(&[0; 10]);
+            let offset = if zip64 { u32::MAX } else { entry.offset as u32 };
+            directory.extend_from_slice(&offset.to_le_bytes());
+            directory.extend_from_slice(entry.name.as_bytes());
+            if zip64 {
+                directory.extend_from_slice(&1u16.to_le_bytes());
+                directory.extend_from_slice(&8u16.to_le_bytes());
+                directory.extend_from_slice(&entry.offset.to_le_bytes());
+            }
+        }
+        self.put(&directory)?;
+
+        let entries = self.entries.len() as u64;
+        let directory_len = directory.len() as u64;
+        let zip64 = entries >= u16::MAX as u64
+            || directory_offset >= u32::MAX as u64
+            || directory_len >= u32::MAX as u64;
+        let mut end = Vec::new();
+        if zip64 {
+            let record_offset = self.offset;
+            end.extend_from_slice(&ZIP64_END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+            end.extend_from_slice(&44u64.to_le_bytes());
+            end.extend_from_slice(&VERSION_ZIP64.to_le_bytes());
+            end.extend_from_slice(&VERSION_ZIP64.to_le_bytes());
+            end.extend_from_slice(&[0; 8]);
+            end.extend_from_slice(&entries.to_le_bytes());
+            end.extend_from_slice(&entries.to_le_bytes());
+            end.extend_from_slice(&directory_len.to_le_bytes());
+            end.extend_from_slice(&directory_offset.to_le_bytes());
+
+            end.extend_from_slice(&ZIP64_LOCATOR.to_le_bytes());
+            end.extend_from_slice(&0u32.to_le_bytes());
+            end.extend_from_slice(&record_offset.to_le_bytes());
+            end.extend_from_slice(&1u32.to_le_bytes());
+        }
+        end.extend_from_slice(&END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        end.extend_from_slice(&[0; 4]);
+        let entries = entries.min(u16::MAX as u64) as u16;
+        end.extend_from_slice(&entries.to_le_bytes());
+        end.extend_from_slice(&entries.to_le_bytes());
+        end.extend_from_slice(&(directory_len.min(u32::MAX as u64) as u32).to_le_bytes());
+        end.extend_from_slice(&(directory_offset.min(u32::MAX as u64) as u32).to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes());
+        self.put(&end)?;
+
+        self.output.flush()?;
+        Ok(self.output)
+    }
+}
+
+/// Writes the bit stream of a capture as a sigrok session with a
+/// single logic channel.
+pub struct SigrokWriter<W> {
+    zip: ZipWriter<W>,
+    chunks: usize,
+    in_chunk: u64,
+    samples: Vec<u8>,
+}
+
+impl<W: Write> SigrokWriter<W> {
+    pub fn new(output: W, sampling_rate: u32) -> std::io::Result<SigrokWriter<W>> {
+        let mut zip = ZipWriter {
+            output,
+            offset: 0,
+            entries: Vec::new(),
+        };
+        zip.start_file("version")?;
+        zip.write(b"2")?;
+        zip.end_file()?;
+
+        zip.start_file("metadata")?;
+        zip.write(
+            format!(
+                "[global]\n\
+                 sigrok version=0.5.2\n\
+                 \n\
+                 [device 1]\n\
+                 capturefile=logic-1\n\
+                 total probes=1\n\
+                 samplerate={}\n\
+                 total analog=0\n\
+                 probe1=D0\n\
+                 unitsize=1\n",
+                sampling_rate
+            )
+            .as_bytes(),
+        )?;
+        zip.end_file()?;
+
+        Ok(SigrokWriter {
+            zip,
+            chunks: 0,
+            in_chunk: CHUNK_LEN,
+            samples: Vec::new(),
+        })
+    }
+
+    /// Adds the first `samples` bits of the given bytes, packed as the
+    /// ESP32 sends them, to the session.
+    pub fn write(&mut self, input: &[u8], samples: usize) -> std::io::Result<()> {
+        self.samples.clear();
+        self.samples.extend(
+            input
+                .iter()
+                .flat_map(|byte| (0..8).rev().map(move |bit| (byte >> bit) & 1))
+                .take(samples),
+        );
+
+        let mut samples = &self.samples[..];
+        while !samples.is_empty() {
+            if self.in_chunk == CHUNK_LEN {
+                if self.chunks > 0 {
+                    self.zip.end_file()?;
+                }
+                self.chunks += 1;
+                self.zip.start_file(&format!("logic-1-{}", self.chunks))?;
+                self.in_chunk = 0;
+            }
+            let len = samples.len().min((CHUNK_LEN - self.in_chunk) as usize);
+            self.zip.write(&samples[..len])?;
+            self.in_chunk += len as u64;
+            samples = &samples[len..];
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.zip.output.flush()
+    }
+
+    /// Ends the session, returning the output.
+    pub fn finish(mut self) -> std::io::Result<W> {
+        if self.chunks > 0 {
+            self.zip.end_file()?;
+        }
+        self.zip.finish()
+    }
+}
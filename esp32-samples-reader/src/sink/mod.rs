@@ -0,0 +1,3 @@
+pub mod multicast;
+pub mod rtp;
+pub mod tcp;
@@ -0,0 +1,168 @@
+#[cfg(feature = "opus")]
+use std::time::Duration;
+use std::{
+    fmt::{self, Display},
+    net::{Ipv4Addr, SocketAddrV4, UdpSocket},
+    str::FromStr,
+};
+
+use anyhow::anyhow;
+
+#[cfg(feature = "opus")]
+use crate::{dsp::Sample, opus::OpusEncoder};
+use crate::{io, pipeline::SinkWriter};
+
+/// Maximum amount of samples sent on each datagram, chosen so packets
+/// fit in the usual Ethernet MTU without being fragmented.
+const MAX_DATAGRAM_PAYLOAD: usize = 1200;
+/// Audio carried by each Opus packet, one per datagram.
+#[cfg(feature = "opus")]
+const OPUS_PACKET_DURATION: Duration = Duration::from_millis(20);
+
+/// Encoding of the samples sent to the group.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum MulticastCodec {
+    /// Raw unsigned 8-bit samples.
+    #[default]
+    Pcm,
+    /// One Opus packet per datagram, at 48 kHz.
+    #[cfg(feature = "opus")]
+    Opus,
+}
+
+impl FromStr for MulticastCodec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pcm" => Ok(MulticastCodec::Pcm),
+            #[cfg(feature = "opus")]
+            "opus" => Ok(MulticastCodec::Opus),
+            _ => Err(format!("Invalid codec '{}'. Expected pcm or opus", s)),
+        }
+    }
+}
+
+impl Display for MulticastCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MulticastCodec::Pcm => f.write_str("pcm"),
+            #[cfg(feature = "opus")]
+            MulticastCodec::Opus => f.write_str("opus"),
+        }
+    }
+}
+
+#[cfg(feature = "opus")]
+struct Opus {
+    encoder: Box<OpusEncoder>,
+    converted: Vec<f32>,
+}
+
+/// Sends the samples to a multicast group, so any number of hosts in
+/// the network can listen to the stream at once.
+pub struct MulticastSink {
+    socket: UdpSocket,
+    group: SocketAddrV4,
+    #[cfg(feature = "opus")]
+    opus: Option<Opus>,
+    sent: u64,
+}
+
+impl MulticastSink {
+    pub fn new(
+        group: SocketAddrV4,
+        ttl: u32,
+        codec: MulticastCodec,
+        sample_rate: u32,
+        channels: u16,
+    ) -> anyhow::Result<MulticastSink> {
+        if !group.ip().is_multicast() {
+            return Err(anyhow!("{} is not a multicast address", group.ip()));
+        }
+        #[cfg(not(feature = "opus"))]
+        let _ = (codec, sample_rate, channels);
+        #[cfg(feature = "opus")]
+        let opus = match codec {
+            MulticastCodec::Pcm => None,
+            MulticastCodec::Opus => Some(Opus {
+                encoder: Box::new(OpusEncoder::new(
+                    sample_rate,
+                    channels,
+                    OPUS_PACKET_DURATION,
+                )?),
+                converted: Vec::new(),
+            }),
+        };
+
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        socket.set_multicast_ttl_v4(ttl)?;
+        socket.set_multicast_loop_v4(true)?;
+
+        Ok(MulticastSink {
+            socket,
+            group,
+            #[cfg(feature = "opus")]
+            opus,
+            sent: 0,
+        })
+    }
+
+    /// Sends raw samples, whatever the codec of the sink.
+    pub fn write(&mut self, samples: &[u8]) -> std::io::Result<()> {
+        for chunk in samples.chunks(MAX_DATAGRAM_PAYLOAD) {
+            self.send(chunk)?;
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, datagram: &[u8]) -> std::io::Result<()> {
+        send_to(&self.socket, self.group, &mut self.sent, datagram)
+    }
+
+    /// Encodes the pending samples, and the rest too at the end of the
+    /// stream, sending each packet on its own datagram.
+    #[cfg(feature = "opus")]
+    fn send_opus(&mut self, end: bool) -> anyhow::Result<()> {
+        let (socket, group, sent) = (&self.socket, self.group, &mut self.sent);
+        let send = |packet: &[u8]| Ok(send_to(socket, group, sent, packet)?);
+        match &mut self.opus {
+            Some(opus) if end => opus.encoder.finish(send),
+            Some(opus) => opus.encoder.push(&opus.converted, send),
+            None => Ok(()),
+        }
+    }
+}
+
+fn send_to(
+    socket: &UdpSocket,
+    group: SocketAddrV4,
+    sent: &mut u64,
+    datagram: &[u8],
+) -> std::io::Result<()> {
+    io::retry_if_interrupted(|| socket.send_to(datagram, group), |e| Some(e))?;
+    *sent += datagram.len() as u64;
+    Ok(())
+}
+
+impl SinkWriter<u8> for MulticastSink {
+    fn write_samples(&mut self, samples: &[u8]) -> anyhow::Result<()> {
+        #[cfg(feature = "opus")]
+        if let Some(opus) = &mut self.opus {
+            opus.converted.clear();
+            opus.converted
+                .extend(samples.iter().map(|sample| sample.to_f32()));
+            return self.send_opus(false);
+        }
+        Ok(self.write(samples)?)
+    }
+
+    #[cfg(feature = "opus")]
+    fn finish(&mut self) -> anyhow::Result<()> {
+        self.send_opus(true)
+    }
+
+    fn bytes_written(&self) -> Option<u64> {
+        Some(self.sent)
+    }
+}
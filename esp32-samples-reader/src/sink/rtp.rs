@@ -0,0 +1,301 @@
+use std::{
+    fmt::{self, Display},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    process,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Context as _};
+
+#[cfg(feature = "opus")]
+use crate::{dsp::Sample, opus::OpusEncoder};
+use crate::{io, pipeline::SinkWriter};
+
+const RTP_VERSION: u8 = 2;
+const HEADER_LEN: usize = 12;
+/// Payloads above this are fragmented on the usual Ethernet MTU.
+const MAX_UNFRAGMENTED_PAYLOAD: usize = 1200;
+/// Largest payload of a UDP datagram.
+const MAX_PAYLOAD: usize = 65507 - HEADER_LEN;
+/// Clock of the timestamps of Opus payloads, whatever the rate of the
+/// signal.
+#[cfg(feature = "opus")]
+const OPUS_CLOCK_RATE: u32 = 48000;
+
+/// Encoding of the samples in the packets.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum RtpPayload {
+    /// Uncompressed 16-bit samples, big endian.
+    #[default]
+    L16,
+    #[cfg(feature = "opus")]
+    Opus,
+}
+
+impl FromStr for RtpPayload {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "l16" => Ok(RtpPayload::L16),
+            #[cfg(feature = "opus")]
+            "opus" => Ok(RtpPayload::Opus),
+            _ => Err(format!("Invalid payload '{}'. Expected l16 or opus", s)),
+        }
+    }
+}
+
+impl Display for RtpPayload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RtpPayload::L16 => f.write_str("l16"),
+            #[cfg(feature = "opus")]
+            RtpPayload::Opus => f.write_str("opus"),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct RtpOptions {
+    pub payload: RtpPayload,
+    /// Payload type in the header of the packets, usually a dynamic one
+    /// (96 to 127) described by the SDP of the session.
+    pub payload_type: u8,
+    /// Audio carried by each packet.
+    pub packet_duration: Duration,
+}
+
+enum Encoding {
+    L16 {
+        /// Samples waiting for a whole packet, already encoded.
+        pending: Vec<u8>,
+        packet_len: usize,
+    },
+    #[cfg(feature = "opus")]
+    Opus {
+        encoder: Box<OpusEncoder>,
+        converted: Vec<f32>,
+    },
+}
+
+/// Puts the payloads in packets and sends them.
+struct Packets {
+    socket: UdpSocket,
+    payload_type: u8,
+    ssrc: u32,
+    sequence: u16,
+    timestamp: u32,
+    packet: Vec<u8>,
+    sent: u64,
+}
+
+impl Packets {
+    /// Sends a packet with the given payload, lasting `ticks` of the
+    /// clock of the payload.
+    fn send(&mut self, payload: &[u8], ticks: u32) -> std::io::Result<()> {
+        self.packet.clear();
+        self.packet.push(RTP_VERSION << 6);
+        // The marker flags the start of the stream.
+        let marker = if self.sent == 0 { 0x80 } else { 0 };
+        self.packet.push(marker | self.payload_type);
+        self.packet.extend_from_slice(&self.sequence.to_be_bytes());
+        self.packet.extend_from_slice(&self.timestamp.to_be_bytes());
+        self.packet.extend_from_slice(&self.ssrc.to_be_bytes());
+        self.packet.extend_from_slice(payload);
+
+        io::retry_if_interrupted(|| self.socket.send(&self.packet), |e| Some(e))?;
+        self.sequence = self.sequence.wrapping_add(1);
+        self.timestamp = self.timestamp.wrapping_add(ticks);
+        self.sent += self.packet.len() as u64;
+        Ok(())
+    }
+}
+
+/// Sends unsigned 8-bit samples as an RTP stream, for playing them
+/// remotely with the usual players.
+pub struct RtpSink {
+    packets: Packets,
+    encoding: Encoding,
+    options: RtpOptions,
+    sample_rate: u32,
+    channels: u16,
+}
+
+impl RtpSink {
+    /// Starts a stream to `destination`, a unicast or multicast
+    /// `host:port`.
+    pub fn connect(
+        destination: &str,
+        sample_rate: u32,
+        channels: u16,
+        options: RtpOptions,
+    ) -> anyhow::Result<RtpSink> {
+        if options.payload_type > 127 {
+            return Err(anyhow!(
+                "Invalid payload type {}. Expected 0 to 127",
+                options.payload_type
+            ));
+        }
+        let encoding = match options.payload {
+            RtpPayload::L16 => {
+                let frames = (sample_rate as u128 * options.packet_duration.as_micros() / 1_000_000)
+                    as usize;
+                let packet_len = frames * channels as usize * 2;
+                if packet_len == 0 || packet_len > MAX_PAYLOAD {
+                    return Err(anyhow!(
+                        "Packets of {} ms at {} Hz don't fit in a datagram",
+                        options.packet_duration.as_millis(),
+                        sample_rate
+                    ));
+                }
+                if packet_len > MAX_UNFRAGMENTED_PAYLOAD {
+                    tracing::warn!(
+                        "Packets of {} bytes will be fragmented. Use shorter packets or a lower rate",
+                        packet_len
+                    );
+                }
+                Encoding::L16 {
+                    pending: Vec::new(),
+                    packet_len,
+                }
+            }
+            #[cfg(feature = "opus")]
+            RtpPayload::Opus => Encoding::Opus {
+                encoder: Box::new(OpusEncoder::new(
+                    sample_rate,
+                    channels,
+                    options.packet_duration,
+                )?),
+                converted: Vec::new(),
+            },
+        };
+
+        let addr = destination
+            .to_socket_addrs()
+            .with_context(|| format!("Unable to resolve {}", destination))?
+            .next()
+            .ok_or_else(|| anyhow!("{} has no addresses", destination))?;
+        let local: SocketAddr = match addr {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(addr)?;
+
+        // Tells the streams of different runs apart.
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos();
+        Ok(RtpSink {
+            packets: Packets {
+                socket,
+                payload_type: options.payload_type,
+                ssrc: nanos ^ process::id().rotate_left(16),
+                sequence: 0,
+                timestamp: 0,
+                packet: Vec::new(),
+                sent: 0,
+            },
+            encoding,
+            options,
+            sample_rate,
+            channels,
+        })
+    }
+
+    /// Session description of the stream, for players that need one to
+    /// make sense of dynamic payload types, like VLC or GStreamer.
+    pub fn sdp(&self) -> anyhow::Result<String> {
+        let local = self.packets.socket.local_addr()?;
+        let peer = self.packets.socket.peer_addr()?;
+        let address = |ip: IpAddr| match ip {
+            IpAddr::V4(ip) => format!("IN IP4 {}", ip),
+            IpAddr::V6(ip) => format!("IN IP6 {}", ip),
+        };
+        let payload_type = self.options.payload_type;
+        let rtpmap = match self.options.payload {
+            RtpPayload::L16 => format!("L16/{}/{}", self.sample_rate, self.channels),
+            // Always declared as stereo, with the actual channels in
+            // the format parameters.
+            #[cfg(feature = "opus")]
+            RtpPayload::Opus => format!(
+                "opus/{}/2\r\na=fmtp:{} sprop-stereo={}",
+                OPUS_CLOCK_RATE,
+                payload_type,
+                (self.channels == 2) as u8
+            ),
+        };
+        Ok(format!(
+            "v=0\r\no=- {} 0 {}\r\ns=ESP32 Signal Reader\r\nc={}\r\nt=0 0\r\nm=audio {} RTP/AVP {}\r\na=rtpmap:{} {}\r\na=ptime:{}\r\n",
+            self.packets.ssrc,
+            address(local.ip()),
+            address(peer.ip()),
+            peer.port(),
+            payload_type,
+            payload_type,
+            rtpmap,
+            self.options.packet_duration.as_millis()
+        ))
+    }
+
+    /// Sends the whole packets of the pending samples, and the rest
+    /// too at the end of the stream.
+    fn send_pending(&mut self, end: bool) -> anyhow::Result<()> {
+        let frame_len = self.channels as usize * 2;
+        let packets = &mut self.packets;
+        match &mut self.encoding {
+            Encoding::L16 {
+                pending,
+                packet_len,
+            } => {
+                let mut start = 0;
+                while pending.len() - start >= *packet_len || (end && start < pending.len()) {
+                    let len = usize::min(*packet_len, pending.len() - start);
+                    packets.send(&pending[start..start + len], (len / frame_len) as u32)?;
+                    start += len;
+                }
+                pending.drain(..start);
+            }
+            #[cfg(feature = "opus")]
+            Encoding::Opus { encoder, converted } => {
+                let ticks = encoder.frame_len() as u32;
+                let send = |packet: &[u8]| Ok(packets.send(packet, ticks)?);
+                if end {
+                    encoder.finish(send)?;
+                } else {
+                    encoder.push(converted, send)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SinkWriter<u8> for RtpSink {
+    fn write_samples(&mut self, samples: &[u8]) -> anyhow::Result<()> {
+        match &mut self.encoding {
+            Encoding::L16 { pending, .. } => {
+                for sample in samples {
+                    let sample = (*sample as i16 - 128) << 8;
+                    pending.extend_from_slice(&sample.to_be_bytes());
+                }
+            }
+            #[cfg(feature = "opus")]
+            Encoding::Opus { converted, .. } => {
+                converted.clear();
+                converted.extend(samples.iter().map(|sample| sample.to_f32()));
+            }
+        }
+        self.send_pending(false)
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        self.send_pending(true)
+    }
+
+    fn bytes_written(&self) -> Option<u64> {
+        Some(self.packets.sent)
+    }
+}
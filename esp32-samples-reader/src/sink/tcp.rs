@@ -0,0 +1,83 @@
+use std::{
+    io::{ErrorKind, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+};
+
+use anyhow::Context as _;
+
+use crate::pipeline::SinkWriter;
+
+/// Serves raw PCM samples to every client connected to a TCP port,
+/// starting from the samples read once they connect. Clients that
+/// can't keep up are disconnected instead of holding up the capture.
+pub struct TcpSink {
+    listener: TcpListener,
+    clients: Vec<(SocketAddr, TcpStream)>,
+    sent: u64,
+}
+
+impl TcpSink {
+    pub fn bind(addr: SocketAddr) -> anyhow::Result<TcpSink> {
+        let listener =
+            TcpListener::bind(addr).with_context(|| format!("Unable to listen on {}", addr))?;
+        listener.set_nonblocking(true)?;
+        Ok(TcpSink {
+            listener,
+            clients: Vec::new(),
+            sent: 0,
+        })
+    }
+
+    /// Takes the clients that connected since the last call.
+    fn accept(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, addr)) => {
+                    if let Err(e) = stream.set_nonblocking(true) {
+                        tracing::warn!("Unable to serve {}: {}", addr, e);
+                        continue;
+                    }
+                    let _ = stream.set_nodelay(true);
+                    tracing::info!("Client {} connected", addr);
+                    self.clients.push((addr, stream));
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) => {
+                    tracing::warn!("Unable to accept clients: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    pub fn write(&mut self, samples: &[u8]) {
+        self.accept();
+        // Dropping clients as soon as they fall behind, even halfway
+        // through the samples, keeps the others' streams whole.
+        self.clients
+            .retain_mut(|(addr, stream)| match stream.write_all(samples) {
+                Ok(()) => true,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    tracing::warn!("Client {} fell behind, disconnecting it", addr);
+                    false
+                }
+                Err(_) => {
+                    tracing::info!("Client {} disconnected", addr);
+                    false
+                }
+            });
+        self.sent += samples.len() as u64;
+    }
+}
+
+impl SinkWriter<u8> for TcpSink {
+    fn write_samples(&mut self, samples: &[u8]) -> anyhow::Result<()> {
+        self.write(samples);
+        Ok(())
+    }
+
+    fn bytes_written(&self) -> Option<u64> {
+        Some(self.sent)
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Cursor, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use anyhow::anyhow;
+
+/// Marks a sparse dump. Plain dumps are the received bytes as is, so
+/// they have no header at all.
+const SPARSE_MAGIC: &[u8; 8] = b"ESP32RLE";
+
+/// Writes the raw data received from the ESP32 to a file that can be
+/// read back with the file: source.
+///
+/// Sparse dumps store runs of repeated bytes as the byte followed by
+/// the length of the run (as a LEB128 varint), so hours of a signal
+/// sitting at a constant level take a few bytes.
+pub enum DumpWriter<W: Write = BufWriter<File>> {
+    Plain(W),
+    Sparse { file: W, run: Option<(u8, u64)> },
+}
+
+impl DumpWriter {
+    pub fn create(path: &Path, sparse: bool) -> std::io::Result<DumpWriter> {
+        DumpWriter::new(BufWriter::new(File::create(path)?), sparse)
+    }
+}
+
+impl<W: Write> DumpWriter<W> {
+    pub fn new(mut file: W, sparse: bool) -> std::io::Result<DumpWriter<W>> {
+        if !sparse {
+            return Ok(DumpWriter::Plain(file));
+        }
+
+        file.write_all(SPARSE_MAGIC)?;
+        Ok(DumpWriter::Sparse { file, run: None })
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        match self {
+            DumpWriter::Plain(file) => file.write_all(data),
+            DumpWriter::Sparse { file, run } => {
+                for byte in data {
+                    match run {
+                        Some((value, count)) if value == byte => *count += 1,
+                        _ => {
+                            if let Some((value, count)) = run.replace((*byte, 1)) {
+                                write_run(file, value, count)?;
+                            }
+                        }
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Pushes what has been written so far to the file. A run still
+    /// going on is only written once it ends.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            DumpWriter::Plain(file) | DumpWriter::Sparse { file, .. } => file.flush(),
+        }
+    }
+
+    /// Writes the pending run, if any, and flushes the file.
+    pub fn finish(self) -> std::io::Result<()> {
+        match self {
+            DumpWriter::Plain(mut file) => file.flush(),
+            DumpWriter::Sparse { mut file, run } => {
+                if let Some((value, count)) = run {
+                    write_run(&mut file, value, count)?;
+                }
+                file.flush()
+            }
+        }
+    }
+}
+
+fn write_run<W: Write>(file: &mut W, value: u8, mut count: u64) -> std::io::Result<()> {
+    file.write_all(&[value])?;
+    loop {
+        let low = (count & 0x7f) as u8;
+        count >>= 7;
+        if count == 0 {
+            return file.write_all(&[low]);
+        }
+        file.write_all(&[low | 0x80])?;
+    }
+}
+
+/// Expands a sparse dump back into the original bytes.
+struct SparseReader<R> {
+    inner: R,
+    run: Option<(u8, u64)>,
+}
+
+impl<R: Read> SparseReader<R> {
+    /// Reads the next run, or None at the end of the dump.
+    fn next_run(&mut self) -> std::io::Result<Option<(u8, u64)>> {
+        let mut value = [0];
+        match self.inner.read_exact(&mut value) {
+            Ok(()) => (),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let mut count = 0u64;
+        for shift in (0..64).step_by(7) {
+            let mut byte = [0];
+            self.inner.read_exact(&mut byte)?;
+            count |= ((byte[0] & 0x7f) as u64) << shift;
+            if byte[0] & 0x80 == 0 {
+                return Ok(Some((value[0], count)));
+            }
+        }
+        Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            "Invalid run length in sparse dump",
+        ))
+    }
+}
+
+impl<R: Read> Read for SparseReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut len = 0;
+        while len < buf.len() {
+            let (value, count) = match self.run.take() {
+                Some(run) => run,
+                None => match self.next_run()? {
+                    Some(run) => run,
+                    None => break,
+                },
+            };
+
+            let n = count.min((buf.len() - len) as u64) as usize;
+            buf[len..len + n].fill(value);
+            len += n;
+            if count > n as u64 {
+                self.run = Some((value, count - n as u64));
+            }
+        }
+        Ok(len)
+    }
+}
+
+/// Opens a dump written by [`DumpWriter`], expanding it if it's sparse.
+pub fn open(path: &Path) -> anyhow::Result<Box<dyn Read + Send>> {
+    let mut file = File::open(path)?;
+    let mut magic = [0; SPARSE_MAGIC.len()];
+    let is_sparse = match file.read_exact(&mut magic) {
+        Ok(()) => &magic == SPARSE_MAGIC,
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => false,
+        Err(e) => return Err(anyhow!("Can't read {}: {}", path.display(), e)),
+    };
+
+    if is_sparse {
+        Ok(Box::new(SparseReader {
+            inner: BufReader::new(file),
+            run: None,
+        }))
+    } else {
+        file.seek(SeekFrom::Start(0))?;
+        Ok(Box::new(file))
+    }
+}
+
+/// Like [`open`], for inputs that can't seek, like stdin.
+pub fn from_reader<R: Read + Send + 'static>(mut input: R) -> anyhow::Result<Box<dyn Read + Send>> {
+    let mut magic = Vec::with_capacity(SPARSE_MAGIC.len());
+    (&mut input)
+        .take(SPARSE_MAGIC.len() as u64)
+        .read_to_end(&mut magic)?;
+
+    if magic == SPARSE_MAGIC {
+        Ok(Box::new(SparseReader {
+            inner: BufReader::new(input),
+            run: None,
+        }))
+    } else {
+        Ok(Box::new(Cursor::new(magic).chain(input)))
+    }
+}
@@ -0,0 +1,180 @@
+use std::{
+    collections::VecDeque,
+    io::{ErrorKind, Read},
+    sync::atomic::Ordering,
+};
+
+use crate::metrics::METRICS;
+
+/// Byte starting every frame.
+pub const SYNC: u8 = 0xa5;
+/// Bytes of samples carried by each frame.
+pub const PAYLOAD_LEN: usize = 16;
+/// Sync byte, sequence number, payload and CRC.
+const FRAME_LEN: usize = PAYLOAD_LEN + 3;
+
+/// CRC-8 with polynomial 0x07 and no reflection, as computed by the
+/// firmware over the sequence number and the payload of each frame.
+pub fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameStats {
+    pub frames: u64,
+    /// Frames missing from the sequence.
+    pub dropped: u64,
+    /// Places where frames were missing.
+    pub gaps: u64,
+    pub duplicated: u64,
+    /// Frames that failed the CRC or the sync check.
+    pub corrupted: u64,
+}
+
+/// Reads the payload of a link using the framed protocol of the
+/// firmware, validating each frame and keeping track of the frames
+/// lost on the way. Missing frames are replaced by silence, so the
+/// timing of the signal is kept.
+///
+/// Sequence numbers wrap every 256 frames, so gaps longer than that
+/// can't be told apart from shorter ones.
+pub struct Deframer<R> {
+    inner: R,
+    raw: Vec<u8>,
+    payload: VecDeque<u8>,
+    last_seq: Option<u8>,
+    in_sync: bool,
+    eof: bool,
+    stats: FrameStats,
+}
+
+impl<R: Read> Deframer<R> {
+    pub fn new(inner: R) -> Deframer<R> {
+        Deframer {
+            inner,
+            raw: Vec::new(),
+            payload: VecDeque::new(),
+            last_seq: None,
+            in_sync: false,
+            eof: false,
+            stats: FrameStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        self.stats
+    }
+
+    fn parse(&mut self) {
+        loop {
+            match self.raw.iter().position(|&byte| byte == SYNC) {
+                Some(0) => (),
+                Some(start) => {
+                    self.lose_sync();
+                    self.raw.drain(..start);
+                }
+                None => {
+                    if !self.raw.is_empty() {
+                        self.lose_sync();
+                    }
+                    self.raw.clear();
+                    return;
+                }
+            }
+            if self.raw.len() < FRAME_LEN {
+                return;
+            }
+
+            let frame = &self.raw[..FRAME_LEN];
+            if crc8(&frame[1..FRAME_LEN - 1]) != frame[FRAME_LEN - 1] {
+                // Might have been a sync byte in the middle of some
+                // payload, so look for the next one.
+                self.lose_sync();
+                self.raw.drain(..1);
+                continue;
+            }
+
+            let seq = frame[1];
+            match self.last_seq {
+                Some(last) if seq == last => self.stats.duplicated += 1,
+                last => {
+                    let lost = match last {
+                        Some(last) => seq.wrapping_sub(last).wrapping_sub(1) as usize,
+                        None => 0,
+                    };
+                    if lost > 0 {
+                        self.stats.gaps += 1;
+                        self.stats.dropped += lost as u64;
+                        METRICS
+                            .bytes_dropped
+                            .fetch_add((lost * PAYLOAD_LEN) as u64, Ordering::Relaxed);
+                        let len = self.payload.len() + lost * PAYLOAD_LEN;
+                        self.payload.resize(len, 0);
+                    }
+                    self.payload.extend(&frame[2..FRAME_LEN - 1]);
+                    self.stats.frames += 1;
+                }
+            }
+            self.last_seq = Some(seq);
+            self.in_sync = true;
+            self.raw.drain(..FRAME_LEN);
+        }
+    }
+
+    /// Only counts the first bad frame after a good one, not every
+    /// byte skipped while looking for the next sync.
+    fn lose_sync(&mut self) {
+        if self.in_sync {
+            self.stats.corrupted += 1;
+            self.in_sync = false;
+        }
+    }
+}
+
+impl<R: Read> Read for Deframer<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.payload.is_empty() && !self.eof {
+            let mut chunk = [0; 1024];
+            match self.inner.read(&mut chunk) {
+                Ok(0) => self.eof = true,
+                Ok(len) => {
+                    self.raw.extend_from_slice(&chunk[..len]);
+                    self.parse();
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        let len = usize::min(buf.len(), self.payload.len());
+        for (out, byte) in buf.iter_mut().zip(self.payload.drain(..len)) {
+            *out = byte;
+        }
+        Ok(len)
+    }
+}
+
+impl<R> Drop for Deframer<R> {
+    fn drop(&mut self) {
+        let stats = self.stats;
+        tracing::info!(
+            "Frames: {} received, {} dropped in {} gaps, {} duplicated, {} corrupted",
+            stats.frames,
+            stats.dropped,
+            stats.gaps,
+            stats.duplicated,
+            stats.corrupted
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use std::io::{ErrorKind, Read};
+
+const SYSEX_START: u8 = 0xF0;
+const SYSEX_END: u8 = 0xF7;
+
+/// Manufacturer ID reserved for non-commercial use. Any SysEx message
+/// carrying a different ID is not ours and gets ignored.
+const SYSEX_MANUFACTURER_ID: u8 = 0x7D;
+
+enum State {
+    Idle,
+    Manufacturer,
+    Data { msbs: u8, group_pos: u8 },
+}
+
+/// Unpacks sample payloads sent as SysEx messages over a MIDI link.
+///
+/// Each message is `F0 7D <data> F7`, where data is packed in groups
+/// of up to 8 bytes: the first one of the group carries the most
+/// significant bits of the (up to) 7 following bytes, bit 0 belonging
+/// to the first of them. MIDI realtime messages interleaved in the
+/// stream are skipped, and any other status byte aborts the current
+/// message, dropping data until the beginning of the next one.
+pub struct SysExReader<R> {
+    inner: R,
+    state: State,
+    frame: Vec<u8>,
+    frame_pos: usize,
+}
+
+impl<R: Read> SysExReader<R> {
+    pub fn new(inner: R) -> SysExReader<R> {
+        SysExReader {
+            inner,
+            state: State::Idle,
+            frame: Vec::new(),
+            frame_pos: 0,
+        }
+    }
+
+    fn read_byte(&mut self) -> std::io::Result<u8> {
+        let mut byte = [0];
+        match self.inner.read(&mut byte)? {
+            0 => Err(ErrorKind::UnexpectedEof.into()),
+            _ => Ok(byte[0]),
+        }
+    }
+
+    // The state is kept in self so reading can be resumed if
+    // interrupted in the middle of a message.
+    fn read_frame(&mut self) -> std::io::Result<()> {
+        loop {
+            let byte = self.read_byte()?;
+            self.state = match (&self.state, byte) {
+                (_, 0xF8..=0xFF) => continue,
+                (_, SYSEX_START) => State::Manufacturer,
+                (State::Manufacturer, SYSEX_MANUFACTURER_ID) => {
+                    self.frame.clear();
+                    self.frame_pos = 0;
+                    State::Data {
+                        msbs: 0,
+                        group_pos: 7,
+                    }
+                }
+                (State::Data { .. }, SYSEX_END) => {
+                    self.state = State::Idle;
+                    if self.frame.is_empty() {
+                        continue;
+                    }
+                    return Ok(());
+                }
+                (State::Data { msbs, group_pos }, 0x00..=0x7F) => {
+                    if *group_pos == 7 {
+                        State::Data {
+                            msbs: byte,
+                            group_pos: 0,
+                        }
+                    } else {
+                        self.frame.push(byte | (((msbs >> group_pos) & 1) << 7));
+                        State::Data {
+                            msbs: *msbs,
+                            group_pos: group_pos + 1,
+                        }
+                    }
+                }
+                _ => State::Idle,
+            };
+        }
+    }
+}
+
+impl<R: Read> Read for SysExReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.frame_pos >= self.frame.len() {
+            // Running out of input between messages, or in the middle
+            // of one, ends the stream.
+            match self.read_frame() {
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(0),
+                result => result?,
+            }
+        }
+
+        let len = usize::min(buf.len(), self.frame.len() - self.frame_pos);
+        buf[..len].copy_from_slice(&self.frame[self.frame_pos..self.frame_pos + len]);
+        self.frame_pos += len;
+        Ok(len)
+    }
+}
@@ -0,0 +1,195 @@
+pub mod dump;
+pub mod framed;
+pub mod midi;
+pub mod pace;
+pub mod pcap;
+pub mod read_ahead;
+pub mod reconnect;
+pub mod usb;
+
+use std::{
+    fmt::{self, Display},
+    fs::{self, File},
+    io::IsTerminal,
+    io::Read,
+    path::Path,
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use serialport::SerialPortType;
+
+use crate::io::{self, SerialConfig};
+use framed::Deframer;
+use midi::SysExReader;
+use pace::Paced;
+use pcap::{PcapLogger, PcapReplay};
+use reconnect::Reconnecting;
+
+/// Baud rate used by MIDI links (31.25 kbaud).
+pub const MIDI_BAUD_RATE: u32 = 31250;
+
+#[derive(Clone, Debug)]
+pub enum SourceSpec {
+    Serial(String),
+    Midi(String),
+    /// Raw data received from the ESP32, saved to a file as is or
+    /// as a sparse dump.
+    File(String),
+    /// Traffic logged with --debug-pcap, replayed with its original
+    /// timing.
+    Pcap(String),
+    /// Same as File, read from the standard input.
+    Stdin,
+}
+
+pub struct OpenOptions<'a> {
+    pub baud_rate: Option<u32>,
+    /// Line settings of serial ports.
+    pub serial: SerialConfig,
+    pub timeout: Duration,
+    /// Logs the traffic of the link to this pcapng file.
+    pub debug_pcap: Option<&'a Path>,
+    /// Recorded sources are delivered in real time for this sampling
+    /// rate, or as fast as possible if not set. Pcap captures keep
+    /// their own timing instead.
+    pub realtime_rate: Option<u32>,
+    /// Serial ports are reopened if the board goes away, instead of
+    /// failing.
+    pub reconnect: bool,
+    /// The time a serial port was gone is filled with silence for
+    /// this sampling rate, if set.
+    pub gap_fill_rate: Option<u32>,
+    /// The link uses the framed protocol of the firmware.
+    pub framed: bool,
+}
+
+impl FromStr for SourceSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "-" || s == "stdin" {
+            return Ok(SourceSpec::Stdin);
+        }
+        match s.split_once(':') {
+            Some(("serial", path)) => Ok(SourceSpec::Serial(path.into())),
+            Some(("midi", path)) => Ok(SourceSpec::Midi(path.into())),
+            Some(("file", path)) => Ok(SourceSpec::File(path.into())),
+            Some(("pcap", path)) => Ok(SourceSpec::Pcap(path.into())),
+            _ => Err(format!(
+                "Invalid source '{}'. Expected serial:<port>, midi:<device>, file:<path>, pcap:<file> or stdin",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for SourceSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceSpec::Serial(path) => write!(f, "serial:{}", path),
+            SourceSpec::Midi(path) => write!(f, "midi:{}", path),
+            SourceSpec::File(path) => write!(f, "file:{}", path),
+            SourceSpec::Pcap(path) => write!(f, "pcap:{}", path),
+            SourceSpec::Stdin => f.write_str("stdin"),
+        }
+    }
+}
+
+/// Looks up the serial number of the USB device behind a serial port.
+pub fn usb_serial_number(port: &str) -> Option<String> {
+    let port = fs::canonicalize(port).ok()?;
+    serialport::available_ports()
+        .ok()?
+        .into_iter()
+        .find(|info| fs::canonicalize(&info.port_name).ok().as_ref() == Some(&port))
+        .and_then(|info| match info.port_type {
+            SerialPortType::UsbPort(usb) => usb.serial_number,
+            _ => None,
+        })
+}
+
+impl SourceSpec {
+    pub fn open(&self, options: &OpenOptions) -> anyhow::Result<Box<dyn Read + Send>> {
+        let OpenOptions {
+            baud_rate,
+            serial,
+            timeout,
+            debug_pcap,
+            realtime_rate,
+            reconnect,
+            gap_fill_rate,
+            framed,
+        } = *options;
+        let _span = tracing::info_span!("open", source = %self).entered();
+
+        let link: Box<dyn Read + Send> = match self {
+            SourceSpec::Serial(path) => {
+                let baud_rate = baud_rate
+                    .ok_or_else(|| anyhow!("A baud rate is required for serial sources"))?;
+                if reconnect {
+                    Box::new(Reconnecting::open(
+                        path,
+                        baud_rate,
+                        timeout,
+                        serial,
+                        gap_fill_rate,
+                    )?)
+                } else {
+                    Box::new(io::open_serial_port(path, baud_rate, timeout, &serial)?)
+                }
+            }
+            SourceSpec::Midi(path) => {
+                let device = File::open(path)?;
+                if device.is_terminal() {
+                    // MIDI-rate UART exposed as a TTY.
+                    drop(device);
+                    Box::new(io::open_serial_port(
+                        path,
+                        baud_rate.unwrap_or(MIDI_BAUD_RATE),
+                        timeout,
+                        &serial,
+                    )?)
+                } else {
+                    // Raw MIDI device, like /dev/snd/midiC1D0.
+                    Box::new(device)
+                }
+            }
+            SourceSpec::File(path) => {
+                let file = dump::open(Path::new(path))?;
+                match realtime_rate {
+                    Some(rate) => Box::new(Paced::new(file, rate)),
+                    None => Box::new(file),
+                }
+            }
+            SourceSpec::Stdin => {
+                let input = dump::from_reader(std::io::stdin())?;
+                match realtime_rate {
+                    Some(rate) => Box::new(Paced::new(input, rate)),
+                    None => input,
+                }
+            }
+            SourceSpec::Pcap(path) => {
+                Box::new(PcapReplay::open(Path::new(path), realtime_rate.is_some())?)
+            }
+        };
+
+        let link: Box<dyn Read + Send> = match debug_pcap {
+            Some(path) => Box::new(PcapLogger::create(path, link)?),
+            None => link,
+        };
+
+        let link: Box<dyn Read + Send> = match self {
+            SourceSpec::Midi(_) => Box::new(SysExReader::new(link)),
+            _ => link,
+        };
+
+        tracing::debug!(framed, reconnect, "Source open");
+        if framed {
+            Ok(Box::new(Deframer::new(link)))
+        } else {
+            Ok(link)
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use std::{
+    io::Read,
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::rate;
+
+/// Monotonic schedule for delivering data at a fixed pace. Each
+/// deadline is an offset from the moment the clock started rather
+/// than from the previous wait, so the time lost oversleeping or
+/// processing doesn't add up, and hours of replayed data still end
+/// in step with the wall clock.
+#[derive(Default)]
+pub struct PacingClock {
+    start: Option<Instant>,
+}
+
+impl PacingClock {
+    pub fn new() -> PacingClock {
+        Self::default()
+    }
+
+    /// Starts the clock, if not started yet, returning when it did.
+    pub fn start(&mut self) -> Instant {
+        *self.start.get_or_insert_with(Instant::now)
+    }
+
+    /// Waits until the given time after the start of the clock has
+    /// passed, returning right away if it already has.
+    pub fn wait_until(&mut self, offset: Duration) {
+        let due = self.start() + offset;
+        if let Some(wait) = due.checked_duration_since(Instant::now()) {
+            thread::sleep(wait);
+        }
+    }
+}
+
+/// Delivers the data of the inner reader no faster than a live link
+/// sampling at the given rate would.
+pub struct Paced<R> {
+    inner: R,
+    sampling_rate: u32,
+    clock: PacingClock,
+    total_bytes: u64,
+}
+
+impl<R: Read> Paced<R> {
+    pub fn new(inner: R, sampling_rate: u32) -> Paced<R> {
+        Paced {
+            inner,
+            sampling_rate,
+            clock: PacingClock::new(),
+            total_bytes: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for Paced<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.clock.start();
+        let len = self.inner.read(buf)?;
+        self.total_bytes += len as u64;
+        self.clock.wait_until(rate::duration_of_bytes(
+            self.sampling_rate,
+            self.total_bytes,
+        ));
+        Ok(len)
+    }
+}
@@ -0,0 +1,208 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::anyhow;
+
+use super::pace::PacingClock;
+
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x00000001;
+const ENHANCED_PACKET_BLOCK: u32 = 0x00000006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B3C4D;
+
+/// DLT_USER0, reserved for private use. Wireshark can be told how to
+/// dissect it in its DLT_USER preferences.
+const LINKTYPE_USER0: u16 = 147;
+
+/// Logs everything read from the inner reader to a pcapng file, one
+/// packet per read, timestamped with microsecond resolution.
+pub struct PcapLogger<R> {
+    inner: R,
+    file: BufWriter<File>,
+}
+
+fn write_block<W: Write>(file: &mut W, block_type: u32, body: &[u8]) -> std::io::Result<()> {
+    let padding = (4 - body.len() % 4) % 4;
+    let total_len = (12 + body.len() + padding) as u32;
+    file.write_all(&block_type.to_le_bytes())?;
+    file.write_all(&total_len.to_le_bytes())?;
+    file.write_all(body)?;
+    file.write_all(&[0; 3][..padding])?;
+    file.write_all(&total_len.to_le_bytes())
+}
+
+impl<R: Read> PcapLogger<R> {
+    pub fn create(path: &Path, inner: R) -> std::io::Result<PcapLogger<R>> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        let mut section = Vec::new();
+        section.extend(BYTE_ORDER_MAGIC.to_le_bytes());
+        section.extend(1u16.to_le_bytes());
+        section.extend(0u16.to_le_bytes());
+        // Unknown section length.
+        section.extend((-1i64).to_le_bytes());
+        write_block(&mut file, SECTION_HEADER_BLOCK, &section)?;
+
+        let mut interface = Vec::new();
+        interface.extend(LINKTYPE_USER0.to_le_bytes());
+        interface.extend(0u16.to_le_bytes());
+        // No snapshot length limit.
+        interface.extend(0u32.to_le_bytes());
+        write_block(&mut file, INTERFACE_DESCRIPTION_BLOCK, &interface)?;
+
+        file.flush()?;
+        Ok(PcapLogger { inner, file })
+    }
+
+    fn log(&mut self, data: &[u8]) -> std::io::Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+
+        let mut packet = Vec::with_capacity(20 + data.len());
+        packet.extend(0u32.to_le_bytes());
+        packet.extend(((timestamp >> 32) as u32).to_le_bytes());
+        packet.extend((timestamp as u32).to_le_bytes());
+        packet.extend((data.len() as u32).to_le_bytes());
+        packet.extend((data.len() as u32).to_le_bytes());
+        packet.extend(data);
+        write_block(&mut self.file, ENHANCED_PACKET_BLOCK, &packet)?;
+        self.file.flush()
+    }
+}
+
+impl<R: Read> Read for PcapLogger<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        if len > 0 {
+            self.log(&buf[..len])?;
+        }
+        Ok(len)
+    }
+}
+
+/// Replays the traffic logged to a pcapng file by PcapLogger, keeping
+/// the original timing between packets if requested.
+pub struct PcapReplay {
+    file: BufReader<File>,
+    big_endian: bool,
+    realtime: bool,
+    packet: Vec<u8>,
+    packet_pos: usize,
+    clock: PacingClock,
+    /// Timestamp of the first packet, in microseconds.
+    first_timestamp: Option<u64>,
+}
+
+impl PcapReplay {
+    pub fn open(path: &Path, realtime: bool) -> anyhow::Result<PcapReplay> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut header = [0; 12];
+        file.read_exact(&mut header)?;
+        if header[0..4] != SECTION_HEADER_BLOCK.to_le_bytes() {
+            return Err(anyhow!("{} is not a pcapng file", path.display()));
+        }
+
+        let big_endian = match header[8..12].try_into().unwrap() {
+            magic if u32::from_le_bytes(magic) == BYTE_ORDER_MAGIC => false,
+            magic if u32::from_be_bytes(magic) == BYTE_ORDER_MAGIC => true,
+            _ => return Err(anyhow!("{} is not a pcapng file", path.display())),
+        };
+
+        let mut replay = PcapReplay {
+            file,
+            big_endian,
+            realtime,
+            packet: Vec::new(),
+            packet_pos: 0,
+            clock: PacingClock::new(),
+            first_timestamp: None,
+        };
+        let section_len = replay.u32_at(&header, 4) as usize;
+        replay.skip(section_len.saturating_sub(header.len()))?;
+        Ok(replay)
+    }
+
+    fn u32_at(&self, data: &[u8], pos: usize) -> u32 {
+        let bytes = data[pos..pos + 4].try_into().unwrap();
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+
+    fn skip(&mut self, len: usize) -> std::io::Result<()> {
+        std::io::copy(&mut (&mut self.file).take(len as u64), &mut std::io::sink())?;
+        Ok(())
+    }
+
+    /// Loads the next packet, waiting until it's due if replaying in
+    /// real time. Returns false at the end of the file.
+    fn next_packet(&mut self) -> std::io::Result<bool> {
+        loop {
+            let mut header = [0; 8];
+            match self.file.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+                Err(e) => return Err(e),
+            }
+
+            let block_type = self.u32_at(&header, 0);
+            let block_len = self.u32_at(&header, 4) as usize;
+            if block_len < 12 {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    "Corrupted pcapng block",
+                ));
+            }
+            if block_type != ENHANCED_PACKET_BLOCK {
+                self.skip(block_len - header.len())?;
+                continue;
+            }
+
+            let mut block = vec![0; block_len - header.len()];
+            self.file.read_exact(&mut block)?;
+            let timestamp = (self.u32_at(&block, 4) as u64) << 32 | self.u32_at(&block, 8) as u64;
+            let captured_len = self.u32_at(&block, 12) as usize;
+            if block.len() < 24 + captured_len {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    "Corrupted pcapng packet",
+                ));
+            }
+
+            if self.realtime {
+                let first_timestamp = *self.first_timestamp.get_or_insert(timestamp);
+                self.clock.wait_until(Duration::from_micros(
+                    timestamp.saturating_sub(first_timestamp),
+                ));
+            }
+
+            self.packet.clear();
+            self.packet.extend(&block[20..20 + captured_len]);
+            self.packet_pos = 0;
+            return Ok(true);
+        }
+    }
+}
+
+impl Read for PcapReplay {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.packet_pos == self.packet.len() {
+            if !self.next_packet()? {
+                return Ok(0);
+            }
+        }
+
+        let len = usize::min(buf.len(), self.packet.len() - self.packet_pos);
+        buf[..len].copy_from_slice(&self.packet[self.packet_pos..self.packet_pos + len]);
+        self.packet_pos += len;
+        Ok(len)
+    }
+}
@@ -0,0 +1,195 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle, Thread},
+    time::{Duration, Instant},
+};
+
+use rtrb::{Consumer, Producer, RingBuffer};
+
+use crate::metrics::METRICS;
+
+/// Longest wait for the other side of the buffer before checking
+/// again, in case a wakeup went to another thread.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+struct Shared {
+    stop: AtomicBool,
+    /// Error of the inner reader, handed over once the data read
+    /// before it has been consumed.
+    error: Mutex<Option<std::io::Error>>,
+    /// Thread reading the buffer, woken up when data arrives. It's the
+    /// last one that read it, which needn't be the one that created it.
+    consumer: Mutex<Thread>,
+}
+
+impl Shared {
+    fn wake_consumer(&self) {
+        self.consumer.lock().unwrap().unpark();
+    }
+}
+
+/// Reads the inner reader on a thread of its own into a bounded
+/// lock-free ring buffer, so the source keeps being drained while the
+/// thread consuming the data is blocked writing it out. Otherwise the
+/// buffers of the serial port overflow and data gets lost at high baud
+/// rates.
+///
+/// Errors of the inner reader are returned in order with the data.
+/// The reading goes on after timeouts and stops after any other
+/// error, or once the inner reader ends.
+pub struct ReadAhead {
+    consumer: Consumer<u8>,
+    shared: Arc<Shared>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl ReadAhead {
+    /// Starts reading the inner reader, `read_len` bytes at most at
+    /// once, buffering up to `capacity` bytes.
+    pub fn spawn(
+        inner: Box<dyn Read + Send>,
+        read_len: usize,
+        capacity: usize,
+    ) -> std::io::Result<ReadAhead> {
+        let (producer, consumer) = RingBuffer::new(capacity.max(1));
+        METRICS
+            .buffer_capacity
+            .store(capacity.max(1) as u64, Ordering::Relaxed);
+        METRICS.read_ahead_waits.start();
+        let shared = Arc::new(Shared {
+            stop: AtomicBool::new(false),
+            error: Mutex::new(None),
+            consumer: Mutex::new(thread::current()),
+        });
+        let reader = thread::Builder::new().name("reader".into()).spawn({
+            let shared = shared.clone();
+            move || read_into(inner, read_len.max(1), producer, &shared)
+        })?;
+        Ok(ReadAhead {
+            consumer,
+            shared,
+            reader: Some(reader),
+        })
+    }
+
+    fn wake_reader(&self) {
+        if let Some(reader) = &self.reader {
+            reader.thread().unpark();
+        }
+    }
+}
+
+fn read_into(
+    mut inner: Box<dyn Read + Send>,
+    read_len: usize,
+    mut producer: Producer<u8>,
+    shared: &Shared,
+) {
+    let mut buf = vec![0; read_len];
+    while !shared.stop.load(Ordering::Relaxed) {
+        let len = match inner.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => {
+                let last = e.kind() != ErrorKind::TimedOut;
+                *shared.error.lock().unwrap() = Some(e);
+                shared.wake_consumer();
+                // Not reading again until the consumer got the error,
+                // so errors don't pile up.
+                while shared.error.lock().unwrap().is_some() && !shared.stop.load(Ordering::Relaxed)
+                {
+                    thread::park_timeout(POLL_INTERVAL);
+                }
+                if last {
+                    break;
+                }
+                continue;
+            }
+        };
+
+        let mut data = &buf[..len];
+        let mut full_since: Option<Instant> = None;
+        while !data.is_empty() && !shared.stop.load(Ordering::Relaxed) {
+            match producer.write(data) {
+                Ok(written) => {
+                    data = &data[written..];
+                    shared.wake_consumer();
+                    if let Some(since) = full_since.take() {
+                        METRICS.read_ahead_waits.count_full(since.elapsed());
+                    }
+                }
+                // Full, as the consumer fell behind.
+                Err(_) => {
+                    full_since.get_or_insert_with(Instant::now);
+                    thread::park_timeout(POLL_INTERVAL)
+                }
+            }
+        }
+    }
+    shared.wake_consumer();
+}
+
+impl Read for ReadAhead {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        {
+            let mut consumer = self.shared.consumer.lock().unwrap();
+            if consumer.id() != thread::current().id() {
+                *consumer = thread::current();
+            }
+        }
+        let mut empty_since: Option<Instant> = None;
+        let read = (|| loop {
+            if !self.consumer.is_empty() {
+                let len = self.consumer.read(buf)?;
+                METRICS
+                    .buffered
+                    .store(self.consumer.slots() as u64, Ordering::Relaxed);
+                self.wake_reader();
+                return Ok(len);
+            }
+            // Checked again with the lock held, as the error has to
+            // come after the data read before it.
+            let error = {
+                let mut error = self.shared.error.lock().unwrap();
+                if !self.consumer.is_empty() {
+                    continue;
+                }
+                error.take()
+            };
+            if let Some(e) = error {
+                self.wake_reader();
+                return Err(e);
+            }
+            if self.consumer.is_abandoned() {
+                // Anything written right before the reader ended.
+                if !self.consumer.is_empty() {
+                    continue;
+                }
+                if let Some(reader) = self.reader.take() {
+                    let _ = reader.join();
+                }
+                return Ok(0);
+            }
+            empty_since.get_or_insert_with(Instant::now);
+            thread::park_timeout(POLL_INTERVAL);
+        })();
+        if let Some(since) = empty_since {
+            METRICS.read_ahead_waits.count_empty(since.elapsed());
+        }
+        read
+    }
+}
+
+impl Drop for ReadAhead {
+    fn drop(&mut self) {
+        // The reader may be blocked on a source that never ends, like
+        // the standard input, so it's left to finish on its own.
+        self.shared.stop.store(true, Ordering::Relaxed);
+        self.wake_reader();
+    }
+}
@@ -0,0 +1,167 @@
+use std::{
+    fs,
+    io::{ErrorKind, Read},
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use nix::unistd;
+use serialport::{SerialPortType, TTYPort};
+
+use crate::{
+    io::{self, SerialConfig},
+    metrics::METRICS,
+    rate,
+    state::{self, CaptureState},
+};
+
+/// USB identity of a port, for finding the board again if it comes
+/// back under a different name.
+#[derive(PartialEq, Eq)]
+struct UsbIdentity {
+    vid: u16,
+    pid: u16,
+    serial_number: Option<String>,
+}
+
+impl UsbIdentity {
+    fn of(path: &str) -> Option<UsbIdentity> {
+        let path = fs::canonicalize(path).ok()?;
+        serialport::available_ports()
+            .ok()?
+            .into_iter()
+            .find(|info| fs::canonicalize(&info.port_name).ok().as_ref() == Some(&path))
+            .and_then(|info| match info.port_type {
+                SerialPortType::UsbPort(usb) => Some(UsbIdentity {
+                    vid: usb.vid,
+                    pid: usb.pid,
+                    serial_number: usb.serial_number,
+                }),
+                _ => None,
+            })
+    }
+
+    /// Name of the only port connected with this identity.
+    fn find_port(&self) -> Option<String> {
+        let mut ports = serialport::available_ports()
+            .ok()?
+            .into_iter()
+            .filter(|info| match &info.port_type {
+                SerialPortType::UsbPort(usb) => {
+                    usb.vid == self.vid
+                        && usb.pid == self.pid
+                        && usb.serial_number == self.serial_number
+                }
+                _ => false,
+            });
+        match (ports.next(), ports.next()) {
+            (Some(port), None) => Some(port.port_name),
+            _ => None,
+        }
+    }
+}
+
+/// Serial link that reopens the port when the board is unplugged or
+/// resets, instead of failing. The port is looked up by its name, or
+/// by its USB identity if it comes back under a different one.
+///
+/// Ctrl+C while waiting for the board ends the input.
+pub struct Reconnecting {
+    port: TTYPort,
+    path: String,
+    usb: Option<UsbIdentity>,
+    baud_rate: u32,
+    timeout: Duration,
+    config: SerialConfig,
+    /// Sampling rate of the link, for filling the time the board was
+    /// gone with silence. Nothing is inserted if not set.
+    fill_rate: Option<u32>,
+    pending_fill: u64,
+}
+
+impl Reconnecting {
+    pub fn open(
+        path: &str,
+        baud_rate: u32,
+        timeout: Duration,
+        config: SerialConfig,
+        fill_rate: Option<u32>,
+    ) -> anyhow::Result<Reconnecting> {
+        Ok(Reconnecting {
+            port: io::open_serial_port(path, baud_rate, timeout, &config)?,
+            path: path.into(),
+            usb: UsbIdentity::of(path),
+            baud_rate,
+            timeout,
+            config,
+            fill_rate,
+            pending_fill: 0,
+        })
+    }
+
+    /// Waits for the board to come back, returning false if Ctrl+C
+    /// was pressed meanwhile.
+    fn reconnect(&mut self) -> bool {
+        tracing::warn!("Lost {}. Waiting for it to come back...", self.path);
+        state::set(CaptureState::Paused);
+        let lost_at = Instant::now();
+
+        loop {
+            // Signals interrupt the sleep, which is how Ctrl+C is
+            // noticed here.
+            if unistd::sleep(1) > 0 {
+                return false;
+            }
+
+            let path = match self.usb.as_ref().and_then(UsbIdentity::find_port) {
+                Some(path) => path,
+                None => self.path.clone(),
+            };
+            if let Ok(port) =
+                io::open_serial_port(&path, self.baud_rate, self.timeout, &self.config)
+            {
+                let elapsed = lost_at.elapsed();
+                tracing::info!(
+                    "Reconnected to {} after {:.1} seconds",
+                    path,
+                    elapsed.as_secs_f64()
+                );
+                METRICS.reconnects.fetch_add(1, Ordering::Relaxed);
+                self.port = port;
+                self.path = path;
+                if let Some(rate) = self.fill_rate {
+                    self.pending_fill += rate::samples_in(rate, elapsed) / 8;
+                }
+                return true;
+            }
+        }
+    }
+}
+
+impl Read for Reconnecting {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            if buf.is_empty() {
+                return Ok(0);
+            }
+            if self.pending_fill > 0 {
+                let len = self.pending_fill.min(buf.len() as u64) as usize;
+                buf[..len].fill(0);
+                self.pending_fill -= len as u64;
+                return Ok(len);
+            }
+
+            match self.port.read(buf) {
+                Ok(0) => (),
+                Ok(len) => return Ok(len),
+                Err(e) if matches!(e.kind(), ErrorKind::Interrupted | ErrorKind::TimedOut) => {
+                    return Err(e)
+                }
+                Err(_) => (),
+            }
+            if !self.reconnect() {
+                return Ok(0);
+            }
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use anyhow::anyhow;
+use serialport::{SerialPortInfo, SerialPortType, UsbPortInfo};
+
+/// USB-to-UART bridges usually found in ESP32 boards, by vendor and
+/// product ID. A product ID of None matches any product of the vendor.
+const ESP32_BRIDGES: [(u16, Option<u16>, &str); 8] = [
+    (0x10c4, Some(0xea60), "CP210x"),
+    (0x10c4, Some(0xea70), "CP2105"),
+    (0x1a86, Some(0x7523), "CH340"),
+    (0x1a86, Some(0x55d4), "CH9102"),
+    (0x0403, Some(0x6001), "FTDI FT232R"),
+    (0x0403, Some(0x6010), "FTDI FT2232"),
+    (0x0403, Some(0x6014), "FTDI FT232H"),
+    (0x303a, None, "Espressif USB"),
+];
+
+/// Returns the name of the bridge if the device is likely an ESP32
+/// board.
+pub fn esp32_bridge(usb: &UsbPortInfo) -> Option<&'static str> {
+    ESP32_BRIDGES
+        .iter()
+        .find(|(vid, pid, _)| *vid == usb.vid && (pid.is_none() || *pid == Some(usb.pid)))
+        .map(|(_, _, name)| *name)
+}
+
+pub fn esp32_ports() -> anyhow::Result<Vec<SerialPortInfo>> {
+    Ok(serialport::available_ports()?
+        .into_iter()
+        .filter(|info| match &info.port_type {
+            SerialPortType::UsbPort(usb) => esp32_bridge(usb).is_some(),
+            _ => false,
+        })
+        .collect())
+}
+
+/// Finds the port of the only ESP32 board connected.
+pub fn find_esp32_port() -> anyhow::Result<String> {
+    let ports = esp32_ports()?;
+    match ports.as_slice() {
+        [] => Err(anyhow!("No ESP32 board found. Check list-ports")),
+        [port] => Ok(port.port_name.clone()),
+        _ => Err(anyhow!(
+            "Several ESP32 boards found ({}). Choose one with --port",
+            ports
+                .iter()
+                .map(|port| port.port_name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+pub fn parse_usb_id(spec: &str) -> anyhow::Result<(u16, u16)> {
+    let (vid, pid) = spec
+        .split_once(':')
+        .ok_or_else(|| anyhow!("Expected usb:<vid>:<pid>, like usb:10c4:ea60"))?;
+    let parse = |id: &str| {
+        u16::from_str_radix(id, 16).map_err(|_| anyhow!("Invalid USB ID '{}' in usb:{}", id, spec))
+    };
+    Ok((parse(vid)?, parse(pid)?))
+}
+
+/// Resolves --port usb:<vid>:<pid> and serial:<number> to the device
+/// of the matching USB port, as device paths change across reboots
+/// and hubs. Other names are taken as the path of the port.
+pub fn resolve_port(port: &str) -> anyhow::Result<String> {
+    let matches: Box<dyn Fn(&UsbPortInfo) -> bool> = if let Some(id) = port.strip_prefix("usb:") {
+        let (vid, pid) = parse_usb_id(id)?;
+        Box::new(move |usb| usb.vid == vid && usb.pid == pid)
+    } else if let Some(serial) = port.strip_prefix("serial:") {
+        Box::new(move |usb| usb.serial_number.as_deref() == Some(serial))
+    } else {
+        return Ok(port.to_string());
+    };
+
+    let ports: Vec<_> = serialport::available_ports()?
+        .into_iter()
+        .filter(|info| match &info.port_type {
+            SerialPortType::UsbPort(usb) => matches(usb),
+            _ => false,
+        })
+        .collect();
+    match ports.as_slice() {
+        [] => Err(anyhow!("No port found for {}. Check list-ports", port)),
+        [info] => Ok(info.port_name.clone()),
+        _ => Err(anyhow!(
+            "Several ports found for {} ({})",
+            port,
+            ports
+                .iter()
+                .map(|info| info.port_name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
@@ -0,0 +1,108 @@
+//! Lifecycle of the capture run by the process, kept in one place so
+//! the status socket, the statistics and whatever else reports on the
+//! capture agree on what it's doing.
+//!
+//! The parts of the capture move it along as they go: opening the
+//! source, the pipeline receiving and finishing the samples, the
+//! source waiting for a board to come back. Moves that don't make
+//! sense from the current state, like anything after the capture
+//! stopped, are ignored.
+
+use std::{
+    fmt::{self, Display},
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptureState {
+    /// Nothing started yet.
+    Idle,
+    /// Opening the source.
+    Connecting,
+    /// The source is open, but no samples came through yet.
+    Prebuffering,
+    /// Samples are going through the pipeline.
+    Streaming,
+    /// The source went away for a while, like a board being reset,
+    /// and the capture waits for it to come back.
+    Paused,
+    /// The input is over, and the last samples and the outputs are
+    /// being finished.
+    Draining,
+    /// The capture finished.
+    Stopped,
+    /// The capture failed.
+    Error,
+}
+
+const STATES: [CaptureState; 8] = [
+    CaptureState::Idle,
+    CaptureState::Connecting,
+    CaptureState::Prebuffering,
+    CaptureState::Streaming,
+    CaptureState::Paused,
+    CaptureState::Draining,
+    CaptureState::Stopped,
+    CaptureState::Error,
+];
+
+impl CaptureState {
+    /// Whether the capture can move from this state to the given one.
+    pub fn can_become(self, next: CaptureState) -> bool {
+        use CaptureState::*;
+        match (self, next) {
+            (Stopped | Error, _) => false,
+            (_, Stopped | Error) => true,
+            (Idle, Connecting) => true,
+            (Connecting, Prebuffering | Streaming) => true,
+            (Prebuffering, Streaming | Paused | Draining) => true,
+            // Back to connecting when moving on to another source, as
+            // round-robin does.
+            (Streaming, Paused | Draining | Connecting) => true,
+            (Paused, Streaming | Draining | Connecting) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the capture is over, for good or not.
+    pub fn is_finished(self) -> bool {
+        matches!(self, CaptureState::Stopped | CaptureState::Error)
+    }
+}
+
+impl Display for CaptureState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CaptureState::Idle => "idle",
+            CaptureState::Connecting => "connecting",
+            CaptureState::Prebuffering => "prebuffering",
+            CaptureState::Streaming => "streaming",
+            CaptureState::Paused => "paused",
+            CaptureState::Draining => "draining",
+            CaptureState::Stopped => "stopped",
+            CaptureState::Error => "error",
+        })
+    }
+}
+
+static STATE: AtomicU8 = AtomicU8::new(CaptureState::Idle as u8);
+
+/// The current state of the capture.
+pub fn current() -> CaptureState {
+    STATES[STATE.load(Ordering::Relaxed) as usize]
+}
+
+/// Moves the capture to the given state, returning whether it did.
+/// Staying in the same state counts as moving.
+pub fn set(next: CaptureState) -> bool {
+    let result = STATE.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |state| {
+        let state = STATES[state as usize];
+        (state == next || state.can_become(next)).then_some(next as u8)
+    });
+    if let Ok(previous) = result {
+        if previous != next as u8 {
+            tracing::debug!(state = %next, "Capture state changed");
+        }
+    }
+    result.is_ok()
+}
@@ -0,0 +1,804 @@
+use std::{
+    borrow::Cow,
+    env,
+    ffi::OsStr,
+    fs::{self, DirBuilder, File},
+    io::{stderr, ErrorKind, IsTerminal, Write},
+    os::unix::{
+        fs::{DirBuilderExt, FileExt, MetadataExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use nix::unistd::getuid;
+
+use crate::{
+    dsp::{meter::SignalMeter, Sample},
+    metrics::METRICS,
+    rate,
+    state::{self, CaptureState},
+};
+
+/// Blocks per second of the previews of the captures.
+pub const PREVIEW_RATE: u32 = 1000;
+
+/// Seconds of the capture kept in the preview.
+const PREVIEW_SECONDS: u64 = 60;
+
+/// Interval of the writes of the preview.
+const PREVIEW_FLUSH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Bytes of the header of the preview files.
+const PREVIEW_HEADER_LEN: u64 = 16;
+
+/// Interval of the status lines printed on terminals that can't
+/// rewrite them, and when the standard error isn't a terminal.
+const PLAIN_LINE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Interval of the updates of the meter.
+const METER_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Interval of the lines written with --stats-json.
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+const METER_BAR_WIDTH: usize = 20;
+
+/// Time the attached clients are given for taking the final state of
+/// the capture.
+const LAST_LINE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Name prefixes of the serial terminal devices. Rewriting the status
+/// line several times a second floods slow serial consoles.
+const SERIAL_CONSOLES: &[&str] = &[
+    "ttyS", "ttyUSB", "ttyACM", "ttyAMA", "ttymxc", "hvc", "console",
+];
+
+/// What the standard error can display.
+#[derive(Clone, Copy)]
+struct Terminal {
+    /// Lines can be rewritten in place with a carriage return.
+    rewrite: bool,
+    /// Text outside of ASCII shows as intended, as the locale uses
+    /// UTF-8.
+    unicode: bool,
+}
+
+impl Terminal {
+    fn detect() -> Terminal {
+        let term = env::var("TERM").unwrap_or_default();
+        let rewrite = stderr().is_terminal()
+            && !term.is_empty()
+            && term != "dumb"
+            && !Self::is_serial_console();
+
+        // The first locale variable set wins, as with setlocale.
+        let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+            .iter()
+            .filter_map(|name| env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let unicode = locale.contains("utf-8") || locale.contains("utf8");
+        Terminal { rewrite, unicode }
+    }
+
+    fn is_serial_console() -> bool {
+        let device = match fs::read_link("/proc/self/fd/2") {
+            Ok(device) => device,
+            Err(_) => return false,
+        };
+        let name = device.file_name().and_then(OsStr::to_str).unwrap_or("");
+        SERIAL_CONSOLES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+    }
+
+    /// The text as the terminal can display it, replacing anything
+    /// outside of ASCII with '?' unless the locale uses UTF-8.
+    fn printable<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        if self.unicode || text.is_ascii() {
+            return Cow::Borrowed(text);
+        }
+        Cow::Owned(
+            text.chars()
+                .map(|c| if c.is_ascii() { c } else { '?' })
+                .collect(),
+        )
+    }
+}
+
+/// Whether the last status line was left without its newline, for
+/// rewriting it.
+static LINE_PENDING: AtomicBool = AtomicBool::new(false);
+
+/// Ends the status line being rewritten, if any, so whatever goes to
+/// the standard error next starts on a line of its own.
+pub fn finish_line() {
+    if LINE_PENDING.swap(false, Ordering::Relaxed) {
+        let _ = writeln!(stderr());
+    }
+}
+
+/// Prints the status lines of a capture, rewriting the previous one
+/// if the terminal can, or on a line of its own every few seconds
+/// otherwise.
+pub struct StatusPrinter {
+    terminal: Terminal,
+    last_printed: Option<Instant>,
+}
+
+impl StatusPrinter {
+    /// Prints to the standard error, as it's able to show them.
+    pub fn stderr() -> StatusPrinter {
+        StatusPrinter {
+            terminal: Terminal::detect(),
+            last_printed: None,
+        }
+    }
+
+    /// Failing to write to the standard error, like when the console
+    /// went away, is not an error of the capture, so it's ignored.
+    pub fn print(&mut self, line: &str) {
+        let line = self.terminal.printable(line);
+        let mut stderr = stderr().lock();
+        if self.terminal.rewrite {
+            let _ = write!(stderr, "{}\r", line);
+            LINE_PENDING.store(true, Ordering::Relaxed);
+            return;
+        }
+        let due = match self.last_printed {
+            Some(last) => last.elapsed() >= PLAIN_LINE_INTERVAL,
+            None => true,
+        };
+        if due {
+            let _ = writeln!(stderr, "{}", line);
+            self.last_printed = Some(Instant::now());
+        }
+    }
+}
+
+/// Directory holding the status sockets of the running captures.
+pub fn socket_dir() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("esp32-samples-reader"),
+        None => env::temp_dir().join(format!("esp32-samples-reader-{}", getuid())),
+    }
+}
+
+/// Creates the directory of the sockets, only accessible by the current
+/// user. As it may be in a shared temporary directory, an existing one
+/// is refused if anybody else could have placed sockets in it.
+pub fn create_socket_dir() -> std::io::Result<PathBuf> {
+    let dir = socket_dir();
+    match DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(()) => Ok(dir),
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => check_socket_dir(&dir).map(|()| dir),
+        Err(e) => Err(e),
+    }
+}
+
+fn check_socket_dir(dir: &Path) -> std::io::Result<()> {
+    let metadata = fs::symlink_metadata(dir)?;
+    if !metadata.is_dir() || metadata.uid() != getuid().as_raw() || metadata.mode() & 0o077 != 0 {
+        return Err(std::io::Error::new(
+            ErrorKind::PermissionDenied,
+            format!(
+                "{} isn't a directory only accessible by the current user. Remove it, or fix its owner and permissions",
+                dir.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+pub fn socket_path(pid: u32) -> PathBuf {
+    socket_dir().join(format!("{}.sock", pid))
+}
+
+pub fn preview_path(pid: u32) -> PathBuf {
+    socket_dir().join(format!("{}.preview", pid))
+}
+
+/// Lists the sockets of the running captures, removing the ones left
+/// behind by captures that didn't exit cleanly.
+pub fn running_captures() -> anyhow::Result<Vec<(u32, PathBuf)>> {
+    let dir = socket_dir();
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    check_socket_dir(&dir)?;
+
+    let mut captures = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension() != Some(OsStr::new("sock")) {
+            continue;
+        }
+
+        let pid = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse::<u32>().ok());
+
+        if let Some(pid) = pid {
+            match UnixStream::connect(&path) {
+                Ok(_) => captures.push((pid, path)),
+                Err(e) if e.kind() == ErrorKind::ConnectionRefused => {
+                    let _ = fs::remove_file(&path);
+                }
+                Err(_) => {}
+            }
+        }
+    }
+    Ok(captures)
+}
+
+/// Heavily decimated copy of the last minute of a capture, written
+/// along with it so other tools can look at it without decoding the
+/// full rate stream.
+///
+/// The file starts with a header holding the number of channels and the
+/// number of blocks kept of each one, as little endian u32, then the
+/// number of blocks written so far to each channel, as a little endian
+/// u64. The blocks of each channel follow in turn, each channel being a
+/// ring where block n is stored at n modulo the blocks kept. Blocks are
+/// pairs of bytes, holding the minimum and the maximum of the block as
+/// unsigned 8-bit samples.
+struct Preview {
+    file: File,
+    path: PathBuf,
+    capacity: u64,
+    block_size: usize,
+    count: usize,
+    /// Channel of the next sample.
+    channel: usize,
+    min: Vec<f32>,
+    max: Vec<f32>,
+    /// Blocks of each channel waiting for the next write.
+    pending: Vec<Vec<u8>>,
+    written: u64,
+    last_flush: Instant,
+}
+
+impl Preview {
+    fn create(sampling_rate: u32, channels: u16) -> std::io::Result<Preview> {
+        let path = preview_path(process::id());
+        let blocks_per_second = u32::min(sampling_rate, PREVIEW_RATE) as u64;
+        let channels = channels as usize;
+        let preview = Preview {
+            file: File::create(&path)?,
+            path,
+            capacity: u64::max(1, blocks_per_second * PREVIEW_SECONDS),
+            block_size: usize::max(1, (sampling_rate / PREVIEW_RATE) as usize),
+            count: 0,
+            channel: 0,
+            min: vec![f32::MAX; channels],
+            max: vec![f32::MIN; channels],
+            pending: vec![Vec::new(); channels],
+            written: 0,
+            last_flush: Instant::now(),
+        };
+        preview
+            .file
+            .set_len(PREVIEW_HEADER_LEN + channels as u64 * preview.capacity * 2)?;
+        preview.write_header()?;
+        Ok(preview)
+    }
+
+    fn write_header(&self) -> std::io::Result<()> {
+        let mut header = Vec::with_capacity(PREVIEW_HEADER_LEN as usize);
+        header.extend_from_slice(&(self.pending.len() as u32).to_le_bytes());
+        header.extend_from_slice(&(self.capacity as u32).to_le_bytes());
+        header.extend_from_slice(&self.written.to_le_bytes());
+        self.file.write_all_at(&header, 0)
+    }
+
+    fn push<S: Sample>(&mut self, samples: &[S]) -> std::io::Result<()> {
+        let channels = self.pending.len();
+        for sample in samples {
+            let value = sample.to_f32();
+            self.min[self.channel] = self.min[self.channel].min(value);
+            self.max[self.channel] = self.max[self.channel].max(value);
+            self.channel += 1;
+            if self.channel < channels {
+                continue;
+            }
+
+            self.channel = 0;
+            self.count += 1;
+            if self.count == self.block_size {
+                for channel in 0..channels {
+                    self.pending[channel].extend_from_slice(&[
+                        u8::from_f32(self.min[channel]),
+                        u8::from_f32(self.max[channel]),
+                    ]);
+                    self.min[channel] = f32::MAX;
+                    self.max[channel] = f32::MIN;
+                }
+                self.count = 0;
+            }
+        }
+
+        if self.last_flush.elapsed() >= PREVIEW_FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the pending blocks in their place of the rings, then the
+    /// header, so readers never see blocks that aren't written yet.
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.last_flush = Instant::now();
+        let blocks = (self.pending[0].len() / 2) as u64;
+        for (channel, pending) in self.pending.iter_mut().enumerate() {
+            let ring = PREVIEW_HEADER_LEN + channel as u64 * self.capacity * 2;
+            // Blocks past a full ring would be overwritten right away.
+            let skipped = blocks.saturating_sub(self.capacity);
+            let mut block = self.written + skipped;
+            let mut blocks_left = &pending[skipped as usize * 2..];
+            // At most two writes, when the blocks wrap around the ring.
+            while !blocks_left.is_empty() {
+                let position = block % self.capacity;
+                let len = usize::min(blocks_left.len(), ((self.capacity - position) * 2) as usize);
+                self.file
+                    .write_all_at(&blocks_left[..len], ring + position * 2)?;
+                block += len as u64 / 2;
+                blocks_left = &blocks_left[len..];
+            }
+            pending.clear();
+        }
+        self.written += blocks;
+        self.write_header()
+    }
+}
+
+/// Minimum and maximum of the latest block of each channel of the
+/// preview of a capture, if any was written yet.
+pub fn latest_preview_blocks(preview: &File) -> std::io::Result<Vec<[u8; 2]>> {
+    let mut header = [0u8; PREVIEW_HEADER_LEN as usize];
+    preview.read_exact_at(&mut header, 0)?;
+    let word = |range: std::ops::Range<usize>| {
+        let mut bytes = [0u8; 8];
+        bytes[..range.len()].copy_from_slice(&header[range]);
+        u64::from_le_bytes(bytes)
+    };
+    let (channels, capacity, written) = (word(0..4), word(4..8), word(8..16));
+    if written == 0 || capacity == 0 {
+        return Ok(Vec::new());
+    }
+
+    (0..channels)
+        .map(|channel| {
+            let mut block = [0u8; 2];
+            let ring = PREVIEW_HEADER_LEN + channel * capacity * 2;
+            preview.read_exact_at(&mut block, ring + (written - 1) % capacity * 2)?;
+            Ok(block)
+        })
+        .collect()
+}
+
+/// Formats a rate with an SI prefix, like "1.50 kHz".
+fn format_si(value: f64, unit: &str) -> String {
+    match value {
+        v if v >= 1e6 => format!("{:.2} M{}", v / 1e6, unit),
+        v if v >= 1e3 => format!("{:.2} k{}", v / 1e3, unit),
+        v => format!("{:.0} {}", v, unit),
+    }
+}
+
+/// Live display of the signal in place of the progress line, shown
+/// with --meter.
+struct Meter {
+    signal: SignalMeter,
+    output_rate: u32,
+    last_update: Instant,
+    last_total: usize,
+}
+
+impl Meter {
+    fn new(output_rate: u32) -> Meter {
+        Meter {
+            signal: SignalMeter::default(),
+            output_rate,
+            last_update: Instant::now(),
+            last_total: 0,
+        }
+    }
+
+    /// The line of the meter, if it's time for a new one. total_samples
+    /// are the bits received so far.
+    fn line(&mut self, total_samples: usize, sampling_rate: u32) -> Option<String> {
+        let elapsed = self.last_update.elapsed();
+        if elapsed < METER_INTERVAL {
+            return None;
+        }
+        let reading = self.signal.take(self.output_rate)?;
+        let throughput = (total_samples - self.last_total) as f64 / 8.0 / elapsed.as_secs_f64();
+        self.last_update = Instant::now();
+        self.last_total = total_samples;
+
+        let level = (reading.duty_cycle * METER_BAR_WIDTH as f32).round() as usize;
+        Some(format!(
+            "{} s  duty {:5.1}% [{}{}]  edges {}  ~{}  {}",
+            rate::format_seconds(sampling_rate, total_samples as u64),
+            reading.duty_cycle * 100.0,
+            "#".repeat(level),
+            " ".repeat(METER_BAR_WIDTH - level),
+            format_si(reading.edge_rate, "/s"),
+            format_si(reading.frequency, "Hz"),
+            format_si(throughput, "B/s"),
+        ))
+    }
+}
+
+/// Where the lines of --stats-json go: a file, or a file descriptor
+/// inherited from the parent process, given as a number.
+#[derive(Clone, Debug)]
+pub enum StatsTarget {
+    Path(PathBuf),
+    Fd(u32),
+}
+
+impl FromStr for StatsTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err("Expected a path or a file descriptor".into());
+        }
+        Ok(match s.parse::<u32>() {
+            Ok(fd) => StatsTarget::Fd(fd),
+            Err(_) => StatsTarget::Path(s.into()),
+        })
+    }
+}
+
+impl StatsTarget {
+    fn open(&self) -> std::io::Result<File> {
+        match self {
+            StatsTarget::Path(path) => File::create(path),
+            // Opened again through procfs, which keeps the descriptor
+            // of the parent untouched.
+            StatsTarget::Fd(fd) => File::options()
+                .write(true)
+                .open(format!("/proc/self/fd/{}", fd)),
+        }
+    }
+}
+
+/// Health of the capture, written as a JSON line every second for
+/// supervising processes.
+struct StatsWriter {
+    file: File,
+    signal: SignalMeter,
+    output_rate: u32,
+    started: Instant,
+    last_write: Instant,
+}
+
+impl StatsWriter {
+    fn write(&mut self, total_samples: usize) -> std::io::Result<()> {
+        if self.last_write.elapsed() < STATS_INTERVAL {
+            return Ok(());
+        }
+        self.last_write = Instant::now();
+
+        let metrics = METRICS.snapshot();
+        let duty_cycle = self.signal.take(self.output_rate).map(|r| r.duty_cycle);
+        let optional = |value: Option<f64>| match value {
+            Some(value) => format!("{:.4}", value),
+            None => "null".into(),
+        };
+        writeln!(
+            self.file,
+            "{{\"state\":\"{}\",\"elapsed\":{:.3},\"samples_read\":{},\"bytes_dropped\":{},\"buffer_fill\":{},\"duty_cycle\":{}}}",
+            state::current(),
+            self.started.elapsed().as_secs_f64(),
+            total_samples,
+            metrics.bytes_dropped,
+            optional(metrics.buffer_fill()),
+            optional(duty_cycle.map(f64::from)),
+        )
+    }
+}
+
+/// A client attached to the status socket. A line the socket only
+/// takes part of is finished before any other is sent, and lines coming
+/// in the meantime are skipped, so the client never gets a line cut
+/// short.
+struct StatusClient {
+    stream: UnixStream,
+    /// Rest of the line being sent.
+    pending: Vec<u8>,
+}
+
+impl StatusClient {
+    /// Sends the rest of the line being sent, then the given line if
+    /// nothing was left of it. False once the client went away.
+    fn send(&mut self, line: &str) -> bool {
+        if !self.write_pending() {
+            return false;
+        }
+        if self.pending.is_empty() {
+            self.pending.extend_from_slice(line.as_bytes());
+            self.pending.push(b'\n');
+            return self.write_pending();
+        }
+        true
+    }
+
+    /// Writes as much of the pending line as the socket takes without
+    /// blocking.
+    fn write_pending(&mut self) -> bool {
+        while !self.pending.is_empty() {
+            match self.stream.write(&self.pending) {
+                Ok(0) => return false,
+                Ok(written) => {
+                    self.pending.drain(..written);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return true,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+
+    /// Sends the rest of the line being sent and then the given one,
+    /// waiting up to LAST_LINE_TIMEOUT for the client to take them.
+    fn send_last(mut self, line: &str) {
+        self.pending.extend_from_slice(line.as_bytes());
+        self.pending.push(b'\n');
+        if self.stream.set_nonblocking(false).is_ok()
+            && self
+                .stream
+                .set_write_timeout(Some(LAST_LINE_TIMEOUT))
+                .is_ok()
+        {
+            let _ = self.stream.write_all(&self.pending);
+        }
+    }
+}
+
+/// Prints the progress of a capture, and mirrors it to the clients
+/// attached to the capture status socket. New clients are handed over
+/// by the listening thread through a channel, so updating the status
+/// never waits for it.
+pub struct StatusLine {
+    clients: Vec<StatusClient>,
+    /// Last progress line, sent again with the final state.
+    last_line: String,
+    new_clients: Receiver<UnixStream>,
+    socket_path: Option<PathBuf>,
+    preview: Option<Preview>,
+    meter: Option<Meter>,
+    stats: Option<StatsWriter>,
+    printer: StatusPrinter,
+}
+
+impl StatusLine {
+    /// Creates the status line of a capture.
+    pub fn create() -> StatusLine {
+        let (sender, new_clients) = mpsc::channel();
+        let socket_path = match Self::listen(sender) {
+            Ok(path) => Some(path),
+            Err(e) => {
+                tracing::warn!(
+                    "Unable to create status socket, attach won't be available: {}",
+                    e
+                );
+                None
+            }
+        };
+
+        StatusLine {
+            clients: Vec::new(),
+            last_line: "Nothing read yet".into(),
+            new_clients,
+            socket_path,
+            preview: None,
+            meter: None,
+            stats: None,
+            printer: StatusPrinter::stderr(),
+        }
+    }
+
+    /// Shows the duty cycle, edge rate, frequency and throughput of
+    /// the signal on the terminal instead of the progress, from the
+    /// samples given to preview. The output should be a single
+    /// channel running at the given rate.
+    pub fn show_meter(&mut self, output_rate: u32) {
+        self.meter = Some(Meter::new(output_rate));
+    }
+
+    /// Writes the health of the capture to the given target every
+    /// second, from the counters of the capture and the samples given
+    /// to preview, which run at the given rate.
+    pub fn write_stats(&mut self, target: &StatsTarget, output_rate: u32) -> std::io::Result<()> {
+        self.stats = Some(StatsWriter {
+            file: target.open()?,
+            signal: SignalMeter::default(),
+            output_rate,
+            started: Instant::now(),
+            // The first line goes out after the first update.
+            last_write: Instant::now() - STATS_INTERVAL,
+        });
+        Ok(())
+    }
+
+    /// Keeps a preview of the samples given to preview next to the
+    /// status socket, for attach and other tools. The output should be
+    /// interleaved channels running at the given rate.
+    pub fn write_preview(&mut self, output_rate: u32, channels: u16) -> std::io::Result<()> {
+        // The preview lives next to the status socket, so it's only
+        // written if the socket could be created.
+        if self.socket_path.is_none() {
+            return Err(std::io::Error::new(
+                ErrorKind::NotFound,
+                "the status socket couldn't be created",
+            ));
+        }
+        self.preview = Some(Preview::create(output_rate, channels)?);
+        Ok(())
+    }
+
+    fn listen(clients: Sender<UnixStream>) -> std::io::Result<PathBuf> {
+        create_socket_dir()?;
+        let path = socket_path(process::id());
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)?;
+
+        thread::spawn(move || {
+            for client in listener.incoming().flatten() {
+                if client.set_nonblocking(true).is_ok() && clients.send(client).is_err() {
+                    // The capture is over.
+                    break;
+                }
+            }
+        });
+        Ok(path)
+    }
+
+    pub fn update(&mut self, total_samples: usize, sampling_rate: u32) {
+        let line = format!(
+            "Total {} samples read; {} seconds of recording...",
+            total_samples,
+            rate::format_seconds(sampling_rate, total_samples as u64)
+        );
+        if let Some(stats) = &mut self.stats {
+            if let Err(e) = stats.write(total_samples) {
+                tracing::warn!("Unable to write the statistics, disabling them: {}", e);
+                self.stats = None;
+            }
+        }
+        match &mut self.meter {
+            Some(meter) => {
+                if let Some(meter_line) = meter.line(total_samples, sampling_rate) {
+                    self.printer.print(&meter_line);
+                }
+            }
+            None => self.printer.print(&line),
+        }
+
+        self.last_line = line;
+        self.send_to_clients();
+    }
+
+    /// Moves the capture to stopped, or to failed unless it succeeded,
+    /// and tells the attached clients, which would otherwise only see
+    /// the socket close. The clients are let go afterwards.
+    pub fn finish(&mut self, succeeded: bool) {
+        state::set(if succeeded {
+            CaptureState::Stopped
+        } else {
+            CaptureState::Error
+        });
+        self.accept_clients();
+        let line = self.client_line();
+        for client in self.clients.drain(..) {
+            client.send_last(&line);
+        }
+    }
+
+    fn accept_clients(&mut self) {
+        self.clients
+            .extend(self.new_clients.try_iter().map(|stream| StatusClient {
+                stream,
+                pending: Vec::new(),
+            }));
+    }
+
+    /// Clients get the state of the capture too, as the line alone
+    /// doesn't tell whether it's still going.
+    fn client_line(&self) -> String {
+        format!("{}: {}", state::current(), self.last_line)
+    }
+
+    fn send_to_clients(&mut self) {
+        self.accept_clients();
+        // Clients that went away are dropped, the ones that aren't
+        // keeping up skip lines.
+        let line = self.client_line();
+        self.clients.retain_mut(|client| client.send(&line));
+    }
+
+    /// Adds the output samples to the preview of the capture, and to
+    /// the meter.
+    pub fn preview<S: Sample>(&mut self, samples: &[S]) {
+        if let Some(meter) = &mut self.meter {
+            meter.signal.push(samples);
+        }
+        if let Some(stats) = &mut self.stats {
+            stats.signal.push(samples);
+        }
+        if let Some(preview) = &mut self.preview {
+            if let Err(e) = preview.push(samples) {
+                tracing::warn!("Unable to write preview, disabling it: {}", e);
+                self.preview = None;
+            }
+        }
+    }
+}
+
+impl Drop for StatusLine {
+    fn drop(&mut self) {
+        if let Some(path) = &self.socket_path {
+            let _ = fs::remove_file(path);
+        }
+        if let Some(preview) = &self.preview {
+            let _ = fs::remove_file(&preview.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slow_clients_only_get_whole_lines() {
+        let (stream, mut reader) = UnixStream::pair().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let mut client = StatusClient {
+            stream,
+            pending: Vec::new(),
+        };
+        // Far more than the socket takes without being read.
+        let line = "x".repeat(1000);
+        for index in 0..1000 {
+            assert!(client.send(&format!("{} {}", index, line)));
+        }
+        assert!(!client.pending.is_empty());
+
+        let received = thread::spawn(move || {
+            let mut received = String::new();
+            std::io::Read::read_to_string(&mut reader, &mut received).unwrap();
+            received
+        });
+        client.send_last("stopped");
+        let received = received.join().unwrap();
+
+        let mut lines: Vec<&str> = received.lines().collect();
+        assert_eq!(lines.pop(), Some("stopped"));
+        assert!(lines.len() < 1000);
+        let mut last_index = None;
+        for received_line in lines {
+            let (index, rest) = received_line.split_once(' ').unwrap();
+            let index: usize = index.parse().unwrap();
+            assert!(last_index < Some(index));
+            assert_eq!(rest, line);
+            last_index = Some(index);
+        }
+    }
+}
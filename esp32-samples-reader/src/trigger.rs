@@ -0,0 +1,62 @@
+use nix::sys::{
+    signal::{sigaction, SaFlags, SigAction, SigHandler, Signal::SIGUSR1},
+    signalfd::SigSet,
+};
+use std::{
+    io::{stdin, BufRead, IsTerminal},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Once,
+    },
+    thread,
+};
+
+static PENDING_TRIGGERS: AtomicUsize = AtomicUsize::new(0);
+static LISTEN_ONCE: Once = Once::new();
+
+#[no_mangle]
+pub extern "C" fn handle_trigger_signal(_signal: i32) {
+    PENDING_TRIGGERS.fetch_add(1, Ordering::Relaxed);
+}
+
+fn listen_signal() -> anyhow::Result<()> {
+    let action = SigAction::new(
+        SigHandler::Handler(handle_trigger_signal),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    unsafe { sigaction(SIGUSR1, &action)? };
+    Ok(())
+}
+
+fn listen_stdin() {
+    if !stdin().is_terminal() {
+        return;
+    }
+
+    thread::spawn(|| {
+        for line in stdin().lock().lines() {
+            if line.is_err() {
+                break;
+            }
+            PENDING_TRIGGERS.fetch_add(1, Ordering::Relaxed);
+        }
+    });
+}
+
+/// Starts listening for user triggers, which are fired either by
+/// pressing Enter in the terminal or by sending SIGUSR1 to the
+/// process.
+pub fn listen() -> anyhow::Result<()> {
+    let mut result = Ok(());
+    LISTEN_ONCE.call_once(|| {
+        listen_stdin();
+        result = listen_signal();
+    });
+    result
+}
+
+/// Returns whether a trigger has been fired since the last call.
+pub fn take() -> bool {
+    PENDING_TRIGGERS.swap(0, Ordering::Relaxed) > 0
+}
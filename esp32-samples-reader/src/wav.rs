@@ -0,0 +1,722 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+#[cfg(feature = "analysis")]
+use crate::dsp::fingerprint::Fingerprinter;
+#[cfg(feature = "opus")]
+use crate::opus::{self, OpusWriter};
+use crate::{
+    dsp::{Sample, I24},
+    flac::{self, FlacWriter},
+    io::{self, ByteCounter, CountingWriter},
+    pipeline::SinkWriter,
+};
+use hound::{SampleFormat, WavSpec, WavWriter};
+
+/// A sample that can be written to a WAV file.
+pub trait WavSample: Sample {
+    const BITS_PER_SAMPLE: u16;
+    const FORMAT: SampleFormat;
+
+    /// Writes the sample through hound, one at a time.
+    fn write_to<W: Write + Seek>(self, writer: &mut WavWriter<W>) -> Result<(), hound::Error>;
+
+    /// Appends the samples as stored in the data chunk of the file.
+    fn write_le(samples: &[Self], bytes: &mut Vec<u8>);
+
+    /// The sample as an integer, for formats only holding integers.
+    fn to_int(self) -> Option<i32>;
+}
+
+impl WavSample for i8 {
+    const BITS_PER_SAMPLE: u16 = 8;
+    const FORMAT: SampleFormat = SampleFormat::Int;
+
+    fn write_to<W: Write + Seek>(self, writer: &mut WavWriter<W>) -> Result<(), hound::Error> {
+        writer.write_sample(self)
+    }
+
+    /// 8-bit WAV samples are stored unsigned.
+    fn write_le(samples: &[i8], bytes: &mut Vec<u8>) {
+        bytes.extend(samples.iter().map(|sample| *sample as u8 ^ 0x80));
+    }
+
+    fn to_int(self) -> Option<i32> {
+        Some(self as i32)
+    }
+}
+
+/// Stored unsigned in the file, as 8-bit WAV samples are, but hound
+/// only takes them signed.
+impl WavSample for u8 {
+    const BITS_PER_SAMPLE: u16 = 8;
+    const FORMAT: SampleFormat = SampleFormat::Int;
+
+    fn write_to<W: Write + Seek>(self, writer: &mut WavWriter<W>) -> Result<(), hound::Error> {
+        writer.write_sample((self ^ 0x80) as i8)
+    }
+
+    fn write_le(samples: &[u8], bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(samples);
+    }
+
+    fn to_int(self) -> Option<i32> {
+        Some(self as i32 - 128)
+    }
+}
+
+impl WavSample for i16 {
+    const BITS_PER_SAMPLE: u16 = 16;
+    const FORMAT: SampleFormat = SampleFormat::Int;
+
+    fn write_to<W: Write + Seek>(self, writer: &mut WavWriter<W>) -> Result<(), hound::Error> {
+        writer.write_sample(self)
+    }
+
+    fn write_le(samples: &[i16], bytes: &mut Vec<u8>) {
+        bytes.extend(samples.iter().flat_map(|sample| sample.to_le_bytes()));
+    }
+
+    fn to_int(self) -> Option<i32> {
+        Some(self as i32)
+    }
+}
+
+impl WavSample for I24 {
+    const BITS_PER_SAMPLE: u16 = 24;
+    const FORMAT: SampleFormat = SampleFormat::Int;
+
+    fn write_to<W: Write + Seek>(self, writer: &mut WavWriter<W>) -> Result<(), hound::Error> {
+        writer.write_sample(self.0)
+    }
+
+    fn write_le(samples: &[I24], bytes: &mut Vec<u8>) {
+        for sample in samples {
+            bytes.extend_from_slice(&sample.0.to_le_bytes()[..3]);
+        }
+    }
+
+    fn to_int(self) -> Option<i32> {
+        Some(self.0)
+    }
+}
+
+impl WavSample for f32 {
+    const BITS_PER_SAMPLE: u16 = 32;
+    const FORMAT: SampleFormat = SampleFormat::Float;
+
+    fn write_to<W: Write + Seek>(self, writer: &mut WavWriter<W>) -> Result<(), hound::Error> {
+        writer.write_sample(self)
+    }
+
+    fn write_le(samples: &[f32], bytes: &mut Vec<u8>) {
+        bytes.extend(samples.iter().flat_map(|sample| sample.to_le_bytes()));
+    }
+
+    fn to_int(self) -> Option<i32> {
+        None
+    }
+}
+
+/// Spec of a WAV file holding samples of type S.
+pub fn spec<S: WavSample>(sample_rate: u32, channels: u16) -> WavSpec {
+    WavSpec {
+        channels,
+        sample_rate,
+        bits_per_sample: S::BITS_PER_SAMPLE,
+        sample_format: S::FORMAT,
+    }
+}
+
+/// Samples converted and written at once, bounding the buffer they're
+/// converted into.
+const WRITE_BLOCK_LEN: usize = 64 * 1024;
+
+/// Writes a WAV file, taking its header from hound. hound writes
+/// samples one at a time, except for 16-bit ones, so the samples of
+/// every format are converted into a buffer instead and written in
+/// blocks. Interrupted writes are retried by the buffered writer of
+/// the file, so blocks are never written twice.
+pub struct WavFileWriter<W: Write + Seek> {
+    writer: W,
+    /// Offset of the length of the data chunk, the last field of the
+    /// header.
+    data_len_offset: u32,
+    data_len: u32,
+    buffer: Vec<u8>,
+    finalized: bool,
+}
+
+impl<W: Write + Seek> WavFileWriter<W> {
+    pub fn new(mut writer: W, spec: WavSpec) -> Result<WavFileWriter<W>, hound::Error> {
+        let mut header = std::io::Cursor::new(Vec::new());
+        WavWriter::new(&mut header, spec)?.finalize()?;
+        let header = header.into_inner();
+        writer.write_all(&header)?;
+        Ok(WavFileWriter {
+            writer,
+            data_len_offset: header.len() as u32 - 4,
+            data_len: 0,
+            buffer: Vec::new(),
+            finalized: false,
+        })
+    }
+
+    pub fn write_samples<S: WavSample>(&mut self, samples: &[S]) -> std::io::Result<()> {
+        for block in samples.chunks(WRITE_BLOCK_LEN) {
+            self.buffer.clear();
+            S::write_le(block, &mut self.buffer);
+            self.data_len = u32::try_from(self.buffer.len())
+                .ok()
+                .and_then(|len| self.data_len.checked_add(len))
+                .ok_or_else(|| {
+                    std::io::Error::other("WAV files can't hold over 4 GiB of samples")
+                })?;
+            self.writer.write_all(&self.buffer)?;
+        }
+        Ok(())
+    }
+
+    fn update_header(&mut self) -> std::io::Result<()> {
+        // The RIFF chunk holds everything after its own header.
+        let riff_len = self.data_len_offset + 4 - 8 + self.data_len;
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&riff_len.to_le_bytes())?;
+        self.writer
+            .seek(SeekFrom::Start(self.data_len_offset as u64))?;
+        self.writer.write_all(&self.data_len.to_le_bytes())
+    }
+
+    /// Updates the header, so the file can be read up to here, and
+    /// flushes it.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        let position = self.writer.stream_position()?;
+        self.update_header()?;
+        self.writer.flush()?;
+        self.writer.seek(SeekFrom::Start(position))?;
+        Ok(())
+    }
+
+    pub fn finalize(mut self) -> std::io::Result<()> {
+        self.finalized = true;
+        self.update_header()?;
+        self.writer.flush()
+    }
+}
+
+/// Like hound, keeps the header of files dropped without being
+/// finalized, after an error, up to date.
+impl<W: Write + Seek> Drop for WavFileWriter<W> {
+    fn drop(&mut self) {
+        if !self.finalized {
+            let _ = self.update_header();
+        }
+    }
+}
+
+/// Appends a tag to the file name of the given path, before its
+/// extension.
+pub fn tagged_path(path: &str, tag: &str) -> PathBuf {
+    let path = Path::new(path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, tag, ext.to_string_lossy()),
+        None => format!("{}-{}", stem, tag),
+    };
+    path.with_file_name(name)
+}
+
+/// Appends a sequence number to the file name of the given path,
+/// before its extension.
+pub fn numbered_path(path: &str, index: usize) -> PathBuf {
+    tagged_path(path, &format!("{:03}", index))
+}
+
+/// Path an output file is written to until it's complete: the final
+/// path with the given suffix appended, or the final path itself if
+/// there is none.
+pub fn in_progress_path(path: &Path, suffix: Option<&str>) -> PathBuf {
+    match suffix {
+        Some(suffix) => {
+            let mut name = path.as_os_str().to_owned();
+            name.push(suffix);
+            name.into()
+        }
+        None => path.to_path_buf(),
+    }
+}
+
+/// Gives a complete output file its final path. Renaming is atomic,
+/// so tools watching the directory never find the file half-written
+/// under that name.
+pub fn complete_output(in_progress: &Path, path: &Path) -> std::io::Result<()> {
+    if in_progress != path {
+        fs::rename(in_progress, path)?;
+    }
+    Ok(())
+}
+
+/// Path of the marker telling a file is complete: the path of the file
+/// with `.done` appended.
+pub fn done_marker_path(path: &Path) -> PathBuf {
+    in_progress_path(path, Some(".done"))
+}
+
+/// Summary of a complete file, for checking it before consuming it.
+pub struct FileDigest {
+    pub frames: u64,
+    pub spec: WavSpec,
+    pub fingerprint: Option<u64>,
+}
+
+/// Writes the marker of a complete file, holding its size and CRC-32
+/// along with the given digest as `key=value` lines. The marker is
+/// renamed into place once written, so tools watching the directory
+/// can take its appearance as the signal that the file is ready.
+pub fn write_done_marker(path: &Path, digest: &FileDigest) -> std::io::Result<()> {
+    let mut file = File::open(path)?;
+    let mut buf = vec![0; 64 * 1024];
+    let (mut crc, mut len) = (0, 0u64);
+    loop {
+        let read = io::recover_if_interrupted(|| file.read(&mut buf), || 0)?;
+        if read == 0 {
+            break;
+        }
+        crc = io::crc32(crc, &buf[..read]);
+        len += read as u64;
+    }
+
+    let mut marker = format!(
+        "file={}\n\
+         bytes={}\n\
+         crc32={:08x}\n\
+         frames={}\n\
+         duration={:.6}\n\
+         sample_rate={}\n\
+         channels={}\n",
+        path.file_name().unwrap_or_default().to_string_lossy(),
+        len,
+        crc,
+        digest.frames,
+        digest.frames as f64 / digest.spec.sample_rate as f64,
+        digest.spec.sample_rate,
+        digest.spec.channels,
+    );
+    if let Some(fingerprint) = digest.fingerprint {
+        marker.push_str(&format!("fingerprint={:016x}\n", fingerprint));
+    }
+
+    let done = done_marker_path(path);
+    let writing = in_progress_path(&done, Some(".tmp"));
+    fs::write(&writing, marker)?;
+    fs::rename(&writing, &done)
+}
+
+/// How the length of a file is brought to a multiple of a number of
+/// samples when finalized.
+#[derive(Clone, Copy, Debug)]
+pub enum Alignment {
+    /// Append silence up to the next multiple.
+    Pad(usize),
+    /// Drop the samples over the previous multiple.
+    Trim(usize),
+}
+
+impl Alignment {
+    fn scaled(self, factor: usize) -> Alignment {
+        match self {
+            Alignment::Pad(block) => Alignment::Pad(block * factor),
+            Alignment::Trim(block) => Alignment::Trim(block * factor),
+        }
+    }
+}
+
+/// Metadata of a recording, as a key and a value.
+#[derive(Clone, Debug)]
+pub struct Tag {
+    pub key: String,
+    pub value: String,
+}
+
+impl FromStr for Tag {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((key, value)) if !key.is_empty() => Ok(Tag {
+                key: key.into(),
+                value: value.into(),
+            }),
+            _ => Err(format!("Invalid tag '{}'. Expected <key>=<value>", s)),
+        }
+    }
+}
+
+impl Tag {
+    /// ID of the INFO chunk the tag is stored in, if it has one of its
+    /// own. Tags without one are stored in the comments.
+    fn info_id(&self) -> Option<[u8; 4]> {
+        let id = match self.key.to_lowercase().as_str() {
+            "title" | "name" => *b"INAM",
+            "artist" | "operator" => *b"IART",
+            "comment" => *b"ICMT",
+            "date" => *b"ICRD",
+            "engineer" => *b"IENG",
+            "subject" => *b"ISBJ",
+            "source" => *b"ISRC",
+            _ => match self.key.as_bytes() {
+                // Keys that are already an INFO ID, like ICOP.
+                &[a, b, c, d] if [a, b, c, d].iter().all(u8::is_ascii_uppercase) => [a, b, c, d],
+                _ => return None,
+            },
+        };
+        Some(id)
+    }
+}
+
+/// Tags as a Vorbis comment block, the way FLAC and Opus files hold
+/// them.
+pub(crate) fn vorbis_comments(tags: &[Tag]) -> Vec<u8> {
+    const VENDOR: &str = concat!("esp32-samples-reader ", env!("CARGO_PKG_VERSION"));
+
+    let mut data = Vec::new();
+    data.extend_from_slice(&(VENDOR.len() as u32).to_le_bytes());
+    data.extend_from_slice(VENDOR.as_bytes());
+    data.extend_from_slice(&(tags.len() as u32).to_le_bytes());
+    for tag in tags {
+        let comment = format!("{}={}", tag.key.to_uppercase(), tag.value);
+        data.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+        data.extend_from_slice(comment.as_bytes());
+    }
+    data
+}
+
+/// Appends a LIST INFO chunk with the given tags to a finished WAV
+/// file.
+fn append_info_chunk(path: &Path, tags: &[Tag]) -> std::io::Result<()> {
+    let mut entries: Vec<([u8; 4], String)> = Vec::new();
+    let mut comments = Vec::new();
+    for tag in tags {
+        match tag.info_id() {
+            Some(id) => entries.push((id, tag.value.clone())),
+            None => comments.push(format!("{}={}", tag.key, tag.value)),
+        }
+    }
+    if !comments.is_empty() {
+        entries.push((*b"ICMT", comments.join("; ")));
+    }
+
+    let mut chunk = b"INFO".to_vec();
+    for (id, value) in entries {
+        // Values are NUL terminated, and padded to an even length.
+        let mut value = value.into_bytes();
+        value.push(0);
+        chunk.extend_from_slice(&id);
+        chunk.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(&value);
+        if value.len() % 2 == 1 {
+            chunk.push(0);
+        }
+    }
+
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let mut len = file.seek(SeekFrom::End(0))?;
+    // Chunks start at even offsets, which the data chunk may not end
+    // at.
+    if len % 2 == 1 {
+        file.write_all(&[0])?;
+        len += 1;
+    }
+    file.write_all(b"LIST")?;
+    file.write_all(&(chunk.len() as u32).to_le_bytes())?;
+    file.write_all(&chunk)?;
+    len += 8 + chunk.len() as u64;
+
+    file.seek(SeekFrom::Start(4))?;
+    file.write_all(&((len - 8) as u32).to_le_bytes())?;
+    Ok(())
+}
+
+/// Container of the written files.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FileFormat {
+    #[default]
+    Wav,
+    /// Losslessly compressed, only for integer samples.
+    Flac,
+    /// Lossy Ogg Opus, resampled to 48 kHz.
+    #[cfg(feature = "opus")]
+    Opus,
+}
+
+impl FromStr for FileFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wav" => Ok(FileFormat::Wav),
+            "flac" => Ok(FileFormat::Flac),
+            #[cfg(feature = "opus")]
+            "opus" => Ok(FileFormat::Opus),
+            #[cfg(not(feature = "opus"))]
+            "opus" => Err("Opus output requires the opus feature".into()),
+            _ => Err(format!(
+                "Invalid file format '{}'. Expected wav, flac or opus",
+                s
+            )),
+        }
+    }
+}
+
+type FileOutput = BufWriter<CountingWriter<File>>;
+
+enum FileWriter {
+    Wav(WavFileWriter<FileOutput>),
+    Flac(Box<FlacWriter<FileOutput>>),
+    #[cfg(feature = "opus")]
+    Opus(Box<OpusWriter<FileOutput>>),
+}
+
+/// Settings of the written files, other than their sample format.
+#[derive(Clone, Default)]
+pub struct FileOptions {
+    pub format: FileFormat,
+    /// Of lossy formats, in kbit/s. Left to the encoder if not set.
+    pub bitrate: Option<u32>,
+    /// Report the fingerprint of the file once finished.
+    pub fingerprint: bool,
+    pub alignment: Option<Alignment>,
+    /// Stored in the INFO chunk of WAV files, or as Vorbis comments
+    /// of FLAC and Opus files.
+    pub tags: Vec<Tag>,
+    /// Appended to the name of the files while they are written, see
+    /// [`in_progress_path`].
+    pub inprogress_suffix: Option<String>,
+    /// Write a `.done` marker next to each file once complete, see
+    /// [`write_done_marker`].
+    pub done_marker: bool,
+    /// Counts the bytes written to all the files created with these
+    /// options.
+    pub written: ByteCounter,
+}
+
+/// A WAV, FLAC or Opus file being written, reporting its fingerprint once
+/// finished if requested.
+pub struct OutputFile<S> {
+    writer: FileWriter,
+    /// Samples converted to integers for FLAC files.
+    converted: Vec<i32>,
+    /// Samples converted to floats for Opus files.
+    #[cfg(feature = "opus")]
+    converted_f32: Vec<f32>,
+    path: PathBuf,
+    /// Where the file is written until finalized.
+    writing: PathBuf,
+    #[cfg(feature = "analysis")]
+    fingerprinter: Option<Fingerprinter>,
+    alignment: Option<Alignment>,
+    written: usize,
+    spec: WavSpec,
+    done_marker: bool,
+    // Samples that don't fill a whole block yet, when trimming.
+    pending: Vec<S>,
+    tags: Vec<Tag>,
+}
+
+impl<S: WavSample> OutputFile<S> {
+    pub fn create(
+        path: PathBuf,
+        spec: WavSpec,
+        options: &FileOptions,
+    ) -> anyhow::Result<OutputFile<S>> {
+        let writing = in_progress_path(&path, options.inprogress_suffix.as_deref());
+        let output = || -> std::io::Result<FileOutput> {
+            let file = File::create(&writing)?;
+            Ok(BufWriter::new(CountingWriter::new(
+                file,
+                options.written.clone(),
+            )))
+        };
+        let writer = match options.format {
+            FileFormat::Wav => FileWriter::Wav(WavFileWriter::new(output()?, spec)?),
+            FileFormat::Flac => {
+                flac::check_spec(&spec)?;
+                FileWriter::Flac(Box::new(FlacWriter::new(output()?, spec, &options.tags)?))
+            }
+            #[cfg(feature = "opus")]
+            FileFormat::Opus => {
+                opus::check_spec(&spec, options.bitrate)?;
+                FileWriter::Opus(Box::new(OpusWriter::new(
+                    output()?,
+                    spec,
+                    options.bitrate,
+                    &options.tags,
+                )?))
+            }
+        };
+        Ok(OutputFile {
+            writer,
+            converted: Vec::new(),
+            #[cfg(feature = "opus")]
+            converted_f32: Vec::new(),
+            path,
+            writing,
+            #[cfg(feature = "analysis")]
+            fingerprinter: options
+                .fingerprint
+                .then(|| Fingerprinter::new(spec.sample_rate)),
+            // Blocks are counted in frames, holding a sample of each
+            // channel.
+            alignment: options
+                .alignment
+                .map(|alignment| alignment.scaled(spec.channels as usize)),
+            written: 0,
+            spec,
+            done_marker: options.done_marker,
+            pending: Vec::new(),
+            tags: options.tags.clone(),
+        })
+    }
+
+    pub fn write(&mut self, samples: &[S]) -> anyhow::Result<()> {
+        match self.alignment {
+            Some(Alignment::Trim(block)) => {
+                self.pending.extend_from_slice(samples);
+                let whole = self.pending.len() / block * block;
+                let aligned: Vec<S> = self.pending.drain(..whole).collect();
+                self.write_unaligned(&aligned)
+            }
+            _ => self.write_unaligned(samples),
+        }
+    }
+
+    fn write_unaligned(&mut self, samples: &[S]) -> anyhow::Result<()> {
+        match &mut self.writer {
+            FileWriter::Wav(writer) => writer.write_samples(samples)?,
+            FileWriter::Flac(writer) => {
+                self.converted.clear();
+                self.converted
+                    .extend(samples.iter().filter_map(|sample| sample.to_int()));
+                writer.write(&self.converted)?;
+            }
+            #[cfg(feature = "opus")]
+            FileWriter::Opus(writer) => {
+                self.converted_f32.clear();
+                self.converted_f32
+                    .extend(samples.iter().map(|sample| sample.to_f32()));
+                writer.write(&self.converted_f32)?;
+            }
+        }
+        self.written += samples.len();
+        #[cfg(feature = "analysis")]
+        if let Some(fingerprinter) = &mut self.fingerprinter {
+            fingerprinter.push(samples);
+        }
+        Ok(())
+    }
+
+    /// Pushes the samples written so far to the disk, keeping WAV
+    /// headers up to date so the file can be read while it grows.
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        match &mut self.writer {
+            FileWriter::Wav(writer) => writer.flush()?,
+            FileWriter::Flac(writer) => writer.flush()?,
+            #[cfg(feature = "opus")]
+            FileWriter::Opus(writer) => writer.flush()?,
+        }
+        Ok(())
+    }
+
+    pub fn finalize(mut self) -> anyhow::Result<()> {
+        if let Some(Alignment::Pad(block)) = self.alignment {
+            let missing = (block - self.written % block) % block;
+            self.write_unaligned(&vec![S::SILENCE; missing])?;
+        }
+
+        match self.writer {
+            FileWriter::Wav(writer) => {
+                writer.finalize()?;
+                if !self.tags.is_empty() {
+                    append_info_chunk(&self.writing, &self.tags)?;
+                }
+            }
+            FileWriter::Flac(writer) => writer.finalize()?,
+            #[cfg(feature = "opus")]
+            FileWriter::Opus(writer) => writer.finalize()?,
+        }
+        complete_output(&self.writing, &self.path)?;
+        #[cfg(feature = "analysis")]
+        let fingerprint = self.fingerprinter.as_ref().map(|f| f.finish());
+        #[cfg(not(feature = "analysis"))]
+        let fingerprint = None;
+        if let Some(fingerprint) = fingerprint {
+            tracing::info!(
+                "Fingerprint of {}: {:016x}",
+                self.path.display(),
+                fingerprint
+            );
+        }
+        if self.done_marker {
+            let digest = FileDigest {
+                frames: (self.written / self.spec.channels as usize) as u64,
+                spec: self.spec,
+                fingerprint,
+            };
+            write_done_marker(&self.path, &digest)?;
+        }
+        Ok(())
+    }
+}
+
+impl<S: WavSample> SinkWriter<S> for OutputFile<S> {
+    fn write_samples(&mut self, samples: &[S]) -> anyhow::Result<()> {
+        self.write(samples)
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        OutputFile::flush(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn check_against_hound<S: WavSample>(samples: &[S], channels: u16) {
+        let spec = spec::<S>(8000, channels);
+        let mut expected = Cursor::new(Vec::new());
+        let mut writer = WavWriter::new(&mut expected, spec).unwrap();
+        for sample in samples {
+            sample.write_to(&mut writer).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let mut actual = Cursor::new(Vec::new());
+        let mut writer = WavFileWriter::new(&mut actual, spec).unwrap();
+        let (first, rest) = samples.split_at(samples.len() / 2);
+        writer.write_samples(first).unwrap();
+        writer.flush().unwrap();
+        writer.write_samples(rest).unwrap();
+        writer.finalize().unwrap();
+
+        assert_eq!(actual.into_inner(), expected.into_inner());
+    }
+
+    #[test]
+    fn writes_the_same_files_as_hound() {
+        check_against_hound(&[-128i8, -1, 0, 1, 127, 5], 2);
+        check_against_hound(&[0u8, 1, 127, 128, 255, 7], 1);
+        check_against_hound(&[i16::MIN, -1, 0, 1, i16::MAX, 300], 3);
+        check_against_hound(
+            &[I24::MIN, I24(-1), I24(0), I24(70000), I24::MAX, I24(3)],
+            2,
+        );
+        check_against_hound(&[-1.0f32, -0.5, 0.0, 0.25, 1.0, 0.1], 1);
+    }
+}
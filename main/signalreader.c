@@ -29,6 +29,14 @@
 #define UART_PORT_NUM      (2)
 #define UART_BAUD_RATE (128000)
 
+// Send the samples in frames (sync byte, sequence number, 16 bytes of
+// samples and a CRC-8), so the reader can find lost data when run
+// with --framed. Adds 3 bytes to every 16, so the baud rate needs to
+// be raised accordingly.
+#define FRAMED_PROTOCOL 0
+#define FRAME_SYNC 0xa5
+#define FRAME_PAYLOAD_LEN 16
+
 // Other stuff
 #define SAMPLING_RATE_PERIOD_US (US_IN_SECOND / SAMPLING_RATE)
 #define TAG "signal_reader"
@@ -38,12 +46,47 @@ volatile uint64_t samples_sent = 0;
 volatile bool io_error;
 uint8_t cur_sample = 0;
 uint8_t cur_sample_bits = 0;
+
+#if FRAMED_PROTOCOL
+uint8_t frame[FRAME_PAYLOAD_LEN + 3] = { FRAME_SYNC };
+uint8_t frame_len = 0;
+uint8_t frame_seq = 0;
+
+// CRC-8, polynomial 0x07.
+static IRAM_ATTR uint8_t crc8(const uint8_t *data, size_t len) {
+  uint8_t crc = 0;
+  for (size_t i = 0; i < len; i++) {
+    crc ^= data[i];
+    for (int bit = 0; bit < 8; bit++) {
+      crc = (crc & 0x80) ? (crc << 1) ^ 0x07 : crc << 1;
+    }
+  }
+  return crc;
+}
+
+static IRAM_ATTR int send_sample(uint8_t sample) {
+  frame[2 + frame_len++] = sample;
+  if (frame_len < FRAME_PAYLOAD_LEN) {
+    return 0;
+  }
+
+  frame[1] = frame_seq++;
+  frame[sizeof(frame) - 1] = crc8(&frame[1], FRAME_PAYLOAD_LEN + 1);
+  frame_len = 0;
+  return uart_write_bytes(UART_PORT_NUM, frame, sizeof(frame));
+}
+#else
+static IRAM_ATTR int send_sample(uint8_t sample) {
+  return uart_write_bytes(UART_PORT_NUM, &sample, 1);
+}
+#endif
+
 static IRAM_ATTR bool sampler_clock_isr(gptimer_handle_t timer, const gptimer_alarm_event_data_t *edata, void *user_ctx) {
   int value = gpio_get_level(SAMPLES_GPIO_SOURCE);
   cur_sample = ((cur_sample << 1) | value);
 
   if (++cur_sample_bits >= 8) {
-    if (uart_write_bytes(UART_PORT_NUM, &cur_sample, 1) < 0) {
+    if (send_sample(cur_sample) < 0) {
       io_error = true;
     } else {
       samples_sent += 8;